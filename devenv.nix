@@ -45,7 +45,10 @@
     set -e
     cargo fmt --check --all
     cargo check --workspace --all-targets
+    cargo check --workspace --all-targets --all-features
+    cargo check --bin wct --features cli
     cargo nextest run --workspace
+    cargo nextest run --workspace --all-features
     for target in wasm32-unknown-unknown wasm32-wasip2; do
       cargo build --release --workspace --target ''${target}
     done
@@ -0,0 +1,249 @@
+//! `wct` composes the packages listed in a graph manifest, then validates or instantiates the
+//! graph, or invokes a function exported by its root package.
+
+mod manifest;
+mod wave;
+
+use anyhow::{Context, ensure};
+use clap::{Args, Parser, Subcommand};
+use manifest::Manifest;
+use regex::Regex;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use wasm_component_trampoline::{
+    GraphRunner, GuestCall, GuestResult, ImportRule, NoopTrampoline, PackageId, PackageTrampoline,
+    RegexMatchFilter, TRACE_CONTEXT_INTERFACE, Trampoline, add_trace_context_to_linker,
+};
+use wasmtime::Store;
+use wasmtime::component::{Component, Instance, Val};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Checks the composition of the graph, reporting its diagnostics.
+    Validate {
+        #[command(flatten)]
+        graph: GraphArgs,
+    },
+
+    /// Instantiates the root package of the graph.
+    Instantiate {
+        #[command(flatten)]
+        graph: GraphArgs,
+    },
+
+    /// Invokes a function exported by the root package of the graph, printing its results.
+    Call {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        /// The function to call, as `interface#function`, or as `function` for a function
+        /// exported directly by the root package
+        function: String,
+
+        /// The arguments of the function, in the WAVE format
+        args: Vec<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct GraphArgs {
+    /// The graph manifest
+    manifest: PathBuf,
+
+    /// Log the calls between packages, with their arguments and results
+    #[arg(short, long)]
+    log_calls: bool,
+
+    /// Define the imports not provided by a package of the graph as functions that trap
+    #[arg(short, long)]
+    trap_unknown_imports: bool,
+}
+
+/// Logs the calls between packages to stderr.
+struct LoggingTrampoline;
+
+impl Trampoline<()> for LoggingTrampoline {
+    fn bounce<'c>(
+        &self,
+        call: GuestCall<'c, (), ()>,
+    ) -> Result<GuestResult<'c, (), ()>, anyhow::Error> {
+        let name = format!("{}#{}", call.interface(), call.method());
+        eprintln!("-> {name}({})", format_vals(call.arguments()));
+
+        let result = call.call();
+        match &result {
+            Ok(result) => eprintln!("<- {name}: {}", format_vals(result.results())),
+            Err(err) => eprintln!("<- {name} failed: {err}"),
+        }
+
+        result
+    }
+}
+
+fn format_vals(vals: &[Val]) -> String {
+    vals.iter()
+        .map(wave::format_val)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+struct Graph {
+    runner: GraphRunner<()>,
+    root: PackageId,
+    components: Vec<Vec<u8>>,
+}
+
+impl Graph {
+    fn load(args: &GraphArgs) -> anyhow::Result<Self> {
+        let manifest = Manifest::read(&args.manifest)?;
+        let root_index = manifest.root_index()?;
+
+        let mut runner = GraphRunner::new()?;
+        let graph = runner.graph_mut();
+        graph.set_warning_handler(|warning| eprintln!("warning: {warning}"));
+
+        // The trace context interface is provided by the host.
+        let trace_context = Regex::new(&format!("^{}$", regex::escape(TRACE_CONTEXT_INTERFACE)))?;
+        let mut filters = vec![RegexMatchFilter::new(trace_context, ImportRule::Skip)];
+        filters.extend(
+            manifest
+                .filters
+                .into_iter()
+                .map(|(regex, rule)| RegexMatchFilter::new(regex, rule)),
+        );
+        graph.set_import_filter(filters);
+
+        let mut root = None;
+        let mut components = Vec::new();
+        for (index, package) in manifest.packages.into_iter().enumerate() {
+            let package_id = if args.log_calls {
+                let trampoline: Arc<dyn Trampoline<()>> = Arc::new(LoggingTrampoline);
+                runner.add_package_file(
+                    package.name,
+                    package.version,
+                    &package.path,
+                    PackageTrampoline::with_default_context(trampoline, ()),
+                )
+            } else {
+                runner.add_package_file(
+                    package.name,
+                    package.version,
+                    &package.path,
+                    NoopTrampoline,
+                )
+            }?;
+
+            if index == root_index {
+                root = Some(package_id);
+            }
+            if args.trap_unknown_imports {
+                components.push(std::fs::read(&package.path)?);
+            }
+        }
+
+        add_trace_context_to_linker(runner.linker_mut(), None)?;
+
+        Ok(Self {
+            runner,
+            root: root.context("the manifest has no packages")?,
+            components,
+        })
+    }
+
+    fn instantiate(&mut self, store: &mut Store<()>) -> anyhow::Result<Instance> {
+        if !self.components.is_empty() {
+            // The graph shadows the traps of the interfaces it provides.
+            let engine = self.runner.engine().clone();
+            let linker = self.runner.linker_mut();
+            linker.allow_shadowing(true);
+            for bytes in self.components.drain(..) {
+                let component = Component::new(&engine, bytes)?;
+                linker.define_unknown_imports_as_traps(&component)?;
+            }
+        }
+
+        Ok(self.runner.instantiate(self.root, &mut *store)?)
+    }
+
+    fn call(
+        &mut self,
+        store: &mut Store<()>,
+        function: &str,
+        args: &[String],
+    ) -> anyhow::Result<Vec<Val>> {
+        let instance = self.instantiate(store)?;
+
+        // The arguments are parsed against the types of the function's parameters.
+        let func = self.runner.func(&mut *store, &instance, function)?;
+        let params = func.params(&*store);
+        ensure!(
+            params.len() == args.len(),
+            "'{function}' takes {} arguments, but {} were given",
+            params.len(),
+            args.len()
+        );
+
+        let params = params
+            .iter()
+            .zip(args)
+            .map(|((name, ty), arg)| {
+                wave::parse_val(arg, ty).with_context(|| format!("invalid argument '{name}'"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.runner.call(&mut *store, &instance, function, &params)
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<ExitCode> {
+    match cli.command {
+        Command::Validate { graph: args } => {
+            let graph = Graph::load(&args)?;
+
+            let diagnostics = graph.runner.graph().validate();
+            for diagnostic in &diagnostics {
+                eprintln!("{diagnostic}");
+            }
+
+            if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+                return Ok(ExitCode::FAILURE);
+            }
+            eprintln!("The graph is valid.");
+        }
+        Command::Instantiate { graph: args } => {
+            let mut graph = Graph::load(&args)?;
+            let mut store = graph.runner.new_store(());
+            graph.instantiate(&mut store)?;
+            eprintln!("The graph was instantiated.");
+        }
+        Command::Call {
+            graph: args,
+            function,
+            args: call_args,
+        } => {
+            let mut graph = Graph::load(&args)?;
+            let mut store = graph.runner.new_store(());
+
+            for result in graph.call(&mut store, &function, &call_args)? {
+                println!("{}", wave::format_val(&result));
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    run(Cli::parse()).unwrap_or_else(|err| {
+        eprintln!("error: {err:#}");
+        ExitCode::FAILURE
+    })
+}
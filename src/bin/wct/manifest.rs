@@ -0,0 +1,151 @@
+//! The JSON manifests describing the graphs composed by `wct`.
+//!
+//! A manifest lists the packages of the graph, with their component files relative to the
+//! manifest, the import filters applied to them, and optionally the package to instantiate:
+//!
+//! ```json
+//! {
+//!     "root": "test:application",
+//!     "packages": [
+//!         { "name": "test:kvstore", "version": "2.1.6", "path": "kvstore.component.wasm" },
+//!         { "name": "test:application", "version": "0.4.0", "path": "application.component.wasm" }
+//!     ],
+//!     "filters": [{ "pattern": "^test:logging/", "rule": "skip" }]
+//! }
+//! ```
+//!
+//! The root defaults to the last package, and can name a specific version as `name@version`.
+
+use anyhow::{Context, bail};
+use regex::Regex;
+use semver::Version;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use wasm_component_trampoline::ImportRule;
+
+#[derive(Debug)]
+pub struct Manifest {
+    pub root: Option<(String, Option<Version>)>,
+    pub packages: Vec<ManifestPackage>,
+    pub filters: Vec<(Regex, ImportRule)>,
+}
+
+#[derive(Debug)]
+pub struct ManifestPackage {
+    pub name: String,
+    pub version: Version,
+    pub path: PathBuf,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, resolving package paths relative to it.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read manifest '{}'", path.display()))?;
+        let json: Value = serde_json::from_str(&text)
+            .with_context(|| format!("invalid manifest '{}'", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        Self::from_json(&json, base)
+            .with_context(|| format!("invalid manifest '{}'", path.display()))
+    }
+
+    fn from_json(json: &Value, base: &Path) -> anyhow::Result<Self> {
+        let root = match json.get("root") {
+            None | Some(Value::Null) => None,
+            Some(root) => {
+                let root = root.as_str().context("'root' must be a string")?;
+                Some(match root.split_once('@') {
+                    Some((name, version)) => (
+                        name.to_string(),
+                        Some(Version::parse(version).context("invalid root version")?),
+                    ),
+                    None => (root.to_string(), None),
+                })
+            }
+        };
+
+        let packages = json
+            .get("packages")
+            .and_then(Value::as_array)
+            .context("'packages' must be an array")?
+            .iter()
+            .enumerate()
+            .map(|(index, package)| {
+                let field = |name: &str| {
+                    package
+                        .get(name)
+                        .and_then(Value::as_str)
+                        .with_context(|| format!("package {index} must have a '{name}' string"))
+                };
+
+                Ok(ManifestPackage {
+                    name: field("name")?.to_string(),
+                    version: Version::parse(field("version")?)
+                        .with_context(|| format!("invalid version of package {index}"))?,
+                    path: base.join(field("path")?),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let filters = match json.get("filters") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(filters) => filters
+                .as_array()
+                .context("'filters' must be an array")?
+                .iter()
+                .enumerate()
+                .map(|(index, filter)| {
+                    let field = |name: &str| {
+                        filter
+                            .get(name)
+                            .and_then(Value::as_str)
+                            .with_context(|| format!("filter {index} must have a '{name}' string"))
+                    };
+
+                    let regex = Regex::new(field("pattern")?)
+                        .with_context(|| format!("invalid pattern of filter {index}"))?;
+                    let rule = match field("rule")? {
+                        "skip" => ImportRule::Skip,
+                        "include" => ImportRule::Include,
+                        "force" => ImportRule::Force,
+                        rule => bail!(
+                            "invalid rule '{rule}' of filter {index}, expected 'skip', \
+                             'include' or 'force'"
+                        ),
+                    };
+
+                    Ok((regex, rule))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        };
+
+        Ok(Self {
+            root,
+            packages,
+            filters,
+        })
+    }
+
+    /// Returns the index of the root package in `packages`.
+    pub fn root_index(&self) -> anyhow::Result<usize> {
+        let Some((name, version)) = &self.root else {
+            return self
+                .packages
+                .len()
+                .checked_sub(1)
+                .context("the manifest has no packages");
+        };
+
+        // Without a version, the latest version of the package is the root.
+        self.packages
+            .iter()
+            .enumerate()
+            .filter(|(_, package)| {
+                package.name == *name && version.as_ref().is_none_or(|v| package.version == *v)
+            })
+            .max_by(|(_, a), (_, b)| a.version.cmp(&b.version))
+            .map(|(index, _)| index)
+            .with_context(|| format!("the root package '{name}' is not in the manifest"))
+    }
+}
@@ -0,0 +1,530 @@
+//! Parsing and formatting of component values in the WAVE text format, for the arguments and
+//! results of the functions invoked with `wct call`.
+//!
+//! Resources, futures, streams and error contexts cannot be written in WAVE, and multiline
+//! strings are not supported.
+
+use anyhow::{Context, bail, ensure};
+use std::fmt::Write;
+use wasmtime::component::{Type, Val};
+
+/// Names that must be prefixed with `%` to be used as labels.
+const KEYWORDS: [&str; 8] = ["true", "false", "some", "none", "ok", "err", "inf", "nan"];
+
+/// Parses `text` as a value of type `ty`.
+pub fn parse_val(text: &str, ty: &Type) -> anyhow::Result<Val> {
+    let mut parser = Parser { text, pos: 0 };
+
+    let val = parser.val(ty)?;
+    parser.skip_whitespace();
+    ensure!(
+        parser.pos == text.len(),
+        "unexpected '{}' after value",
+        &text[parser.pos..]
+    );
+
+    Ok(val)
+}
+
+/// Formats `val` in WAVE.
+pub fn format_val(val: &Val) -> String {
+    let mut text = String::new();
+    write_val(&mut text, val);
+    text
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.text.len() - self.rest().trim_start().len();
+    }
+
+    /// Consumes `token` if it is next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> anyhow::Result<()> {
+        ensure!(self.eat(token), "expected '{token}' at '{}'", self.rest());
+        Ok(())
+    }
+
+    /// Consumes the run of characters next that can be part of a number or label.
+    fn word(&mut self) -> &str {
+        self.skip_whitespace();
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.' | '_')))
+            .unwrap_or(self.rest().len());
+        self.pos += len;
+        &self.text[start..self.pos]
+    }
+
+    fn label(&mut self) -> anyhow::Result<String> {
+        self.skip_whitespace();
+        let escaped = self.eat("%");
+        let label = self.word();
+        ensure!(!label.is_empty(), "expected a label at '{}'", self.rest());
+        ensure!(
+            escaped || !KEYWORDS.contains(&label),
+            "'{label}' must be written as '%{label}' to be used as a label"
+        );
+        Ok(label.to_string())
+    }
+
+    /// Parses the values of a comma-separated sequence ending with `close`, after its opening
+    /// delimiter.
+    fn sequence(
+        &mut self,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        while !self.eat(close) {
+            item(self)?;
+            if !self.eat(",") {
+                return self.expect(close);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses an optional parenthesized payload of type `ty`.
+    fn payload(&mut self, ty: Option<Type>) -> anyhow::Result<Option<Box<Val>>> {
+        match ty {
+            Some(ty) => {
+                self.expect("(")?;
+                let val = self.val(&ty)?;
+                self.expect(")")?;
+                Ok(Some(Box::new(val)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn quoted(&mut self, quote: char) -> anyhow::Result<String> {
+        self.skip_whitespace();
+        let mut chars = self.rest().char_indices();
+        ensure!(
+            chars.next().map(|(_, c)| c) == Some(quote),
+            "expected {quote} at '{}'",
+            self.rest()
+        );
+
+        let mut text = String::new();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.pos += index + c.len_utf8();
+                    return Ok(text);
+                }
+                '\\' => text.push(match chars.next().map(|(_, c)| c) {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some(c @ ('\\' | '\'' | '"')) => c,
+                    Some('u') => {
+                        ensure!(
+                            chars.next().map(|(_, c)| c) == Some('{'),
+                            "expected '{{' in unicode escape"
+                        );
+                        let code = chars
+                            .by_ref()
+                            .map(|(_, c)| c)
+                            .take_while(|c| *c != '}')
+                            .collect::<String>();
+                        u32::from_str_radix(&code, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .with_context(|| format!("invalid unicode escape '{code}'"))?
+                    }
+                    escape => bail!("invalid escape '\\{}'", escape.unwrap_or_default()),
+                }),
+                c => text.push(c),
+            }
+        }
+
+        bail!("unterminated {quote}")
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, name: &str) -> anyhow::Result<T>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let word = self.word();
+        word.parse()
+            .with_context(|| format!("invalid {name} '{word}'"))
+    }
+
+    fn float(&mut self, name: &str) -> anyhow::Result<f64> {
+        Ok(match self.word() {
+            "nan" => f64::NAN,
+            "inf" => f64::INFINITY,
+            "-inf" => f64::NEG_INFINITY,
+            word => word
+                .parse()
+                .with_context(|| format!("invalid {name} '{word}'"))?,
+        })
+    }
+
+    fn val(&mut self, ty: &Type) -> anyhow::Result<Val> {
+        Ok(match ty {
+            Type::Bool => match self.word() {
+                "true" => Val::Bool(true),
+                "false" => Val::Bool(false),
+                word => bail!("invalid bool '{word}'"),
+            },
+            Type::S8 => Val::S8(self.number("s8")?),
+            Type::U8 => Val::U8(self.number("u8")?),
+            Type::S16 => Val::S16(self.number("s16")?),
+            Type::U16 => Val::U16(self.number("u16")?),
+            Type::S32 => Val::S32(self.number("s32")?),
+            Type::U32 => Val::U32(self.number("u32")?),
+            Type::S64 => Val::S64(self.number("s64")?),
+            Type::U64 => Val::U64(self.number("u64")?),
+            #[expect(clippy::cast_possible_truncation)]
+            Type::Float32 => Val::Float32(self.float("f32")? as f32),
+            Type::Float64 => Val::Float64(self.float("f64")?),
+            Type::Char => {
+                let text = self.quoted('\'')?;
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Val::Char(c),
+                    _ => bail!("invalid char '{text}'"),
+                }
+            }
+            Type::String => Val::String(self.quoted('"')?),
+            Type::List(list) => {
+                let mut vals = Vec::new();
+                self.expect("[")?;
+                self.sequence("]", |parser| {
+                    vals.push(parser.val(&list.ty())?);
+                    Ok(())
+                })?;
+                Val::List(vals)
+            }
+            Type::Tuple(tuple) => {
+                let mut vals = Vec::new();
+                self.expect("(")?;
+                for (index, ty) in tuple.types().enumerate() {
+                    if index > 0 {
+                        self.expect(",")?;
+                    }
+                    vals.push(self.val(&ty)?);
+                }
+                self.eat(",");
+                self.expect(")")?;
+                Val::Tuple(vals)
+            }
+            Type::Record(record) => {
+                let mut fields = Vec::new();
+                self.expect("{")?;
+                if !self.eat(":") {
+                    self.sequence("}", |parser| {
+                        let name = parser.label()?;
+                        let field = record
+                            .fields()
+                            .find(|field| field.name == name)
+                            .with_context(|| format!("unknown record field '{name}'"))?;
+                        parser.expect(":")?;
+                        fields.push((name, parser.val(&field.ty)?));
+                        Ok(())
+                    })?;
+                } else {
+                    self.expect("}")?;
+                }
+
+                // Fields are listed in the order of the type, and omitted options are `none`.
+                let mut ordered = Vec::new();
+                for field in record.fields() {
+                    let val = match fields.iter().position(|(name, _)| name == field.name) {
+                        Some(index) => fields.swap_remove(index).1,
+                        None if matches!(field.ty, Type::Option(_)) => Val::Option(None),
+                        None => bail!("missing record field '{}'", field.name),
+                    };
+                    ordered.push((field.name.to_string(), val));
+                }
+                Val::Record(ordered)
+            }
+            Type::Variant(variant) => {
+                let name = self.label()?;
+                let case = variant
+                    .cases()
+                    .find(|case| case.name == name)
+                    .with_context(|| format!("unknown variant case '{name}'"))?;
+                let payload = self.payload(case.ty)?;
+                Val::Variant(name, payload)
+            }
+            Type::Enum(enum_) => {
+                let name = self.label()?;
+                ensure!(
+                    enum_.names().any(|case| case == name),
+                    "unknown enum case '{name}'"
+                );
+                Val::Enum(name)
+            }
+            Type::Option(option) => {
+                let start = self.pos;
+                match self.word() {
+                    "none" => Val::Option(None),
+                    "some" => Val::Option(self.payload(Some(option.ty()))?),
+                    // Options of non-option types can be written as their value.
+                    _ if !matches!(option.ty(), Type::Option(_)) => {
+                        self.pos = start;
+                        Val::Option(Some(Box::new(self.val(&option.ty())?)))
+                    }
+                    word => bail!("expected 'some' or 'none', found '{word}'"),
+                }
+            }
+            Type::Result(result) => {
+                let (ok, ty) = match self.word() {
+                    "ok" => (true, result.ok()),
+                    "err" => (false, result.err()),
+                    word => bail!("expected 'ok' or 'err', found '{word}'"),
+                };
+                let payload = self.payload(ty)?;
+                Val::Result(if ok { Ok(payload) } else { Err(payload) })
+            }
+            Type::Flags(flags) => {
+                let mut names = Vec::new();
+                self.expect("{")?;
+                self.sequence("}", |parser| {
+                    let name = parser.label()?;
+                    ensure!(
+                        flags.names().any(|flag| flag == name),
+                        "unknown flag '{name}'"
+                    );
+                    names.push(name);
+                    Ok(())
+                })?;
+                Val::Flags(names)
+            }
+            Type::Own(_) | Type::Borrow(_) => bail!("resources cannot be written in WAVE"),
+            Type::Future(_) | Type::Stream(_) | Type::ErrorContext => {
+                bail!("futures, streams and error contexts cannot be written in WAVE")
+            }
+        })
+    }
+}
+
+fn write_label(text: &mut String, label: &str) {
+    if KEYWORDS.contains(&label) {
+        text.push('%');
+    }
+    text.push_str(label);
+}
+
+fn write_quoted(text: &mut String, quote: char, value: &str) {
+    text.push(quote);
+    for c in value.chars() {
+        match c {
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            '\t' => text.push_str("\\t"),
+            '\\' | '\'' | '"' => {
+                text.push('\\');
+                text.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(text, "\\u{{{:x}}}", u32::from(c));
+            }
+            c => text.push(c),
+        }
+    }
+    text.push(quote);
+}
+
+fn write_float(text: &mut String, value: f64) {
+    if value.is_nan() {
+        text.push_str("nan");
+    } else if value.is_infinite() {
+        text.push_str(if value > 0.0 { "inf" } else { "-inf" });
+    } else {
+        let _ = write!(text, "{value}");
+    }
+}
+
+fn write_payload(text: &mut String, payload: Option<&Val>) {
+    if let Some(payload) = payload {
+        text.push('(');
+        write_val(text, payload);
+        text.push(')');
+    }
+}
+
+fn write_sequence<'a, T: 'a>(
+    text: &mut String,
+    open: &str,
+    close: &str,
+    items: impl IntoIterator<Item = &'a T>,
+    mut write_item: impl FnMut(&mut String, &'a T),
+) {
+    text.push_str(open);
+    for (index, item) in items.into_iter().enumerate() {
+        if index > 0 {
+            text.push_str(", ");
+        }
+        write_item(text, item);
+    }
+    text.push_str(close);
+}
+
+fn write_val(text: &mut String, val: &Val) {
+    let _ = match val {
+        Val::Bool(value) => write!(text, "{value}"),
+        Val::S8(value) => write!(text, "{value}"),
+        Val::U8(value) => write!(text, "{value}"),
+        Val::S16(value) => write!(text, "{value}"),
+        Val::U16(value) => write!(text, "{value}"),
+        Val::S32(value) => write!(text, "{value}"),
+        Val::U32(value) => write!(text, "{value}"),
+        Val::S64(value) => write!(text, "{value}"),
+        Val::U64(value) => write!(text, "{value}"),
+        Val::Float32(value) => {
+            write_float(text, f64::from(*value));
+            Ok(())
+        }
+        Val::Float64(value) => {
+            write_float(text, *value);
+            Ok(())
+        }
+        Val::Char(value) => {
+            write_quoted(text, '\'', value.encode_utf8(&mut [0; 4]));
+            Ok(())
+        }
+        Val::String(value) => {
+            write_quoted(text, '"', value);
+            Ok(())
+        }
+        Val::List(vals) => {
+            write_sequence(text, "[", "]", vals, write_val);
+            Ok(())
+        }
+        Val::Tuple(vals) => {
+            write_sequence(text, "(", ")", vals, write_val);
+            Ok(())
+        }
+        Val::Record(fields) if fields.is_empty() => write!(text, "{{:}}"),
+        Val::Record(fields) => {
+            // Fields set to `none` are omitted, as they are implied.
+            let fields = fields
+                .iter()
+                .filter(|(_, val)| !matches!(val, Val::Option(None)));
+            write_sequence(text, "{", "}", fields, |text, (name, val)| {
+                write_label(text, name);
+                text.push_str(": ");
+                write_val(text, val);
+            });
+            Ok(())
+        }
+        Val::Variant(name, payload) => {
+            write_label(text, name);
+            write_payload(text, payload.as_deref());
+            Ok(())
+        }
+        Val::Enum(name) => {
+            write_label(text, name);
+            Ok(())
+        }
+        Val::Option(None) => write!(text, "none"),
+        Val::Option(Some(payload)) => {
+            text.push_str("some");
+            write_payload(text, Some(payload));
+            Ok(())
+        }
+        Val::Result(Ok(payload)) => {
+            text.push_str("ok");
+            write_payload(text, payload.as_deref());
+            Ok(())
+        }
+        Val::Result(Err(payload)) => {
+            text.push_str("err");
+            write_payload(text, payload.as_deref());
+            Ok(())
+        }
+        Val::Flags(names) => {
+            write_sequence(text, "{", "}", names, |text, name| write_label(text, name));
+            Ok(())
+        }
+        Val::Resource(_) => write!(text, "<resource>"),
+        Val::Future(_) => write!(text, "<future>"),
+        Val::Stream(_) => write!(text, "<stream>"),
+        Val::ErrorContext(_) => write!(text, "<error-context>"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::Engine;
+    use wasmtime::component::Component;
+    use wasmtime::component::types::ComponentItem;
+
+    #[test]
+    fn test_wave_roundtrip() {
+        let engine = Engine::default();
+        let component = Component::new(
+            &engine,
+            r#"(component
+                (import "types" (instance
+                    (type $color-def (enum "red" "green"))
+                    (export "color" (type $color (eq $color-def)))
+                    (type $shape-def (variant (case "circle" f64) (case "point")))
+                    (export "shape" (type $shape (eq $shape-def)))
+                    (type $point-def (record (field "x" s32) (field "label" (option string)) (field "color" $color)))
+                    (export "point" (type $point (eq $point-def)))
+                    (type $flags-def (flags "none" "read" "write"))
+                    (export "flags" (type $flags (eq $flags-def)))
+                    (export "f" (func
+                        (param "a" (list $point))
+                        (param "b" (tuple $shape char (result u8 (error string))))
+                        (param "c" $flags)
+                    ))
+                ))
+            )"#,
+        )
+        .unwrap();
+
+        let (_, ComponentItem::ComponentInstance(instance)) =
+            component.component_type().imports(&engine).next().unwrap()
+        else {
+            panic!("expected an instance import");
+        };
+        let ComponentItem::ComponentFunc(func) = instance.get_export(&engine, "f").unwrap() else {
+            panic!("expected a function export");
+        };
+        let params = func.params().map(|(_, ty)| ty).collect::<Vec<_>>();
+
+        for (text, ty) in [
+            (
+                r#"[{x: -1, color: green}, {x: 2, label: "a\n\u{1f600}", color: %red}]"#,
+                &params[0],
+            ),
+            (r#"(circle(1.5), '\\', err("bad"))"#, &params[1]),
+            ("(point, 'x', ok(7))", &params[1]),
+            ("{%none, write}", &params[2]),
+        ] {
+            let val = parse_val(text, ty).unwrap();
+            assert_eq!(parse_val(&format_val(&val), ty).unwrap(), val);
+        }
+
+        let val = parse_val(r#"[{x: 1, color: red}]"#, &params[0]).unwrap();
+        assert_eq!(format_val(&val), "[{x: 1, color: red}]");
+        assert!(parse_val("[{x: 1}]", &params[0]).is_err());
+        assert!(parse_val("{none}", &params[2]).is_err());
+    }
+}
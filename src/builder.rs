@@ -0,0 +1,276 @@
+use crate::graph::{PackageVerifierRef, verify_package};
+use crate::{AddPackageError, CompositionGraph, DynPackageTrampoline, PackageId, PackagePolicy};
+use semver::Version;
+use std::sync::{Mutex, PoisonError};
+use wasmparser::{Validator, WasmFeatures};
+
+/// Prepares packages on multiple threads, to be added to a `CompositionGraph` at once, such as
+/// while downloading a catalog in parallel.
+///
+/// The graph itself cannot be shared between threads, since all of its packages register their
+/// types in one type collection. A builder, created with `CompositionGraph::builder`, does the
+/// work of adding a package that does not need the graph on the thread adding it: checking the
+/// package against the graph's package policy and verifier, and validating the whole component,
+/// including the function bodies the graph leaves to compilation. `build` then merges the
+/// prepared packages into the graph, which only parses their types.
+pub struct GraphBuilder<D, C: Clone = ()> {
+    package_policy: Option<PackagePolicy>,
+    package_verifier: Option<PackageVerifierRef>,
+    packages: Mutex<Vec<PreparedPackage<D, C>>>,
+}
+
+fn _assert_graph_builder_sync(builder: &GraphBuilder<()>) -> &(dyn Sync + '_) {
+    builder
+}
+
+/// A validated package waiting to be merged into the graph.
+struct PreparedPackage<D, C: Clone> {
+    name: String,
+    version: Version,
+    bytes: Vec<u8>,
+    trampoline: Box<dyn DynPackageTrampoline<D, C> + Send>,
+}
+
+impl<D, C: Clone> GraphBuilder<D, C> {
+    pub(crate) fn new(
+        package_policy: Option<PackagePolicy>,
+        package_verifier: Option<PackageVerifierRef>,
+    ) -> Self {
+        Self {
+            package_policy,
+            package_verifier,
+            packages: Mutex::default(),
+        }
+    }
+
+    /// Checks and validates a package on the calling thread, and adds it to the builder if
+    /// valid, returning its index in the results of `build`.
+    ///
+    /// Packages denied by the package policy, failing verification or invalid are refused here.
+    /// Errors that depend on the other packages of the graph, such as duplicate packages, are
+    /// reported by `build`.
+    pub fn add_package(
+        &self,
+        name: String,
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        trampoline: impl DynPackageTrampoline<D, C> + Send + 'static,
+    ) -> Result<usize, AddPackageError> {
+        let bytes = bytes.into();
+        verify_package(
+            self.package_policy.as_ref(),
+            self.package_verifier.as_ref(),
+            &name,
+            &version,
+            &bytes,
+        )?;
+
+        Validator::new_with_features(WasmFeatures::all())
+            .validate_all(&bytes)
+            .map_err(|err| AddPackageError::PackageParseError { source: err.into() })?;
+
+        let package = PreparedPackage {
+            name,
+            version,
+            bytes,
+            trampoline: Box::new(trampoline),
+        };
+
+        // Pushing cannot leave the packages in an inconsistent state, so a poisoned lock is safe
+        // to reuse.
+        let mut packages = self.packages.lock().unwrap_or_else(PoisonError::into_inner);
+        packages.push(package);
+        Ok(packages.len() - 1)
+    }
+
+    /// Returns the number of packages added to the builder.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.packages
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Returns `true` if no packages have been added to the builder.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Merges the packages into `graph`, in the order they were added to the builder.
+    ///
+    /// Returns the result of merging each package, indexed like the return values of
+    /// `GraphBuilder::add_package`. A package failing to be merged does not prevent the others
+    /// from being merged. Packages are not checked again against the package policy and verifier
+    /// of `graph`, which should be the graph the builder was created from.
+    pub fn build(
+        self,
+        graph: &mut CompositionGraph<D, C>,
+    ) -> Vec<Result<PackageId, AddPackageError>> {
+        self.packages
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .into_iter()
+            .map(|package| {
+                graph.add_verified_package(
+                    package.name,
+                    package.version,
+                    package.bytes,
+                    package.trampoline,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoopTrampoline;
+    use crate::testing::{ComponentFixture, FixtureFunc};
+    use wasmtime::component::Linker;
+    use wasmtime::{Engine, Store};
+
+    fn kvstore() -> Vec<u8> {
+        ComponentFixture::new()
+            .export("test:kvstore/store@1.0.0", [("get", FixtureFunc::Echo)])
+            .to_bytes()
+            .unwrap()
+    }
+
+    fn app() -> Vec<u8> {
+        ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .export(
+                "test:app/run@1.0.0",
+                [(
+                    "run",
+                    FixtureFunc::Forward {
+                        interface: "test:kvstore/store@1.0.0".to_string(),
+                        function: "get".to_string(),
+                    },
+                )],
+            )
+            .to_bytes()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_graph_builder() {
+        let mut graph = CompositionGraph::<()>::new();
+        let builder = graph.builder();
+
+        let (kvstore, app) = std::thread::scope(|scope| {
+            let add = |name: &str, bytes| {
+                let builder = &builder;
+                let name = name.to_string();
+                scope.spawn(move || {
+                    builder.add_package(name, Version::new(1, 0, 0), bytes, NoopTrampoline)
+                })
+            };
+            let (kvstore, app) = (add("test:kvstore", kvstore()), add("test:app", app()));
+            (
+                kvstore.join().unwrap().unwrap(),
+                app.join().unwrap().unwrap(),
+            )
+        });
+        assert_eq!(builder.len(), 2);
+
+        let results = builder.build(&mut graph);
+        let app_id = *results[app].as_ref().unwrap();
+        assert!(results[kvstore].is_ok());
+        assert!(graph.validate_package(app_id).unwrap().is_resolved());
+
+        let engine = Engine::default();
+        let mut store = Store::new(&engine, ());
+        let instance = graph
+            .instantiate(app_id, &mut Linker::new(&engine), &mut store, &engine)
+            .unwrap();
+        let interface = instance.get_export_index(&mut store, None, "test:app/run@1.0.0");
+        let index = instance
+            .get_export_index(&mut store, interface.as_ref(), "run")
+            .unwrap();
+        let run = instance
+            .get_typed_func::<(u32,), (u32,)>(&mut store, &index)
+            .unwrap();
+        assert_eq!(run.call(&mut store, (7,)).unwrap(), (7,));
+    }
+
+    #[test]
+    fn test_graph_builder_validates_function_bodies() {
+        let bytes = wat::parse_str(
+            r#"(component
+                (core module
+                    (func (result i32) i64.const 0)
+                )
+            )"#,
+        )
+        .unwrap();
+
+        // The graph only validates function bodies when compiling the component.
+        let mut graph = CompositionGraph::<()>::new();
+        let builder = graph.builder();
+        graph
+            .add_package(
+                "test:invalid".to_string(),
+                Version::new(1, 0, 0),
+                bytes.clone(),
+                NoopTrampoline,
+            )
+            .unwrap();
+
+        let err = builder
+            .add_package(
+                "test:invalid".to_string(),
+                Version::new(2, 0, 0),
+                bytes,
+                NoopTrampoline,
+            )
+            .unwrap_err();
+        assert!(matches!(err, AddPackageError::PackageParseError { .. }));
+        assert!(builder.is_empty());
+    }
+
+    #[test]
+    fn test_graph_builder_checks() {
+        let mut graph = CompositionGraph::<()>::new();
+        graph
+            .add_package(
+                "test:kvstore".to_string(),
+                Version::new(1, 0, 0),
+                kvstore(),
+                NoopTrampoline,
+            )
+            .unwrap();
+        graph.set_package_verifier(|name: &str, _: &Version, _: &[u8]| {
+            anyhow::ensure!(name != "test:untrusted", "untrusted publisher");
+            Ok(())
+        });
+        let builder = graph.builder();
+
+        let err = builder
+            .add_package(
+                "test:untrusted".to_string(),
+                Version::new(1, 0, 0),
+                kvstore(),
+                NoopTrampoline,
+            )
+            .unwrap_err();
+        assert!(matches!(err, AddPackageError::VerificationFailed { .. }));
+
+        // Duplicates are only found when merging into the graph.
+        let duplicate = builder
+            .add_package(
+                "test:kvstore".to_string(),
+                Version::new(1, 0, 0),
+                kvstore(),
+                NoopTrampoline,
+            )
+            .unwrap();
+        assert!(matches!(
+            builder.build(&mut graph)[duplicate],
+            Err(AddPackageError::DuplicatePackage { .. })
+        ));
+    }
+}
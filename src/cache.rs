@@ -0,0 +1,313 @@
+use crate::Sha256Digest;
+use crate::logging::log_debug;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use wasmtime::Engine;
+use wasmtime::component::Component;
+
+/// Compiled components, keyed by the digest of the bytes they were compiled from and the engine
+/// they were compiled for.
+///
+/// Components are cached through shared references, so that graphs shared between threads compile
+/// them on first instantiation.
+#[derive(Default)]
+pub(crate) struct ComponentCache {
+    components: Mutex<HashMap<Sha256Digest, Vec<(Engine, Component)>>>,
+    /// Serialized components provided by the host, by the digest of the bytes they were compiled
+    /// from.
+    precompiled: HashMap<Sha256Digest, Vec<u8>>,
+    disk_dir: Option<PathBuf>,
+}
+
+impl ComponentCache {
+    /// Returns the component compiled from `bytes` for `engine`, compiling it on a cache miss.
+    ///
+    /// The cache is not locked while compiling, so concurrent misses may compile the same
+    /// component, keeping the first one cached.
+    pub(crate) fn get_or_compile(
+        &self,
+        engine: &Engine,
+        bytes: &[u8],
+    ) -> Result<Component, anyhow::Error> {
+        let digest = Sha256Digest::of(bytes);
+
+        if let Some(component) = self.get_digested(engine, &digest) {
+            return Ok(component);
+        }
+
+        let component = self.compile_digested(engine, bytes, &digest)?;
+        self.insert_digested(engine, digest, component.clone());
+
+        Ok(component)
+    }
+
+    /// Returns the component compiled from `bytes` for `engine`, if it is cached in memory.
+    pub(crate) fn get(&self, engine: &Engine, bytes: &[u8]) -> Option<Component> {
+        self.get_digested(engine, &Sha256Digest::of(bytes))
+    }
+
+    fn get_digested(&self, engine: &Engine, digest: &Sha256Digest) -> Option<Component> {
+        self.components
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(digest)?
+            .iter()
+            .find(|(compiled_engine, _)| Engine::same(compiled_engine, engine))
+            .map(|(_, component)| component.clone())
+    }
+
+    /// Compiles `bytes` for `engine` without caching the component in memory, deserializing its
+    /// precompiled component or loading it from the cache directory instead if possible.
+    pub(crate) fn compile(
+        &self,
+        engine: &Engine,
+        bytes: &[u8],
+    ) -> Result<Component, anyhow::Error> {
+        self.compile_digested(engine, bytes, &Sha256Digest::of(bytes))
+    }
+
+    fn compile_digested(
+        &self,
+        engine: &Engine,
+        bytes: &[u8],
+        digest: &Sha256Digest,
+    ) -> Result<Component, anyhow::Error> {
+        if let Some(component) = self.deserialize_precompiled(engine, digest) {
+            return Ok(component);
+        }
+
+        let Some(dir) = &self.disk_dir else {
+            return Component::new(engine, bytes);
+        };
+
+        let path = disk_path(dir, engine, digest);
+
+        if let Some(component) = load(engine, &path, bytes) {
+            log_debug!(path:? = path; "Loaded compiled component from the cache directory");
+            return Ok(component);
+        }
+
+        let component = Component::new(engine, bytes)?;
+        // The disk cache is best effort, a failed write only costs a recompilation in the next
+        // process.
+        let _ = store(&path, bytes, &component);
+
+        Ok(component)
+    }
+
+    /// Deserializes the precompiled component of the bytes with `digest`, if it has one
+    /// compatible with `engine`.
+    fn deserialize_precompiled(&self, engine: &Engine, digest: &Sha256Digest) -> Option<Component> {
+        let serialized = self.precompiled.get(digest)?;
+
+        // SAFETY: Precompiled components are trusted by the host, which guarantees it by adding
+        // them with `CompositionGraph::add_precompiled_package`.
+        let component = unsafe { Component::deserialize(engine, serialized) }.ok();
+        if component.is_none() {
+            log_debug!("Precompiled component is incompatible with the engine, compiling instead");
+        }
+
+        component
+    }
+
+    /// Uses the serialized component `precompiled` instead of compiling `bytes`, for engines it
+    /// is compatible with.
+    ///
+    /// # Safety
+    ///
+    /// See `CompositionGraph::add_precompiled_package`.
+    pub(crate) unsafe fn insert_precompiled(&mut self, bytes: &[u8], precompiled: Vec<u8>) {
+        self.precompiled
+            .insert(Sha256Digest::of(bytes), precompiled);
+    }
+
+    /// Caches `component`, compiled from `bytes` for `engine`, in memory.
+    pub(crate) fn insert(&self, engine: &Engine, bytes: &[u8], component: Component) {
+        self.insert_digested(engine, Sha256Digest::of(bytes), component);
+    }
+
+    fn insert_digested(&self, engine: &Engine, digest: Sha256Digest, component: Component) {
+        let mut components = self
+            .components
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let compiled = components.entry(digest).or_default();
+
+        if !compiled
+            .iter()
+            .any(|(compiled_engine, _)| Engine::same(compiled_engine, engine))
+        {
+            compiled.push((engine.clone(), component));
+        }
+    }
+
+    /// Removes the components compiled from `bytes`, for all engines.
+    pub(crate) fn invalidate(&mut self, bytes: &[u8]) {
+        self.components
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&Sha256Digest::of(bytes));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.components
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    pub(crate) fn disk_dir(&self) -> Option<&Path> {
+        self.disk_dir.as_deref()
+    }
+
+    /// Sets the directory compiled components are persisted in.
+    ///
+    /// # Safety
+    ///
+    /// See `CompositionGraph::set_component_cache_dir`.
+    pub(crate) unsafe fn set_disk_dir(&mut self, dir: Option<PathBuf>) {
+        self.disk_dir = dir;
+    }
+}
+
+/// Returns the path of the component compiled from the bytes with `digest`, for engines
+/// compatible with `engine`.
+fn disk_path(dir: &Path, engine: &Engine, digest: &Sha256Digest) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    let digest = digest.to_string();
+    dir.join(format!(
+        "{}-{:016x}.cwasm",
+        digest.trim_start_matches("sha256:"),
+        hasher.finish()
+    ))
+}
+
+/// Loads the component persisted at `path`, if it was compiled from `bytes`.
+///
+/// Cache files start with the length and contents of the bytes they were compiled from, so that
+/// hash collisions are detected before deserializing, followed by the serialized component.
+fn load(engine: &Engine, path: &Path, bytes: &[u8]) -> Option<Component> {
+    let file = fs::read(path).ok()?;
+    let (len, file) = file.split_first_chunk::<8>()?;
+
+    if usize::try_from(u64::from_le_bytes(*len)).ok()? != bytes.len() {
+        return None;
+    }
+
+    let serialized = file.strip_prefix(bytes)?;
+
+    // SAFETY: The cache directory only contains components serialized by `store`, which the host
+    // guarantees by setting it with `CompositionGraph::set_component_cache_dir`.
+    unsafe { Component::deserialize(engine, serialized) }.ok()
+}
+
+/// Persists `component`, compiled from `bytes`, at `path`.
+fn store(path: &Path, bytes: &[u8], component: &Component) -> Result<(), anyhow::Error> {
+    let serialized = component.serialize()?;
+
+    let mut file = Vec::with_capacity(8 + bytes.len() + serialized.len());
+    file.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    file.extend_from_slice(bytes);
+    file.extend_from_slice(&serialized);
+
+    // Write to a temporary file first, so that concurrent processes never load a partially
+    // written component.
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temp_path, file)?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn len(cache: &ComponentCache) -> usize {
+        cache
+            .components
+            .lock()
+            .unwrap()
+            .values()
+            .map(Vec::len)
+            .sum()
+    }
+
+    #[test]
+    fn test_component_cache() {
+        let engine = Engine::default();
+        let mut cache = ComponentCache::default();
+
+        cache.get_or_compile(&engine, b"(component)").unwrap();
+        cache.get_or_compile(&engine, b"(component)").unwrap();
+        assert_eq!(len(&cache), 1);
+
+        cache
+            .get_or_compile(&Engine::default(), b"(component)")
+            .unwrap();
+        assert_eq!(len(&cache), 2);
+
+        assert!(cache.get_or_compile(&engine, b"(module)").is_err());
+        assert_eq!(len(&cache), 2);
+
+        cache.invalidate(b"(component)");
+        assert_eq!(len(&cache), 0);
+    }
+
+    #[test]
+    fn test_component_cache_keys() {
+        let engine = Engine::default();
+        let mut cache = ComponentCache::default();
+
+        cache.get_or_compile(&engine, b"(component)").unwrap();
+        cache.get_or_compile(&engine, b"(component )").unwrap();
+        assert_eq!(len(&cache), 2);
+
+        // Only the component compiled from the exact bytes is evicted.
+        cache.invalidate(b"(component )");
+        assert_eq!(len(&cache), 1);
+        assert!(cache.get(&engine, b"(component)").is_some());
+        assert!(cache.get(&engine, b"(component )").is_none());
+    }
+
+    #[test]
+    fn test_component_disk_cache() {
+        let dir = std::env::temp_dir().join(format!("wct-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let engine = Engine::default();
+        let path = disk_path(&dir, &engine, &Sha256Digest::of(b"(component)"));
+
+        let mut cache = ComponentCache::default();
+        unsafe { cache.set_disk_dir(Some(dir.clone())) };
+        cache.get_or_compile(&engine, b"(component)").unwrap();
+        assert!(path.exists());
+
+        assert!(load(&engine, &path, b"(component)").is_some());
+        assert!(load(&engine, &path, b"(component )").is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_precompiled_components() {
+        let engine = Engine::default();
+        let serialized = Component::new(&engine, b"(component)")
+            .unwrap()
+            .serialize()
+            .unwrap();
+
+        // The precompiled component is used instead of compiling the bytes, which would fail.
+        let mut cache = ComponentCache::default();
+        unsafe { cache.insert_precompiled(b"(module)", serialized) };
+        assert!(cache.compile(&engine, b"(module)").is_ok());
+
+        // Incompatible precompiled components fall back to compiling.
+        unsafe { cache.insert_precompiled(b"(component)", b"not a cwasm".to_vec()) };
+        assert!(cache.compile(&engine, b"(component)").is_ok());
+    }
+}
@@ -0,0 +1,622 @@
+//! Static composition of packages into a single fused component, in the style of `wac` and
+//! `wasm-compose`, for the links of a graph that need no trampoline. See
+//! `CompositionGraph::compose_static`.
+//!
+//! The composed component is encoded directly with `wasm-encoder` rather than through
+//! `wac-graph`. The graph has already resolved every link in its `ResolutionReport`, against
+//! package types registered in its own `wac_types::Types`, so composing only takes
+//! instantiating the packages in load order, aliasing the instances satisfying each import and
+//! importing the types of the remaining host imports. A `wac-graph` composition would register
+//! every package again in a separate type collection and resolve the same links a second time,
+//! with no way to carry over the graph's provider selection, import rules and version
+//! strategies.
+
+use crate::graph::package_label;
+use crate::path::InterfacePath;
+use crate::{CycleEdge, ForeignInterfacePath, InstantiateError, LoadPackageError, PackageId};
+use crate::{ResolutionReport, ResolvedEdge};
+use std::collections::HashMap;
+use std::str::FromStr;
+use wac_types::{
+    DefinedType, DefinedTypeId, FuncTypeId, InterfaceId, ItemKind, Package, PrimitiveType,
+    ResourceId, Type, Types, ValueType,
+};
+use wasm_encoder::{
+    Alias, ComponentBuilder, ComponentExportKind, ComponentOuterAliasKind, ComponentTypeRef,
+    ComponentValType, InstanceType, PrimitiveValType, TypeBounds,
+};
+
+/// A package that cannot be composed statically with `CompositionGraph::compose_static`.
+#[derive(snafu::Snafu, Debug)]
+#[snafu(module, visibility(pub(crate)))]
+pub enum ComposeError {
+    #[snafu(display("Failed to resolve the dependencies of the package"))]
+    Resolve { source: InstantiateError },
+
+    #[snafu(display("Failed to resolve an import of the package"))]
+    Unresolved { source: LoadPackageError },
+
+    #[snafu(display("The dependencies of the package form a cycle"))]
+    PackageCycle { edges: Vec<CycleEdge> },
+
+    #[snafu(display("Import '{import}' of package '{importer}' cannot be composed: {reason}"))]
+    UnsupportedImport {
+        importer: String,
+        import: String,
+        reason: &'static str,
+    },
+}
+
+/// Composes the packages of `report` into a component that instantiates them in its load order
+/// and exports the exports of the validated package.
+///
+/// Imports resolved by an edge of `report` are satisfied by the exports of the instance of its
+/// provider, and all other imports become imports of the composed component.
+pub(crate) fn compose<'a>(
+    types: &Types,
+    report: &ResolutionReport,
+    package: impl Fn(PackageId) -> Option<&'a Package>,
+) -> Result<Vec<u8>, ComposeError> {
+    let mut composer = Composer {
+        types,
+        report,
+        builder: ComponentBuilder::default(),
+        instances: HashMap::new(),
+        host_instances: HashMap::new(),
+        export_instances: HashMap::new(),
+        used_types: HashMap::new(),
+    };
+
+    let mut root = None;
+    for &package_id in &report.load_order {
+        let package = package(package_id).ok_or(ComposeError::Resolve {
+            source: InstantiateError::PackageNotFound { id: package_id },
+        })?;
+        let importer = package_label(package);
+
+        let mut args = Vec::new();
+        for (name, kind) in &types[package.ty()].imports {
+            let ItemKind::Instance(interface) = kind else {
+                return compose_error::UnsupportedImportSnafu {
+                    importer,
+                    import: name.as_str(),
+                    reason: "only interfaces can be imported",
+                }
+                .fail();
+            };
+
+            let instance = composer.import_instance(package_id, &importer, name, *interface)?;
+            args.push((name.as_str(), ComponentExportKind::Instance, instance));
+        }
+
+        let component = composer.builder.component_raw(package.bytes());
+        let instance = composer.builder.instantiate(component, args);
+        composer.instances.insert(package_id, instance);
+        root = Some((package, instance));
+    }
+
+    if let Some((package, instance)) = root {
+        for (name, kind) in &types[package.ty()].exports {
+            let kind = export_kind(kind);
+            let index = composer.builder.alias_export(instance, name, kind);
+            composer.builder.export(name, kind, index, None);
+        }
+    }
+
+    Ok(composer.builder.finish())
+}
+
+fn export_kind(kind: &ItemKind) -> ComponentExportKind {
+    match kind {
+        ItemKind::Type(_) => ComponentExportKind::Type,
+        ItemKind::Func(_) => ComponentExportKind::Func,
+        ItemKind::Instance(_) => ComponentExportKind::Instance,
+        ItemKind::Component(_) => ComponentExportKind::Component,
+        ItemKind::Module(_) => ComponentExportKind::Module,
+        ItemKind::Value(_) => ComponentExportKind::Value,
+    }
+}
+
+struct Composer<'a> {
+    types: &'a Types,
+    report: &'a ResolutionReport,
+    builder: ComponentBuilder,
+    /// The instance of each package instantiated so far.
+    instances: HashMap<PackageId, u32>,
+    /// The instances imported by the composed component, by import name.
+    host_instances: HashMap<String, u32>,
+    /// The instance exports of packages aliased so far, by package and export name.
+    export_instances: HashMap<(PackageId, String), u32>,
+    /// The types aliased from instances so far, by instance and export name.
+    used_types: HashMap<(u32, String), u32>,
+}
+
+impl Composer<'_> {
+    /// Returns the instance satisfying the import `name` of the package `importer_id`.
+    fn import_instance(
+        &mut self,
+        importer_id: PackageId,
+        importer: &str,
+        name: &str,
+        interface: InterfaceId,
+    ) -> Result<u32, ComposeError> {
+        if let Some(edge) = self.edge(importer_id, name) {
+            let export = ForeignInterfacePath::new(
+                edge.import.package_name().to_string(),
+                edge.import.interface_name().to_string(),
+                Some(edge.version.clone()),
+            );
+            let key = (edge.provider, export.as_str().to_string());
+            if let Some(&instance) = self.export_instances.get(&key) {
+                return Ok(instance);
+            }
+
+            // Providers precede their importers in the load order.
+            let provider =
+                self.instances
+                    .get(&edge.provider)
+                    .copied()
+                    .ok_or(ComposeError::Resolve {
+                        source: InstantiateError::PackageNotFound { id: edge.provider },
+                    })?;
+            let instance =
+                self.builder
+                    .alias_export(provider, export.as_str(), ComponentExportKind::Instance);
+            self.export_instances.insert(key, instance);
+            return Ok(instance);
+        }
+
+        if let Some(&instance) = self.host_instances.get(name) {
+            return Ok(instance);
+        }
+
+        let ty = self.instance_type(importer_id, importer, name, interface)?;
+        let ty = self.builder.type_instance(&ty);
+        let instance = self.builder.import(name, ComponentTypeRef::Instance(ty));
+        self.host_instances.insert(name.to_string(), instance);
+        Ok(instance)
+    }
+
+    fn edge(&self, importer: PackageId, name: &str) -> Option<&ResolvedEdge> {
+        let import = InterfacePath::from_str(name).ok()?.into_foreign()?;
+        self.report
+            .edges_of(importer)
+            .find(|edge| edge.import == import)
+    }
+
+    /// Encodes the type of the interface imported by the composed component as `name`.
+    ///
+    /// Types the interface uses from other interfaces are aliased from the instances of those
+    /// interfaces, which are imported first if they are not provided by a package.
+    fn instance_type(
+        &mut self,
+        importer_id: PackageId,
+        importer: &str,
+        name: &str,
+        interface: InterfaceId,
+    ) -> Result<InstanceType, ComposeError> {
+        let types = self.types;
+        let unsupported = |reason| {
+            compose_error::UnsupportedImportSnafu {
+                importer,
+                import: name,
+                reason,
+            }
+            .build()
+        };
+
+        let mut outer_types = HashMap::new();
+        for (type_name, used) in &types[interface].uses {
+            let used_name = types[used.interface]
+                .id
+                .as_deref()
+                .ok_or_else(|| unsupported("it uses types of an unnamed interface"))?;
+            let instance =
+                self.import_instance(importer_id, importer, used_name, used.interface)?;
+
+            let export = used.name.as_deref().unwrap_or(type_name);
+            let key = (instance, export.to_string());
+            let outer = match self.used_types.get(&key) {
+                Some(&outer) => outer,
+                None => {
+                    let outer =
+                        self.builder
+                            .alias_export(instance, export, ComponentExportKind::Type);
+                    self.used_types.insert(key, outer);
+                    outer
+                }
+            };
+            outer_types.insert(type_name.as_str(), outer);
+        }
+
+        let mut encoder = InstanceTypeEncoder {
+            types,
+            ty: InstanceType::new(),
+            resources: HashMap::new(),
+            defined: HashMap::new(),
+        };
+
+        for (export_name, kind) in &types[interface].exports {
+            match kind {
+                ItemKind::Type(ty) => {
+                    let bounds = if let Some(&outer) = outer_types.get(export_name.as_str()) {
+                        encoder.ty.alias(Alias::Outer {
+                            kind: ComponentOuterAliasKind::Type,
+                            count: 1,
+                            index: outer,
+                        });
+                        TypeBounds::Eq(encoder.ty.type_count() - 1)
+                    } else {
+                        match ty {
+                            Type::Resource(_) => TypeBounds::SubResource,
+                            Type::Value(ty) => TypeBounds::Eq(
+                                encoder
+                                    .type_index(*ty)
+                                    .map_err(|_| unsupported("it exports an unsupported type"))?,
+                            ),
+                            _ => return Err(unsupported("it exports an unsupported type")),
+                        }
+                    };
+
+                    encoder
+                        .ty
+                        .export(export_name, ComponentTypeRef::Type(bounds));
+                    encoder.exported(*ty, encoder.ty.type_count() - 1);
+                }
+                ItemKind::Func(func) => {
+                    let func = encoder
+                        .func_type(*func)
+                        .map_err(|_| unsupported("it exports a function of an unsupported type"))?;
+                    encoder.ty.export(export_name, ComponentTypeRef::Func(func));
+                }
+                _ => {
+                    return Err(unsupported(
+                        "it exports items other than types and functions",
+                    ));
+                }
+            }
+        }
+
+        Ok(encoder.ty)
+    }
+}
+
+/// A type that cannot be encoded in an instance type, such as a record that the instance does
+/// not export.
+struct UnsupportedType;
+
+/// Encodes the types of an interface into an instance type.
+struct InstanceTypeEncoder<'a> {
+    types: &'a Types,
+    ty: InstanceType,
+    /// The exported resources, by the resource they alias if any.
+    resources: HashMap<ResourceId, u32>,
+    /// The exported and anonymous defined types encoded so far.
+    defined: HashMap<DefinedTypeId, u32>,
+}
+
+impl InstanceTypeEncoder<'_> {
+    fn root_resource(&self, mut resource: ResourceId) -> ResourceId {
+        while let Some(alias) = &self.types[resource].alias {
+            resource = alias.source;
+        }
+        resource
+    }
+
+    /// Records that `ty` was exported as the type `index`.
+    fn exported(&mut self, ty: Type, index: u32) {
+        match ty {
+            Type::Resource(resource) => {
+                let root = self.root_resource(resource);
+                self.resources.insert(resource, index);
+                self.resources.insert(root, index);
+            }
+            Type::Value(ValueType::Defined(mut defined)) => {
+                self.defined.insert(defined, index);
+                while let DefinedType::Alias(ValueType::Defined(aliased)) = &self.types[defined] {
+                    defined = *aliased;
+                    self.defined.insert(defined, index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn resource(&self, resource: ResourceId) -> Result<u32, UnsupportedType> {
+        self.resources
+            .get(&resource)
+            .or_else(|| self.resources.get(&self.root_resource(resource)))
+            .copied()
+            .ok_or(UnsupportedType)
+    }
+
+    /// Returns the index of a type defined as `ty`.
+    fn type_index(&mut self, ty: ValueType) -> Result<u32, UnsupportedType> {
+        match self.value_type(ty)? {
+            ComponentValType::Type(index) => Ok(index),
+            ComponentValType::Primitive(primitive) => {
+                self.ty.ty().defined_type().primitive(primitive);
+                Ok(self.ty.type_count() - 1)
+            }
+        }
+    }
+
+    fn value_type(&mut self, ty: ValueType) -> Result<ComponentValType, UnsupportedType> {
+        let defined = match ty {
+            ValueType::Primitive(primitive) => {
+                return Ok(ComponentValType::Primitive(primitive_type(primitive)));
+            }
+            ValueType::Own(resource) => {
+                let resource = self.resource(resource)?;
+                self.ty.ty().defined_type().own(resource);
+                return Ok(ComponentValType::Type(self.ty.type_count() - 1));
+            }
+            ValueType::Borrow(resource) => {
+                let resource = self.resource(resource)?;
+                self.ty.ty().defined_type().borrow(resource);
+                return Ok(ComponentValType::Type(self.ty.type_count() - 1));
+            }
+            ValueType::Defined(defined) => defined,
+        };
+
+        if let Some(&index) = self.defined.get(&defined) {
+            return Ok(ComponentValType::Type(index));
+        }
+
+        let optional = |encoder: &mut Self, ty: Option<ValueType>| {
+            ty.map(|ty| encoder.value_type(ty)).transpose()
+        };
+
+        let types = self.types;
+        match &types[defined] {
+            DefinedType::Alias(ty) => return self.value_type(*ty),
+            DefinedType::Tuple(tys) => {
+                let tys = tys
+                    .iter()
+                    .map(|ty| self.value_type(*ty))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.ty.ty().defined_type().tuple(tys);
+            }
+            DefinedType::List(ty) => {
+                let ty = self.value_type(*ty)?;
+                self.ty.ty().defined_type().list(ty);
+            }
+            DefinedType::FixedSizeList(ty, elements) => {
+                let ty = self.value_type(*ty)?;
+                self.ty.ty().defined_type().fixed_size_list(ty, *elements);
+            }
+            DefinedType::Option(ty) => {
+                let ty = self.value_type(*ty)?;
+                self.ty.ty().defined_type().option(ty);
+            }
+            DefinedType::Result { ok, err } => {
+                let ok = optional(self, *ok)?;
+                let err = optional(self, *err)?;
+                self.ty.ty().defined_type().result(ok, err);
+            }
+            DefinedType::Stream(ty) => {
+                let ty = optional(self, *ty)?;
+                self.ty.ty().defined_type().stream(ty);
+            }
+            DefinedType::Future(ty) => {
+                let ty = optional(self, *ty)?;
+                self.ty.ty().defined_type().future(ty);
+            }
+            DefinedType::Variant(variant) => {
+                let cases = variant
+                    .cases
+                    .iter()
+                    .map(|(name, ty)| {
+                        Ok((
+                            name.as_str(),
+                            ty.map(|ty| self.value_type(ty)).transpose()?,
+                            None,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.ty.ty().defined_type().variant(cases);
+            }
+            DefinedType::Record(record) => {
+                let fields = record
+                    .fields
+                    .iter()
+                    .map(|(name, ty)| Ok((name.as_str(), self.value_type(*ty)?)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.ty.ty().defined_type().record(fields);
+            }
+            DefinedType::Flags(flags) => {
+                self.ty
+                    .ty()
+                    .defined_type()
+                    .flags(flags.0.iter().map(String::as_str));
+            }
+            DefinedType::Enum(cases) => {
+                self.ty
+                    .ty()
+                    .defined_type()
+                    .enum_type(cases.0.iter().map(String::as_str));
+            }
+        }
+
+        let index = self.ty.type_count() - 1;
+        self.defined.insert(defined, index);
+        Ok(ComponentValType::Type(index))
+    }
+
+    fn func_type(&mut self, func: FuncTypeId) -> Result<u32, UnsupportedType> {
+        let types = self.types;
+        let func = &types[func];
+        let params = func
+            .params
+            .iter()
+            .map(|(name, ty)| Ok((name.as_str(), self.value_type(*ty)?)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = func.result.map(|ty| self.value_type(ty)).transpose()?;
+
+        self.ty.ty().function().params(params).result(result);
+        Ok(self.ty.type_count() - 1)
+    }
+}
+
+fn primitive_type(primitive: PrimitiveType) -> PrimitiveValType {
+    match primitive {
+        PrimitiveType::U8 => PrimitiveValType::U8,
+        PrimitiveType::S8 => PrimitiveValType::S8,
+        PrimitiveType::U16 => PrimitiveValType::U16,
+        PrimitiveType::S16 => PrimitiveValType::S16,
+        PrimitiveType::U32 => PrimitiveValType::U32,
+        PrimitiveType::S32 => PrimitiveValType::S32,
+        PrimitiveType::U64 => PrimitiveValType::U64,
+        PrimitiveType::S64 => PrimitiveValType::S64,
+        PrimitiveType::F32 => PrimitiveValType::F32,
+        PrimitiveType::F64 => PrimitiveValType::F64,
+        PrimitiveType::Char => PrimitiveValType::Char,
+        PrimitiveType::Bool => PrimitiveValType::Bool,
+        PrimitiveType::String => PrimitiveValType::String,
+        PrimitiveType::ErrorContext => PrimitiveValType::ErrorContext,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ComponentFixture, FixtureFunc};
+    use crate::{
+        CompositionGraph, DynPackageTrampoline, ImportFilter, ImportRule, NoopTrampoline,
+        PackageId, PackageTrampoline, StaticTrampoline, Trampoline,
+    };
+    use semver::Version;
+    use wasmtime::component::{Component, Instance, Linker};
+    use wasmtime::{Engine, Store};
+
+    struct SkipHost;
+
+    /// A trampoline passing calls through, which still shadows the interfaces it is used for.
+    #[derive(Clone)]
+    struct Passthrough;
+
+    impl Trampoline<()> for Passthrough {}
+
+    impl ImportFilter for SkipHost {
+        fn filter_rule(&self, import_path: &ForeignInterfacePath) -> ImportRule {
+            if import_path.package_name() == "test:host" {
+                ImportRule::Skip
+            } else {
+                ImportRule::Include
+            }
+        }
+    }
+
+    fn forward(interface: &str, function: &str) -> FixtureFunc {
+        FixtureFunc::Forward {
+            interface: interface.to_string(),
+            function: function.to_string(),
+        }
+    }
+
+    /// A graph of a kvstore counting its calls, linked through `trampoline`, and of an app
+    /// calling it, which also imports a host interface skipped by the graph.
+    fn graph(trampoline: impl DynPackageTrampoline<(), ()>) -> (CompositionGraph<()>, PackageId) {
+        let store_bytes = ComponentFixture::new()
+            .export("test:kvstore/store@1.0.0", [("get", FixtureFunc::Counter)])
+            .to_bytes()
+            .unwrap();
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .import("test:host/log@1.0.0", ["write"])
+            .export(
+                "test:app/run@1.0.0",
+                [
+                    ("run", forward("test:kvstore/store@1.0.0", "get")),
+                    ("log", forward("test:host/log@1.0.0", "write")),
+                ],
+            )
+            .to_bytes()
+            .unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        graph.set_import_filter(SkipHost);
+        let version = Version::new(1, 0, 0);
+        graph
+            .add_package(
+                "test:kvstore".to_string(),
+                version.clone(),
+                store_bytes,
+                trampoline,
+            )
+            .unwrap();
+        let app_id = graph
+            .add_package("test:app".to_string(), version, app_bytes, NoopTrampoline)
+            .unwrap();
+
+        (graph, app_id)
+    }
+
+    /// Links the host interface the graph skips in `linker`.
+    fn link_host(linker: &mut Linker<()>) {
+        linker
+            .instance("test:host/log@1.0.0")
+            .unwrap()
+            .func_wrap("write", |_, (value,): (u32,)| Ok((value + 1,)))
+            .unwrap();
+    }
+
+    /// Calls `function` of the app's `run` interface in `instance` with `value`.
+    fn call(instance: &Instance, store: &mut Store<()>, function: &str, value: u32) -> u32 {
+        let run = instance.get_export_index(&mut *store, None, "test:app/run@1.0.0");
+        let index = instance
+            .get_export_index(&mut *store, run.as_ref(), function)
+            .unwrap();
+        let func = instance
+            .get_typed_func::<(u32,), (u32,)>(&mut *store, &index)
+            .unwrap();
+        let (result,) = func.call(&mut *store, (value,)).unwrap();
+        func.post_return(&mut *store).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_compose_static() {
+        let (graph, app_id) = graph(NoopTrampoline);
+        let composed = graph.compose_static(app_id).unwrap();
+
+        let engine = Engine::default();
+        let component = Component::new(&engine, &composed).unwrap();
+        let mut linker = Linker::new(&engine);
+        link_host(&mut linker);
+
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component).unwrap();
+        assert_eq!(call(&instance, &mut store, "run", 41), 1);
+        assert_eq!(call(&instance, &mut store, "run", 41), 2);
+        assert_eq!(call(&instance, &mut store, "log", 41), 42);
+    }
+
+    #[test]
+    fn test_compose_static_keeps_trampolined_imports() {
+        let (graph, app_id) = graph(PackageTrampoline::new(StaticTrampoline(Passthrough)));
+        let composed = graph.compose_static(app_id).unwrap();
+
+        // Imports linked through a trampoline stay imports of the composed component.
+        let engine = Engine::default();
+        let component = Component::new(&engine, &composed).unwrap();
+        let imports = component
+            .component_type()
+            .imports(&engine)
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(imports, ["test:kvstore/store@1.0.0", "test:host/log@1.0.0"]);
+
+        let mut linker = Linker::new(&engine);
+        linker
+            .instance("test:kvstore/store@1.0.0")
+            .unwrap()
+            .func_wrap("get", |_, (value,): (u32,)| Ok((value * 2,)))
+            .unwrap();
+        link_host(&mut linker);
+
+        let mut store = Store::new(&engine, ());
+        let instance = linker.instantiate(&mut store, &component).unwrap();
+        assert_eq!(call(&instance, &mut store, "run", 21), 42);
+    }
+}
@@ -0,0 +1,185 @@
+//! Building composition graphs from declarative config files, which describe the packages of a
+//! graph like a `CompositionManifest` and name their trampolines in a `TrampolineRegistry`.
+
+use crate::{
+    CompositionManifest, DynPackageTrampoline, ManifestError, ManifestResolver, PackageManifest,
+};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type TrampolineFactory<D, C> = Arc<dyn Fn() -> Box<dyn DynPackageTrampoline<D, C>> + Send + Sync>;
+
+/// The trampolines that packages of a config file can name, by identifier.
+pub struct TrampolineRegistry<D, C: Clone = ()> {
+    trampolines: HashMap<String, TrampolineFactory<D, C>>,
+}
+
+impl<D, C: Clone> Default for TrampolineRegistry<D, C> {
+    fn default() -> Self {
+        Self {
+            trampolines: HashMap::new(),
+        }
+    }
+}
+
+impl<D, C: Clone> std::fmt::Debug for TrampolineRegistry<D, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.trampolines.keys()).finish()
+    }
+}
+
+impl<D, C: Clone> TrampolineRegistry<D, C> {
+    /// Creates a registry without trampolines.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `trampoline` as `id`, replacing any trampoline registered as `id` before. Each
+    /// package naming it is added with a clone of the trampoline.
+    #[must_use]
+    pub fn with_trampoline<T>(mut self, id: impl Into<String>, trampoline: T) -> Self
+    where
+        T: DynPackageTrampoline<D, C> + Clone + Send + Sync + 'static,
+    {
+        self.trampolines.insert(
+            id.into(),
+            Arc::new(move || Box::new(trampoline.clone()) as Box<dyn DynPackageTrampoline<D, C>>),
+        );
+        self
+    }
+
+    /// Returns whether a trampoline is registered as `id`.
+    #[must_use]
+    pub fn contains(&self, id: &str) -> bool {
+        self.trampolines.contains_key(id)
+    }
+}
+
+/// Reads the component files of the packages of a config file, relative to the directory of the
+/// config file.
+struct ConfigResolver<'a, D, C: Clone> {
+    dir: &'a Path,
+    registry: &'a TrampolineRegistry<D, C>,
+}
+
+impl<D, C: Clone> ManifestResolver<D, C> for ConfigResolver<'_, D, C> {
+    fn package_bytes(&self, package: &PackageManifest) -> Result<Vec<u8>, anyhow::Error> {
+        let path = package
+            .path
+            .as_ref()
+            .map(|path| self.dir.join(path))
+            .ok_or_else(|| anyhow::anyhow!("config packages must have a path"))?;
+
+        std::fs::read(&path).map_err(|err| {
+            anyhow::Error::new(err).context(format!("failed to read '{}'", path.display()))
+        })
+    }
+
+    fn trampoline(&self, id: &str) -> Option<Box<dyn DynPackageTrampoline<D, C>>> {
+        self.registry.trampolines.get(id).map(|factory| factory())
+    }
+}
+
+/// A config file that cannot be loaded with `CompositionGraph::from_config_file`.
+#[derive(Snafu, Debug)]
+#[snafu(module, visibility(pub(crate)))]
+pub enum ConfigError {
+    #[snafu(display("Failed to read config file '{}'", path.display()))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Config file '{}' is not a TOML or JSON file", path.display()))]
+    UnsupportedFormat { path: PathBuf },
+
+    #[snafu(display("Failed to parse config file '{}'", path.display()))]
+    Parse {
+        path: PathBuf,
+        source: anyhow::Error,
+    },
+
+    #[snafu(display("Failed to build the graph of config file '{}'", path.display()))]
+    Build {
+        path: PathBuf,
+        source: ManifestError,
+    },
+}
+
+/// Reads the config file at `path` as a manifest, parsing it as TOML or, with the `json` feature,
+/// as JSON depending on its extension.
+pub(crate) fn read_config(path: &Path) -> Result<CompositionManifest, ConfigError> {
+    let parse: fn(&str) -> Result<CompositionManifest, anyhow::Error> =
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => |document| Ok(CompositionManifest::from_toml(document)?),
+            #[cfg(feature = "json")]
+            Some("json") => |document| Ok(serde_json::from_str(document)?),
+            _ => return Err(ConfigError::UnsupportedFormat { path: path.into() }),
+        };
+
+    let document = std::fs::read_to_string(path).context(config_error::ReadSnafu { path })?;
+    parse(&document).context(config_error::ParseSnafu { path })
+}
+
+/// Returns the resolver of the packages of the config file at `path`.
+pub(crate) fn config_resolver<'a, D, C: Clone>(
+    path: &'a Path,
+    registry: &'a TrampolineRegistry<D, C>,
+) -> impl ManifestResolver<D, C> + 'a {
+    ConfigResolver {
+        dir: path.parent().unwrap_or(Path::new("")),
+        registry,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompositionGraph, NoopTrampoline};
+
+    #[test]
+    fn test_config_file() {
+        let dir = std::env::temp_dir().join(format!("graph-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.component.wasm"), b"\0asm\x0d\x00\x01\x00").unwrap();
+
+        let config = dir.join("graph.toml");
+        std::fs::write(
+            &config,
+            r#"
+            [[packages]]
+            name = "test:app"
+            version = "1.0.0"
+            path = "app.component.wasm"
+            trampoline = "noop"
+            "#,
+        )
+        .unwrap();
+
+        let registry = TrampolineRegistry::new().with_trampoline("noop", NoopTrampoline);
+        let graph = CompositionGraph::<()>::from_config_file(&config, &registry).unwrap();
+        assert_eq!(graph.packages().count(), 1);
+
+        let empty = TrampolineRegistry::new();
+        assert!(matches!(
+            CompositionGraph::<()>::from_config_file(&config, &empty),
+            Err(ConfigError::Build {
+                source: ManifestError::UnknownTrampoline { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            CompositionGraph::<()>::from_config_file(dir.join("graph.yaml"), &registry),
+            Err(ConfigError::UnsupportedFormat { .. })
+        ));
+        assert!(matches!(
+            CompositionGraph::<()>::from_config_file(dir.join("missing.toml"), &registry),
+            Err(ConfigError::Read { .. })
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,524 @@
+//! A sandbox preset providing guests with deterministic time and randomness instead of ambient
+//! WASI capabilities, so that compositions can be replayed reproducibly, e.g. for debugging or
+//! consensus.
+
+use crate::{ForeignInterfacePath, ImportFilter, ImportRule};
+use std::collections::HashMap;
+use std::time::Duration;
+use wasmtime::component::{Linker, Resource, ResourceType, Val};
+
+/// The version the deterministic WASI shims are defined with. Guests importing any compatible
+/// `0.2` version are linked against them.
+const WASI_VERSION: &str = "0.2.0";
+
+/// The WASI interfaces defined by `add_deterministic_shims_to_linker`, as `package/interface`.
+pub const DETERMINISTIC_INTERFACES: &[&str] = &[
+    "wasi:clocks/monotonic-clock",
+    "wasi:clocks/wall-clock",
+    "wasi:io/poll",
+    "wasi:random/random",
+    "wasi:random/insecure",
+    "wasi:random/insecure-seed",
+];
+
+/// The state of the deterministic clocks and random number generator of a store, read by the
+/// shims defined with `add_deterministic_shims_to_linker`.
+///
+/// Both clocks start at their start time and advance by a fixed step each time either is read,
+/// and random values are drawn from a generator seeded with a fixed seed, so guests observe the
+/// same time and randomness whenever they make the same calls. Blocking on a pollable of the
+/// monotonic clock advances the clocks to its deadline at once, rather than waiting.
+#[derive(Clone, Debug)]
+pub struct DeterministicState {
+    start_time: Duration,
+    clock_step: Duration,
+    elapsed: Duration,
+    rng: u64,
+    max_random_bytes: u64,
+    /// The deadline of each live pollable, by its resource representation.
+    pollables: HashMap<u32, Duration>,
+    next_pollable: u32,
+}
+
+/// A `wasi:io/poll.pollable` of the monotonic clock, which is ready once the clocks have
+/// elapsed its deadline.
+struct DeterministicPollable;
+
+/// The number of random bytes a guest may request in one call by default, see
+/// `DeterministicState::with_max_random_bytes`.
+pub const DEFAULT_MAX_RANDOM_BYTES: u64 = 1 << 20;
+
+impl DeterministicState {
+    /// Creates a state drawing random values seeded with `seed`, whose wall clock starts at the
+    /// Unix epoch and whose clocks advance by one millisecond per read.
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            start_time: Duration::ZERO,
+            clock_step: Duration::from_millis(1),
+            elapsed: Duration::ZERO,
+            rng: seed,
+            max_random_bytes: DEFAULT_MAX_RANDOM_BYTES,
+            pollables: HashMap::new(),
+            next_pollable: 0,
+        }
+    }
+
+    /// Starts the wall clock at `start_time` since the Unix epoch instead.
+    #[must_use]
+    pub fn with_start_time(mut self, start_time: Duration) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
+    /// Advances the clocks by `clock_step` per read instead.
+    #[must_use]
+    pub fn with_clock_step(mut self, clock_step: Duration) -> Self {
+        self.clock_step = clock_step;
+        self
+    }
+
+    /// Traps guests requesting more than `max` random bytes in one call, instead of
+    /// `DEFAULT_MAX_RANDOM_BYTES`.
+    #[must_use]
+    pub fn with_max_random_bytes(mut self, max: u64) -> Self {
+        self.max_random_bytes = max;
+        self
+    }
+
+    /// Returns the time elapsed on the clocks, which is what the monotonic clock reads.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    fn tick(&mut self) -> Duration {
+        self.elapsed = self.elapsed.saturating_add(self.clock_step);
+        self.elapsed
+    }
+
+    /// Creates a pollable that is ready once the clocks have elapsed `deadline`.
+    fn subscribe(&mut self, deadline: Duration) -> anyhow::Result<Resource<DeterministicPollable>> {
+        let rep = self.next_pollable;
+        self.next_pollable = rep
+            .checked_add(1)
+            .ok_or_else(|| anyhow::anyhow!("Too many pollables were created"))?;
+        self.pollables.insert(rep, deadline);
+        Ok(Resource::new_own(rep))
+    }
+
+    fn deadline(&self, pollable: &Resource<DeterministicPollable>) -> anyhow::Result<Duration> {
+        self.pollables
+            .get(&pollable.rep())
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Unknown pollable {}", pollable.rep()))
+    }
+
+    fn ready(&self, pollable: &Resource<DeterministicPollable>) -> anyhow::Result<bool> {
+        Ok(self.elapsed >= self.deadline(pollable)?)
+    }
+
+    /// Advances the clocks to the deadline of `pollable`, if they have not elapsed it yet.
+    fn block(&mut self, pollable: &Resource<DeterministicPollable>) -> anyhow::Result<()> {
+        self.elapsed = self.elapsed.max(self.deadline(pollable)?);
+        Ok(())
+    }
+
+    /// Advances the clocks to the earliest deadline of `pollables`, if they have not elapsed it
+    /// yet, and returns the indices of the pollables that are ready.
+    fn poll(&mut self, pollables: &[Resource<DeterministicPollable>]) -> anyhow::Result<Vec<u32>> {
+        let deadlines = pollables
+            .iter()
+            .map(|pollable| self.deadline(pollable))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // `wasi:io/poll` blocks until at least one of the pollables is ready.
+        let earliest = deadlines
+            .iter()
+            .min()
+            .ok_or_else(|| anyhow::anyhow!("Polled an empty list of pollables"))?;
+        self.elapsed = self.elapsed.max(*earliest);
+
+        Ok((0..)
+            .zip(&deadlines)
+            .filter(|(_, deadline)| **deadline <= self.elapsed)
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    /// Draws the next value of the SplitMix64 generator.
+    fn next_u64(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut value = self.rng;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        value ^ (value >> 31)
+    }
+
+    /// Draws `len` random bytes, failing if the guest requested more than the maximum, since
+    /// the length is guest-controlled.
+    fn next_bytes(&mut self, len: u64) -> anyhow::Result<Vec<u8>> {
+        if len > self.max_random_bytes {
+            anyhow::bail!(
+                "Requested {len} random bytes, more than the maximum of {}",
+                self.max_random_bytes
+            );
+        }
+
+        let len = usize::try_from(len)?;
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let remaining = (len - bytes.len()).min(8);
+            bytes.extend_from_slice(&self.next_u64().to_le_bytes()[..remaining]);
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// An import filter skipping the `DETERMINISTIC_INTERFACES`, which are provided by the host, and
+/// filtering all other imports with an inner filter.
+#[derive(Clone, Debug, Default)]
+pub struct DeterministicImportFilter<F: ImportFilter = ImportRule> {
+    inner: F,
+}
+
+impl<F: ImportFilter> DeterministicImportFilter<F> {
+    pub fn new(inner: F) -> Self {
+        Self { inner }
+    }
+}
+
+impl<F: ImportFilter> ImportFilter for DeterministicImportFilter<F> {
+    fn filter_rule(&self, import_path: &ForeignInterfacePath) -> ImportRule {
+        if is_deterministic_interface(import_path) {
+            ImportRule::Skip
+        } else {
+            self.inner.filter_rule(import_path)
+        }
+    }
+}
+
+/// Returns whether `import_path` is one of the `DETERMINISTIC_INTERFACES`, in any version.
+fn is_deterministic_interface(import_path: &ForeignInterfacePath) -> bool {
+    DETERMINISTIC_INTERFACES.iter().any(|interface| {
+        interface
+            .split_once('/')
+            .is_some_and(|(package, interface)| {
+                import_path.package_name() == package && import_path.interface_name() == interface
+            })
+    })
+}
+
+/// Defines the `DETERMINISTIC_INTERFACES` in `linker`, reading the clocks and random values of
+/// each store from the `DeterministicState` returned by `get`.
+///
+/// The pollables of `wasi:io/poll` are only created by the monotonic clock, and blocking on them
+/// advances the clocks to their deadline. No other WASI interfaces are defined, so packages
+/// importing ambient capabilities such as the filesystem, sockets or environment fail to
+/// instantiate, unless the host defines them itself.
+///
+/// `CompositionGraph::set_deterministic_state` defines the shims when instantiating packages of a
+/// graph. Otherwise, graph packages importing the shims should skip them with a
+/// `DeterministicImportFilter`, since they are provided by the host rather than by a package.
+pub fn add_deterministic_shims_to_linker<D: 'static>(
+    linker: &mut Linker<D>,
+    get: fn(&mut D) -> &mut DeterministicState,
+) -> anyhow::Result<()> {
+    let mut monotonic = linker.instance(&format!("wasi:clocks/monotonic-clock@{WASI_VERSION}"))?;
+    monotonic.func_wrap("now", move |mut store, ()| {
+        Ok((get(store.data_mut()).tick().as_nanos() as u64,))
+    })?;
+    monotonic.func_wrap("resolution", move |mut store, ()| {
+        Ok((get(store.data_mut()).clock_step.as_nanos() as u64,))
+    })?;
+    monotonic.func_wrap("subscribe-instant", move |mut store, (when,): (u64,)| {
+        Ok((get(store.data_mut()).subscribe(Duration::from_nanos(when))?,))
+    })?;
+    monotonic.func_wrap(
+        "subscribe-duration",
+        move |mut store, (duration,): (u64,)| {
+            let state = get(store.data_mut());
+            let deadline = state.elapsed.saturating_add(Duration::from_nanos(duration));
+            Ok((state.subscribe(deadline)?,))
+        },
+    )?;
+
+    let mut poll = linker.instance(&format!("wasi:io/poll@{WASI_VERSION}"))?;
+    poll.resource(
+        "pollable",
+        ResourceType::host::<DeterministicPollable>(),
+        move |mut store, rep| {
+            get(store.data_mut()).pollables.remove(&rep);
+            Ok(())
+        },
+    )?;
+    poll.func_wrap(
+        "[method]pollable.ready",
+        move |mut store, (pollable,): (Resource<DeterministicPollable>,)| {
+            Ok((get(store.data_mut()).ready(&pollable)?,))
+        },
+    )?;
+    poll.func_wrap(
+        "[method]pollable.block",
+        move |mut store, (pollable,): (Resource<DeterministicPollable>,)| {
+            get(store.data_mut()).block(&pollable)
+        },
+    )?;
+    poll.func_wrap(
+        "poll",
+        move |mut store, (pollables,): (Vec<Resource<DeterministicPollable>>,)| {
+            Ok((get(store.data_mut()).poll(&pollables)?,))
+        },
+    )?;
+
+    let mut wall = linker.instance(&format!("wasi:clocks/wall-clock@{WASI_VERSION}"))?;
+    wall.func_new("now", move |mut store, _arguments, results| {
+        let state = get(store.data_mut());
+        let now = state.start_time.saturating_add(state.tick());
+        results[0] = datetime(now);
+        Ok(())
+    })?;
+    wall.func_new("resolution", move |mut store, _arguments, results| {
+        results[0] = datetime(get(store.data_mut()).clock_step);
+        Ok(())
+    })?;
+
+    for (interface, prefix) in [
+        ("random", "get-random"),
+        ("insecure", "get-insecure-random"),
+    ] {
+        let mut random = linker.instance(&format!("wasi:random/{interface}@{WASI_VERSION}"))?;
+        random.func_wrap(
+            &format!("{prefix}-bytes"),
+            move |mut store, (len,): (u64,)| Ok((get(store.data_mut()).next_bytes(len)?,)),
+        )?;
+        random.func_wrap(&format!("{prefix}-u64"), move |mut store, ()| {
+            Ok((get(store.data_mut()).next_u64(),))
+        })?;
+    }
+
+    let mut seed = linker.instance(&format!("wasi:random/insecure-seed@{WASI_VERSION}"))?;
+    seed.func_wrap("insecure-seed", move |mut store, ()| {
+        let state = get(store.data_mut());
+        Ok(((state.next_u64(), state.next_u64()),))
+    })?;
+
+    Ok(())
+}
+
+/// Returns the `wasi:clocks/wall-clock.datetime` record of `time`.
+fn datetime(time: Duration) -> Val {
+    Val::Record(vec![
+        ("seconds".to_string(), Val::U64(time.as_secs())),
+        ("nanoseconds".to_string(), Val::U32(time.subsec_nanos())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::component::{Component, TypedFunc};
+    use wasmtime::{Engine, Store};
+
+    /// A guest importing the shims at a later compatible WASI version, the way `wasip2` guests
+    /// do. `sleep` blocks on a pollable of the monotonic clock, and returns whether it was
+    /// ready before.
+    const GUEST_WAT: &str = r#"(component $guest
+        (import "wasi:io/poll@0.2.3" (instance $poll
+            (export "pollable" (type $pollable (sub resource)))
+            (export "[method]pollable.ready" (func (param "self" (borrow $pollable)) (result bool)))
+            (export "[method]pollable.block" (func (param "self" (borrow $pollable))))
+        ))
+        (alias export $poll "pollable" (type $pollable))
+        (import "wasi:clocks/monotonic-clock@0.2.3" (instance $clock
+            (alias outer $guest $pollable (type $outer-pollable))
+            (export "pollable" (type $pollable (eq $outer-pollable)))
+            (export "now" (func (result u64)))
+            (export "subscribe-duration" (func (param "when" u64) (result (own $pollable))))
+        ))
+        (import "wasi:random/random@0.2.3" (instance $random
+            (export "get-random-u64" (func (result u64)))
+        ))
+        (core func $get-random (canon lower (func $random "get-random-u64")))
+        (core func $get-now (canon lower (func $clock "now")))
+        (core func $subscribe (canon lower (func $clock "subscribe-duration")))
+        (core func $ready (canon lower (func $poll "[method]pollable.ready")))
+        (core func $block (canon lower (func $poll "[method]pollable.block")))
+        (core func $drop (canon resource.drop $pollable))
+        (core module $module
+            (import "host" "random" (func $random (result i64)))
+            (import "host" "now" (func $now (result i64)))
+            (import "host" "subscribe" (func $subscribe (param i64) (result i32)))
+            (import "host" "ready" (func $ready (param i32) (result i32)))
+            (import "host" "block" (func $block (param i32)))
+            (import "host" "drop" (func $drop (param i32)))
+            (func (export "random") (result i64) call $random)
+            (func (export "now") (result i64) call $now)
+            (func (export "sleep") (param i64) (result i32)
+                (local $pollable i32)
+                (local $ready i32)
+                (local.set $pollable (call $subscribe (local.get 0)))
+                (local.set $ready (call $ready (local.get $pollable)))
+                (call $block (local.get $pollable))
+                (call $drop (local.get $pollable))
+                (local.get $ready))
+        )
+        (core instance $host
+            (export "random" (func $get-random))
+            (export "now" (func $get-now))
+            (export "subscribe" (func $subscribe))
+            (export "ready" (func $ready))
+            (export "block" (func $block))
+            (export "drop" (func $drop))
+        )
+        (core instance $instance (instantiate $module (with "host" (instance $host))))
+        (func (export "random") (result u64) (canon lift (core func $instance "random")))
+        (func (export "now") (result u64) (canon lift (core func $instance "now")))
+        (func (export "sleep") (param "nanos" u64) (result bool)
+            (canon lift (core func $instance "sleep")))
+    )"#;
+
+    struct Guest {
+        store: Store<DeterministicState>,
+        random: TypedFunc<(), (u64,)>,
+        now: TypedFunc<(), (u64,)>,
+        sleep: TypedFunc<(u64,), (bool,)>,
+    }
+
+    impl Guest {
+        fn new(state: DeterministicState) -> Self {
+            let engine = Engine::default();
+            let mut linker = Linker::new(&engine);
+            add_deterministic_shims_to_linker(&mut linker, |state| state).unwrap();
+            let component = Component::new(&engine, GUEST_WAT).unwrap();
+
+            let mut store = Store::new(&engine, state);
+            let instance = linker.instantiate(&mut store, &component).unwrap();
+            Self {
+                random: instance.get_typed_func(&mut store, "random").unwrap(),
+                now: instance.get_typed_func(&mut store, "now").unwrap(),
+                sleep: instance.get_typed_func(&mut store, "sleep").unwrap(),
+                store,
+            }
+        }
+
+        fn call<P: wasmtime::component::ComponentNamedList + wasmtime::component::Lower, R>(
+            &mut self,
+            func: fn(&Self) -> TypedFunc<P, (R,)>,
+            params: P,
+        ) -> R
+        where
+            (R,): wasmtime::component::ComponentNamedList + wasmtime::component::Lift,
+        {
+            let func = func(self);
+            let (result,) = func.call(&mut self.store, params).unwrap();
+            func.post_return(&mut self.store).unwrap();
+            result
+        }
+
+        fn random(&mut self) -> u64 {
+            self.call(|guest| guest.random, ())
+        }
+
+        fn now(&mut self) -> u64 {
+            self.call(|guest| guest.now, ())
+        }
+
+        fn sleep(&mut self, nanos: u64) -> bool {
+            self.call(|guest| guest.sleep, (nanos,))
+        }
+    }
+
+    #[test]
+    fn test_deterministic_shims_link() {
+        let mut guest = Guest::new(DeterministicState::new(7));
+
+        assert_eq!(guest.now(), 1_000_000);
+        assert_eq!(guest.now(), 2_000_000);
+        assert_ne!(guest.random(), guest.random());
+    }
+
+    #[test]
+    fn test_deterministic_replay() {
+        let run = |seed| {
+            let mut guest = Guest::new(DeterministicState::new(seed));
+            [guest.random(), guest.now(), guest.random()]
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7)[0], run(8)[0]);
+        assert_eq!(run(7)[1], run(8)[1]);
+    }
+
+    #[test]
+    fn test_deterministic_pollables() {
+        let mut guest = Guest::new(DeterministicState::new(7));
+
+        // Blocking advances the clocks to the deadline at once, and the pollable is dropped.
+        assert!(!guest.sleep(5_000_000));
+        assert_eq!(guest.store.data().elapsed(), Duration::from_millis(5));
+        assert!(guest.store.data().pollables.is_empty());
+        assert_eq!(guest.now(), 6_000_000);
+
+        // A pollable of no duration is ready at once and does not advance the clocks.
+        assert!(guest.sleep(0));
+        assert_eq!(guest.now(), 7_000_000);
+    }
+
+    #[test]
+    fn test_deterministic_poll() {
+        let mut state = DeterministicState::new(7);
+        let later = state.subscribe(Duration::from_millis(20)).unwrap();
+        let sooner = state.subscribe(Duration::from_millis(10)).unwrap();
+        let also_sooner = state.subscribe(Duration::from_millis(10)).unwrap();
+
+        let pollables = [later, sooner, also_sooner];
+        assert_eq!(state.poll(&pollables).unwrap(), [1, 2]);
+        assert_eq!(state.elapsed(), Duration::from_millis(10));
+        assert_eq!(state.poll(&pollables[..1]).unwrap(), [0]);
+        assert_eq!(state.elapsed(), Duration::from_millis(20));
+
+        assert!(state.poll(&[]).is_err());
+        assert!(state.poll(&[Resource::new_own(42)]).is_err());
+    }
+
+    #[test]
+    fn test_deterministic_random_bytes() {
+        let mut state = DeterministicState::new(7).with_max_random_bytes(16);
+        assert_eq!(state.next_bytes(13).unwrap().len(), 13);
+        assert!(state.next_bytes(17).is_err());
+        assert!(DeterministicState::new(7).next_bytes(u64::MAX).is_err());
+
+        assert_eq!(
+            DeterministicState::new(7).next_bytes(16).unwrap(),
+            DeterministicState::new(7).next_bytes(16).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deterministic_import_filter() {
+        let filter = DeterministicImportFilter::new(ImportRule::Include);
+        let path = |package: &str, interface: &str| {
+            ForeignInterfacePath::new(package.to_string(), interface.to_string(), None)
+        };
+
+        for interface in DETERMINISTIC_INTERFACES {
+            let (package, interface) = interface.split_once('/').unwrap();
+            assert!(matches!(
+                filter.filter_rule(&path(package, interface)),
+                ImportRule::Skip
+            ));
+        }
+        assert!(matches!(
+            filter.filter_rule(&path("wasi:filesystem", "types")),
+            ImportRule::Include
+        ));
+        assert!(matches!(
+            DeterministicImportFilter::new(ImportRule::Skip)
+                .filter_rule(&path("wasi:filesystem", "types")),
+            ImportRule::Skip
+        ));
+    }
+}
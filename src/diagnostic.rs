@@ -0,0 +1,416 @@
+use crate::{
+    AddPackageError, ForeignInterfacePath, GraphWarning, InstantiateError, InstantiatePackageError,
+    LoadPackageError, PackageId,
+};
+use std::error::Error;
+use std::fmt::Display;
+
+/// The severity of a `Diagnostic`, ordered from least to most severe.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Severity {
+    /// Additional information that does not indicate a problem.
+    Note,
+
+    /// A problem that does not prevent instantiation, but likely indicates a mistake.
+    Warning,
+
+    /// A problem that prevents instantiation.
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Note => write!(f, "note"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a composition graph, in a uniform format for hosts to render.
+///
+/// Diagnostics are returned by `CompositionGraph::validate`, and can be created from all graph
+/// errors and warnings with `Diagnostic::from`.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: &'static str,
+    message: String,
+    package: Option<PackageId>,
+    related_paths: Vec<ForeignInterfacePath>,
+    suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            package: None,
+            related_paths: Vec::new(),
+            suggestion: None,
+        }
+    }
+
+    pub(crate) fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    pub(crate) fn with_package(mut self, package: PackageId) -> Self {
+        self.package = Some(package);
+        self
+    }
+
+    pub(crate) fn with_related_path(mut self, path: ForeignInterfacePath) -> Self {
+        self.related_paths.push(path);
+        self
+    }
+
+    pub(crate) fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    /// Returns the severity of the problem.
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Returns `true` if the problem prevents instantiation.
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Returns the stable, machine-readable code of the problem.
+    ///
+    /// Problems that would surface as an error at instantiation time share that error's code.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Returns the human-readable description of the problem.
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the package the problem was found in, if it is specific to one package.
+    #[must_use]
+    pub fn package(&self) -> Option<PackageId> {
+        self.package
+    }
+
+    /// Returns the interface paths involved in the problem.
+    #[must_use]
+    pub fn related_paths(&self) -> &[ForeignInterfacePath] {
+        &self.related_paths
+    }
+
+    /// Returns a suggested fix for the problem, if one is known.
+    #[must_use]
+    pub fn suggestion(&self) -> Option<&str> {
+        self.suggestion.as_deref()
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (help: {suggestion})")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for Diagnostic {}
+
+impl From<&GraphWarning> for Diagnostic {
+    fn from(warning: &GraphWarning) -> Self {
+        let diagnostic = Diagnostic::new(Severity::Warning, warning.code(), warning.to_string());
+
+        match warning {
+            GraphWarning::DeprecatedPackage { import, .. } => diagnostic
+                .with_related_path(import.clone())
+                .with_suggestion("Update the import to a version that is not deprecated"),
+            GraphWarning::UnparsableImport { .. } => diagnostic,
+            GraphWarning::SkippedExport { export, .. } => diagnostic
+                .with_related_path(export.clone())
+                .with_suggestion(
+                    "Include the interface in the import filter to link imports of it against \
+                     the package",
+                ),
+        }
+    }
+}
+
+impl From<&AddPackageError> for Diagnostic {
+    fn from(err: &AddPackageError) -> Self {
+        let diagnostic = Diagnostic::new(Severity::Error, err.code(), error_message(err));
+
+        match err {
+            AddPackageError::DuplicatePackage { .. } => diagnostic.with_suggestion(
+                "Add the package with a different version, or set a provider selector to allow \
+                 multiple providers per version",
+            ),
+            AddPackageError::PackageParseError { .. }
+            | AddPackageError::ImportParseError { .. }
+            | AddPackageError::ReadError { .. }
+            | AddPackageError::PackageNotFound { .. } => diagnostic,
+            AddPackageError::InternalError { .. } => {
+                diagnostic.with_suggestion(INTERNAL_ERROR_SUGGESTION)
+            }
+            #[cfg(feature = "preinit")]
+            AddPackageError::PreinitializeError { .. } => diagnostic.with_suggestion(
+                "Check that the init export is lifted from a core function of a top-level core \
+                 module that does not call imports, or add the package without pre-initializing it",
+            ),
+            AddPackageError::DeniedByPackagePolicy { .. } => diagnostic
+                .with_suggestion("Add a version of the package that the package policy allows"),
+            AddPackageError::VerificationFailed { .. } => diagnostic.with_suggestion(
+                "Only add packages from trusted sources that pass verification, such as packages \
+                 matching their expected digest or signed by a trusted publisher",
+            ),
+            AddPackageError::FetchError { .. } => diagnostic.with_suggestion(
+                "Check that the reference exists in the source and that the source is reachable",
+            ),
+        }
+    }
+}
+
+impl From<&InstantiateError> for Diagnostic {
+    fn from(err: &InstantiateError) -> Self {
+        let diagnostic = match err {
+            InstantiateError::LoadPackageError { source } => {
+                Diagnostic::from(source).with_message(error_message(err))
+            }
+            InstantiateError::InstantiatePackageDependencyError { source, .. } => {
+                Diagnostic::from(source.as_ref()).with_message(error_message(err))
+            }
+            InstantiateError::LazyPackageError { source } => {
+                Diagnostic::from(source).with_message(error_message(err))
+            }
+            InstantiateError::PackageNotFound { .. }
+            | InstantiateError::ComponentInstantiationError { .. }
+            | InstantiateError::ComponentCompilationError { .. }
+            | InstantiateError::GuestTrap { .. } => {
+                Diagnostic::new(Severity::Error, err.code(), error_message(err))
+            }
+            InstantiateError::InterfaceTypeMismatch { source } => {
+                Diagnostic::new(Severity::Error, err.code(), error_message(err))
+                    .with_related_path(source.import.clone())
+                    .with_suggestion(TYPE_MISMATCH_SUGGESTION)
+            }
+            InstantiateError::UnpreparedPackage { .. } => {
+                Diagnostic::new(Severity::Error, err.code(), error_message(err))
+                    .with_suggestion(UNPREPARED_PACKAGE_SUGGESTION)
+            }
+        };
+
+        match err.wasm_backtrace() {
+            Some(backtrace) => {
+                let message = format!("{}\n{backtrace}", diagnostic.message());
+                diagnostic.with_message(message)
+            }
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&LoadPackageError> for Diagnostic {
+    fn from(err: &LoadPackageError) -> Self {
+        let diagnostic = Diagnostic::new(Severity::Error, err.code(), error_message(err));
+
+        match err {
+            LoadPackageError::PackageCycle { edges, .. } => edges
+                .iter()
+                .fold(diagnostic, |diagnostic, edge| {
+                    diagnostic.with_related_path(edge.import.clone())
+                })
+                .with_suggestion(
+                    "Skip or rewrite one of the interface imports forming the cycle with an \
+                     import filter",
+                ),
+            LoadPackageError::MissingPackageDependency {
+                package_name,
+                import,
+                ..
+            } => diagnostic
+                .with_related_path(import.as_ref().clone())
+                .with_suggestion(format!(
+                    "Add a package named '{package_name}' to the graph, or skip its imports with \
+                     an import filter"
+                )),
+            LoadPackageError::CannotResolvePackageVersion { name, import, .. } => diagnostic
+                .with_related_path(import.as_ref().clone())
+                .with_suggestion(format!(
+                    "Add a version of package '{name}' that is compatible with the import"
+                )),
+            LoadPackageError::AmbiguousPackageProvider { .. } => diagnostic
+                .with_suggestion("Return one of the given provider ids from the provider selector"),
+            LoadPackageError::DeniedByPolicy { import, .. } => diagnostic
+                .with_related_path(import.as_ref().clone())
+                .with_suggestion(
+                    "Allow the edge with an edge rule of the policy, or skip the import with an \
+                     import filter",
+                ),
+            LoadPackageError::DeniedByPackagePolicy { import, .. } => diagnostic
+                .with_related_path(import.as_ref().clone())
+                .with_suggestion(
+                    "Add a version of the package that the package policy allows, or remove the \
+                     denied package",
+                ),
+        }
+    }
+}
+
+impl From<&InstantiatePackageError> for Diagnostic {
+    fn from(err: &InstantiatePackageError) -> Self {
+        let diagnostic = Diagnostic::new(Severity::Error, err.code(), error_message(err));
+
+        match err {
+            InstantiatePackageError::InvalidTrampolineSynchronicity => diagnostic.with_suggestion(
+                "Use `instantiate_async` for graphs containing asynchronous trampolines",
+            ),
+            InstantiatePackageError::MissingInterfaceExport { path } => {
+                diagnostic.with_related_path(path.clone())
+            }
+            InstantiatePackageError::InterfaceTypeMismatch { source } => diagnostic
+                .with_related_path(source.import.clone())
+                .with_suggestion(TYPE_MISMATCH_SUGGESTION),
+            InstantiatePackageError::InternalError { .. } => {
+                diagnostic.with_suggestion(INTERNAL_ERROR_SUGGESTION)
+            }
+            InstantiatePackageError::LazyPackageError { source } => {
+                Diagnostic::from(source).with_message(error_message(err))
+            }
+            InstantiatePackageError::UnsupportedResourceExport { .. } => diagnostic
+                .with_suggestion(
+                    "Instantiate packages depending on resources with `instantiate` instead of \
+                 `instantiate_pre`",
+                ),
+            InstantiatePackageError::ComponentInstantiationError { .. }
+            | InstantiatePackageError::ComponentCompilationError { .. }
+            | InstantiatePackageError::GuestTrap { .. }
+            | InstantiatePackageError::LinkerInstanceError { .. }
+            | InstantiatePackageError::InstanceMissingInterfaceExport { .. }
+            | InstantiatePackageError::InstanceMissingInterfaceFuncExport { .. }
+            | InstantiatePackageError::ComponentFuncRetrievalError { .. }
+            | InstantiatePackageError::LinkFuncInstantiationError { .. }
+            | InstantiatePackageError::PackageNotFound { .. } => diagnostic,
+        }
+    }
+}
+
+const TYPE_MISMATCH_SUGGESTION: &str =
+    "Rebuild the importing package against the exported interface version";
+
+const UNPREPARED_PACKAGE_SUGGESTION: &str =
+    "Call `CompositionGraph::prepare` for the package, or `precompile_all`, before instantiating";
+
+const INTERNAL_ERROR_SUGGESTION: &str =
+    "This is a bug in the composition graph, please report it to the library authors";
+
+/// Formats an error along with its chain of sources.
+fn error_message(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CycleEdge;
+    use semver::Version;
+
+    fn path(package: &str, interface: &str) -> ForeignInterfacePath {
+        ForeignInterfacePath::new(
+            package.to_string(),
+            interface.to_string(),
+            Some(Version::new(1, 0, 0)),
+        )
+    }
+
+    #[test]
+    fn test_severity_order() {
+        assert!(Severity::Note < Severity::Warning);
+        assert!(Severity::Warning < Severity::Error);
+    }
+
+    #[test]
+    fn test_diagnostic_from_cycle() {
+        let edge = |importer: &str, exporter: &str| CycleEdge {
+            importer: importer.to_string(),
+            import: path(exporter, "api"),
+            exporter: exporter.to_string(),
+        };
+        let err = LoadPackageError::PackageCycle {
+            cycle: vec!["a:a".to_string(), "b:b".to_string(), "a:a".to_string()],
+            edges: vec![edge("a:a", "b:b"), edge("b:b", "a:a")],
+        };
+
+        let diagnostic = Diagnostic::from(&err);
+        assert!(diagnostic.is_error());
+        assert_eq!(diagnostic.code(), "WCT0201");
+        assert_eq!(
+            diagnostic.related_paths(),
+            [path("b:b", "api"), path("a:a", "api")]
+        );
+        assert!(diagnostic.suggestion().is_some());
+        assert!(
+            diagnostic
+                .to_string()
+                .starts_with("error[WCT0201]: Package import cycle detected: 'a:a' imports")
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_from_nested_error() {
+        let err = InstantiateError::LoadPackageError {
+            source: LoadPackageError::MissingPackageDependency {
+                package_name: "test:kvstore".to_string(),
+                importer: "test:application@0.4.0".to_string(),
+                import: Box::new(path("test:kvstore", "store")),
+            },
+        };
+
+        let diagnostic = Diagnostic::from(&err);
+        assert_eq!(diagnostic.code(), "WCT0202");
+        assert_eq!(
+            diagnostic.message(),
+            "Failed to load package: Package dependency test:kvstore not found, for import \
+             'test:kvstore/store@1.0.0' of package 'test:application@0.4.0'"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_from_warning() {
+        let warning = GraphWarning::DeprecatedPackage {
+            importer: "test:application".to_string(),
+            import: path("test:kvstore", "store"),
+            version: Version::new(1, 0, 0),
+            message: "use 2.x".to_string(),
+        };
+
+        let diagnostic = Diagnostic::from(&warning);
+        assert_eq!(diagnostic.severity(), Severity::Warning);
+        assert!(!diagnostic.is_error());
+        assert_eq!(diagnostic.related_paths(), [path("test:kvstore", "store")]);
+    }
+}
@@ -0,0 +1,94 @@
+//! The SHA-256 digests identifying the component bytes of packages.
+//!
+//! Unlike verification, digests are part of the graph core: the component cache keys compiled
+//! components by the digest of their bytes, so `sha2` is not an optional dependency.
+
+use sha2::{Digest, Sha256};
+use snafu::Snafu;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+/// The SHA-256 digest of the component bytes of a package.
+///
+/// Digests are formatted and parsed as `sha256:<hex>`, where the `sha256:` prefix is optional
+/// when parsing.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Sha256Digest([u8; 32]);
+
+impl Sha256Digest {
+    /// Computes the digest of `bytes`.
+    #[must_use]
+    pub fn of(bytes: &[u8]) -> Self {
+        Self(Sha256::digest(bytes).into())
+    }
+
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Sha256Digest {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Display for Sha256Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("sha256:")?;
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl Debug for Sha256Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[derive(Snafu, Debug, Clone, PartialEq, Eq)]
+#[snafu(display("Invalid SHA-256 digest '{digest}', expected 64 hexadecimal digits"))]
+pub struct DigestParseError {
+    digest: String,
+}
+
+impl FromStr for Sha256Digest {
+    type Err = DigestParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("sha256:").unwrap_or(s);
+        let error = || DigestParseError {
+            digest: s.to_string(),
+        };
+
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(error());
+        }
+
+        let mut digest = [0; 32];
+        for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            // The digits are ASCII, so each pair is a valid string.
+            let pair = std::str::from_utf8(pair).map_err(|_| error())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| error())?;
+        }
+
+        Ok(Self(digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_digest() {
+        let digest = Sha256Digest::of(b"\0asm\x0d\x00\x01\x00");
+        assert_eq!(digest.to_string().parse::<Sha256Digest>(), Ok(digest));
+        assert_eq!(
+            digest.to_string().trim_start_matches("sha256:").parse(),
+            Ok(digest)
+        );
+        assert!("sha256:00".parse::<Sha256Digest>().is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{AddPackageError, CallError, InstantiateError, InstantiatePackageError};
+use wasmtime::Trap;
+
+/// Whether an operation that failed may succeed when retried.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RetryHint {
+    /// The error is deterministic or its cause unknown, so retrying is not safe to assume useful.
+    DoNotRetry,
+
+    /// The guest was interrupted or ran out of fuel, and a retry may succeed. Trapped component
+    /// instances cannot be entered again, so the retry must instantiate into a new store.
+    RetryInNewStore,
+}
+
+/// The party responsible for an error, and thus where it needs to be fixed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Fault {
+    /// The composition graph is wrong, e.g. an import cannot be resolved or is linked against a
+    /// mismatching export. The graph needs fixing.
+    Composition,
+
+    /// A guest component is invalid or crashed. The plugin needs fixing.
+    Guest,
+
+    /// A trampoline or other host logic failed. The host needs fixing.
+    Host,
+}
+
+impl Fault {
+    /// Attributes an error returned by a call to a shadowed guest function.
+    ///
+    /// Errors are attributed by their `CallError` context, which is attached to all errors
+    /// returned through trampolines. Other errors are attributed to the guest if they carry a
+    /// `wasmtime::Trap`, and to the host otherwise.
+    #[must_use]
+    pub fn of_call_error(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<CallError>() {
+            Some(CallError::Guest { .. }) => Fault::Guest,
+            Some(CallError::Trampoline { .. }) => Fault::Host,
+            None if err.downcast_ref::<Trap>().is_some() => Fault::Guest,
+            None => Fault::Host,
+        }
+    }
+}
+
+/// The broad origin of an error raised while instantiating or calling WebAssembly components.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ErrorClass {
+    /// The guest trapped, with the trap code reported by `wasmtime`.
+    GuestTrap(Trap),
+
+    /// A host function or trampoline returned an error.
+    Host,
+
+    /// The component could not be compiled or linked, e.g. because of a missing import or a
+    /// mismatched function type.
+    Link,
+}
+
+impl ErrorClass {
+    /// Classifies an error returned by a WebAssembly function call, such as a guest call made
+    /// through a trampoline.
+    ///
+    /// Errors carrying a `wasmtime::Trap` are guest traps, and all others are host errors.
+    #[must_use]
+    pub fn of_call_error(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<Trap>() {
+            Some(trap) => ErrorClass::GuestTrap(*trap),
+            None => ErrorClass::Host,
+        }
+    }
+
+    /// Classifies an error returned while compiling, linking or instantiating a component.
+    ///
+    /// Errors carrying a `wasmtime::Trap` are guest traps raised by the component's start
+    /// function, and all others are link errors.
+    #[must_use]
+    pub fn of_instantiation_error(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<Trap>() {
+            Some(trap) => ErrorClass::GuestTrap(*trap),
+            None => ErrorClass::Link,
+        }
+    }
+
+    /// Returns the trap code if the error is a guest trap.
+    #[must_use]
+    pub fn trap(&self) -> Option<Trap> {
+        match self {
+            ErrorClass::GuestTrap(trap) => Some(*trap),
+            ErrorClass::Host | ErrorClass::Link => None,
+        }
+    }
+
+    /// Returns whether the failed operation may succeed when retried.
+    ///
+    /// Host errors are never considered transient, since their cause is unknown to the graph;
+    /// retry middleware may know better for the errors it raises itself.
+    #[must_use]
+    pub fn retry_hint(&self) -> RetryHint {
+        match self {
+            ErrorClass::GuestTrap(Trap::Interrupt | Trap::OutOfFuel) => RetryHint::RetryInNewStore,
+            ErrorClass::GuestTrap(_) | ErrorClass::Host | ErrorClass::Link => RetryHint::DoNotRetry,
+        }
+    }
+
+    /// Returns `true` if the failed operation may succeed when retried.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        self.retry_hint() != RetryHint::DoNotRetry
+    }
+}
+
+impl InstantiateError {
+    /// Classifies the `wasmtime` error underlying this error, or returns `None` if the error was
+    /// raised by the graph itself, e.g. for unresolved imports.
+    #[must_use]
+    pub fn error_class(&self) -> Option<ErrorClass> {
+        match self {
+            InstantiateError::InstantiatePackageDependencyError { source, .. } => {
+                source.error_class()
+            }
+            InstantiateError::ComponentInstantiationError { source }
+            | InstantiateError::ComponentCompilationError { source }
+            | InstantiateError::GuestTrap { source } => {
+                Some(ErrorClass::of_instantiation_error(source))
+            }
+            InstantiateError::PackageNotFound { .. }
+            | InstantiateError::LoadPackageError { .. }
+            | InstantiateError::InterfaceTypeMismatch { .. }
+            | InstantiateError::LazyPackageError { .. }
+            | InstantiateError::UnpreparedPackage { .. } => None,
+        }
+    }
+
+    /// Returns the party responsible for the error.
+    #[must_use]
+    pub fn fault(&self) -> Fault {
+        match self {
+            InstantiateError::InstantiatePackageDependencyError { source, .. } => source.fault(),
+            InstantiateError::LazyPackageError { source } => lazy_package_fault(source),
+            InstantiateError::ComponentCompilationError { .. }
+            | InstantiateError::GuestTrap { .. } => Fault::Guest,
+            InstantiateError::PackageNotFound { .. }
+            | InstantiateError::LoadPackageError { .. }
+            | InstantiateError::ComponentInstantiationError { .. }
+            | InstantiateError::InterfaceTypeMismatch { .. } => Fault::Composition,
+            InstantiateError::UnpreparedPackage { .. } => Fault::Host,
+        }
+    }
+
+    /// Returns the trap code if the instantiation failed because a guest trapped.
+    #[must_use]
+    pub fn trap(&self) -> Option<Trap> {
+        self.error_class().and_then(|class| class.trap())
+    }
+
+    /// Returns whether the instantiation may succeed when retried. Errors raised by the graph
+    /// itself are never transient.
+    #[must_use]
+    pub fn retry_hint(&self) -> RetryHint {
+        self.error_class()
+            .map_or(RetryHint::DoNotRetry, |class| class.retry_hint())
+    }
+
+    /// Returns `true` if the instantiation may succeed when retried.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        self.retry_hint() != RetryHint::DoNotRetry
+    }
+}
+
+impl InstantiatePackageError {
+    /// Classifies the `wasmtime` error underlying this error, or returns `None` if the error was
+    /// raised by the graph itself.
+    #[must_use]
+    pub fn error_class(&self) -> Option<ErrorClass> {
+        match self {
+            InstantiatePackageError::ComponentInstantiationError { source }
+            | InstantiatePackageError::ComponentCompilationError { source }
+            | InstantiatePackageError::GuestTrap { source }
+            | InstantiatePackageError::LinkerInstanceError { source }
+            | InstantiatePackageError::LinkFuncInstantiationError { source } => {
+                Some(ErrorClass::of_instantiation_error(source))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the party responsible for the error.
+    #[must_use]
+    pub fn fault(&self) -> Fault {
+        match self {
+            InstantiatePackageError::LazyPackageError { source } => lazy_package_fault(source),
+            InstantiatePackageError::ComponentCompilationError { .. }
+            | InstantiatePackageError::GuestTrap { .. } => Fault::Guest,
+            InstantiatePackageError::InvalidTrampolineSynchronicity
+            | InstantiatePackageError::InternalError { .. }
+            | InstantiatePackageError::UnsupportedResourceExport { .. }
+            | InstantiatePackageError::PackageNotFound { .. } => Fault::Host,
+            InstantiatePackageError::ComponentInstantiationError { .. }
+            | InstantiatePackageError::LinkerInstanceError { .. }
+            | InstantiatePackageError::InstanceMissingInterfaceExport { .. }
+            | InstantiatePackageError::InstanceMissingInterfaceFuncExport { .. }
+            | InstantiatePackageError::ComponentFuncRetrievalError { .. }
+            | InstantiatePackageError::LinkFuncInstantiationError { .. }
+            | InstantiatePackageError::MissingInterfaceExport { .. }
+            | InstantiatePackageError::InterfaceTypeMismatch { .. } => Fault::Composition,
+        }
+    }
+
+    /// Returns the trap code if the error was caused by a guest trap.
+    #[must_use]
+    pub fn trap(&self) -> Option<Trap> {
+        self.error_class().and_then(|class| class.trap())
+    }
+
+    /// Returns whether the instantiation may succeed when retried. Errors raised by the graph
+    /// itself are never transient.
+    #[must_use]
+    pub fn retry_hint(&self) -> RetryHint {
+        self.error_class()
+            .map_or(RetryHint::DoNotRetry, |class| class.retry_hint())
+    }
+
+    /// Returns `true` if the instantiation may succeed when retried.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        self.retry_hint() != RetryHint::DoNotRetry
+    }
+}
+
+/// Returns the party responsible for failing to parse a lazily added package.
+fn lazy_package_fault(err: &AddPackageError) -> Fault {
+    match err {
+        AddPackageError::PackageParseError { .. }
+        | AddPackageError::ImportParseError { .. }
+        | AddPackageError::VerificationFailed { .. } => Fault::Guest,
+        #[cfg(feature = "preinit")]
+        AddPackageError::PreinitializeError { .. } => Fault::Guest,
+        AddPackageError::InternalError { .. }
+        | AddPackageError::ReadError { .. }
+        | AddPackageError::PackageNotFound { .. }
+        | AddPackageError::FetchError { .. } => Fault::Host,
+        AddPackageError::DuplicatePackage { .. }
+        | AddPackageError::DeniedByPackagePolicy { .. } => Fault::Composition,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_error_class() {
+        let trap = anyhow::Error::from(Trap::UnreachableCodeReached).context("guest call failed");
+        assert_eq!(
+            ErrorClass::of_call_error(&trap),
+            ErrorClass::GuestTrap(Trap::UnreachableCodeReached)
+        );
+
+        let host = anyhow::anyhow!("host function failed");
+        assert_eq!(ErrorClass::of_call_error(&host), ErrorClass::Host);
+        assert_eq!(ErrorClass::of_call_error(&host).trap(), None);
+    }
+
+    #[test]
+    fn test_retry_hint() {
+        let interrupt = anyhow::Error::from(Trap::Interrupt);
+        assert_eq!(
+            ErrorClass::of_call_error(&interrupt).retry_hint(),
+            RetryHint::RetryInNewStore
+        );
+
+        let unreachable = anyhow::Error::from(Trap::UnreachableCodeReached);
+        assert!(!ErrorClass::of_call_error(&unreachable).is_transient());
+        assert!(!ErrorClass::Host.is_transient());
+
+        let err = InstantiateError::InstantiatePackageDependencyError {
+            name: "test:kvstore".to_string(),
+            version: None,
+            source: Box::new(InstantiatePackageError::GuestTrap {
+                source: Trap::OutOfFuel.into(),
+            }),
+        };
+        assert!(err.is_transient());
+
+        let err = InstantiateError::LoadPackageError {
+            source: crate::LoadPackageError::PackageCycle {
+                cycle: Vec::new(),
+                edges: Vec::new(),
+            },
+        };
+        assert_eq!(err.retry_hint(), RetryHint::DoNotRetry);
+    }
+
+    #[test]
+    fn test_instantiate_error_class() {
+        let err = InstantiateError::InstantiatePackageDependencyError {
+            name: "test:kvstore".to_string(),
+            version: None,
+            source: Box::new(InstantiatePackageError::GuestTrap {
+                source: Trap::StackOverflow.into(),
+            }),
+        };
+        assert_eq!(err.trap(), Some(Trap::StackOverflow));
+        assert_eq!(err.fault(), Fault::Guest);
+
+        let err = InstantiateError::ComponentInstantiationError {
+            source: anyhow::anyhow!("missing import"),
+        };
+        assert_eq!(err.error_class(), Some(ErrorClass::Link));
+        assert_eq!(err.fault(), Fault::Composition);
+
+        let err = InstantiateError::LoadPackageError {
+            source: crate::LoadPackageError::PackageCycle {
+                cycle: Vec::new(),
+                edges: Vec::new(),
+            },
+        };
+        assert_eq!(err.error_class(), None);
+        assert_eq!(err.fault(), Fault::Composition);
+    }
+
+    #[test]
+    fn test_call_error_fault() {
+        let path =
+            crate::ForeignInterfacePath::new("test:kvstore".to_string(), "store".to_string(), None);
+
+        let guest = anyhow::Error::from(Trap::UnreachableCodeReached).context(CallError::Guest {
+            interface: path.clone(),
+            method: "get".to_string(),
+        });
+        assert_eq!(Fault::of_call_error(&guest), Fault::Guest);
+
+        let trampoline = anyhow::anyhow!("access denied").context(CallError::Trampoline {
+            interface: path,
+            method: "get".to_string(),
+        });
+        assert_eq!(Fault::of_call_error(&trampoline), Fault::Host);
+
+        let trap = anyhow::Error::from(Trap::StackOverflow);
+        assert_eq!(Fault::of_call_error(&trap), Fault::Guest);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::recent::RecentWindow;
+use crate::sampling::matches_pattern;
+use crate::{ForeignInterfacePath, RecentCalls};
+use derivative::Derivative;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// The default period error rates are computed over.
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The default number of calls within the window below which error rates are not checked.
+const DEFAULT_MIN_CALLS: u64 = 10;
+
+type ErrorRateCallback = Arc<dyn Fn(&ErrorRateAlert) + Send + Sync>;
+
+/// Tracks rolling error rates of the calls to each shadowed interface, invoking a callback when
+/// they cross a threshold, once set with `CompositionGraph::set_error_rate_monitor`.
+///
+/// Interfaces use the threshold of the first override whose pattern matches their path, where
+/// `*` matches any sequence of characters like in `CallSampling`, or the default threshold if
+/// none matches. The callback is invoked once when an interface's error rate exceeds its
+/// threshold, and once when it falls back to it.
+///
+/// Clones share the same error rates.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct ErrorRateMonitor {
+    threshold: Option<f64>,
+    overrides: Vec<(String, Option<f64>)>,
+    window: Duration,
+    min_calls: u64,
+    #[derivative(Debug = "ignore")]
+    callback: ErrorRateCallback,
+    interfaces: Arc<Mutex<HashMap<ForeignInterfacePath, Arc<InterfaceErrors>>>>,
+}
+
+/// An interface whose error rate crossed its `ErrorRateMonitor` threshold.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorRateAlert {
+    pub interface: ForeignInterfacePath,
+    /// Whether the error rate exceeded the threshold, or fell back to it.
+    pub exceeded: bool,
+    pub error_rate: f64,
+    pub threshold: f64,
+    /// The calls within the window the error rate was computed over.
+    pub recent: RecentCalls,
+}
+
+impl ErrorRateMonitor {
+    /// Creates a monitor invoking `callback` when the error rate of an interface crosses
+    /// `threshold`, between 0 and 1, or for no interfaces if `threshold` is `None`, unless
+    /// overridden.
+    ///
+    /// Error rates are computed over the last minute, once an interface had 10 calls within it.
+    pub fn new(
+        threshold: Option<f64>,
+        callback: impl Fn(&ErrorRateAlert) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            threshold,
+            overrides: Vec::new(),
+            window: DEFAULT_WINDOW,
+            min_calls: DEFAULT_MIN_CALLS,
+            callback: Arc::new(callback),
+            interfaces: Arc::default(),
+        }
+    }
+
+    /// Uses `threshold` for the interfaces matching `pattern` instead, unless they match an
+    /// override added before. A `None` threshold disables monitoring for them.
+    #[must_use]
+    pub fn with_override(mut self, pattern: impl Into<String>, threshold: Option<f64>) -> Self {
+        self.overrides.push((pattern.into(), threshold));
+        self
+    }
+
+    /// Computes error rates over about the last `window` instead.
+    #[must_use]
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Only checks the error rates of interfaces with at least `min_calls` calls within the
+    /// window instead.
+    #[must_use]
+    pub fn with_min_calls(mut self, min_calls: u64) -> Self {
+        self.min_calls = min_calls;
+        self
+    }
+
+    /// Returns the threshold of the error rate of `interface`, if it is monitored.
+    #[must_use]
+    pub fn threshold(&self, interface: &ForeignInterfacePath) -> Option<f64> {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| matches_pattern(pattern, interface.as_str()))
+            .map_or(self.threshold, |(_, threshold)| *threshold)
+    }
+
+    /// Returns the calls to `interface` within the window, if it is monitored.
+    #[must_use]
+    pub fn recent_calls(&self, interface: &ForeignInterfacePath) -> Option<RecentCalls> {
+        let interfaces = self
+            .interfaces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        interfaces
+            .get(interface)
+            .map(|errors| errors.window.calls())
+    }
+
+    /// Returns whether the error rate of `interface` currently exceeds its threshold.
+    #[must_use]
+    pub fn is_exceeded(&self, interface: &ForeignInterfacePath) -> bool {
+        let interfaces = self
+            .interfaces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        interfaces
+            .get(interface)
+            .is_some_and(|errors| errors.exceeded.load(Ordering::Relaxed))
+    }
+
+    /// Returns the handle the shadowed functions of `interface` record their calls with, if it
+    /// is monitored. All functions of an interface share its error rate.
+    pub(crate) fn interface(&self, interface: &ForeignInterfacePath) -> Option<MonitoredInterface> {
+        let threshold = self.threshold(interface)?;
+
+        let mut interfaces = self
+            .interfaces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let errors = interfaces
+            .entry(interface.clone())
+            .or_insert_with(|| {
+                Arc::new(InterfaceErrors {
+                    interface: interface.clone(),
+                    window: RecentWindow::new(self.window),
+                    exceeded: AtomicBool::new(false),
+                })
+            })
+            .clone();
+
+        Some(MonitoredInterface {
+            errors,
+            threshold,
+            min_calls: self.min_calls,
+            callback: self.callback.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct InterfaceErrors {
+    interface: ForeignInterfacePath,
+    window: RecentWindow,
+    exceeded: AtomicBool,
+}
+
+/// A shadowed interface recording its calls in an `ErrorRateMonitor`.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub(crate) struct MonitoredInterface {
+    errors: Arc<InterfaceErrors>,
+    threshold: f64,
+    min_calls: u64,
+    #[derivative(Debug = "ignore")]
+    callback: ErrorRateCallback,
+}
+
+impl MonitoredInterface {
+    pub(crate) fn record(&self, succeeded: bool) {
+        self.errors.window.record(succeeded);
+
+        let recent = self.errors.window.calls();
+        if recent.calls < self.min_calls {
+            return;
+        }
+
+        let error_rate = recent.error_rate().unwrap_or_default();
+        let exceeded = error_rate > self.threshold;
+
+        // Only the call that flips the state invokes the callback.
+        if self
+            .errors
+            .exceeded
+            .compare_exchange(!exceeded, exceeded, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            (self.callback)(&ErrorRateAlert {
+                interface: self.errors.interface.clone(),
+                exceeded,
+                error_rate,
+                threshold: self.threshold,
+                recent,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_rate_monitor() {
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let monitor = ErrorRateMonitor::new(Some(0.5), {
+            let alerts = alerts.clone();
+            move |alert: &ErrorRateAlert| alerts.lock().unwrap().push(alert.exceeded)
+        })
+        .with_min_calls(4);
+
+        let path = ForeignInterfacePath::new("test:kvstore".to_string(), "store".to_string(), None);
+        let store = monitor.interface(&path).unwrap();
+
+        for succeeded in [false, false, false, false, true, true, true, true, true] {
+            store.record(succeeded);
+        }
+
+        assert_eq!(*alerts.lock().unwrap(), [true, false]);
+        assert!(!monitor.is_exceeded(&path));
+        assert_eq!(
+            monitor.recent_calls(&path),
+            Some(RecentCalls {
+                calls: 9,
+                errors: 4
+            })
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{CallError, ForeignInterfacePath, PackageId};
+use semver::Version;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+use wasmtime::Trap;
+
+/// An event raised by a composition graph, delivered to the receivers returned by
+/// `CompositionGraph::subscribe_events`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum GraphEvent {
+    /// A shadowed function was called.
+    CallStarted {
+        interface: ForeignInterfacePath,
+        method: String,
+    },
+
+    /// A call to a shadowed function returned.
+    CallFinished {
+        interface: ForeignInterfacePath,
+        method: String,
+        duration: Duration,
+        /// The side of the call that failed, if it failed. For nested calls, this is the
+        /// innermost failing call.
+        error: Option<CallError>,
+    },
+
+    /// A shadowed function trapped. Raised before the call's `CallFinished` event.
+    Trapped {
+        interface: ForeignInterfacePath,
+        method: String,
+        trap: Trap,
+    },
+
+    /// A package was instantiated, either as the instantiated package or as a dependency.
+    Instantiated {
+        package: PackageId,
+        name: String,
+        version: Option<Version>,
+        duration: Duration,
+    },
+
+    /// The component and trampoline of a package were replaced with
+    /// `CompositionGraph::replace_package`.
+    PackageReplaced {
+        package: PackageId,
+        name: String,
+        version: Version,
+    },
+}
+
+/// The senders of the event receivers of a graph, shared with its shadow functions.
+#[derive(Default, Debug)]
+pub(crate) struct EventSubscribers {
+    senders: Mutex<Vec<Sender<GraphEvent>>>,
+    // Checked before locking `senders`, so that calls do not contend on the lock without
+    // subscribers.
+    active: AtomicBool,
+}
+
+impl EventSubscribers {
+    pub(crate) fn subscribe(&self) -> Receiver<GraphEvent> {
+        let (sender, receiver) = mpsc::channel();
+
+        let mut senders = self.senders.lock().unwrap_or_else(PoisonError::into_inner);
+        senders.push(sender);
+        self.active.store(true, Ordering::Relaxed);
+
+        receiver
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Sends the event built by `event` to all receivers, dropping the senders of receivers
+    /// that were dropped. The event is only built if there are receivers.
+    pub(crate) fn emit(&self, event: impl FnOnce() -> GraphEvent) {
+        if !self.is_active() {
+            return;
+        }
+
+        let mut senders = self.senders.lock().unwrap_or_else(PoisonError::into_inner);
+        let event = event();
+
+        senders.retain(|sender| sender.send(event.clone()).is_ok());
+        self.active.store(!senders.is_empty(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_started() -> GraphEvent {
+        GraphEvent::CallStarted {
+            interface: ForeignInterfacePath::new(
+                "test:kvstore".to_string(),
+                "store".to_string(),
+                None,
+            ),
+            method: "get".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_event_subscribers() {
+        let subscribers = EventSubscribers::default();
+        subscribers.emit(|| unreachable!("no subscribers"));
+
+        let first = subscribers.subscribe();
+        let second = subscribers.subscribe();
+        subscribers.emit(call_started);
+
+        assert!(matches!(
+            first.try_recv(),
+            Ok(GraphEvent::CallStarted { .. })
+        ));
+        assert!(matches!(
+            second.try_recv(),
+            Ok(GraphEvent::CallStarted { .. })
+        ));
+
+        drop(first);
+        drop(second);
+        subscribers.emit(call_started);
+        assert!(!subscribers.is_active());
+    }
+}
@@ -7,13 +7,18 @@ pub trait ImportFilter {
     fn filter_rule(&self, import_path: &ForeignInterfacePath) -> ImportRule;
 }
 
-impl Default for Box<dyn ImportFilter> {
+impl Default for Box<dyn ImportFilter + Send + Sync> {
     fn default() -> Self {
         Box::new(ImportRule::default())
     }
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "manifest",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum ImportRule {
     /// Skip the import and do not include it in the graph.
     Skip,
@@ -26,31 +31,31 @@ pub enum ImportRule {
     Force,
 }
 
-impl<F: ImportFilter> ImportFilter for &F {
+impl<F: ImportFilter + ?Sized> ImportFilter for &F {
     fn filter_rule(&self, path: &ForeignInterfacePath) -> ImportRule {
         (**self).filter_rule(path)
     }
 }
 
-impl<F: ImportFilter> ImportFilter for &mut F {
+impl<F: ImportFilter + ?Sized> ImportFilter for &mut F {
     fn filter_rule(&self, path: &ForeignInterfacePath) -> ImportRule {
         (**self).filter_rule(path)
     }
 }
 
-impl<F: ImportFilter> ImportFilter for Box<F> {
+impl<F: ImportFilter + ?Sized> ImportFilter for Box<F> {
     fn filter_rule(&self, path: &ForeignInterfacePath) -> ImportRule {
         (**self).filter_rule(path)
     }
 }
 
-impl<F: ImportFilter> ImportFilter for Rc<F> {
+impl<F: ImportFilter + ?Sized> ImportFilter for Rc<F> {
     fn filter_rule(&self, path: &ForeignInterfacePath) -> ImportRule {
         (**self).filter_rule(path)
     }
 }
 
-impl<F: ImportFilter> ImportFilter for Arc<F> {
+impl<F: ImportFilter + ?Sized> ImportFilter for Arc<F> {
     fn filter_rule(&self, path: &ForeignInterfacePath) -> ImportRule {
         (**self).filter_rule(path)
     }
@@ -81,6 +86,7 @@ impl<F: ImportFilter> ImportFilter for Vec<F> {
     }
 }
 
+#[cfg(feature = "regex")]
 #[derive(Clone, Debug)]
 pub struct RegexMatchFilter<F: ImportFilter, D: ImportFilter = ImportRule> {
     regex: regex::Regex,
@@ -88,12 +94,14 @@ pub struct RegexMatchFilter<F: ImportFilter, D: ImportFilter = ImportRule> {
     default_rule: D,
 }
 
+#[cfg(feature = "regex")]
 impl<F: ImportFilter> RegexMatchFilter<F, ImportRule> {
     pub fn new(regex: regex::Regex, match_rule: F) -> Self {
         Self::with_default(regex, match_rule, ImportRule::Include)
     }
 }
 
+#[cfg(feature = "regex")]
 impl<F: ImportFilter, D: ImportFilter> RegexMatchFilter<F, D> {
     pub fn with_default(regex: regex::Regex, match_rule: F, default_rule: D) -> Self {
         Self {
@@ -104,9 +112,10 @@ impl<F: ImportFilter, D: ImportFilter> RegexMatchFilter<F, D> {
     }
 }
 
+#[cfg(feature = "regex")]
 impl<F: ImportFilter, D: ImportFilter> ImportFilter for RegexMatchFilter<F, D> {
     fn filter_rule(&self, import_path: &ForeignInterfacePath) -> ImportRule {
-        if self.regex.is_match(&import_path.to_string()) {
+        if self.regex.is_match(import_path.as_str()) {
             self.match_rule.filter_rule(import_path)
         } else {
             self.default_rule.filter_rule(import_path)
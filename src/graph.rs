@@ -1,19 +1,107 @@
+#[cfg(feature = "compose")]
+use crate::ComposeError;
+#[cfg(feature = "deterministic")]
+use crate::DeterministicState;
+#[cfg(feature = "http")]
+use crate::HttpClient;
+#[cfg(any(feature = "http", feature = "manifest"))]
+use crate::Sha256Digest;
+use crate::cache::ComponentCache;
+#[cfg(feature = "compose")]
+use crate::compose::{compose, compose_error};
+#[cfg(feature = "manifest")]
+use crate::config::{config_error, config_resolver, read_config};
+use crate::error_rates::MonitoredInterface;
+use crate::events::EventSubscribers;
+#[cfg(feature = "http")]
+use crate::http::download;
+use crate::in_flight::{CallFrame, InFlightFunc};
+use crate::logging::{log_debug, log_trace, log_warn};
+#[cfg(feature = "manifest")]
+use crate::manifest::manifest_error;
+use crate::memory::{Attribution, TrackedFunc, TrackedPackage};
+#[cfg(feature = "metrics")]
+use crate::metrics::FuncMetrics;
+use crate::mismatch::interface_mismatches;
 use crate::path::{ForeignInterfacePath, InterfacePath, InterfacePathParseError};
-use crate::{DynInterfaceTrampoline, DynPackageTrampoline, ImportFilter, ImportRule};
+use crate::policy::{CallDeadline, PolicedFunc};
+#[cfg(feature = "recording")]
+use crate::profile::ProfiledFunc;
+use crate::resolver::resolve_error;
+use crate::resources::{
+    PackageResources, ShadowResources, ShadowedResource, declared_resources, func_has_resources,
+};
+use crate::scopes::Scope;
+use crate::slow_calls::SlowCallFunc;
+#[cfg(feature = "recording")]
+use crate::trace::TracedFunc;
+use crate::trampoline::with_call_error;
+use crate::{
+    AsyncTrampoline, CallError, ContextOverlay, Diagnostic, DynInterfaceTrampoline,
+    DynPackageTrampoline, ErrorRateMonitor, GraphBuilder, GraphEvent, GraphHealth, ImportFilter,
+    ImportRule, ImportStub, InFlightCalls, InterfaceTrampoline, InterfaceTypeMismatch,
+    MemoryTracker, MissingPackage, PackageLimits, PackageMetadata, PackagePolicy, PackageResolver,
+    PackageSource, PackageVerification, PackageVerifier, Policy, ResolutionReport, ResolveError,
+    ResolvedEdge, Severity, SlowCallDetector, StaticAsyncTrampoline, StaticInterfaceTrampoline,
+    StaticTrampoline, StoreScopes, Trampoline,
+};
+#[cfg(feature = "metrics")]
+use crate::{CallMetrics, InterfaceHealth};
+#[cfg(feature = "recording")]
+use crate::{CallProfiler, CallTrace};
+#[cfg(feature = "manifest")]
+use crate::{
+    CompositionManifest, ConfigError, ImportRuleManifest, ManifestError, ManifestResolver,
+    NoopTrampoline, PackageManifest, TrampolineRegistry,
+};
+#[cfg(feature = "deterministic")]
+use crate::{DeterministicImportFilter, add_deterministic_shims_to_linker};
+#[cfg(feature = "preinit")]
+use crate::{PreinitializeError, preinitialize};
 use derivative::Derivative;
+#[cfg(feature = "async")]
+use futures_io::AsyncRead;
 use indexmap::{IndexMap, IndexSet};
 use semver::Version;
 use slab::Slab;
 use snafu::{ResultExt, Snafu};
-use std::collections::HashMap;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+#[cfg(feature = "async")]
+use std::future::poll_fn;
+use std::num::NonZeroUsize;
 use std::ops::{Deref, Index};
-use std::rc::Rc;
+use std::panic::resume_unwind;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "async")]
+use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::Arc;
-use wac_types::{InterfaceId, ItemKind, Package};
-use wasm_component_semver::VersionMap;
-use wasmtime::component::{Component, Instance, LinkerInstance};
-use wasmtime::{AsContextMut, component};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, OnceLock};
+use std::task::Poll;
+use std::time::Instant;
+use wac_types::{InterfaceId, ItemKind, Package, SubtypeChecker};
+use wasm_component_semver::{
+    AlternateStrategy, BuildMetadataPolicy, PreReleasePolicy, ResolutionMode, VersionMap,
+    VersionPriority,
+};
+#[cfg(feature = "async")]
+use wasmparser::{Chunk, Parser, ValidPayload, Validator, WasmFeatures};
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::{
+    Component, ComponentExportIndex, Instance, InstancePre, LinkerInstance, ResourceType, Val,
+};
+use wasmtime::{AsContext, AsContextMut, Trap, WasmBacktrace, component};
+
+type WarningHandler = Arc<dyn Fn(&GraphWarning) + Send + Sync>;
+pub(crate) type PackageVerifierRef = Arc<dyn PackageVerifier>;
+/// Finds the `StoreScopes` in the data of a store, type-erased for `CallMeta`.
+type ScopesAccessor = Arc<dyn Fn(&dyn Any) -> Option<StoreScopes> + Send + Sync>;
+type ProviderSelector =
+    Arc<dyn Fn(&str, &Version, &[PackageId]) -> Option<PackageId> + Send + Sync>;
 
 /// A graph for composing multiple WebAssembly components into a single linker, while allowing for
 /// automatic insertion of "trampoline" functions between cross-component calls.
@@ -24,11 +112,55 @@ pub struct CompositionGraph<D, C: Clone = ()> {
     nonce: usize,
     types: wac_types::Types,
     packages: Slab<PackageWrapper>,
-    package_map: HashMap<String, VersionMap<PackageId>>,
-    exported_interfaces: HashMap<ForeignInterfacePath, InterfaceExport<D, C>>,
+    package_map: HashMap<String, VersionMap<Vec<PackageId>>>,
+    exported_interfaces: HashMap<(PackageId, ForeignInterfacePath), InterfaceExport<D, C>>,
     imported_interfaces: HashMap<PackageId, IndexSet<ForeignInterfacePath>>,
+    imported_functions: HashMap<PackageId, HashMap<ForeignInterfacePath, HashSet<String>>>,
+    package_metadata: HashMap<PackageId, Arc<PackageMetadata>>,
+    package_limits: HashMap<PackageId, Arc<PackageLimits>>,
+    store_scopes: Option<fn(&D) -> &StoreScopes>,
+    #[derivative(Debug = "ignore")]
+    #[derivative(Default(value = "Arc::new(ImportRule::default())"))]
+    import_filter: Arc<dyn ImportFilter + Send + Sync>,
+    /// The rules of the import filter, if it was set with `set_import_rules`.
+    #[cfg(feature = "manifest")]
+    import_rules: Option<Vec<ImportRuleManifest>>,
+    /// The component paths and trampoline identifiers recorded with `set_package_origin`.
+    #[cfg(feature = "manifest")]
+    package_origins: HashMap<PackageId, (Option<PathBuf>, Option<String>)>,
+    version_strategy: AlternateStrategy,
+    pre_release_policy: PreReleasePolicy,
+    build_metadata_policy: BuildMetadataPolicy,
+    resolution_mode: ResolutionMode,
+    version_priority: Option<VersionPriority>,
+    #[derivative(Debug = "ignore")]
+    warning_handler: Option<WarningHandler>,
+    #[derivative(Debug = "ignore")]
+    provider_selector: Option<ProviderSelector>,
     #[derivative(Debug = "ignore")]
-    import_filter: Box<dyn ImportFilter>,
+    package_verifier: Option<PackageVerifierRef>,
+    invariant_policy: InvariantPolicy,
+    #[derivative(Debug = "ignore")]
+    component_cache: ComponentCache,
+    #[derivative(Debug = "ignore")]
+    pending_packages: HashMap<PackageId, PendingPackage<D, C>>,
+    #[cfg(feature = "metrics")]
+    call_metrics: Option<CallMetrics>,
+    #[cfg(feature = "recording")]
+    call_profiler: Option<CallProfiler>,
+    in_flight_calls: Option<InFlightCalls>,
+    #[cfg(feature = "recording")]
+    call_trace: Option<CallTrace>,
+    memory_tracker: Option<MemoryTracker>,
+    slow_call_detector: Option<SlowCallDetector>,
+    error_rate_monitor: Option<ErrorRateMonitor>,
+    policy: Option<Policy>,
+    package_policy: Option<PackagePolicy>,
+    lazy_instantiation: bool,
+    import_stub: Option<ImportStub>,
+    #[cfg(feature = "deterministic")]
+    deterministic_state: Option<fn(&mut D) -> &mut DeterministicState>,
+    events: Arc<EventSubscribers>,
 }
 
 impl<D, C: Clone> CompositionGraph<D, C> {
@@ -42,583 +174,4650 @@ impl<D, C: Clone> CompositionGraph<D, C> {
     /// The filter can be removed by using the default `ImportRule::default()` filter.
     pub fn set_import_filter<F>(&mut self, filter: F)
     where
-        F: ImportFilter + 'static,
+        F: ImportFilter + Send + Sync + 'static,
     {
-        self.import_filter = Box::new(filter);
+        self.import_filter = Arc::new(filter);
+        #[cfg(feature = "manifest")]
+        {
+            self.import_rules = None;
+        }
     }
 
-    /// Adds a package (component) to the composition graph.
-    ///
-    /// Components can be added in any order, and dependencies will be resolved at instantiation time.
-    pub fn add_package(
+    #[cfg(feature = "manifest")]
+    /// Filters package imports with `rules`, like `set_import_filter`, and records the rules so
+    /// that they are included in `to_manifest`.
+    pub fn set_import_rules(&mut self, rules: Vec<ImportRuleManifest>) {
+        self.import_filter = Arc::new(rules.clone());
+        self.import_rules = Some(rules);
+    }
+
+    #[cfg(feature = "manifest")]
+    /// Records the file the component of the package `package_id` was read from, and the
+    /// identifier of its trampoline, so that they are included in `to_manifest`.
+    pub fn set_package_origin(
         &mut self,
-        name: String,
-        version: Version,
-        bytes: impl Into<Vec<u8>>,
-        trampoline: impl DynPackageTrampoline<D, C>,
-    ) -> Result<PackageId, AddPackageError> {
-        let package = Package::from_bytes(name.as_str(), Some(&version), bytes, &mut self.types)
-            .context(add_package_error::PackageParseSnafu)?;
+        package_id: PackageId,
+        path: Option<PathBuf>,
+        trampoline: Option<String>,
+    ) {
+        self.package_origins.insert(package_id, (path, trampoline));
+    }
 
-        let package_id = PackageId {
-            id: self.packages.insert(PackageWrapper {
-                package,
-                nonce: self.nonce,
-            }),
-            nonce: self.nonce,
-        };
-        self.nonce += 1;
+    #[cfg(feature = "manifest")]
+    /// Describes the packages of the graph, in the order they were added, and its import rules.
+    ///
+    /// Packages are described by their digest, and by the path and trampoline identifier recorded
+    /// with `set_package_origin`, if any. Import filters set with `set_import_filter` cannot be
+    /// described, so the manifest only has import rules set with `set_import_rules`.
+    #[must_use]
+    pub fn to_manifest(&self) -> CompositionManifest {
+        let packages = self
+            .packages()
+            .filter_map(|package_id| {
+                let (name, version, bytes) = match self.pending_packages.get(&package_id) {
+                    Some(pending) => (
+                        pending.name.clone(),
+                        pending.version.clone(),
+                        pending.bytes.as_slice(),
+                    ),
+                    None => {
+                        let package = self.package(package_id)?;
+                        (
+                            package.name().to_string(),
+                            package.version()?.clone(),
+                            package.bytes(),
+                        )
+                    }
+                };
+                let (path, trampoline) = self
+                    .package_origins
+                    .get(&package_id)
+                    .cloned()
+                    .unwrap_or_default();
 
-        let version_set = self.package_map.entry(name.to_string()).or_default();
+                Some(PackageManifest {
+                    name,
+                    version,
+                    path,
+                    digest: Some(Sha256Digest::of(bytes)),
+                    trampoline,
+                })
+            })
+            .collect();
 
-        if let Err((version, _)) = version_set.try_insert(version, package_id) {
-            return Err(AddPackageError::DuplicatePackage {
-                name: name.to_string(),
-                version: version.clone(),
-            });
+        CompositionManifest {
+            packages,
+            import_rules: self.import_rules.clone().unwrap_or_default(),
         }
+    }
 
-        let package = self.packages.get_mut(package_id.id).unwrap();
+    #[cfg(feature = "manifest")]
+    /// Builds a graph from the config file at `path`, a manifest in TOML or, with the `json`
+    /// feature, JSON format, picked by the file extension.
+    ///
+    /// The component files of the packages are read from their paths, relative to the directory
+    /// of the config file, and their trampolines are looked up in `trampolines`.
+    pub fn from_config_file(
+        path: impl AsRef<Path>,
+        trampolines: &TrampolineRegistry<D, C>,
+    ) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let manifest = read_config(path)?;
 
-        let package_prefix = format!("{}/", package.name());
-        let version_suffix = package.version().map_or(String::new(), |v| format!("@{v}"));
+        Self::from_manifest(&manifest, &config_resolver(path, trampolines))
+            .context(config_error::BuildSnafu { path })
+    }
 
-        let exports = &self.types[package.ty()].exports;
+    #[cfg(feature = "manifest")]
+    /// Builds a graph from `manifest`, reading the component bytes and trampolines of its
+    /// packages with `resolver`.
+    ///
+    /// Packages with a digest are verified against it, and packages without a trampoline are
+    /// added with a `NoopTrampoline`. The paths and trampoline identifiers of the packages are
+    /// recorded, so that `to_manifest` describes the graph like `manifest`.
+    pub fn from_manifest(
+        manifest: &CompositionManifest,
+        resolver: &impl ManifestResolver<D, C>,
+    ) -> Result<Self, ManifestError> {
+        let mut graph = Self::new();
+        graph.set_import_rules(manifest.import_rules.clone());
 
-        for (export_name, export_kind) in exports {
-            let ItemKind::Instance(interface_id) = export_kind else {
-                continue;
-            };
+        for package in &manifest.packages {
+            let bytes = resolver.package_bytes(package).with_context(|_err| {
+                manifest_error::ReadPackageSnafu {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                }
+            })?;
 
-            let interface_name = export_name
-                .strip_prefix(&package_prefix)
-                .and_then(|export_name| export_name.strip_suffix(&version_suffix));
+            let trampoline = match &package.trampoline {
+                Some(id) => {
+                    resolver
+                        .trampoline(id)
+                        .ok_or_else(|| ManifestError::UnknownTrampoline {
+                            name: package.name.clone(),
+                            version: package.version.clone(),
+                            trampoline: id.clone(),
+                        })?
+                }
+                None => Box::new(NoopTrampoline),
+            };
 
-            if let Some(interface_name) = interface_name {
-                let path = ForeignInterfacePath::new(
-                    package.name().to_string(),
-                    interface_name.to_string(),
-                    package.version().cloned(),
-                );
+            let verification = match package.digest {
+                Some(digest) => PackageVerification::new().with_digest(digest),
+                None => PackageVerification::new(),
+            };
 
-                let interface_trampoline = InterfaceExport {
-                    package: package_id,
-                    interface: *interface_id,
-                    trampoline: trampoline.interface_trampoline(interface_name),
-                };
+            let package_id = graph
+                .add_package_verified(
+                    package.name.clone(),
+                    package.version.clone(),
+                    bytes,
+                    &verification,
+                    trampoline,
+                )
+                .with_context(|_err| manifest_error::AddPackageSnafu {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                })?;
 
-                if self
-                    .exported_interfaces
-                    .insert(path.clone(), interface_trampoline)
-                    .is_some()
-                {
-                    // This would be a programming error, since the package name/version tuple is
-                    // guaranteed to be unique.
-                    panic!("duplicate exported interface key {path:?}");
-                }
-            }
+            graph.set_package_origin(package_id, package.path.clone(), package.trampoline.clone());
         }
 
-        let mut import = |package_id: PackageId, interface_id: InterfaceId, import_name: &str| {
-            let import_interface_path = InterfacePath::from_str(import_name).context(
-                add_package_error::ImportParseSnafu {
-                    interface: import_name.to_string(),
-                },
-            )?;
+        Ok(graph)
+    }
 
-            if let Some(import) = import_interface_path.into_foreign() {
-                match self.import_filter.filter_rule(&import) {
-                    ImportRule::Skip => return Ok(()),
+    /// Sets a handler that is called for each non-fatal warning raised by the graph, such as a
+    /// deprecated package version being selected for an import. Warnings are discarded when no
+    /// handler is set, so the handler should be set before adding packages to receive the
+    /// warnings raised by `add_package`.
+    pub fn set_warning_handler<F>(&mut self, handler: F)
+    where
+        F: Fn(&GraphWarning) + Send + Sync + 'static,
+    {
+        self.warning_handler = Some(Arc::new(handler));
+    }
 
-                    ImportRule::Include => {
-                        // If the interface defines no functions, skip it.
-                        let interface = &self.types[interface_id];
-                        let interface_has_func = interface
-                            .exports
-                            .iter()
-                            .any(|(_item_name, item_kind)| matches!(item_kind, ItemKind::Func(_)));
-                        if !interface_has_func {
-                            return Ok(());
-                        }
-                    }
+    /// Sets a hook that chooses between multiple packages added with the same name and version.
+    ///
+    /// Without a selector, adding a package whose name and version are already present fails with
+    /// `AddPackageError::DuplicatePackage`. Once a selector is set, all such packages are kept as
+    /// providers of that version. The selector receives the provider ids in the order they were
+    /// added, and is called at most once per package version during each instantiation, so every
+    /// importer is linked against the same provider. Returning `None`, or an id that is not one of
+    /// the providers, fails with `LoadPackageError::AmbiguousPackageProvider`.
+    pub fn set_provider_selector<F>(&mut self, selector: F)
+    where
+        F: Fn(&str, &Version, &[PackageId]) -> Option<PackageId> + Send + Sync + 'static,
+    {
+        self.provider_selector = Some(Arc::new(selector));
+    }
 
-                    ImportRule::Force => { /* continue */ }
-                }
+    /// Sets a hook that checks the name, version and component bytes of every package before it
+    /// is parsed, refusing the package with `AddPackageError::VerificationFailed` if it fails.
+    ///
+    /// Packages already in the graph are not verified again.
+    pub fn set_package_verifier(&mut self, verifier: impl PackageVerifier + 'static) {
+        self.package_verifier = Some(Arc::new(verifier));
+    }
 
-                // Add the interface to the list of imports.
-                self.imported_interfaces
-                    .entry(package_id)
-                    .or_default()
-                    .insert(import);
-            }
+    /// Checks the package `name` at `version` against the package policy and with the package
+    /// verifier, if any.
+    fn verify_package(
+        &self,
+        name: &str,
+        version: &Version,
+        bytes: &[u8],
+    ) -> Result<(), AddPackageError> {
+        verify_package(
+            self.package_policy.as_ref(),
+            self.package_verifier.as_ref(),
+            name,
+            version,
+            bytes,
+        )
+    }
 
-            Ok(())
-        };
+    /// Marks an added package version as deprecated. Deprecated packages are still used to
+    /// resolve imports, but a `GraphWarning::DeprecatedPackage` is raised when one is selected.
+    ///
+    /// Returns `false` if no package with the exact name and version has been added.
+    pub fn deprecate_package(
+        &mut self,
+        name: &str,
+        version: &Version,
+        message: impl Into<String>,
+    ) -> bool {
+        self.package_map
+            .get_mut(name)
+            .is_some_and(|version_map| version_map.deprecate(version, message))
+    }
 
-        for (package_id, package) in &self.packages {
-            let package_id = PackageId {
-                id: package_id,
-                nonce: package.nonce,
-            };
-            let package_ty = &self.types[package.ty()];
+    /// Returns the strategy used to resolve imported package versions to added packages.
+    #[must_use]
+    pub fn version_strategy(&self) -> AlternateStrategy {
+        self.version_strategy
+    }
 
-            for (import_name, import_kind) in &package_ty.imports {
-                let ItemKind::Instance(interface_id) = import_kind else {
-                    continue;
-                };
+    /// Sets the strategy used to resolve imported package versions to added packages.
+    ///
+    /// Defaults to `AlternateStrategy::CaretCompatible`, matching the `wasmtime` component linker.
+    /// Applies to all packages, including those already added.
+    pub fn set_version_strategy(&mut self, strategy: AlternateStrategy) {
+        self.version_strategy = strategy;
 
-                import(package_id, *interface_id, import_name)?;
-            }
+        for version_map in self.package_map.values_mut() {
+            version_map.set_strategy(strategy);
         }
+    }
 
-        Ok(package_id)
+    /// Returns the policy deciding whether imports can resolve to pre-release package versions.
+    #[must_use]
+    pub fn pre_release_policy(&self) -> PreReleasePolicy {
+        self.pre_release_policy
     }
 
-    /// Instantiates a component from the composition graph, resolving all component dependencies.
+    /// Sets the policy deciding whether imports can resolve to pre-release package versions.
     ///
-    /// Host functions and other resources can be provided through the `linker` argument prior to
-    /// instantiation.
-    pub fn instantiate(
-        &mut self,
-        package_id: PackageId,
-        linker: &mut component::Linker<D>,
-        mut store: impl AsContextMut<Data = D>,
-        engine: &wasmtime::Engine,
-    ) -> Result<Instance, InstantiateError>
-    where
-        D: 'static,
-        C: Send + Sync + 'static,
-    {
-        let mut interfaces = IndexMap::<PackageId, IndexSet<String>>::new();
+    /// Defaults to `PreReleasePolicy::Exact`, matching the `wasmtime` component linker, where
+    /// pre-release versions are only ever matched exactly. Applies to all packages, including
+    /// those already added.
+    pub fn set_pre_release_policy(&mut self, policy: PreReleasePolicy) {
+        self.pre_release_policy = policy;
 
-        let load_order = self
-            .package_load_order(package_id, &mut interfaces)
-            .context(instantiate_error::LoadPackageSnafu)?;
+        for version_map in self.package_map.values_mut() {
+            version_map.set_pre_release_policy(policy);
+        }
+    }
 
-        let package = self
-            .packages
-            .get(package_id.id)
-            .ok_or(InstantiateError::PackageNotFound { id: package_id })?;
+    /// Returns the policy deciding how the build metadata of imported versions is matched.
+    #[must_use]
+    pub fn build_metadata_policy(&self) -> BuildMetadataPolicy {
+        self.build_metadata_policy
+    }
 
-        let component = Component::new(engine, package.bytes())
-            .context(instantiate_error::ComponentInstantiationSnafu)?;
+    /// Sets the policy deciding how the build metadata of imported versions is matched, so that
+    /// packages distinguished only by `+build` metadata can be added side by side.
+    ///
+    /// Defaults to `BuildMetadataPolicy::Exact`. Applies to all packages, including those already
+    /// added.
+    pub fn set_build_metadata_policy(&mut self, policy: BuildMetadataPolicy) {
+        self.build_metadata_policy = policy;
 
-        for shadow_package_id in load_order {
-            if shadow_package_id == package_id {
-                break;
-            }
+        for version_map in self.package_map.values_mut() {
+            version_map.set_build_metadata_policy(policy);
+        }
+    }
 
-            let shadow_package = self.packages.get(shadow_package_id.id).ok_or(
-                InstantiateError::PackageNotFound {
-                    id: shadow_package_id,
-                },
-            )?;
+    /// Returns the mode used to select among the package versions that can satisfy an import.
+    #[must_use]
+    pub fn resolution_mode(&self) -> ResolutionMode {
+        self.resolution_mode
+    }
 
-            let empty_set = IndexSet::new();
-            let shadow_interfaces = interfaces.get(&shadow_package_id).unwrap_or(&empty_set);
+    /// Sets the mode used to select among the package versions that can satisfy an import.
+    ///
+    /// `ResolutionMode::Minimal` selects the oldest package version that is at least the imported
+    /// version, which verifies that components declare accurate import versions.
+    /// Applies to all packages, including those already added.
+    pub fn set_resolution_mode(&mut self, mode: ResolutionMode) {
+        self.resolution_mode = mode;
 
-            self.instantiate_shadowed_package(
-                shadow_package,
-                linker,
-                &mut store,
-                engine,
-                shadow_interfaces,
-            )
-            .with_context(|_err| {
-                instantiate_error::InstantiatePackageDependencySnafu {
-                    name: shadow_package.name().to_string(),
-                    version: shadow_package.version().cloned(),
-                }
-            })?;
+        for version_map in self.package_map.values_mut() {
+            version_map.set_resolution_mode(mode);
         }
+    }
 
-        let instance = linker
-            .instantiate(&mut store, &component)
-            .context(instantiate_error::ComponentInstantiationSnafu)?;
+    /// Returns the function used to rank the package versions that can satisfy an import, if any.
+    #[must_use]
+    pub fn version_priority(&self) -> Option<VersionPriority> {
+        self.version_priority
+    }
 
-        Ok(instance)
+    /// Sets or clears the function used to rank the package versions that can satisfy an import.
+    ///
+    /// Versions with a higher priority are selected first, and the resolution mode decides between
+    /// versions of equal priority. Applies to all packages, including those already added.
+    pub fn set_version_priority(&mut self, priority: Option<VersionPriority>) {
+        self.version_priority = priority;
+
+        for version_map in self.package_map.values_mut() {
+            version_map.set_priority(priority);
+        }
     }
 
-    /// Like `instantiate`, but for asynchronous contexts.
-    pub async fn instantiate_async(
-        &mut self,
-        package_id: PackageId,
-        linker: &mut component::Linker<D>,
-        mut store: impl AsContextMut<Data = D>,
-        engine: &wasmtime::Engine,
-    ) -> Result<Instance, InstantiateError>
-    where
-        D: Send + 'static,
-        C: Send + Sync + 'static,
-    {
-        let mut interfaces = IndexMap::<PackageId, IndexSet<String>>::new();
+    /// Returns how violations of the graph's internal invariants are handled.
+    #[must_use]
+    pub fn invariant_policy(&self) -> InvariantPolicy {
+        self.invariant_policy
+    }
 
-        let load_order = self
-            .package_load_order(package_id, &mut interfaces)
-            .context(instantiate_error::LoadPackageSnafu)?;
+    /// Sets how violations of the graph's internal invariants are handled. Hosts that must never
+    /// abort can use `InvariantPolicy::Error` to receive an `InternalError` instead of a panic.
+    pub fn set_invariant_policy(&mut self, policy: InvariantPolicy) {
+        self.invariant_policy = policy;
+    }
 
-        let package = self
+    /// Removes the cached compilations of a package's component, for all engines.
+    ///
+    /// `instantiate` caches the compiled components of the instantiated package and its
+    /// dependencies by their content and engine, so the cache only needs to be invalidated to free
+    /// memory.
+    pub fn invalidate_compiled_component(&mut self, package_id: PackageId) {
+        if let Some(package) = self
             .packages
             .get(package_id.id)
-            .ok_or(InstantiateError::PackageNotFound { id: package_id })?;
-
-        let component = Component::new(engine, package.bytes())
-            .context(instantiate_error::ComponentInstantiationSnafu)?;
+            .and_then(|package| package.package.as_ref())
+        {
+            self.component_cache.invalidate(package.bytes());
+        }
+    }
 
-        for shadow_package_id in load_order {
-            if shadow_package_id == package_id {
-                break;
-            }
+    /// Removes all cached component compilations.
+    ///
+    /// Compilations persisted in the component cache directory are kept.
+    pub fn clear_compiled_components(&mut self) {
+        self.component_cache.clear();
+    }
 
-            let shadow_package = self.packages.get(shadow_package_id.id).ok_or(
-                InstantiateError::PackageNotFound {
-                    id: shadow_package_id,
-                },
-            )?;
+    /// The directory compiled components are persisted in, if any.
+    pub fn component_cache_dir(&self) -> Option<&Path> {
+        self.component_cache.disk_dir()
+    }
 
-            let empty_set = IndexSet::new();
-            let shadow_interfaces = interfaces.get(&shadow_package_id).unwrap_or(&empty_set);
+    /// Sets the directory compiled components are persisted in, so that restarted processes load
+    /// unchanged components instead of recompiling them. `None` disables persistence.
+    ///
+    /// Components are stored by the hash of their content and the engine's compatibility hash, and
+    /// the directory must exist. Failures to read or write the directory fall back to compiling.
+    /// Entries are never removed by the graph, including by `invalidate_compiled_component` and
+    /// `clear_compiled_components`.
+    ///
+    /// Alternatively, hosts can enable wasmtime's own compilation cache in the `Config` of their
+    /// engine.
+    ///
+    /// # Safety
+    ///
+    /// Files in the directory are loaded as native code without validation, see
+    /// `wasmtime::component::Component::deserialize`. The directory must only be writable by
+    /// trusted processes.
+    pub unsafe fn set_component_cache_dir(&mut self, dir: Option<PathBuf>) {
+        // SAFETY: Forwarded to the caller.
+        unsafe { self.component_cache.set_disk_dir(dir) };
+    }
 
-            self.instantiate_shadowed_package_async(
-                shadow_package,
-                linker,
-                &mut store,
-                engine,
-                shadow_interfaces,
-            )
-            .await
-            .with_context(|_err| {
-                instantiate_error::InstantiatePackageDependencySnafu {
-                    name: shadow_package.name().to_string(),
-                    version: shadow_package.version().cloned(),
-                }
-            })?;
-        }
+    #[cfg(feature = "metrics")]
+    /// Records the calls to shadowed functions in `metrics`, or stops recording them with `None`.
+    ///
+    /// Only affects packages instantiated after the metrics are set.
+    pub fn set_call_metrics(&mut self, metrics: Option<CallMetrics>) {
+        self.call_metrics = metrics;
+    }
 
-        let instance = linker
-            .instantiate_async(&mut store, &component)
-            .await
-            .context(instantiate_error::ComponentInstantiationSnafu)?;
+    #[cfg(feature = "metrics")]
+    /// The metrics calls to shadowed functions are recorded in, if any.
+    #[must_use]
+    pub fn call_metrics(&self) -> Option<&CallMetrics> {
+        self.call_metrics.as_ref()
+    }
 
-        Ok(instance)
+    #[cfg(feature = "recording")]
+    /// Attributes the time spent in calls to shadowed functions in `profiler`, or stops
+    /// attributing it with `None`.
+    ///
+    /// Only affects packages instantiated after the profiler is set.
+    pub fn set_call_profiler(&mut self, profiler: Option<CallProfiler>) {
+        self.call_profiler = profiler;
     }
 
-    /// Gets a reference to the type collection of the graph.
+    #[cfg(feature = "recording")]
+    /// The profiler the time spent in calls to shadowed functions is attributed in, if any.
     #[must_use]
-    pub fn types(&self) -> &wac_types::Types {
-        &self.types
+    pub fn call_profiler(&self) -> Option<&CallProfiler> {
+        self.call_profiler.as_ref()
     }
 
-    /// Gets a mutable reference to the type collection of the graph.
+    /// Tracks the calls to shadowed functions that are executing in `in_flight`, or stops
+    /// tracking them with `None`.
     ///
-    /// This type collection is used to define types directly in the graph.
-    pub fn types_mut(&mut self) -> &mut wac_types::Types {
-        &mut self.types
+    /// Only affects packages instantiated after the tracker is set.
+    pub fn set_in_flight_calls(&mut self, in_flight: Option<InFlightCalls>) {
+        self.in_flight_calls = in_flight;
     }
 
-    fn package_load_order(
-        &self,
-        origin: PackageId,
-        interfaces: &mut IndexMap<PackageId, IndexSet<String>>,
-    ) -> Result<impl IntoIterator<Item = PackageId> + 'static, LoadPackageError> {
-        let mut package_stack = vec![(origin, 0)];
+    /// The tracker of the calls to shadowed functions that are executing, if any.
+    #[must_use]
+    pub fn in_flight_calls(&self) -> Option<&InFlightCalls> {
+        self.in_flight_calls.as_ref()
+    }
 
-        let mut load_order = IndexSet::<PackageId>::new();
-        let mut load_stack = IndexSet::<PackageId>::new();
+    #[cfg(feature = "recording")]
+    /// Records a timeline of the calls to shadowed functions in `trace`, or stops recording it
+    /// with `None`.
+    ///
+    /// Only affects packages instantiated after the trace is set.
+    pub fn set_call_trace(&mut self, trace: Option<CallTrace>) {
+        self.call_trace = trace;
+    }
 
-        while let Some((package_id, offset)) = package_stack.pop() {
-            load_order.extend(load_stack.drain(offset..).rev());
+    #[cfg(feature = "recording")]
+    /// The trace the timeline of calls to shadowed functions is recorded in, if any.
+    #[must_use]
+    pub fn call_trace(&self) -> Option<&CallTrace> {
+        self.call_trace.as_ref()
+    }
 
-            if let Some(cycle_start) = load_stack.get_index_of(&package_id) {
-                let self_import = (cycle_start == load_stack.len() - 1)
-                    && load_stack.index(cycle_start) == &package_id;
+    /// Checks the calls to shadowed functions against the latency thresholds of `detector`, or
+    /// stops checking them with `None`.
+    ///
+    /// Only affects packages instantiated after the detector is set.
+    pub fn set_slow_call_detector(&mut self, detector: Option<SlowCallDetector>) {
+        self.slow_call_detector = detector;
+    }
 
-                if self_import {
-                    continue;
-                }
+    /// The detector calls to shadowed functions are checked with, if any.
+    #[must_use]
+    pub fn slow_call_detector(&self) -> Option<&SlowCallDetector> {
+        self.slow_call_detector.as_ref()
+    }
 
-                let mut cycle = load_stack
-                    .iter()
-                    .skip(cycle_start)
-                    .copied()
-                    .collect::<Vec<_>>();
+    /// Tracks the error rates of the calls to shadowed interfaces in `monitor`, or stops tracking
+    /// them with `None`.
+    ///
+    /// Only affects packages instantiated after the monitor is set.
+    pub fn set_error_rate_monitor(&mut self, monitor: Option<ErrorRateMonitor>) {
+        self.error_rate_monitor = monitor;
+    }
+
+    /// The monitor the error rates of calls to shadowed interfaces are tracked in, if any.
+    #[must_use]
+    pub fn error_rate_monitor(&self) -> Option<&ErrorRateMonitor> {
+        self.error_rate_monitor.as_ref()
+    }
+
+    /// Enforces `policy` on the graph, or stops enforcing a policy with `None`.
+    ///
+    /// Imports through edges the policy does not allow fail to resolve with `DeniedByPolicy`,
+    /// calls to shadowed functions exceeding its rate limits fail with a `PolicyViolation`, and
+    /// calls exceeding their latency budget are logged as warnings. Calls exceeding their timeout
+    /// are interrupted in the stores enforcing timeouts, see `enforce_timeouts`. Rate limits,
+    /// latency budgets and timeouts only affect packages instantiated after the policy is set.
+    pub fn set_policy(&mut self, policy: Option<Policy>) {
+        self.policy = policy;
+    }
+
+    /// The policy enforced on the graph, if any.
+    #[must_use]
+    pub fn policy(&self) -> Option<&Policy> {
+        self.policy.as_ref()
+    }
+
+    /// Denies the packages matching the rules of `policy`, or stops denying packages with `None`.
+    ///
+    /// Adding a denied package fails with `AddPackageError::DeniedByPackagePolicy`, and imports
+    /// resolving to a denied package, including one added before the policy was set, fail with
+    /// `LoadPackageError::DeniedByPackagePolicy`. Both name the rule that denied the package.
+    pub fn set_package_policy(&mut self, policy: Option<PackagePolicy>) {
+        self.package_policy = policy;
+    }
+
+    /// The package policy enforced on the graph, if any.
+    #[must_use]
+    pub fn package_policy(&self) -> Option<&PackagePolicy> {
+        self.package_policy.as_ref()
+    }
+
+    /// Whether dependency packages are instantiated on the first call into their interfaces,
+    /// rather than before the package importing them.
+    #[must_use]
+    pub fn lazy_instantiation(&self) -> bool {
+        self.lazy_instantiation
+    }
+
+    /// Defers instantiating dependency packages until the first call into any of their shadowed
+    /// interfaces, so that rarely called dependencies of large graphs cost no startup time or
+    /// memory until they are used.
+    ///
+    /// Only synchronous instantiations defer dependencies. Dependencies exporting resources
+    /// through their shadowed interfaces, and dependencies reused from `ShadowInstances`, are
+    /// still instantiated up front. Lazily instantiated dependencies are not recorded in
+    /// `ShadowInstances`, and their instantiation failures are returned from the first call into
+    /// them as guest errors.
+    pub fn set_lazy_instantiation(&mut self, lazy: bool) {
+        self.lazy_instantiation = lazy;
+    }
+
+    /// How the functions of imports that no package provides behave, if they are stubbed.
+    #[must_use]
+    pub fn import_stubs(&self) -> Option<ImportStub> {
+        self.import_stub
+    }
+
+    /// Stubs the imports that no package of the graph provides, for missing packages or
+    /// versions, instead of failing to instantiate the packages importing them, or stops stubbing
+    /// them with `None`. This lets partially assembled graphs instantiate, e.g. for testing.
+    ///
+    /// The stubbed functions are defined in the linker when instantiating, and behave as `stub`
+    /// describes. `validate` does not report imports that are stubbed.
+    pub fn set_import_stubs(&mut self, stub: Option<ImportStub>) {
+        self.import_stub = stub;
+    }
+
+    #[cfg(feature = "deterministic")]
+    /// Provides packages with deterministic clocks and randomness instead of ambient WASI
+    /// capabilities, read from the `DeterministicState` found in each store's data with `state`,
+    /// or stops providing them with `None`.
+    ///
+    /// Imports of the `DETERMINISTIC_INTERFACES` are skipped as if by a
+    /// `DeterministicImportFilter`, and the shims of `add_deterministic_shims_to_linker` are
+    /// defined in the linker when instantiating, so the linker must allow shadowing if it defines
+    /// them already. Set the state before adding packages, since imports are filtered as packages
+    /// are added.
+    pub fn set_deterministic_state(
+        &mut self,
+        state: Option<fn(&mut D) -> &mut DeterministicState>,
+    ) {
+        self.deterministic_state = state;
+    }
+
+    #[cfg(feature = "deterministic")]
+    /// Returns how the `DeterministicState` of a store is found in its data, if set.
+    #[must_use]
+    pub fn deterministic_state(&self) -> Option<fn(&mut D) -> &mut DeterministicState> {
+        self.deterministic_state
+    }
+
+    /// Returns the import filter, skipping the interfaces provided by the deterministic shims if
+    /// they are enabled.
+    fn effective_import_filter(&self) -> Arc<dyn ImportFilter + Send + Sync> {
+        #[cfg(feature = "deterministic")]
+        if self.deterministic_state.is_some() {
+            return Arc::new(DeterministicImportFilter::new(self.import_filter.clone()));
+        }
+
+        self.import_filter.clone()
+    }
+
+    /// Attributes the growth of linear memories to packages and their shadowed functions in
+    /// `tracker`, or stops attributing it with `None`. Growth is only observed if the store's
+    /// resource limiter is a `MemoryLimiter` of `tracker`, and only attributed if the graph finds
+    /// the store's scopes, see `set_store_scopes`.
+    ///
+    /// Only affects packages instantiated after the tracker is set.
+    pub fn set_memory_tracker(&mut self, tracker: Option<MemoryTracker>) {
+        self.memory_tracker = tracker;
+    }
+
+    /// The tracker memory growth is attributed in, if any.
+    #[must_use]
+    pub fn memory_tracker(&self) -> Option<&MemoryTracker> {
+        self.memory_tracker.as_ref()
+    }
+
+    /// Returns a receiver of the events raised by the graph from now on, such as calls to shadowed
+    /// functions and package instantiations.
+    ///
+    /// Events are delivered to all receivers and are buffered until received, so receivers
+    /// should be drained or dropped. Dropped receivers are unsubscribed when the next event is
+    /// raised.
+    pub fn subscribe_events(&self) -> Receiver<GraphEvent> {
+        self.events.subscribe()
+    }
+
+    /// Compiles the components of all packages in the graph for `engine` ahead of instantiation,
+    /// parsing lazily added packages first. The components are cached like those compiled when
+    /// instantiating, so hosts can move compilation to a warm-up phase and keep the latency of
+    /// the first instantiations predictable.
+    ///
+    /// Returns the packages that failed to parse or compile, which fail the same way when
+    /// instantiated.
+    pub fn precompile_all(
+        &mut self,
+        engine: &wasmtime::Engine,
+    ) -> Vec<(PackageId, InstantiatePackageError)> {
+        self.precompile_all_parallel(engine, NonZeroUsize::MIN)
+    }
+
+    /// Like `precompile_all`, but compiles up to `threads` packages in parallel.
+    pub fn precompile_all_parallel(
+        &mut self,
+        engine: &wasmtime::Engine,
+        threads: NonZeroUsize,
+    ) -> Vec<(PackageId, InstantiatePackageError)> {
+        let mut failures = self.parse_all_pending_packages();
+
+        let uncompiled = self
+            .packages
+            .iter()
+            .filter_map(|(id, wrapper)| {
+                let package = wrapper.package.as_ref()?;
+                let package_id = PackageId {
+                    id,
+                    nonce: wrapper.nonce,
+                };
+
+                self.component_cache
+                    .get(engine, package.bytes())
+                    .is_none()
+                    .then_some((package_id, package.bytes()))
+            })
+            .collect::<Vec<_>>();
+
+        let cache = &self.component_cache;
+        let compile = |(_, bytes): &(PackageId, &[u8])| cache.compile(engine, bytes);
+
+        let compiled = if threads.get() == 1 || uncompiled.len() <= 1 {
+            uncompiled.iter().map(compile).collect::<Vec<_>>()
+        } else {
+            let next = AtomicUsize::new(0);
 
-                cycle.push(package_id);
-
-                return Err(LoadPackageError::PackageCycle {
-                    cycle: cycle
-                        .into_iter()
-                        .map(|package| {
-                            self.packages
-                                .get(package.id)
-                                .map_or("{{UNKNOWN_PACKAGE}}".to_string(), |package| {
-                                    package.name().to_string()
-                                })
+            let mut compiled = std::thread::scope(|scope| {
+                let workers = (0..threads.get().min(uncompiled.len()))
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut compiled = Vec::new();
+
+                            while let Some(package) =
+                                uncompiled.get(next.fetch_add(1, Ordering::Relaxed))
+                            {
+                                compiled.push((package.0, compile(package)));
+                            }
+
+                            compiled
                         })
-                        .collect(),
-                });
+                    })
+                    .collect::<Vec<_>>();
+
+                workers
+                    .into_iter()
+                    .flat_map(|worker| worker.join().unwrap_or_else(|err| resume_unwind(err)))
+                    .collect::<Vec<_>>()
+            });
+
+            compiled.sort_unstable_by_key(|(package_id, _)| *package_id);
+            compiled.into_iter().map(|(_, compiled)| compiled).collect()
+        };
+
+        for ((package_id, bytes), compiled) in uncompiled.into_iter().zip(compiled) {
+            match compiled {
+                Ok(component) => self.component_cache.insert(engine, bytes, component),
+                Err(source) => failures.push((
+                    package_id,
+                    InstantiatePackageError::ComponentCompilationError { source },
+                )),
             }
+        }
 
-            if load_order.contains(&package_id) {
+        failures
+    }
+
+    /// Like `precompile_all`, but yields to the executor after compiling each package, so that
+    /// warming up does not block other tasks for its whole duration.
+    pub async fn precompile_all_async(
+        &mut self,
+        engine: &wasmtime::Engine,
+    ) -> Vec<(PackageId, InstantiatePackageError)> {
+        let mut failures = self.parse_all_pending_packages();
+
+        for (id, wrapper) in &self.packages {
+            let Some(package) = &wrapper.package else {
                 continue;
+            };
+            let package_id = PackageId {
+                id,
+                nonce: wrapper.nonce,
+            };
+
+            if let Err(source) = self.component_cache.get_or_compile(engine, package.bytes()) {
+                failures.push((
+                    package_id,
+                    InstantiatePackageError::ComponentCompilationError { source },
+                ));
             }
 
-            load_stack.insert(package_id);
+            yield_now().await;
+        }
 
-            let imports = self
-                .imported_interfaces
-                .get(&package_id)
-                .map(IndexSet::as_slice)
-                .unwrap_or_default();
+        failures
+    }
 
-            for import in imports {
-                let version_map = self.package_map.get(import.package_name()).ok_or_else(|| {
-                    LoadPackageError::MissingPackageDependency {
-                        package_name: import.package_name().to_string(),
-                    }
-                })?;
+    /// Fetches the packages that the package `package_id` transitively depends on but that no
+    /// package of the graph provides from `resolver`, such as a warg registry client, and adds
+    /// them to the graph. Returns the added packages, in the order they were resolved.
+    ///
+    /// The dependencies of the added packages are resolved as well, until every missing package
+    /// is added or declined by the resolver. Declined packages are left missing, so instantiating
+    /// the package still fails with `MissingPackageDependency` unless its imports are stubbed.
+    /// Lazily added dependencies are prepared first.
+    pub async fn resolve_missing_async(
+        &mut self,
+        package_id: PackageId,
+        resolver: &impl PackageResolver<D, C>,
+    ) -> Result<Vec<PackageId>, ResolveError> {
+        let mut added = Vec::new();
+        let mut declined = HashSet::new();
 
-                let import_package =
-                    version_map.get_or_latest(import.version()).ok_or_else(|| {
-                        LoadPackageError::CannotResolvePackageVersion {
-                            name: import.package_name().to_string(),
+        loop {
+            self.prepare(package_id)
+                .context(resolve_error::DependenciesSnafu)?;
+            let report = self
+                .validate_package(package_id)
+                .context(resolve_error::DependenciesSnafu)?;
+
+            let mut missing = IndexMap::new();
+            for err in report.unresolved {
+                if let LoadPackageError::MissingPackageDependency {
+                    package_name,
+                    importer,
+                    import,
+                } = err
+                    && !declined.contains(&package_name)
+                {
+                    missing
+                        .entry(package_name.clone())
+                        .or_insert_with(|| MissingPackage {
+                            name: package_name,
                             version: import.version().cloned(),
-                        }
+                            importer,
+                            import: *import,
+                        });
+                }
+            }
+
+            if missing.is_empty() {
+                return Ok(added);
+            }
+
+            for (name, missing) in missing {
+                let Some(resolved) = resolver
+                    .resolve(&missing)
+                    .await
+                    .context(resolve_error::ResolverSnafu { name: name.clone() })?
+                else {
+                    log_debug!(package:% = name; "Resolver declined missing package");
+                    declined.insert(name);
+                    continue;
+                };
+
+                let version = resolved.version;
+                let id = self
+                    .add_package(
+                        name.clone(),
+                        version.clone(),
+                        resolved.bytes,
+                        resolved.trampoline,
+                    )
+                    .context(resolve_error::AddPackageSnafu {
+                        name: name.clone(),
+                        version: version.clone(),
                     })?;
 
-                package_stack.push((*import_package, load_stack.len()));
+                log_debug!(id:? = id, package:% = name, version:% = version; "Resolved missing package");
+                added.push(id);
+            }
+        }
+    }
+
+    /// Like `instantiate_async`, but when a dependency of the package is missing, first adds
+    /// the missing packages fetched by `resolver` with `resolve_missing_async`.
+    ///
+    /// Packages the resolver declines are left missing, so instantiation then fails with
+    /// `MissingPackageDependency` unless their imports are stubbed.
+    pub async fn instantiate_resolving_async(
+        &mut self,
+        package_id: PackageId,
+        resolver: &impl PackageResolver<D, C>,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Instance, ResolveError>
+    where
+        D: Send + 'static,
+        C: Send + Sync + 'static,
+    {
+        let missing = matches!(
+            self.plan(package_id),
+            Err(InstantiateError::LoadPackageError {
+                source: LoadPackageError::MissingPackageDependency { .. }
+            } | InstantiateError::UnpreparedPackage { .. })
+        );
+        if missing {
+            self.resolve_missing_async(package_id, resolver).await?;
+        }
+
+        self.instantiate_async(package_id, linker, store, engine)
+            .await
+            .context(resolve_error::InstantiateSnafu)
+    }
+
+    /// Parses all lazily added packages, returning those that failed to parse.
+    fn parse_all_pending_packages(&mut self) -> Vec<(PackageId, InstantiatePackageError)> {
+        let mut pending = self.pending_packages.keys().copied().collect::<Vec<_>>();
+        pending.sort_unstable();
+
+        pending
+            .into_iter()
+            .filter_map(|package_id| {
+                self.parse_pending_package(package_id)
+                    .context(instantiate_package_error::LazyPackageSnafu)
+                    .err()
+                    .map(|err| (package_id, err))
+            })
+            .collect()
+    }
+
+    /// Adds a package (component) to the composition graph.
+    ///
+    /// Components can be added in any order, and dependencies will be resolved at instantiation time.
+    ///
+    /// Versioned interfaces of other packages exported by the component, such as a standard
+    /// interface implemented by an adapter, are registered as well, so the package also provides
+    /// that package at the exported version.
+    pub fn add_package(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        if self.contains_package(&name, &version) && self.provider_selector.is_none() {
+            return Err(AddPackageError::DuplicatePackage { name, version });
+        }
+
+        let bytes = bytes.into();
+        self.verify_package(&name, &version, &bytes)?;
+
+        self.add_verified_package(name, version, bytes, trampoline)
+    }
+
+    /// Returns a builder preparing packages to be added to the graph on multiple threads,
+    /// checking them against the package policy and verifier the graph has now.
+    #[must_use]
+    pub fn builder(&self) -> GraphBuilder<D, C> {
+        GraphBuilder::new(self.package_policy.clone(), self.package_verifier.clone())
+    }
+
+    /// Like `add_package`, but for a package already checked against the package policy and
+    /// verifier.
+    pub(crate) fn add_verified_package(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: Vec<u8>,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        if self.contains_package(&name, &version) && self.provider_selector.is_none() {
+            return Err(AddPackageError::DuplicatePackage { name, version });
+        }
+
+        let package = Package::from_bytes(name.as_str(), Some(&version), bytes, &mut self.types)
+            .context(add_package_error::PackageParseSnafu)?;
+
+        let package_id = self.insert_package(name, version, Some(package));
+        self.register_package(package_id, &trampoline)?;
+
+        Ok(package_id)
+    }
+
+    /// Like `add_package`, but attaches `metadata` to the package, which can be queried with
+    /// `package_metadata` and is available to trampolines during calls to the package.
+    pub fn add_package_with_metadata(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        trampoline: impl DynPackageTrampoline<D, C>,
+        metadata: PackageMetadata,
+    ) -> Result<PackageId, AddPackageError> {
+        let package_id = self.add_package(name, version, bytes, trampoline)?;
+        self.package_metadata.insert(package_id, Arc::new(metadata));
+
+        Ok(package_id)
+    }
+
+    /// Returns the metadata attached to the package `package_id` with
+    /// `add_package_with_metadata`, if any.
+    #[must_use]
+    pub fn package_metadata(&self, package_id: PackageId) -> Option<&PackageMetadata> {
+        self.package_metadata.get(&package_id).map(Arc::as_ref)
+    }
+
+    /// Sets the memory and table limits of the package `package_id`, or removes them with `None`.
+    ///
+    /// The limits are enforced while the package is instantiated and during calls to its shadowed
+    /// functions, if the store's resource limiter is a `PackageLimiter` and the graph finds the
+    /// store's scopes, see `set_store_scopes`. Instances created before the limits are set keep
+    /// the limits of their instantiation for calls.
+    pub fn set_package_limits(&mut self, package_id: PackageId, limits: Option<PackageLimits>) {
+        match limits {
+            Some(limits) => {
+                self.package_limits.insert(package_id, Arc::new(limits));
+            }
+            None => {
+                self.package_limits.remove(&package_id);
+            }
+        }
+    }
+
+    /// Returns the limits of the package `package_id` set with `set_package_limits`, if any.
+    #[must_use]
+    pub fn package_limits(&self, package_id: PackageId) -> Option<&PackageLimits> {
+        self.package_limits.get(&package_id).map(Arc::as_ref)
+    }
+
+    /// Finds the `StoreScopes` of each store in its data with `scopes`, or stops tracking scopes
+    /// with `None`.
+    ///
+    /// The graph enters the package being instantiated or called in the scopes of the store doing
+    /// so, which is how a `PackageLimiter` knows which limits to enforce, a `MemoryLimiter` which
+    /// package to attribute growth to, and `InFlightCalls` which call made another. Package limits
+    /// are not enforced, memory growth is not attributed and callers are not identified without
+    /// store scopes.
+    ///
+    /// Only affects packages instantiated after the scopes are set.
+    pub fn set_store_scopes(&mut self, scopes: Option<fn(&D) -> &StoreScopes>) {
+        self.store_scopes = scopes;
+    }
+
+    /// Returns how the `StoreScopes` of a store are found in its data, if set.
+    #[must_use]
+    pub fn store_scopes(&self) -> Option<fn(&D) -> &StoreScopes> {
+        self.store_scopes
+    }
+
+    /// Interrupts the calls in `store` running past the timeouts of the graph's policy, checking
+    /// them every `ticks` epochs.
+    ///
+    /// Timeouts are checked on epoch deadlines, so the engine must be configured with
+    /// `Config::epoch_interruption` and its epoch incremented periodically with
+    /// `Engine::increment_epoch`. Interrupted calls fail with a `wasmtime::Trap::Interrupt`,
+    /// carrying the `PolicyViolation::DeadlineExceeded` of the outermost call past its timeout.
+    /// This replaces the epoch deadline callback of the store, and requires store scopes, see
+    /// `set_store_scopes`.
+    pub fn enforce_timeouts(&self, store: &mut wasmtime::Store<D>, ticks: u64)
+    where
+        D: 'static,
+    {
+        let Some(scopes) = self.store_scopes else {
+            log_warn!("Timeouts are not enforced without store scopes, see `set_store_scopes`");
+            return;
+        };
+
+        store.set_epoch_deadline(ticks);
+        store.epoch_deadline_callback(move |store| {
+            match scopes(store.data())
+                .deadlines
+                .find_map(|deadline| deadline.exceeded())
+            {
+                Some(violation) => Err(anyhow::Error::from(Trap::Interrupt).context(violation)),
+                None => Ok(wasmtime::UpdateDeadline::Continue(ticks)),
+            }
+        });
+    }
+
+    /// Like `add_package`, but refuses the package with `AddPackageError::VerificationFailed`
+    /// unless its bytes pass `verification`, such as matching an expected digest or embedding a
+    /// valid signature. The source of the error is the `VerificationError`.
+    pub fn add_package_verified(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        verification: &PackageVerification,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        let bytes = bytes.into();
+        verification
+            .verify_package(&name, &version, &bytes)
+            .context(add_package_error::VerificationFailedSnafu {
+                name: &name,
+                version: version.clone(),
+            })?;
+
+        self.add_package(name, version, bytes, trampoline)
+    }
+
+    /// Like `add_package_verified`, but downloads the package bytes from `url` with `client`,
+    /// following redirects, and refuses them unless they have the digest `expected_sha256`.
+    /// `HttpsClient::new()` is a client for both `https://` and `http://` URLs.
+    ///
+    /// Download failures are returned as `FetchError`, and digest mismatches as
+    /// `VerificationFailed` with a `VerificationError::DigestMismatch` source.
+    #[cfg(feature = "http")]
+    pub fn add_package_from_url(
+        &mut self,
+        name: String,
+        version: Version,
+        client: impl HttpClient,
+        url: &str,
+        expected_sha256: Sha256Digest,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        let bytes =
+            download(&client, url).context(add_package_error::FetchSnafu { reference: url })?;
+        let verification = PackageVerification::new().with_digest(expected_sha256);
+
+        self.add_package_verified(name, version, bytes, &verification, trampoline)
+    }
+
+    /// Like `add_package`, but instantiates the package from `precompiled`, the output of
+    /// `wasmtime::component::Component::serialize` for the component `bytes`, rather than
+    /// compiling the component.
+    ///
+    /// The precompiled component is deserialized when the package is instantiated or
+    /// precompiled, for engines compatible with the engine it was compiled for; other engines
+    /// compile `bytes` instead. The component bytes are still needed, since the imports and
+    /// exports of the package are read from them.
+    ///
+    /// # Safety
+    ///
+    /// `precompiled` is loaded as native code without validation, see
+    /// `wasmtime::component::Component::deserialize`. It must come from a trusted source, such as
+    /// the host's own build.
+    pub unsafe fn add_precompiled_package(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        precompiled: impl Into<Vec<u8>>,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        let package_id = self.add_package(name, version, bytes, trampoline)?;
+
+        let package = &self.packages[package_id.id];
+        // SAFETY: Forwarded to the caller.
+        unsafe {
+            self.component_cache
+                .insert_precompiled(package.bytes(), precompiled.into());
+        }
+
+        Ok(package_id)
+    }
+
+    /// Like `add_package`, but instantiates the package from `component`, already compiled from
+    /// `bytes` by the host, rather than compiling the component again.
+    ///
+    /// The component is cached for the engine it was compiled for, so hosts managing their own
+    /// compilation caches do not compile components twice. The component bytes are still needed,
+    /// since the imports and exports of the package are read from them. Other engines, and the
+    /// engine once `clear_compiled_components` is called, compile `bytes` instead.
+    pub fn add_compiled_package(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        component: &Component,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        let package_id = self.add_package(name, version, bytes, trampoline)?;
+
+        let package = &self.packages[package_id.id];
+        self.component_cache
+            .insert(component.engine(), package.bytes(), component.clone());
+
+        Ok(package_id)
+    }
+
+    #[cfg(feature = "preinit")]
+    /// Like `add_package`, but pre-initializes the package by running its init export
+    /// `init_export` once and snapshotting the initialized state into the package bytes, so
+    /// instances of the package start out initialized. See `preinitialize` for the requirements
+    /// on the package.
+    pub fn add_package_preinitialized(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: impl AsRef<[u8]>,
+        init_export: &str,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        let bytes = preinitialize(bytes.as_ref(), init_export)
+            .context(add_package_error::PreinitializeSnafu)?;
+
+        self.add_package(name, version, bytes, trampoline)
+    }
+
+    /// Replaces the component bytes and trampoline of the package `package_id`, keeping its id,
+    /// name and version, so that subsequent instantiations pick up the new component.
+    ///
+    /// The replacement is atomic: if the new component cannot be added, the package is left
+    /// unchanged. Instances of the old component keep running it, and the cached compilation of
+    /// the old component is invalidated. Replacing a lazily added package parses the new
+    /// component right away.
+    pub fn replace_package(
+        &mut self,
+        package_id: PackageId,
+        bytes: impl Into<Vec<u8>>,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<(), AddPackageError> {
+        let (name, version) =
+            match self.pending_packages.get(&package_id) {
+                Some(pending) => (pending.name.clone(), pending.version.clone()),
+                None => {
+                    let package = self
+                        .packages
+                        .get(package_id.id)
+                        .filter(|wrapper| wrapper.nonce == package_id.nonce)
+                        .and_then(|wrapper| wrapper.package.as_ref())
+                        .ok_or(AddPackageError::PackageNotFound { id: package_id })?;
+                    let version = package.version().cloned().ok_or_else(|| {
+                        AddPackageError::InternalError {
+                            message: self.invariant_violation(format!(
+                                "package {package_id:?} has no version"
+                            )),
+                        }
+                    })?;
+
+                    (package.name().to_string(), version)
+                }
+            };
+
+        let bytes = bytes.into();
+        self.verify_package(&name, &version, &bytes)?;
+
+        let package = Package::from_bytes(&name, Some(&version), bytes, &mut self.types)
+            .context(add_package_error::PackageParseSnafu)?;
+
+        let Some(wrapper) = self.packages.get_mut(package_id.id) else {
+            return Err(AddPackageError::InternalError {
+                message: self
+                    .invariant_violation(format!("replaced package {package_id:?} not found")),
+            });
+        };
+        let old_package = wrapper.package.replace(package);
+        let old_pending = self.pending_packages.remove(&package_id);
+
+        let exported = self
+            .exported_interfaces
+            .keys()
+            .filter(|(exporter, _)| *exporter == package_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        let old_exports = exported
+            .into_iter()
+            .filter_map(|key| self.exported_interfaces.remove_entry(&key))
+            .collect::<Vec<_>>();
+        let old_imports = self.imported_interfaces.remove(&package_id);
+        let old_functions = self.imported_functions.remove(&package_id);
+        self.remove_foreign_providers(package_id, &name);
+
+        if let Err(err) = self.register_package(package_id, &trampoline) {
+            // Restore the old package, dropping whatever the new one registered.
+            self.remove_foreign_providers(package_id, &name);
+            for ((_, path), _) in &old_exports {
+                if path.package_name() != name
+                    && let Some(version) = path.version()
+                {
+                    self.insert_provider(
+                        path.package_name().to_string(),
+                        version.clone(),
+                        package_id,
+                    );
+                }
+            }
+            self.exported_interfaces
+                .retain(|(exporter, _), _| *exporter != package_id);
+            self.exported_interfaces.extend(old_exports);
+            self.imported_interfaces.remove(&package_id);
+            self.imported_interfaces
+                .extend(old_imports.map(|imports| (package_id, imports)));
+            self.imported_functions.remove(&package_id);
+            self.imported_functions
+                .extend(old_functions.map(|functions| (package_id, functions)));
+            if let Some(pending) = old_pending {
+                self.pending_packages.insert(package_id, pending);
+            }
+            if let Some(wrapper) = self.packages.get_mut(package_id.id) {
+                wrapper.package = old_package;
+            }
+
+            return Err(err);
+        }
+
+        if let Some(old_package) = &old_package {
+            self.component_cache.invalidate(old_package.bytes());
+        }
+
+        log_debug!(
+            id:? = package_id, package:% = self.label_of(package_id);
+            "Replaced package"
+        );
+
+        self.events.emit(|| GraphEvent::PackageReplaced {
+            package: package_id,
+            name,
+            version,
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    /// Like `add_package`, but streams the package bytes from `reader`, validating the component
+    /// as it is read, so that invalid components fail as soon as the invalid section arrives.
+    ///
+    /// The bytes are read directly into the buffer kept by the graph, so the component is held in
+    /// memory only once. The graph keeps the whole component to compile it for instantiation, so
+    /// the bytes cannot be dropped once they are validated.
+    pub async fn add_package_from_reader(
+        &mut self,
+        name: String,
+        version: Version,
+        mut reader: impl AsyncRead + Unpin,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        let mut bytes = Vec::new();
+        let mut parser = Parser::new(0);
+        let mut validator = Validator::new_with_features(WasmFeatures::all());
+        // The parsers of the components and modules enclosing the one being parsed.
+        let mut enclosing = Vec::new();
+        let mut offset = 0;
+        let mut eof = false;
+
+        loop {
+            let chunk = parser
+                .parse(&bytes[offset..], eof)
+                .map_err(anyhow::Error::from)
+                .context(add_package_error::PackageParseSnafu)?;
+
+            let (consumed, payload) = match chunk {
+                Chunk::NeedMoreData(hint) => {
+                    let start = bytes.len();
+                    let len = usize::try_from(hint).unwrap_or(usize::MAX).max(CHUNK_SIZE);
+                    bytes.resize(start + len, 0);
+                    let read =
+                        poll_fn(|cx| Pin::new(&mut reader).poll_read(cx, &mut bytes[start..]))
+                            .await
+                            .context(add_package_error::ReadSnafu)?;
+                    bytes.truncate(start + read);
+                    eof = read == 0;
+                    continue;
+                }
+                Chunk::Parsed { consumed, payload } => (consumed, payload),
+            };
+
+            offset += consumed;
+            let valid = validator
+                .payload(&payload)
+                .map_err(anyhow::Error::from)
+                .context(add_package_error::PackageParseSnafu)?;
+
+            match valid {
+                ValidPayload::Ok => {}
+                ValidPayload::Parser(nested) => {
+                    enclosing.push(std::mem::replace(&mut parser, nested))
+                }
+                ValidPayload::Func(func, body) => {
+                    func.into_validator(Default::default())
+                        .validate(&body)
+                        .map_err(anyhow::Error::from)
+                        .context(add_package_error::PackageParseSnafu)?;
+                }
+                ValidPayload::End(_) => match enclosing.pop() {
+                    Some(outer) => parser = outer,
+                    None => break,
+                },
+            }
+        }
+
+        bytes.shrink_to_fit();
+        self.add_package(name, version, bytes, trampoline)
+    }
+
+    /// Like `add_package`, but fetches the package bytes identified by `reference` from
+    /// `source`, such as an `OciSource` pulling them from a registry.
+    ///
+    /// The bytes are fetched before the graph is checked for a duplicate package, so hosts adding
+    /// packages that may already be present should check with `packages` first.
+    pub fn add_package_from_source(
+        &mut self,
+        name: String,
+        version: Version,
+        source: impl PackageSource,
+        reference: &str,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> Result<PackageId, AddPackageError> {
+        let bytes = source
+            .fetch(reference)
+            .context(add_package_error::FetchSnafu { reference })?;
+
+        self.add_package(name, version, bytes, trampoline)
+    }
+
+    /// Like `add_package`, but defers parsing the package until it is first referenced.
+    ///
+    /// The package is parsed when it is instantiated, when a package being instantiated imports
+    /// any version of a package with its name, or when its function table is built. Parse errors
+    /// are then returned from those methods instead, and warnings about the package are raised at
+    /// that time. Until it is parsed, the package is not checked by `validate`, and indexing the
+    /// graph with its id panics.
+    pub fn add_package_lazy(
+        &mut self,
+        name: String,
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        trampoline: impl DynPackageTrampoline<D, C> + Send + Sync + 'static,
+    ) -> Result<PackageId, AddPackageError> {
+        if self.contains_package(&name, &version) && self.provider_selector.is_none() {
+            return Err(AddPackageError::DuplicatePackage { name, version });
+        }
+
+        let bytes = bytes.into();
+        self.verify_package(&name, &version, &bytes)?;
+
+        let pending = PendingPackage {
+            name: name.clone(),
+            version: version.clone(),
+            bytes,
+            trampoline: Box::new(trampoline),
+        };
+
+        let package_id = self.insert_package(name, version, None);
+        self.pending_packages.insert(package_id, pending);
+
+        log_debug!(id:? = package_id; "Added lazily parsed package");
+
+        Ok(package_id)
+    }
+
+    fn insert_package(
+        &mut self,
+        name: String,
+        version: Version,
+        package: Option<Package>,
+    ) -> PackageId {
+        let package_id = PackageId {
+            id: self.packages.insert(PackageWrapper {
+                package,
+                nonce: self.nonce,
+            }),
+            nonce: self.nonce,
+        };
+        self.nonce += 1;
+
+        self.insert_provider(name, version, package_id);
+
+        package_id
+    }
+
+    /// Registers the package `package_id` as a provider of the package `name` at `version`, which
+    /// is either its own name and version, or those of an interface it exports.
+    fn insert_provider(&mut self, name: String, version: Version, package_id: PackageId) {
+        let version_set = self.package_map.entry(name).or_insert_with(|| {
+            let mut version_map = VersionMap::new()
+                .with_strategy(self.version_strategy)
+                .with_pre_release_policy(self.pre_release_policy)
+                .with_build_metadata_policy(self.build_metadata_policy)
+                .with_resolution_mode(self.resolution_mode);
+            version_map.set_priority(self.version_priority);
+            version_map
+        });
+
+        let providers = version_set.get_or_insert_with(version, Vec::new);
+        if !providers.contains(&package_id) {
+            providers.push(package_id);
+        }
+    }
+
+    /// Unregisters the package `package_id` as a provider of packages other than `name`, its own.
+    fn remove_foreign_providers(&mut self, package_id: PackageId, name: &str) {
+        self.package_map.retain(|provided, version_map| {
+            if provided == name {
+                return true;
+            }
+
+            let mut unprovided = Vec::new();
+            for (version, providers) in version_map.iter_mut() {
+                providers.retain(|provider| *provider != package_id);
+                if providers.is_empty() {
+                    unprovided.push(version.clone());
+                }
+            }
+            for version in &unprovided {
+                version_map.remove(version);
+            }
+
+            !version_map.is_empty()
+        });
+    }
+
+    /// Returns whether a package named `name` has been added at `version`, rather than only
+    /// packages exporting interfaces of that package.
+    fn contains_package(&self, name: &str, version: &Version) -> bool {
+        self.package_map
+            .get(name)
+            .and_then(|version_map| version_map.get_exact(version))
+            .is_some_and(|providers| {
+                providers
+                    .iter()
+                    .any(|provider| match self.pending_packages.get(provider) {
+                        Some(pending) => pending.name == name,
+                        None => self
+                            .packages
+                            .get(provider.id)
+                            .and_then(|wrapper| wrapper.package.as_ref())
+                            .is_some_and(|package| package.name() == name),
+                    })
+            })
+    }
+
+    /// Parses a lazily added package, if it has not been parsed yet.
+    fn parse_pending_package(&mut self, package_id: PackageId) -> Result<(), AddPackageError> {
+        let Some(pending) = self.pending_packages.remove(&package_id) else {
+            return Ok(());
+        };
+
+        // The bytes are kept until parsing succeeds, so that a failed parse is reported again
+        // rather than leaving an unparsed package behind.
+        let parsed = Package::from_bytes(
+            &pending.name,
+            Some(&pending.version),
+            pending.bytes.as_slice(),
+            &mut self.types,
+        );
+
+        let package = match parsed {
+            Ok(package) => package,
+            Err(source) => {
+                self.pending_packages.insert(package_id, pending);
+                return Err(AddPackageError::PackageParseError { source });
+            }
+        };
+
+        match self.packages.get_mut(package_id.id) {
+            Some(wrapper) => wrapper.package = Some(package),
+            None => {
+                return Err(AddPackageError::InternalError {
+                    message: self
+                        .invariant_violation(format!("lazy package {package_id:?} not found")),
+                });
+            }
+        }
+
+        self.register_package(package_id, pending.trampoline.as_ref())
+    }
+
+    /// Parses the lazily added packages that imports of `origin` may transitively resolve to, i.e.
+    /// all versions of the packages named by the imports of `origin` and of the parsed packages.
+    fn parse_referenced_packages(&mut self, origin: PackageId) -> Result<(), AddPackageError> {
+        if self.pending_packages.is_empty() {
+            return Ok(());
+        }
+
+        let mut queue = vec![origin];
+        let mut visited = HashSet::new();
+
+        while let Some(package_id) = queue.pop() {
+            if !visited.insert(package_id) {
+                continue;
+            }
+
+            self.parse_pending_package(package_id)?;
+
+            let Some(imports) = self.imported_interfaces.get(&package_id) else {
+                continue;
+            };
+
+            for import in imports {
+                if let Some(version_map) = self.package_map.get(import.package_name()) {
+                    queue.extend(version_map.iter().flat_map(|(_, ids)| ids.iter().copied()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Registers the exported and imported interfaces of a parsed package.
+    fn register_package(
+        &mut self,
+        package_id: PackageId,
+        trampoline: &dyn DynPackageTrampoline<D, C>,
+    ) -> Result<(), AddPackageError> {
+        let Some(package) = self
+            .packages
+            .get(package_id.id)
+            .and_then(|package| package.package.as_ref())
+        else {
+            return Err(AddPackageError::InternalError {
+                message: self
+                    .invariant_violation(format!("added package {package_id:?} not found")),
+            });
+        };
+        let mut warnings = Vec::new();
+
+        let package_prefix = format!("{}/", package.name());
+        let version_suffix = package.version().map_or(String::new(), |v| format!("@{v}"));
+
+        let exports = &self.types[package.ty()].exports;
+        let mut foreign_exports = Vec::new();
+        let import_filter = self.effective_import_filter();
+
+        for (export_name, export_kind) in exports {
+            let ItemKind::Instance(interface_id) = export_kind else {
+                continue;
+            };
+
+            let interface_name = export_name
+                .strip_prefix(&package_prefix)
+                .and_then(|export_name| export_name.strip_suffix(&version_suffix));
+
+            let path = match interface_name {
+                Some(interface_name) => Some(ForeignInterfacePath::new(
+                    package.name().to_string(),
+                    interface_name.to_string(),
+                    package.version().cloned(),
+                )),
+
+                // Interfaces of other packages, such as standard interfaces implemented by an
+                // adapter, are provided at the version they are exported with.
+                None => match InterfacePath::from_str(export_name)
+                    .ok()
+                    .and_then(InterfacePath::into_foreign)
+                {
+                    Some(path) if path.package_name() == package.name() => None,
+                    Some(path) if path.version().is_none() => {
+                        log_debug!(
+                            id:? = package_id, export:% = path;
+                            "Unversioned export of another package not registered"
+                        );
+                        None
+                    }
+                    Some(path) => {
+                        foreign_exports.push(path.clone());
+                        Some(path)
+                    }
+                    None => None,
+                },
+            };
+
+            if let Some(path) = path {
+                if matches!(import_filter.filter_rule(&path), ImportRule::Skip) {
+                    warnings.push(GraphWarning::SkippedExport {
+                        package: package_label(package),
+                        export: path.clone(),
+                    });
+                }
+
+                let interface_trampoline = InterfaceExport {
+                    package: package_id,
+                    interface: *interface_id,
+                    trampoline: trampoline.interface_trampoline(path.interface_name()),
+                };
+
+                if self
+                    .exported_interfaces
+                    .insert((package_id, path.clone()), interface_trampoline)
+                    .is_some()
+                {
+                    // This would be a programming error, since the package id is guaranteed to be
+                    // unique.
+                    return Err(AddPackageError::InternalError {
+                        message: self.invariant_violation(format!(
+                            "duplicate exported interface key {path:?}"
+                        )),
+                    });
+                }
+            }
+        }
+
+        for path in foreign_exports {
+            if let Some(version) = path.version() {
+                self.insert_provider(path.package_name().to_string(), version.clone(), package_id);
+            }
+        }
+
+        let mut unparsable_imports = Vec::new();
+        let added_package_id = package_id;
+        let import_filter = self.effective_import_filter();
+
+        let mut import = |package_id: PackageId, interface_id: InterfaceId, import_name: &str| {
+            let interface = &self.types[interface_id];
+            let interface_has_func = interface
+                .exports
+                .iter()
+                .any(|(_item_name, item_kind)| matches!(item_kind, ItemKind::Func(_)));
+
+            let import_interface_path = match InterfacePath::from_str(import_name) {
+                Ok(path) => path,
+
+                // Imports without functions are never linked, so they don't need to be parsed.
+                Err(err) if !interface_has_func => {
+                    unparsable_imports.push((package_id, import_name.to_string(), err.to_string()));
+                    return Ok(());
+                }
+
+                Err(err) => {
+                    return Err(err).context(add_package_error::ImportParseSnafu {
+                        interface: import_name.to_string(),
+                    });
+                }
+            };
+
+            if let Some(import) = import_interface_path.into_foreign() {
+                match import_filter.filter_rule(&import) {
+                    ImportRule::Skip => {
+                        if package_id == added_package_id {
+                            log_debug!(
+                                id:? = package_id, import:% = import;
+                                "Import skipped by the import filter"
+                            );
+                        }
+                        return Ok(());
+                    }
+
+                    ImportRule::Include => {
+                        // If the interface defines no functions, skip it.
+                        if !interface_has_func {
+                            return Ok(());
+                        }
+                    }
+
+                    ImportRule::Force => { /* continue */ }
+                }
+
+                // Only the imported functions of an interface are shadowed when instantiating.
+                let functions = interface
+                    .exports
+                    .iter()
+                    .filter(|(_item_name, item_kind)| matches!(item_kind, ItemKind::Func(_)))
+                    .map(|(item_name, _item_kind)| item_name.clone());
+
+                self.imported_functions
+                    .entry(package_id)
+                    .or_default()
+                    .entry(import.clone())
+                    .or_default()
+                    .extend(functions);
+
+                // Add the interface to the list of imports.
+                self.imported_interfaces
+                    .entry(package_id)
+                    .or_default()
+                    .insert(import);
+            }
+
+            Ok(())
+        };
+
+        for (package_id, wrapper) in &self.packages {
+            let package_id = PackageId {
+                id: package_id,
+                nonce: wrapper.nonce,
+            };
+            let Some(package) = &wrapper.package else {
+                continue;
+            };
+            let package_ty = &self.types[package.ty()];
+
+            for (import_name, import_kind) in &package_ty.imports {
+                let ItemKind::Instance(interface_id) = import_kind else {
+                    continue;
+                };
+
+                import(package_id, *interface_id, import_name)?;
+            }
+        }
+
+        warnings.extend(
+            unparsable_imports
+                .into_iter()
+                .filter(|(package_id, _, _)| *package_id == added_package_id)
+                .map(
+                    |(package_id, import, error)| GraphWarning::UnparsableImport {
+                        package: self.label_of(package_id),
+                        import,
+                        error,
+                    },
+                ),
+        );
+
+        log_debug!(
+            id:? = package_id, package:% = self.label_of(package_id);
+            "Added package"
+        );
+
+        for warning in warnings {
+            self.warn(warning);
+        }
+
+        Ok(())
+    }
+
+    /// Parses the lazily added packages that the package `package_id` may depend on, so that it
+    /// can be instantiated.
+    ///
+    /// Instantiation only reads the graph, so that a graph behind an `Arc` can serve concurrent
+    /// instantiations. Graphs with lazily added packages must therefore be prepared for the
+    /// packages they instantiate, or with `precompile_all`, which parses all of them.
+    pub fn prepare(&mut self, package_id: PackageId) -> Result<(), InstantiateError> {
+        self.parse_referenced_packages(package_id)
+            .context(instantiate_error::LazyPackageSnafu)
+    }
+
+    /// Fails with `UnpreparedPackage` if the package `package_id` may depend on a lazily added
+    /// package that has not been parsed yet.
+    fn check_prepared(&self, package_id: PackageId) -> Result<(), InstantiateError> {
+        if self.pending_packages.is_empty() {
+            return Ok(());
+        }
+
+        let mut queue = vec![package_id];
+        let mut visited = HashSet::new();
+
+        while let Some(package_id) = queue.pop() {
+            if !visited.insert(package_id) {
+                continue;
+            }
+
+            if self.pending_packages.contains_key(&package_id) {
+                return Err(InstantiateError::UnpreparedPackage { id: package_id });
+            }
+
+            let Some(imports) = self.imported_interfaces.get(&package_id) else {
+                continue;
+            };
+
+            for import in imports {
+                if let Some(version_map) = self.package_map.get(import.package_name()) {
+                    queue.extend(version_map.iter().flat_map(|(_, ids)| ids.iter().copied()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Instantiates a component from the composition graph, resolving all component dependencies.
+    ///
+    /// Host functions and other resources can be provided through the `linker` argument prior to
+    /// instantiation. Lazily added packages must have been parsed with `prepare`.
+    ///
+    /// Package dependencies are instantiated anew on every call. Use `instantiate_reusing` to
+    /// share the dependency instances between instantiations in the same store.
+    pub fn instantiate(
+        &self,
+        package_id: PackageId,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        self.instantiate_with(package_id, linker, store, engine, InstantiateOptions::new())
+    }
+
+    /// Like `instantiate`, but links the shadowed interfaces into a clone of `linker`, so that
+    /// every call creates a fully isolated composed instance in `store`.
+    ///
+    /// The host interfaces defined in `linker` are available to the packages, but no linker state
+    /// is shared between instantiations, so the same package can be instantiated into any number
+    /// of stores, or repeatedly into one store, without allowing shadowing. Compiled components
+    /// are still shared through the component cache.
+    pub fn instantiate_isolated(
+        &self,
+        package_id: PackageId,
+        linker: &component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        self.instantiate(package_id, &mut linker.clone(), store, engine)
+    }
+
+    /// Like `instantiate`, but reuses the package instances recorded in `instances` rather than
+    /// instantiating those packages again, and records the instances it creates.
+    ///
+    /// `instances` must only be used with one store, since instances cannot be shared between
+    /// stores. Reused dependencies are linked again into `linker`, so a linker used for multiple
+    /// instantiations must allow shadowing with `Linker::allow_shadowing`.
+    pub fn instantiate_reusing(
+        &self,
+        package_id: PackageId,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+        instances: &mut ShadowInstances,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        self.instantiate_with(
+            package_id,
+            linker,
+            store,
+            engine,
+            InstantiateOptions::new().with_instances(instances),
+        )
+    }
+
+    /// Like `instantiate`, but with the instance reuse and trampoline context overrides given by
+    /// `options`.
+    pub fn instantiate_with(
+        &self,
+        package_id: PackageId,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+        options: InstantiateOptions<'_, C>,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        self.plan(package_id)?
+            .execute_with(linker, store, engine, options)
+    }
+
+    /// Instantiates several packages into `store`, such as applications sharing the same
+    /// dependencies, and returns the instance of each package in the order of `package_ids`.
+    ///
+    /// The packages are instantiated in their combined load order, and every dependency they
+    /// share, including packages that are dependencies of others in `package_ids`, is
+    /// instantiated once. Like `instantiate_isolated`, the shadowed interfaces are linked into
+    /// clones of `linker`.
+    pub fn instantiate_many(
+        &self,
+        package_ids: &[PackageId],
+        linker: &component::Linker<D>,
+        mut store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Vec<Instance>, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        let mut instances = ShadowInstances::new();
+        for package_id in self.combined_load_order(package_ids)? {
+            self.instantiate_reusing(
+                package_id,
+                &mut linker.clone(),
+                &mut store,
+                engine,
+                &mut instances,
+            )?;
+        }
+
+        root_instances(package_ids, &instances)
+    }
+
+    /// Returns `package_ids` without duplicates, ordered by their position in the combined load
+    /// order of the packages, so that packages depending on others in `package_ids` come later.
+    fn combined_load_order(
+        &self,
+        package_ids: &[PackageId],
+    ) -> Result<Vec<PackageId>, InstantiateError> {
+        let mut load_order = IndexSet::new();
+        for &package_id in package_ids {
+            load_order.extend(self.plan(package_id)?.load_order);
+        }
+
+        Ok(load_order
+            .into_iter()
+            .filter(|package_id| package_ids.contains(package_id))
+            .collect())
+    }
+
+    /// Links the package `package_id` and its dependencies into `linker` without instantiating
+    /// anything, returning an `InstancePre` that instantiates the package cheaply in any number
+    /// of stores, such as one per request.
+    ///
+    /// Dependencies are instantiated in each store on the first call into their shadowed
+    /// interfaces, and recorded in the `ShadowInstances` of that store returned by `instances`.
+    /// Only synchronous trampolines are supported, and the returned `InstancePre` must be
+    /// instantiated with `InstancePre::instantiate`. Dependencies exporting resources through
+    /// their shadowed interfaces fail with `InstantiatePackageError::UnsupportedResourceExport`.
+    pub fn instantiate_pre(
+        &self,
+        package_id: PackageId,
+        linker: &mut component::Linker<D>,
+        engine: &wasmtime::Engine,
+        instances: fn(&mut D) -> &mut ShadowInstances,
+    ) -> Result<InstancePre<D>, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        self.plan(package_id)?
+            .execute_pre(linker, engine, instances)
+    }
+
+    /// Resolves the packages to instantiate for the package `package_id` and the interfaces to
+    /// shadow in each, without compiling or instantiating anything.
+    ///
+    /// The returned plan can be inspected, and executed against any number of stores without
+    /// resolving the dependencies again. Lazily added packages must have been parsed with
+    /// `prepare`.
+    pub fn plan(
+        &self,
+        package_id: PackageId,
+    ) -> Result<InstantiationPlan<'_, D, C>, InstantiateError> {
+        self.check_prepared(package_id)?;
+
+        let mut interfaces = IndexMap::<PackageId, ShadowedInterfaces>::new();
+        let mut stubs = StubbedImports::new();
+
+        let load_order = self
+            .package_load_order(package_id, &mut interfaces, &mut stubs)
+            .context(instantiate_error::LoadPackageSnafu)?
+            .into_iter()
+            .collect();
+
+        self.packages
+            .get(package_id.id)
+            .ok_or(InstantiateError::PackageNotFound { id: package_id })?;
+
+        Ok(InstantiationPlan {
+            graph: self,
+            package_id,
+            load_order,
+            interfaces,
+            stubs,
+        })
+    }
+
+    /// Like `instantiate`, but for asynchronous contexts.
+    pub async fn instantiate_async(
+        &self,
+        package_id: PackageId,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: Send + 'static,
+        C: Send + Sync + 'static,
+    {
+        self.instantiate_with_async(package_id, linker, store, engine, InstantiateOptions::new())
+            .await
+    }
+
+    /// Like `instantiate_isolated`, but for asynchronous contexts.
+    pub async fn instantiate_isolated_async(
+        &self,
+        package_id: PackageId,
+        linker: &component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: Send + 'static,
+        C: Send + Sync + 'static,
+    {
+        self.instantiate_async(package_id, &mut linker.clone(), store, engine)
+            .await
+    }
+
+    /// Like `instantiate_reusing`, but for asynchronous contexts.
+    pub async fn instantiate_reusing_async(
+        &self,
+        package_id: PackageId,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+        instances: &mut ShadowInstances,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: Send + 'static,
+        C: Send + Sync + 'static,
+    {
+        self.instantiate_with_async(
+            package_id,
+            linker,
+            store,
+            engine,
+            InstantiateOptions::new().with_instances(instances),
+        )
+        .await
+    }
+
+    /// Like `instantiate_with`, but for asynchronous contexts.
+    pub async fn instantiate_with_async(
+        &self,
+        package_id: PackageId,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+        options: InstantiateOptions<'_, C>,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: Send + 'static,
+        C: Send + Sync + 'static,
+    {
+        self.plan(package_id)?
+            .execute_with_async(linker, store, engine, options)
+            .await
+    }
+
+    /// Like `instantiate_many`, but for asynchronous contexts.
+    pub async fn instantiate_many_async(
+        &self,
+        package_ids: &[PackageId],
+        linker: &component::Linker<D>,
+        mut store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Vec<Instance>, InstantiateError>
+    where
+        D: Send + 'static,
+        C: Send + Sync + 'static,
+    {
+        let mut instances = ShadowInstances::new();
+        for package_id in self.combined_load_order(package_ids)? {
+            self.instantiate_reusing_async(
+                package_id,
+                &mut linker.clone(),
+                &mut store,
+                engine,
+                &mut instances,
+            )
+            .await?;
+        }
+
+        root_instances(package_ids, &instances)
+    }
+
+    /// Resolves the functions of all interfaces exported by a package against its compiled
+    /// component, as they are shadowed by trampolines when instantiating packages importing them.
+    ///
+    /// The compiled component is cached like the components of instantiated packages.
+    pub fn function_table(
+        &mut self,
+        package_id: PackageId,
+        engine: &wasmtime::Engine,
+    ) -> Result<FunctionTable<D, C>, InstantiatePackageError>
+    where
+        D: 'static,
+    {
+        self.parse_pending_package(package_id)
+            .context(instantiate_package_error::LazyPackageSnafu)?;
+
+        let package = self
+            .packages
+            .get(package_id.id)
+            .filter(|package| package.nonce == package_id.nonce)
+            .ok_or(InstantiatePackageError::PackageNotFound { id: package_id })?;
+
+        let component = self
+            .component_cache
+            .get_or_compile(engine, package.bytes())
+            .context(instantiate_package_error::ComponentCompilationSnafu)?;
+
+        let mut interfaces = self
+            .exported_interfaces
+            .keys()
+            .filter(|(id, _)| *id == package_id)
+            .map(|(_, path)| (path, None))
+            .collect::<Vec<_>>();
+        interfaces.sort_unstable_by_key(|(path, _)| path.as_str());
+
+        self.build_function_table(package_id, &component, interfaces, None, None)
+    }
+
+    /// Checks every package in the graph for problems that would make its instantiation fail,
+    /// collecting all of them rather than stopping at the first.
+    ///
+    /// Each package's imports are resolved and type checked against the interfaces exported by
+    /// the resolved packages, and package import cycles are detected. Imports skipped by the
+    /// import filter are reported as warnings if a package in the graph exports the skipped
+    /// interface, since the host is then expected to provide it instead, as are imports resolved
+    /// to deprecated package versions. An empty result means no problems were found.
+    #[must_use]
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let import_filter = self.effective_import_filter();
+        let mut dependencies =
+            IndexMap::<PackageId, IndexMap<PackageId, ForeignInterfacePath>>::new();
+        let mut subtype_cache = HashSet::new();
+
+        for (id, wrapper) in &self.packages {
+            let package_id = PackageId {
+                id,
+                nonce: wrapper.nonce,
+            };
+            let Some(package) = &wrapper.package else {
+                continue;
+            };
+            let package_name = package_label(package);
+            let included_imports = self.imported_interfaces.get(&package_id);
+            let mut selected_providers = HashMap::new();
+
+            for (import_name, import_kind) in &self.types[package.ty()].imports {
+                let ItemKind::Instance(import_interface) = import_kind else {
+                    continue;
+                };
+
+                let Some(import) = InterfacePath::from_str(import_name)
+                    .ok()
+                    .and_then(InterfacePath::into_foreign)
+                else {
+                    continue;
+                };
+
+                let is_included = included_imports.is_some_and(|imports| imports.contains(&import));
+
+                let (version, provider) =
+                    match self.resolve_import(package_id, &import, &mut selected_providers) {
+                        Ok(resolved) => resolved,
+                        Err(err) => {
+                            if is_included && !self.is_stubbed(&err) {
+                                diagnostics.push(Diagnostic::from(&err).with_package(package_id));
+                            }
+                            continue;
+                        }
+                    };
+
+                // The exports of lazily added packages are unknown until they are parsed.
+                if self.pending_packages.contains_key(&provider) {
+                    continue;
+                }
+
+                let export_path = ForeignInterfacePath::new(
+                    import.package_name().to_string(),
+                    import.interface_name().to_string(),
+                    Some(version.clone()),
+                );
+                let export = self.exported_interfaces.get(&(provider, export_path));
+
+                if !is_included {
+                    let is_skipped = matches!(import_filter.filter_rule(&import), ImportRule::Skip);
+
+                    if is_skipped && export.is_some() {
+                        diagnostics.push(
+                            Diagnostic::new(
+                                Severity::Warning,
+                                "WCT0401",
+                                format!(
+                                    "Package '{package_name}' import '{import}' is skipped by the \
+                                     import filter, but is exported by package '{}'",
+                                    self.label_of(provider)
+                                ),
+                            )
+                            .with_package(package_id)
+                            .with_related_path(import.clone())
+                            .with_suggestion(
+                                "Include the import in the import filter to link it against the \
+                                 exporting package",
+                            ),
+                        );
+                    }
+                    continue;
+                }
+
+                let Some(export) = export else {
+                    let err = InstantiatePackageError::MissingInterfaceExport {
+                        path: import.clone(),
+                    };
+                    diagnostics.push(
+                        Diagnostic::from(&err)
+                            .with_message(format!("Package '{package_name}': {err}"))
+                            .with_package(package_id),
+                    );
+                    continue;
+                };
+
+                let deprecation = self
+                    .package_map
+                    .get(import.package_name())
+                    .and_then(|version_map| version_map.deprecation(version));
+
+                if let Some(message) = deprecation {
+                    let warning = GraphWarning::DeprecatedPackage {
+                        importer: package.name().to_string(),
+                        import: import.clone(),
+                        version: version.clone(),
+                        message: message.to_string(),
+                    };
+                    diagnostics.push(Diagnostic::from(&warning).with_package(package_id));
+                }
+
+                if provider != package_id {
+                    dependencies
+                        .entry(package_id)
+                        .or_default()
+                        .entry(provider)
+                        .or_insert_with(|| import.clone());
+                }
+
+                let mismatch = self.import_type_mismatch(
+                    package,
+                    &import,
+                    *import_interface,
+                    provider,
+                    export.interface,
+                    &mut subtype_cache,
+                );
+
+                if let Some(source) = mismatch {
+                    let err = InstantiatePackageError::InterfaceTypeMismatch { source };
+                    diagnostics.push(Diagnostic::from(&err).with_package(package_id));
+                }
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let mut cycles = Vec::new();
+        for package_id in dependencies.keys() {
+            let mut stack = IndexSet::new();
+            self.collect_cycles(
+                *package_id,
+                &dependencies,
+                &mut stack,
+                &mut visited,
+                &mut cycles,
+            );
+        }
+
+        diagnostics.extend(
+            cycles
+                .iter()
+                .map(|(package_id, err)| Diagnostic::from(err).with_package(*package_id)),
+        );
+
+        diagnostics
+    }
+
+    /// Resolves the dependencies of the package `package_id` like `instantiate`, without
+    /// compiling or instantiating any component, and reports the resolved imports, the imports
+    /// that cannot be resolved and the import cycles.
+    ///
+    /// Unlike `validate`, all unresolved imports and cycles of the package are reported rather
+    /// than the first, and only the packages the package depends on are checked. Import types are
+    /// not checked. Lazily added packages must have been parsed with `prepare`.
+    pub fn validate_package(
+        &self,
+        package_id: PackageId,
+    ) -> Result<ResolutionReport, InstantiateError> {
+        self.package(package_id)
+            .ok_or(InstantiateError::PackageNotFound { id: package_id })?;
+        self.check_prepared(package_id)?;
+
+        let mut edges = Vec::new();
+        let mut unresolved = Vec::new();
+        let mut dependencies =
+            IndexMap::<PackageId, IndexMap<PackageId, ForeignInterfacePath>>::new();
+        let mut selected_providers = HashMap::new();
+
+        let mut reached = IndexSet::from([package_id]);
+        let mut next = 0;
+        while let Some(&importer) = reached.get_index(next) {
+            next += 1;
+
+            for import in self
+                .imported_interfaces
+                .get(&importer)
+                .into_iter()
+                .flatten()
+            {
+                match self.resolve_import(importer, import, &mut selected_providers) {
+                    Ok((version, provider)) => {
+                        edges.push(ResolvedEdge {
+                            importer,
+                            import: import.clone(),
+                            version: version.clone(),
+                            provider,
+                        });
+
+                        if provider != importer {
+                            dependencies
+                                .entry(importer)
+                                .or_default()
+                                .entry(provider)
+                                .or_insert_with(|| import.clone());
+                        }
+                        reached.insert(provider);
+                    }
+                    Err(err) => unresolved.push(err),
+                }
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let mut cycles = Vec::new();
+        self.collect_cycles(
+            package_id,
+            &dependencies,
+            &mut IndexSet::new(),
+            &mut visited,
+            &mut cycles,
+        );
+        let cycles = cycles
+            .into_iter()
+            .filter_map(|(_, err)| match err {
+                LoadPackageError::PackageCycle { edges, .. } => Some(edges),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let load_order = if unresolved.is_empty() && cycles.is_empty() {
+            self.package_load_order(package_id, &mut IndexMap::new(), &mut IndexMap::new())
+                .map(|load_order| load_order.into_iter().collect())
+                .context(instantiate_error::LoadPackageSnafu)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(ResolutionReport {
+            package: package_id,
+            load_order,
+            edges,
+            unresolved,
+            cycles,
+        })
+    }
+
+    #[cfg(feature = "compose")]
+    /// Composes the package `package_id` and the packages it depends on into a single component,
+    /// in the style of `wac` and `wasm-compose`, which can be run without the graph.
+    ///
+    /// The composed component nests the component of each package, instantiates them in load
+    /// order with the imports resolved to other packages wired to their exports, and exports the
+    /// exports of the package. All other imports, such as those skipped by the import filter,
+    /// become imports of the composed component.
+    ///
+    /// Only links that need no trampoline are composed statically, i.e. imports provided by an
+    /// interface with a passthrough trampoline, e.g. of a package added with `NoopTrampoline`.
+    /// Imports linked through any other trampoline stay imports of the composed component, for the
+    /// host to link through the graph, and providers reached only through them are left out. Calls
+    /// between the composed packages are direct, so call metrics, traces, policies and the other
+    /// instrumentation of the graph do not apply to them.
+    pub fn compose_static(&self, package_id: PackageId) -> Result<Vec<u8>, ComposeError> {
+        let mut report = self
+            .validate_package(package_id)
+            .context(compose_error::ResolveSnafu)?;
+
+        if !report.unresolved.is_empty() {
+            return Err(report.unresolved.swap_remove(0)).context(compose_error::UnresolvedSnafu);
+        }
+        if let Some(edges) = report.cycles.pop() {
+            return compose_error::PackageCycleSnafu { edges }.fail();
+        }
+
+        report.edges.retain(|edge| {
+            let export_path = ForeignInterfacePath::new(
+                edge.import.package_name().to_string(),
+                edge.import.interface_name().to_string(),
+                Some(edge.version.clone()),
+            );
+            self.exported_interfaces
+                .get(&(edge.provider, export_path))
+                .is_some_and(|export| {
+                    matches!(export.trampoline, DynInterfaceTrampoline::Passthrough)
+                })
+        });
+
+        // Importers follow their providers in the load order, so walking it backwards from the
+        // package reaches every package still linked to it before its providers.
+        let mut reached = HashSet::from([package_id]);
+        for &importer in report.load_order.iter().rev() {
+            if reached.contains(&importer) {
+                let providers = report.edges_of(importer).map(|edge| edge.provider);
+                reached.extend(providers.collect::<Vec<_>>());
+            }
+        }
+        report.load_order.retain(|id| reached.contains(id));
+        report.edges.retain(|edge| reached.contains(&edge.importer));
+
+        compose(&self.types, &report, |package_id| self.package(package_id))
+    }
+
+    /// Returns a graph of the package `root` and the packages its imports transitively resolve
+    /// to, such as for testing a single application against its dependency closure out of a
+    /// larger shared graph.
+    ///
+    /// The packages keep their ids and trampolines, and the subgraph has the import filter,
+    /// policy and resolution settings of the graph. Instrumentation such as call metrics, traces
+    /// and event subscribers is not carried over, and neither are lazily added packages that have
+    /// not been parsed.
+    pub fn subgraph(&self, root: PackageId) -> Result<Self, InstantiateError> {
+        let report = self.validate_package(root)?;
+        let reached = std::iter::once(root)
+            .chain(report.edges.iter().map(|edge| edge.provider))
+            .collect::<HashSet<_>>();
+        let is_reached = |package_id: &PackageId| reached.contains(package_id);
+
+        let mut packages = self.packages.clone();
+        packages.retain(|id, wrapper| {
+            wrapper.package.is_some()
+                && is_reached(&PackageId {
+                    id,
+                    nonce: wrapper.nonce,
+                })
+        });
+
+        let mut package_map = HashMap::new();
+        for (name, version_map) in &self.package_map {
+            let mut version_map = version_map.clone();
+            let mut unreached = Vec::new();
+            for (version, package_ids) in version_map.iter_mut() {
+                package_ids.retain(is_reached);
+                if package_ids.is_empty() {
+                    unreached.push(version.clone());
+                }
+            }
+            for version in &unreached {
+                version_map.remove(version);
+            }
+
+            if !version_map.is_empty() {
+                package_map.insert(name.clone(), version_map);
+            }
+        }
+
+        Ok(Self {
+            nonce: self.nonce,
+            types: self.types.clone(),
+            packages,
+            package_map,
+            exported_interfaces: self
+                .exported_interfaces
+                .iter()
+                .filter(|((package_id, _), _)| is_reached(package_id))
+                .map(|(key, export)| (key.clone(), export.clone()))
+                .collect(),
+            imported_interfaces: self
+                .imported_interfaces
+                .iter()
+                .filter(|(package_id, _)| is_reached(package_id))
+                .map(|(package_id, imports)| (*package_id, imports.clone()))
+                .collect(),
+            imported_functions: self
+                .imported_functions
+                .iter()
+                .filter(|(package_id, _)| is_reached(package_id))
+                .map(|(package_id, functions)| (*package_id, functions.clone()))
+                .collect(),
+            package_metadata: self
+                .package_metadata
+                .iter()
+                .filter(|(package_id, _)| is_reached(package_id))
+                .map(|(package_id, metadata)| (*package_id, metadata.clone()))
+                .collect(),
+            package_limits: self
+                .package_limits
+                .iter()
+                .filter(|(package_id, _)| is_reached(package_id))
+                .map(|(package_id, limits)| (*package_id, limits.clone()))
+                .collect(),
+            import_filter: self.import_filter.clone(),
+            #[cfg(feature = "manifest")]
+            import_rules: self.import_rules.clone(),
+            #[cfg(feature = "manifest")]
+            package_origins: self
+                .package_origins
+                .iter()
+                .filter(|(package_id, _)| is_reached(package_id))
+                .map(|(package_id, origin)| (*package_id, origin.clone()))
+                .collect(),
+            version_strategy: self.version_strategy,
+            pre_release_policy: self.pre_release_policy,
+            build_metadata_policy: self.build_metadata_policy,
+            resolution_mode: self.resolution_mode,
+            version_priority: self.version_priority,
+            warning_handler: self.warning_handler.clone(),
+            provider_selector: self.provider_selector.clone(),
+            package_verifier: self.package_verifier.clone(),
+            invariant_policy: self.invariant_policy,
+            policy: self.policy.clone(),
+            package_policy: self.package_policy.clone(),
+            lazy_instantiation: self.lazy_instantiation,
+            import_stub: self.import_stub,
+            #[cfg(feature = "deterministic")]
+            deterministic_state: self.deterministic_state,
+            ..Self::default()
+        })
+    }
+
+    /// Reports whether the graph's imports resolve, as by `validate`, the recent error rates of
+    /// the shadowed interfaces if the graph records call metrics, and the calls executing in its
+    /// package instances if it tracks calls in flight, see `set_in_flight_calls`.
+    #[must_use]
+    pub fn health(&self) -> GraphHealth {
+        #[cfg(feature = "metrics")]
+        let interfaces = self
+            .call_metrics
+            .iter()
+            .flat_map(CallMetrics::recent_calls_by_interface)
+            .map(|(interface, recent)| InterfaceHealth { interface, recent })
+            .collect();
+        #[cfg(not(feature = "metrics"))]
+        let interfaces = Vec::new();
+
+        GraphHealth {
+            packages: self.packages.len(),
+            pending_packages: self.pending_packages.len(),
+            diagnostics: self.validate(),
+            interfaces,
+            in_flight_calls: self
+                .in_flight_calls
+                .as_ref()
+                .map(InFlightCalls::snapshot)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the ids of all packages in the graph, including lazily added packages that have
+    /// not been parsed yet, in the order they were added.
+    pub fn packages(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.packages.iter().map(|(id, wrapper)| PackageId {
+            id,
+            nonce: wrapper.nonce,
+        })
+    }
+
+    /// Returns the package `package_id`, or `None` if it is not in the graph or is a lazily
+    /// added package that has not been parsed yet.
+    #[must_use]
+    pub fn package(&self, package_id: PackageId) -> Option<&Package> {
+        self.packages
+            .get(package_id.id)
+            .filter(|wrapper| wrapper.nonce == package_id.nonce)
+            .and_then(|wrapper| wrapper.package.as_ref())
+    }
+
+    /// Returns the interfaces exported by the package `package_id`, sorted by path.
+    ///
+    /// Lazily added packages export no interfaces until they are parsed.
+    #[must_use]
+    pub fn exports_of(&self, package_id: PackageId) -> Vec<&ForeignInterfacePath> {
+        let mut exports = self
+            .exported_interfaces
+            .keys()
+            .filter(|(exporter, _)| *exporter == package_id)
+            .map(|(_, path)| path)
+            .collect::<Vec<_>>();
+        exports.sort_unstable();
+        exports
+    }
+
+    /// Returns the interfaces imported by the package `package_id` that are resolved against
+    /// other packages of the graph, i.e. those not skipped by the import filter, in import order.
+    ///
+    /// Lazily added packages import no interfaces until they are parsed.
+    pub fn imports_of(
+        &self,
+        package_id: PackageId,
+    ) -> impl Iterator<Item = &ForeignInterfacePath> + '_ {
+        self.imported_interfaces
+            .get(&package_id)
+            .into_iter()
+            .flatten()
+    }
+
+    /// Gets a reference to the type collection of the graph.
+    #[must_use]
+    pub fn types(&self) -> &wac_types::Types {
+        &self.types
+    }
+
+    /// Gets a mutable reference to the type collection of the graph.
+    ///
+    /// This type collection is used to define types directly in the graph.
+    pub fn types_mut(&mut self) -> &mut wac_types::Types {
+        &mut self.types
+    }
+
+    fn package_load_order(
+        &self,
+        origin: PackageId,
+        interfaces: &mut IndexMap<PackageId, ShadowedInterfaces>,
+        stubs: &mut StubbedImports,
+    ) -> Result<impl IntoIterator<Item = PackageId> + 'static, LoadPackageError> {
+        // Each stacked package is paired with the import through which it was reached.
+        let mut package_stack = vec![(origin, 0, None)];
+
+        let mut load_order = IndexSet::<PackageId>::new();
+        let mut load_stack = IndexMap::<PackageId, Option<&ForeignInterfacePath>>::new();
+        let mut selected_providers = HashMap::new();
+
+        while let Some((package_id, offset, via_import)) = package_stack.pop() {
+            load_order.extend(load_stack.drain(offset..).rev().map(|(package, _)| package));
+
+            if let Some(cycle_start) = load_stack.get_index_of(&package_id) {
+                let self_import = cycle_start == load_stack.len() - 1;
+
+                if self_import {
+                    continue;
+                }
+
+                let path = &load_stack.as_slice()[cycle_start..];
+                let edges = path
+                    .iter()
+                    .zip(path.iter().skip(1))
+                    .map(|((importer, _), (exporter, import))| (*importer, *import, *exporter))
+                    .chain(
+                        path.last()
+                            .map(|(importer, _)| (*importer, via_import, package_id)),
+                    )
+                    .filter_map(|(importer, import, exporter)| {
+                        import.map(|import| (importer, import, exporter))
+                    });
+
+                return Err(self.package_cycle_error(edges));
+            }
+
+            if load_order.contains(&package_id) {
+                continue;
+            }
+
+            load_stack.insert(package_id, via_import);
+
+            let imports = self
+                .imported_interfaces
+                .get(&package_id)
+                .map(IndexSet::as_slice)
+                .unwrap_or_default();
+
+            for import in imports {
+                let (import_version, import_package) =
+                    match self.resolve_import(package_id, import, &mut selected_providers) {
+                        Ok(resolved) => resolved,
+                        Err(err) if self.is_stubbed(&err) => {
+                            let importers = stubs.entry(import.clone()).or_default();
+                            if !importers.contains(&package_id) {
+                                importers.push(package_id);
+                            }
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+
+                let deprecation = self
+                    .package_map
+                    .get(import.package_name())
+                    .and_then(|version_map| version_map.deprecation(import_version));
+
+                if let Some(message) = deprecation {
+                    self.warn(GraphWarning::DeprecatedPackage {
+                        importer: self.name_of(package_id).to_string(),
+                        import: import.clone(),
+                        version: import_version.clone(),
+                        message: message.to_string(),
+                    });
+                }
+
+                package_stack.push((import_package, load_stack.len(), Some(import)));
+
+                let functions = self
+                    .imported_functions
+                    .get(&package_id)
+                    .and_then(|imports| imports.get(import))
+                    .into_iter()
+                    .flatten()
+                    .cloned();
+
+                let export_path = ForeignInterfacePath::new(
+                    import.package_name().to_string(),
+                    import.interface_name().to_string(),
+                    Some(import_version.clone()),
+                );
+
+                let shadowed = interfaces
+                    .entry(import_package)
+                    .or_default()
+                    .entry(export_path)
+                    .or_default();
+                shadowed.functions.extend(functions);
+
+                // The linker only matches pre-release imports exactly, so pre-release imports
+                // resolved to another version are linked under their own name as well.
+                if import
+                    .version()
+                    .is_some_and(|version| !version.pre.is_empty() && version != import_version)
+                {
+                    shadowed.aliases.insert(import.clone());
+                }
+            }
+        }
+
+        let load_stack = load_stack.into_keys().collect::<Vec<_>>();
+
+        Ok(load_order.into_iter().chain(load_stack.into_iter().rev()))
+    }
+
+    /// Returns whether the import failing to resolve with `err` is stubbed rather than failing.
+    fn is_stubbed(&self, err: &LoadPackageError) -> bool {
+        self.import_stub.is_some()
+            && matches!(
+                err,
+                LoadPackageError::MissingPackageDependency { .. }
+                    | LoadPackageError::CannotResolvePackageVersion { .. }
+            )
+    }
+
+    #[cfg(feature = "deterministic")]
+    /// Defines the deterministic shims in `linker`, if they are enabled.
+    fn link_deterministic(&self, linker: &mut component::Linker<D>) -> Result<(), InstantiateError>
+    where
+        D: 'static,
+    {
+        if let Some(state) = self.deterministic_state {
+            add_deterministic_shims_to_linker(linker, state)
+                .map_err(InstantiateError::from_instantiation)?;
+        }
+
+        Ok(())
+    }
+
+    /// Defines the stubbed imports of the packages of a plan in `linker`, with the function types
+    /// of their importers.
+    fn link_stubs(
+        &self,
+        stubs: &StubbedImports,
+        linker: &mut component::Linker<D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<(), InstantiateError>
+    where
+        D: 'static,
+    {
+        let Some(stub) = self.import_stub else {
+            return Ok(());
+        };
+
+        for (import, importers) in stubs {
+            let mut instance = linker
+                .instance(import.as_str())
+                .map_err(InstantiateError::from_instantiation)?;
+            let mut defined = Vec::new();
+
+            for importer in importers {
+                let package = self
+                    .packages
+                    .get(importer.id)
+                    .ok_or(InstantiateError::PackageNotFound { id: *importer })?;
+                let component = self
+                    .component_cache
+                    .get_or_compile(engine, package.bytes())
+                    .context(instantiate_error::ComponentCompilationSnafu)?;
+
+                let Some(ComponentItem::ComponentInstance(interface)) = component
+                    .component_type()
+                    .get_import(engine, import.as_str())
+                else {
+                    continue;
+                };
+
+                stub.define(&mut instance, engine, import, &interface, &mut defined)
+                    .map_err(InstantiateError::from_instantiation)?;
+            }
+
+            log_debug!(import:% = import, functions = defined.len(); "Stubbed unresolved import");
+        }
+
+        Ok(())
+    }
+
+    fn resolve_import<'a>(
+        &'a self,
+        importer: PackageId,
+        import: &ForeignInterfacePath,
+        selected_providers: &mut HashMap<(&'a str, &'a Version), PackageId>,
+    ) -> Result<(&'a Version, PackageId), LoadPackageError> {
+        let (package_name, version_map) = self
+            .package_map
+            .get_key_value(import.package_name())
+            .ok_or_else(|| LoadPackageError::MissingPackageDependency {
+                package_name: import.package_name().to_string(),
+                importer: self.label_of(importer),
+                import: Box::new(import.clone()),
+            })?;
+
+        let (import_version, providers) = version_map
+            .get_or_latest_version(import.version())
+            .ok_or_else(|| LoadPackageError::CannotResolvePackageVersion {
+                name: import.package_name().to_string(),
+                version: import.version().cloned(),
+                importer: self.label_of(importer),
+                import: Box::new(import.clone()),
+            })?;
+
+        if let Some(policy) = &self.policy
+            && !policy.allows_edge(self.name_of(importer), import)
+        {
+            return Err(LoadPackageError::DeniedByPolicy {
+                importer: self.label_of(importer),
+                import: Box::new(import.clone()),
+            });
+        }
+
+        if let Some(rule) = self
+            .package_policy
+            .as_ref()
+            .and_then(|policy| policy.denies(package_name, import_version))
+        {
+            return Err(LoadPackageError::DeniedByPackagePolicy {
+                importer: self.label_of(importer),
+                import: Box::new(import.clone()),
+                package: format!("{package_name}@{import_version}"),
+                policy: rule.name.clone(),
+            });
+        }
+
+        let import_package =
+            self.select_provider(package_name, import_version, providers, selected_providers)?;
+
+        log_trace!(
+            importer:? = importer, import:% = import, version:% = import_version,
+            provider:? = import_package;
+            "Resolved import"
+        );
+
+        Ok((import_version, import_package))
+    }
+
+    fn select_provider<'a>(
+        &self,
+        name: &'a str,
+        version: &'a Version,
+        providers: &[PackageId],
+        selected_providers: &mut HashMap<(&'a str, &'a Version), PackageId>,
+    ) -> Result<PackageId, LoadPackageError> {
+        if let [provider] = providers {
+            return Ok(*provider);
+        }
+
+        if let Some(provider) = selected_providers.get(&(name, version)) {
+            return Ok(*provider);
+        }
+
+        let provider = self
+            .provider_selector
+            .as_ref()
+            .and_then(|selector| selector(name, version, providers))
+            .filter(|provider| providers.contains(provider))
+            .ok_or_else(|| LoadPackageError::AmbiguousPackageProvider {
+                name: name.to_string(),
+                version: version.clone(),
+                providers: providers.len(),
+            })?;
+
+        selected_providers.insert((name, version), provider);
+
+        log_debug!(
+            name = name, version:% = version, provider:? = provider;
+            "Selected package provider"
+        );
+
+        Ok(provider)
+    }
+
+    fn package_cycle_error<'a>(
+        &self,
+        edges: impl IntoIterator<Item = (PackageId, &'a ForeignInterfacePath, PackageId)>,
+    ) -> LoadPackageError {
+        let package_name = |package: PackageId| {
+            self.packages
+                .get(package.id)
+                .map_or("{{UNKNOWN_PACKAGE}}".to_string(), |package| {
+                    package.name().to_string()
+                })
+        };
+
+        let edges = edges
+            .into_iter()
+            .map(|(importer, import, exporter)| CycleEdge {
+                importer: package_name(importer),
+                import: import.clone(),
+                exporter: package_name(exporter),
+            })
+            .collect::<Vec<_>>();
+
+        let cycle = edges
+            .iter()
+            .map(|edge| edge.importer.clone())
+            .chain(edges.last().map(|edge| edge.exporter.clone()))
+            .collect();
+
+        LoadPackageError::PackageCycle { cycle, edges }
+    }
+
+    fn collect_cycles(
+        &self,
+        package_id: PackageId,
+        dependencies: &IndexMap<PackageId, IndexMap<PackageId, ForeignInterfacePath>>,
+        stack: &mut IndexSet<PackageId>,
+        visited: &mut HashSet<PackageId>,
+        cycles: &mut Vec<(PackageId, LoadPackageError)>,
+    ) {
+        if let Some(cycle_start) = stack.get_index_of(&package_id) {
+            let path = &stack.as_slice()[cycle_start..];
+            let edges = path
+                .iter()
+                .zip(path.iter().skip(1).chain([&package_id]))
+                .map(|(importer, exporter)| {
+                    (*importer, &dependencies[importer][exporter], *exporter)
+                });
+
+            cycles.push((package_id, self.package_cycle_error(edges)));
+            return;
+        }
+
+        if !visited.insert(package_id) {
+            return;
+        }
+
+        stack.insert(package_id);
+
+        for dependency in dependencies
+            .get(&package_id)
+            .into_iter()
+            .flat_map(IndexMap::keys)
+        {
+            self.collect_cycles(*dependency, dependencies, stack, visited, cycles);
+        }
+
+        stack.pop();
+    }
+
+    /// Returns the `name@version` label of the package `package_id`, or its id if the package is
+    /// not in the graph or not parsed yet.
+    fn label_of(&self, package_id: PackageId) -> String {
+        self.package(package_id)
+            .map_or_else(|| format!("{package_id:?}"), package_label)
+    }
+
+    /// Returns the name of the package `package_id`, or an empty name if the package is not in
+    /// the graph or not parsed yet.
+    fn name_of(&self, package_id: PackageId) -> &str {
+        self.package(package_id).map_or("", Package::name)
+    }
+
+    /// Panics with the message of a violated internal invariant, or returns it to be raised as an
+    /// `InternalError`, depending on the invariant policy.
+    fn invariant_violation(&self, message: String) -> String {
+        match self.invariant_policy {
+            InvariantPolicy::Panic => panic!("{message}"),
+            InvariantPolicy::Error => message,
+        }
+    }
+
+    /// Enforces the limits of the package `package_id` in `store`, if any, until the scope is
+    /// dropped.
+    fn limit_scope(
+        &self,
+        package_id: PackageId,
+        store: impl AsContext<Data = D>,
+    ) -> Option<Scope<PackageLimits>>
+    where
+        D: 'static,
+    {
+        let limits = self.package_limits.get(&package_id)?;
+
+        let Some(scopes) = self.store_scopes else {
+            log_warn!(
+                id:? = package_id;
+                "Package limits are not enforced without store scopes, see `set_store_scopes`"
+            );
+            return None;
+        };
+
+        Some(
+            scopes(store.as_context().data())
+                .limits
+                .enter(limits.clone()),
+        )
+    }
+
+    /// Attributes memory growth in `store` to the instantiation of `package`, if memory is
+    /// tracked, until the scope is dropped.
+    fn memory_scope(
+        &self,
+        package: &Package,
+        store: impl AsContext<Data = D>,
+    ) -> Option<Scope<Attribution>>
+    where
+        D: 'static,
+    {
+        let tracked = self.tracked_package(package)?;
+
+        let Some(scopes) = self.store_scopes else {
+            log_warn!(
+                package:% = package_label(package);
+                "Memory growth is not attributed without store scopes, see `set_store_scopes`"
+            );
+            return None;
+        };
+
+        Some(tracked.instantiating(scopes(store.as_context().data())))
+    }
+
+    fn tracked_package(&self, package: &Package) -> Option<TrackedPackage> {
+        self.memory_tracker
+            .as_ref()
+            .map(|tracker| tracker.package(package.name(), package.version()))
+    }
+
+    fn emit_instantiated(&self, package_id: PackageId, package: &Package, started: Instant) {
+        log_debug!(
+            id:? = package_id, package:% = package_label(package), duration:? = started.elapsed();
+            "Instantiated package"
+        );
+
+        self.events.emit(|| GraphEvent::Instantiated {
+            package: package_id,
+            name: package.name().to_string(),
+            version: package.version().cloned(),
+            duration: started.elapsed(),
+        });
+    }
+
+    fn warn(&self, warning: GraphWarning) {
+        log_warn!("{warning}");
+
+        if let Some(handler) = &self.warning_handler {
+            handler(&warning);
+        }
+    }
+
+    /// Checks the types of the imports of a package against the interfaces they are linked
+    /// against, since mismatches are otherwise only detected when calling mismatching functions.
+    fn check_import_types(
+        &self,
+        package_id: PackageId,
+        package: &Package,
+    ) -> Result<(), Box<InterfaceTypeMismatch>> {
+        let Some(included_imports) = self.imported_interfaces.get(&package_id) else {
+            return Ok(());
+        };
+
+        let mut selected_providers = HashMap::new();
+        let mut subtype_cache = HashSet::new();
+
+        for (import_name, import_kind) in &self.types[package.ty()].imports {
+            let ItemKind::Instance(import_interface) = import_kind else {
+                continue;
+            };
+
+            let Some(import) = InterfacePath::from_str(import_name)
+                .ok()
+                .and_then(InterfacePath::into_foreign)
+                .filter(|import| included_imports.contains(import))
+            else {
+                continue;
+            };
+
+            // Resolution errors are reported by the load order, and missing exports when
+            // shadowing the exporting package.
+            let Ok((version, provider)) =
+                self.resolve_import(package_id, &import, &mut selected_providers)
+            else {
+                continue;
+            };
+
+            let export_path = ForeignInterfacePath::new(
+                import.package_name().to_string(),
+                import.interface_name().to_string(),
+                Some(version.clone()),
+            );
+            let Some(export) = self.exported_interfaces.get(&(provider, export_path)) else {
+                continue;
+            };
+
+            let mismatch = self.import_type_mismatch(
+                package,
+                &import,
+                *import_interface,
+                provider,
+                export.interface,
+                &mut subtype_cache,
+            );
+
+            if let Some(mismatch) = mismatch {
+                return Err(mismatch);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn import_type_mismatch(
+        &self,
+        importer: &Package,
+        import: &ForeignInterfacePath,
+        import_interface: InterfaceId,
+        exporter: PackageId,
+        export_interface: InterfaceId,
+        subtype_cache: &mut HashSet<(ItemKind, ItemKind)>,
+    ) -> Option<Box<InterfaceTypeMismatch>> {
+        SubtypeChecker::new(subtype_cache)
+            .is_subtype(
+                ItemKind::Instance(export_interface),
+                &self.types,
+                ItemKind::Instance(import_interface),
+                &self.types,
+            )
+            .err()?;
+
+        Some(Box::new(InterfaceTypeMismatch {
+            importer: package_label(importer),
+            import: import.clone(),
+            exporter: self.label_of(exporter),
+            mismatches: interface_mismatches(
+                &self.types,
+                import_interface,
+                export_interface,
+                subtype_cache,
+            ),
+        }))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn instantiate_shadowed_package(
+        &self,
+        package_id: PackageId,
+        package: &Package,
+        linker: &mut component::Linker<D>,
+        mut store: impl AsContextMut<Data = D>,
+        component: Component,
+        interfaces: &ShadowedInterfaces,
+        instances: &mut ShadowInstances,
+        contexts: Option<&ContextOverlay<C>>,
+    ) -> Result<(), InstantiatePackageError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        let reused = instances
+            .instances
+            .get(&package_id)
+            .map(|(instance, _)| *instance);
+        let resources = instances.resources.package(package_id);
+
+        let table = self.build_function_table(
+            package_id,
+            &component,
+            interfaces
+                .iter()
+                .map(|(path, shadowed)| (path, Some(shadowed))),
+            contexts,
+            Some(&resources),
+        )?;
+
+        let defers = self.lazy_instantiation
+            && table
+                .interfaces
+                .iter()
+                .all(|interface| interface.resources.is_empty());
+
+        let shadow_instance = match reused {
+            Some(instance) => {
+                log_debug!(id:? = package_id; "Reusing dependency package instance");
+                ShadowInstance::Instantiated(instance)
+            }
+            None if defers => {
+                self.check_import_types(package_id, package)
+                    .context(instantiate_package_error::InterfaceTypeMismatchSnafu)?;
+
+                log_debug!(id:? = package_id; "Deferring dependency package instantiation");
+
+                // The linker already defines the imports of the package, since its dependencies
+                // are linked before it.
+                ShadowInstance::Lazy(Arc::new(LazyInstance {
+                    package_id,
+                    name: package.name().to_string(),
+                    version: package.version().cloned(),
+                    component,
+                    linker: linker.clone(),
+                    slot: LazySlot::Once(OnceLock::new()),
+                    tracked: self.tracked_package(package),
+                    limits: self.package_limits.get(&package_id).cloned(),
+                    scopes: self.store_scopes,
+                    events: self.events.clone(),
+                }))
+            }
+            None => {
+                self.check_import_types(package_id, package)
+                    .context(instantiate_package_error::InterfaceTypeMismatchSnafu)?;
+
+                let started = Instant::now();
+                let _memory_scope = self.memory_scope(package, &store);
+                let _limit_scope = self.limit_scope(package_id, &store);
+                let instance = linker
+                    .instantiate(&mut store, &component)
+                    .map_err(InstantiatePackageError::from_instantiation)?;
+
+                instances.record(package_id, instance, component);
+                self.emit_instantiated(package_id, package, started);
+                ShadowInstance::Instantiated(instance)
+            }
+        };
+
+        self.shadow_package(
+            &table,
+            shadow_instance,
+            &resources,
+            linker,
+            Some(store),
+            SyncInstanceShadower,
+        )
+    }
+
+    /// Links the shadowed interfaces of a dependency package into `linker` without a store, for
+    /// `instantiate_pre`. The package is instantiated on the first call into it in each store,
+    /// and recorded in the `ShadowInstances` returned by `instances`.
+    fn link_deferred_package(
+        &self,
+        package_id: PackageId,
+        package: &Package,
+        linker: &mut component::Linker<D>,
+        component: Component,
+        interfaces: &ShadowedInterfaces,
+        instances: fn(&mut D) -> &mut ShadowInstances,
+    ) -> Result<(), InstantiatePackageError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        let table = self.build_function_table(
+            package_id,
+            &component,
+            interfaces
+                .iter()
+                .map(|(path, shadowed)| (path, Some(shadowed))),
+            None,
+            None,
+        )?;
+
+        // The types of resources exported by guests are only known once they are instantiated.
+        if let Some(interface) = table
+            .interfaces
+            .iter()
+            .find(|interface| !interface.resources.is_empty())
+        {
+            return Err(InstantiatePackageError::UnsupportedResourceExport {
+                interface_name: interface.name.clone(),
+            });
+        }
+
+        self.check_import_types(package_id, package)
+            .context(instantiate_package_error::InterfaceTypeMismatchSnafu)?;
+
+        let shadow_instance = ShadowInstance::Lazy(Arc::new(LazyInstance {
+            package_id,
+            name: package.name().to_string(),
+            version: package.version().cloned(),
+            component,
+            linker: linker.clone(),
+            slot: LazySlot::PerStore(instances),
+            tracked: self.tracked_package(package),
+            limits: self.package_limits.get(&package_id).cloned(),
+            scopes: self.store_scopes,
+            events: self.events.clone(),
+        }));
+
+        self.shadow_package(
+            &table,
+            shadow_instance,
+            &ShadowInstances::new().resources.package(package_id),
+            linker,
+            None::<&mut wasmtime::Store<D>>,
+            SyncInstanceShadower,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn instantiate_shadowed_package_async(
+        &self,
+        package_id: PackageId,
+        package: &Package,
+        linker: &mut component::Linker<D>,
+        mut store: impl AsContextMut<Data = D>,
+        component: Component,
+        interfaces: &ShadowedInterfaces,
+        instances: &mut ShadowInstances,
+        contexts: Option<&ContextOverlay<C>>,
+    ) -> Result<(), InstantiatePackageError>
+    where
+        D: Send + 'static,
+        C: Send + Sync + 'static,
+    {
+        let reused = instances
+            .instances
+            .get(&package_id)
+            .map(|(instance, _)| *instance);
+        let resources = instances.resources.package(package_id);
+
+        let table = self.build_function_table(
+            package_id,
+            &component,
+            interfaces
+                .iter()
+                .map(|(path, shadowed)| (path, Some(shadowed))),
+            contexts,
+            Some(&resources),
+        )?;
+
+        let shadow_instance = match reused {
+            Some(instance) => {
+                log_debug!(id:? = package_id; "Reusing dependency package instance");
+                instance
+            }
+            None => {
+                self.check_import_types(package_id, package)
+                    .context(instantiate_package_error::InterfaceTypeMismatchSnafu)?;
+
+                let started = Instant::now();
+                let _memory_scope = self.memory_scope(package, &store);
+                let _limit_scope = self.limit_scope(package_id, &store);
+                let instance = linker
+                    .instantiate_async(&mut store, &component)
+                    .await
+                    .map_err(InstantiatePackageError::from_instantiation)?;
+
+                instances.record(package_id, instance, component);
+                self.emit_instantiated(package_id, package, started);
+                instance
+            }
+        };
+
+        self.shadow_package(
+            &table,
+            ShadowInstance::Instantiated(shadow_instance),
+            &resources,
+            linker,
+            Some(store),
+            AsyncInstanceShadower,
+        )
+    }
+
+    /// Resolves the functions of the given interfaces exported by a package against the
+    /// package's compiled component, so that each instance of the component can be shadowed
+    /// without looking up exports by name.
+    ///
+    /// Interfaces are paired with the functions to resolve and the aliases to link them under, or
+    /// `None` to resolve all of them. Functions passing resource handles proxy them in
+    /// `resources`, if any.
+    fn build_function_table<'i>(
+        &self,
+        package_id: PackageId,
+        component: &Component,
+        interfaces: impl IntoIterator<Item = (&'i ForeignInterfacePath, Option<&'i ShadowedInterface>)>,
+        contexts: Option<&ContextOverlay<C>>,
+        resources: Option<&PackageResources>,
+    ) -> Result<FunctionTable<D, C>, InstantiatePackageError>
+    where
+        D: 'static,
+    {
+        let package = self
+            .packages
+            .get(package_id.id)
+            .filter(|package| package.nonce == package_id.nonce)
+            .ok_or_else(|| InstantiatePackageError::InternalError {
+                message: self
+                    .invariant_violation(format!("shadowed package {package_id:?} not found")),
+            })?;
+
+        let mut table = FunctionTable {
+            interfaces: Vec::new(),
+        };
+        let tracked_package = self.tracked_package(package);
+        let scopes = self.store_scopes.map(|scopes| -> ScopesAccessor {
+            Arc::new(move |data: &dyn Any| {
+                data.downcast_ref::<D>().map(|data| scopes(data).clone())
+            })
+        });
+
+        for (interface_path, shadowed) in interfaces {
+            let interface_path = interface_path.clone();
+            let interface_full_name = interface_path.as_str();
+
+            let interface_index = component
+                .get_export_index(None, interface_full_name)
+                .ok_or_else(|| InstantiatePackageError::InstanceMissingInterfaceExport {
+                    interface_name: interface_full_name.to_string(),
+                })?;
+
+            let interface_export = self
+                .exported_interfaces
+                .get(&(package_id, interface_path.clone()))
+                .ok_or_else(|| InstantiatePackageError::MissingInterfaceExport {
+                    path: interface_path.clone(),
+                })?;
+
+            let interface = &self.types[interface_export.interface];
+            let mut functions = Vec::new();
+            let error_rate = self
+                .error_rate_monitor
+                .as_ref()
+                .and_then(|monitor| monitor.interface(&interface_path));
+
+            for (export_name, export_kind) in &interface.exports {
+                let ItemKind::Func(func_id) = export_kind else {
+                    continue;
+                };
+
+                if shadowed.is_some_and(|shadowed| !shadowed.functions.contains(export_name)) {
+                    continue;
+                }
+
+                let func_index = component
+                    .get_export_index(Some(&interface_index), export_name)
+                    .ok_or_else(
+                        || InstantiatePackageError::InstanceMissingInterfaceFuncExport {
+                            interface_name: interface_full_name.to_string(),
+                            func_name: export_name.to_string(),
+                        },
+                    )?;
+
+                let meta = Arc::new(CallMeta {
+                    interface_path: interface_path.clone(),
+                    export_name: export_name.clone(),
+                    func_ty: self.types[*func_id].clone(),
+                    #[cfg(feature = "metrics")]
+                    metrics: self
+                        .call_metrics
+                        .as_ref()
+                        .map(|metrics| metrics.function(&interface_path, export_name)),
+                    #[cfg(feature = "recording")]
+                    profile: self
+                        .call_profiler
+                        .as_ref()
+                        .map(|profiler| profiler.function(&interface_path, export_name)),
+                    in_flight: self
+                        .in_flight_calls
+                        .as_ref()
+                        .map(|in_flight| in_flight.function(&interface_path, export_name)),
+                    #[cfg(feature = "recording")]
+                    trace: self
+                        .call_trace
+                        .as_ref()
+                        .map(|trace| trace.function(&interface_path, export_name)),
+                    memory: tracked_package
+                        .as_ref()
+                        .map(|package| package.function(&interface_path, export_name)),
+                    limits: self.package_limits.get(&package_id).cloned(),
+                    scopes: scopes.clone(),
+                    slow_call: self
+                        .slow_call_detector
+                        .as_ref()
+                        .and_then(|detector| detector.function(&interface_path, export_name)),
+                    error_rate: error_rate.clone(),
+                    policy: self
+                        .policy
+                        .as_ref()
+                        .and_then(|policy| policy.function(&interface_path, export_name)),
+                    events: self.events.clone(),
+                    resources: resources
+                        .filter(|_| func_has_resources(&self.types, &self.types[*func_id]))
+                        .cloned(),
+                    package_metadata: self.package_metadata.get(&package_id).cloned(),
+                });
+
+                functions.push((meta, func_index));
+            }
+
+            table.interfaces.push(FunctionTableInterface {
+                name: interface_full_name.to_string(),
+                aliases: shadowed
+                    .into_iter()
+                    .flat_map(|shadowed| &shadowed.aliases)
+                    .map(|alias| alias.as_str().to_string())
+                    .collect(),
+                resources: declared_resources(&self.types, &interface.exports)
+                    .map(ToString::to_string)
+                    .collect(),
+                trampoline: match contexts.and_then(|contexts| contexts.get(&interface_path)) {
+                    Some(context) => interface_export.trampoline.with_context(context.clone()),
+                    None => interface_export.trampoline.clone(),
+                },
+                functions,
+            });
+        }
+
+        Ok(table)
+    }
+
+    fn shadow_package(
+        &self,
+        table: &FunctionTable<D, C>,
+        shadow_instance: ShadowInstance<D>,
+        resources: &PackageResources,
+        linker: &mut component::Linker<D>,
+        mut store: Option<impl AsContextMut<Data = D>>,
+        shadower: impl InstanceShadower<D, C>,
+    ) -> Result<(), InstantiatePackageError> {
+        let missing_store = || InstantiatePackageError::InternalError {
+            message: self
+                .invariant_violation("instantiated package shadowed without its store".to_string()),
+        };
+
+        // Reused across interfaces, so that packages exporting many functions do not allocate a
+        // buffer per interface.
+        let mut functions = Vec::new();
+
+        for interface in &table.interfaces {
+            for (meta, func_index) in &interface.functions {
+                let shadow_func = match &shadow_instance {
+                    ShadowInstance::Instantiated(instance) => instance
+                        .get_func(store.as_mut().ok_or_else(missing_store)?, func_index)
+                        .map(ShadowFunc::Instantiated)
+                        .ok_or_else(|| InstantiatePackageError::ComponentFuncRetrievalError {
+                            interface_name: interface.name.clone(),
+                            func_name: meta.export_name.clone(),
+                        })?,
+                    ShadowInstance::Lazy(instance) => ShadowFunc::Lazy {
+                        instance: instance.clone(),
+                        index: *func_index,
+                    },
+                };
+
+                functions.push((meta.clone(), shadow_func));
+            }
+
+            let mut front_instance = linker
+                .instance(&interface.name)
+                .context(instantiate_package_error::LinkerInstanceSnafu)?;
+
+            if !interface.resources.is_empty() {
+                let ShadowInstance::Instantiated(shadow_instance) = &shadow_instance else {
+                    return Err(InstantiatePackageError::InternalError {
+                        message: self.invariant_violation(format!(
+                            "lazily instantiated package exports resources of '{}'",
+                            interface.name
+                        )),
+                    });
+                };
+                let mut store = store.as_mut().ok_or_else(missing_store)?;
+                let interface_index =
+                    shadow_instance.get_export_index(&mut store, None, &interface.name);
+
+                for name in &interface.resources {
+                    let ty = interface_index
+                        .and_then(|index| {
+                            shadow_instance.get_export_index(&mut store, Some(&index), name)
+                        })
+                        .and_then(|index| shadow_instance.get_resource(&mut store, index))
+                        .ok_or_else(|| InstantiatePackageError::InternalError {
+                            message: self.invariant_violation(format!(
+                                "resource '{name}' of '{}' not found in its instance",
+                                interface.name
+                            )),
+                        })?;
+
+                    resources.export_type(ty);
+                    shadower.shadow_resource(&mut front_instance, name, resources.clone())?;
+                }
+            }
+
+            let aliased = if interface.aliases.is_empty() {
+                Vec::new()
+            } else {
+                functions.clone()
+            };
+            shadower.shadow_interface(
+                &mut front_instance,
+                functions.drain(..),
+                &interface.trampoline,
+            )?;
+
+            for alias in &interface.aliases {
+                let mut alias_instance = linker
+                    .instance(alias)
+                    .context(instantiate_package_error::LinkerInstanceSnafu)?;
+
+                for name in &interface.resources {
+                    shadower.shadow_resource(&mut alias_instance, name, resources.clone())?;
+                }
+
+                shadower.shadow_interface(
+                    &mut alias_instance,
+                    aliased.clone().drain(..),
+                    &interface.trampoline,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// # Panics
+///
+/// Panics if the package is not in the graph or is a lazily added package that has not been
+/// parsed yet, regardless of the invariant policy. Use `CompositionGraph::package` to look up
+/// packages without panicking.
+impl<D, C: Clone> Index<PackageId> for CompositionGraph<D, C> {
+    type Output = Package;
+
+    fn index(&self, index: PackageId) -> &Self::Output {
+        let package = self
+            .packages
+            .get(index.id)
+            .expect("package id out of bounds");
+
+        assert_eq!(
+            package.nonce, index.nonce,
+            "package nonce mismatch for id {index:?}"
+        );
+
+        package
+    }
+}
+
+/// Returns the instance of each of the root packages `package_ids`, in order, once they were all
+/// instantiated into `instances`.
+fn root_instances(
+    package_ids: &[PackageId],
+    instances: &ShadowInstances,
+) -> Result<Vec<Instance>, InstantiateError> {
+    package_ids
+        .iter()
+        .map(|&package_id| {
+            instances
+                .get(package_id)
+                .ok_or(InstantiateError::PackageNotFound { id: package_id })
+        })
+        .collect()
+}
+
+pub(crate) fn package_label(package: &Package) -> String {
+    match package.version() {
+        Some(version) => format!("{}@{version}", package.name()),
+        None => package.name().to_string(),
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PackageWrapper {
+    /// The parsed package, which is `None` until a lazily added package is parsed.
+    package: Option<Package>,
+    nonce: usize,
+}
+
+impl Deref for PackageWrapper {
+    type Target = Package;
+
+    fn deref(&self) -> &Self::Target {
+        self.package
+            .as_ref()
+            .expect("lazily added package has not been parsed")
+    }
+}
+
+/// A package added with `CompositionGraph::add_package_lazy` that has not been parsed yet.
+struct PendingPackage<D, C: Clone> {
+    name: String,
+    version: Version,
+    bytes: Vec<u8>,
+    trampoline: Box<dyn DynPackageTrampoline<D, C> + Send + Sync>,
+}
+
+/// Yields to the executor once, so that long synchronous work in asynchronous functions does not
+/// starve other tasks.
+async fn yield_now() {
+    let mut yielded = false;
+
+    std::future::poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// The imported functions of the interfaces exported by a package, by export path.
+type ShadowedInterfaces = IndexMap<ForeignInterfacePath, ShadowedInterface>;
+
+/// The functions of an interface exported by a package that its importers import.
+#[derive(Clone, Default, Debug)]
+struct ShadowedInterface {
+    functions: HashSet<String>,
+    /// The import paths the interface is linked under besides its export path, for imports the
+    /// linker does not match to it.
+    aliases: IndexSet<ForeignInterfacePath>,
+}
+
+/// The packages importing each stubbed import, see `CompositionGraph::set_import_stubs`.
+type StubbedImports = IndexMap<ForeignInterfacePath, Vec<PackageId>>;
+
+/// The immutable description of a function shadowed by a trampoline, shared by all calls to it.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct CallMeta {
+    interface_path: ForeignInterfacePath,
+    export_name: String,
+    func_ty: wac_types::FuncType,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<FuncMetrics>>,
+    #[cfg(feature = "recording")]
+    profile: Option<ProfiledFunc>,
+    in_flight: Option<InFlightFunc>,
+    #[cfg(feature = "recording")]
+    trace: Option<TracedFunc>,
+    memory: Option<TrackedFunc>,
+    limits: Option<Arc<PackageLimits>>,
+    #[derivative(Debug = "ignore")]
+    scopes: Option<ScopesAccessor>,
+    slow_call: Option<SlowCallFunc>,
+    error_rate: Option<MonitoredInterface>,
+    policy: Option<PolicedFunc>,
+    events: Arc<EventSubscribers>,
+    resources: Option<PackageResources>,
+    package_metadata: Option<Arc<PackageMetadata>>,
+}
+
+/// A call to a shadowed function admitted under the graph's policy.
+#[derive(Default)]
+struct AdmittedCall {
+    /// When the call started, if it has a latency budget or timeout.
+    started: Option<Instant>,
+    /// The deadline of the call, left when the call returns.
+    _deadline: Option<Scope<CallDeadline>>,
+}
+
+/// A call to a shadowed function being recorded.
+struct StartedCall {
+    started: Instant,
+    /// The fuel remaining in the store when the call started, if fuel is measured.
+    fuel: Option<u64>,
+    in_flight: Option<(u64, Option<Scope<CallFrame>>)>,
+    memory: Option<Scope<Attribution>>,
+    limits: Option<Scope<PackageLimits>>,
+}
+
+impl CallMeta {
+    /// Returns the path of the interface exporting the function.
+    #[must_use]
+    pub fn interface_path(&self) -> &ForeignInterfacePath {
+        &self.interface_path
+    }
+
+    /// Returns the name of the function within its interface.
+    #[must_use]
+    pub fn method(&self) -> &str {
+        &self.export_name
+    }
+
+    /// Returns the WIT type of the function.
+    #[must_use]
+    pub fn func_type(&self) -> &wac_types::FuncType {
+        &self.func_ty
+    }
+
+    /// Returns the metadata of the package exporting the function, if any.
+    #[must_use]
+    pub fn package_metadata(&self) -> Option<&PackageMetadata> {
+        self.package_metadata.as_deref()
+    }
+
+    /// Returns whether calls are recorded in metrics.
+    #[cfg(feature = "metrics")]
+    fn records_metrics(&self) -> bool {
+        self.metrics.is_some()
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn records_metrics(&self) -> bool {
+        false
+    }
+
+    /// Returns whether calls are recorded in profiles or traces.
+    #[cfg(feature = "recording")]
+    fn records_calls(&self) -> bool {
+        self.profile.is_some() || self.trace.is_some()
+    }
+
+    #[cfg(not(feature = "recording"))]
+    fn records_calls(&self) -> bool {
+        false
+    }
+
+    /// Starts recording a call, if calls are recorded in metrics, profiles, traces, events,
+    /// in-flight calls, memory usage or error rates, checked for slowness, or limited.
+    fn start_call<T: 'static>(&self, store: impl AsContext<Data = T>) -> Option<StartedCall> {
+        let emits_events = self.events.is_active();
+
+        if emits_events {
+            self.events.emit(|| GraphEvent::CallStarted {
+                interface: self.interface_path.clone(),
+                method: self.export_name.clone(),
+            });
+        }
+
+        if !emits_events
+            && !self.records_metrics()
+            && !self.records_calls()
+            && self.in_flight.is_none()
+            && self.memory.is_none()
+            && self.limits.is_none()
+            && self.slow_call.is_none()
+            && self.error_rate.is_none()
+        {
+            return None;
+        }
+
+        let started = Instant::now();
+        let scopes = self
+            .scopes
+            .as_ref()
+            .and_then(|scopes| scopes(store.as_context().data()));
+
+        #[cfg(feature = "recording")]
+        if let Some(profile) = &self.profile {
+            profile.enter(started);
+        }
+
+        #[cfg(feature = "metrics")]
+        let fuel = self
+            .metrics
+            .as_ref()
+            .and_then(|metrics| Some((metrics, store.as_context().get_fuel().ok()?)))
+            .map(|(metrics, fuel)| {
+                metrics.enter_fuel();
+                fuel
+            });
+        // Only the fuel metrics read the store.
+        #[cfg(not(feature = "metrics"))]
+        let (fuel, _) = (None, store);
+
+        Some(StartedCall {
+            started,
+            fuel,
+            in_flight: self
+                .in_flight
+                .as_ref()
+                .map(|in_flight| in_flight.enter(started, scopes.as_ref())),
+            memory: self
+                .memory
+                .as_ref()
+                .zip(scopes.as_ref())
+                .map(|(memory, scopes)| memory.enter(scopes)),
+            limits: self
+                .limits
+                .clone()
+                .zip(scopes.as_ref())
+                .map(|(limits, scopes)| scopes.limits.enter(limits)),
+        })
+    }
+
+    fn finish_call<T>(
+        &self,
+        call: Option<StartedCall>,
+        store: impl AsContext,
+        result: &Result<T, anyhow::Error>,
+    ) {
+        let Some(StartedCall {
+            started,
+            fuel,
+            in_flight,
+            memory,
+            limits,
+        }) = call
+        else {
+            return;
+        };
+
+        drop(limits);
+        drop(memory);
+
+        if let (Some(func), Some((id, scope))) = (&self.in_flight, in_flight) {
+            drop(scope);
+            func.exit(id);
+        }
+
+        #[cfg(feature = "recording")]
+        if let Some(profile) = &self.profile {
+            profile.exit(started);
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record(started, result.is_ok());
+
+            if let Some(before) = fuel {
+                let after = store.as_context().get_fuel().unwrap_or(before);
+                metrics.exit_fuel(before, after);
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = (fuel, store);
+
+        #[cfg(feature = "recording")]
+        if let Some(trace) = &self.trace {
+            trace.record(started, result.is_ok());
+        }
+
+        if let Some(slow_call) = &self.slow_call {
+            slow_call.check(started.elapsed(), result.is_ok());
+        }
+
+        if let Some(error_rate) = &self.error_rate {
+            error_rate.record(result.is_ok());
+        }
+
+        let err = result.as_ref().err();
+        let call_error = err.and_then(|err| err.downcast_ref::<CallError>());
+
+        // Traps propagate through all calls on the stack, but are only raised for the call that
+        // trapped.
+        let trapped = call_error.is_some_and(|call_error| match call_error {
+            CallError::Guest { interface, method } => {
+                *interface == self.interface_path && *method == self.export_name
+            }
+            CallError::Trampoline { .. } => false,
+        });
+
+        if let Some(trap) = err
+            .and_then(|err| err.downcast_ref::<Trap>())
+            .filter(|_| trapped)
+        {
+            self.events.emit(|| GraphEvent::Trapped {
+                interface: self.interface_path.clone(),
+                method: self.export_name.clone(),
+                trap: *trap,
+            });
+        }
+
+        self.events.emit(|| GraphEvent::CallFinished {
+            interface: self.interface_path.clone(),
+            method: self.export_name.clone(),
+            duration: started.elapsed(),
+            error: call_error.cloned(),
+        });
+    }
+
+    /// Replaces the proxies of the resource handles passed by the importing package with the
+    /// handles of the shadowed package.
+    fn unwrap_resources<'a>(
+        &self,
+        store: impl AsContextMut,
+        arguments: &'a [Val],
+    ) -> Result<Cow<'a, [Val]>, anyhow::Error> {
+        match &self.resources {
+            Some(resources) => resources.unwrap_arguments(store, arguments),
+            None => Ok(Cow::Borrowed(arguments)),
+        }
+    }
+
+    /// Replaces the resource handles returned by the shadowed package with proxies.
+    fn wrap_resources(
+        &self,
+        store: impl AsContextMut,
+        results: &mut [Val],
+    ) -> Result<(), anyhow::Error> {
+        match &self.resources {
+            Some(resources) => resources.wrap_results(store, results),
+            None => Ok(()),
+        }
+    }
+
+    /// Admits a call in `store` under the graph's policy, entering its deadline in the store's
+    /// scopes if it has a timeout.
+    fn admit(&self, store: impl AsContext) -> Result<AdmittedCall, anyhow::Error> {
+        let Some(policy) = &self.policy else {
+            return Ok(AdmittedCall::default());
+        };
+
+        let started = policy
+            .admit()
+            .map_err(|violation| self.trampoline_error(violation.into()))?;
+        let deadline = policy
+            .deadline(started)
+            .zip(
+                self.scopes
+                    .as_ref()
+                    .and_then(|scopes| scopes(store.as_context().data())),
+            )
+            .map(|(deadline, scopes)| scopes.deadlines.enter(Arc::new(deadline)));
+
+        Ok(AdmittedCall {
+            started,
+            _deadline: deadline,
+        })
+    }
+
+    /// Warns about an admitted call if it exceeded its latency budget under the graph's policy.
+    fn check_latency_budget(&self, admitted: &AdmittedCall) {
+        if let Some((elapsed, budget)) = self
+            .policy
+            .as_ref()
+            .and_then(|policy| policy.over_budget(admitted.started))
+        {
+            log_warn!(
+                interface:% = self.interface_path,
+                method = self.export_name.as_str(),
+                elapsed:?,
+                budget:?;
+                "Call exceeded its latency budget"
+            );
+            #[cfg(not(feature = "log"))]
+            let _ = (elapsed, budget);
+        }
+    }
+
+    fn guest_error(&self, err: anyhow::Error) -> anyhow::Error {
+        with_call_error(err, || {
+            CallError::guest(self.interface_path.clone(), self.export_name.clone())
+        })
+    }
+
+    fn trampoline_error(&self, err: anyhow::Error) -> anyhow::Error {
+        with_call_error(err, || {
+            CallError::trampoline(self.interface_path.clone(), self.export_name.clone())
+        })
+    }
+}
+
+/// The packages to instantiate for a package and the interfaces to shadow in each, resolved by
+/// `CompositionGraph::plan`.
+///
+/// The plan borrows the graph, so the graph cannot change while the plan is alive, and can be
+/// executed against multiple stores without resolving the dependencies again.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub struct InstantiationPlan<'a, D, C: Clone = ()> {
+    #[derivative(Debug = "ignore")]
+    graph: &'a CompositionGraph<D, C>,
+    package_id: PackageId,
+    load_order: Vec<PackageId>,
+    interfaces: IndexMap<PackageId, ShadowedInterfaces>,
+    stubs: StubbedImports,
+}
+
+impl<D, C: Clone> InstantiationPlan<'_, D, C> {
+    /// The package instantiated by the plan.
+    #[must_use]
+    pub fn package_id(&self) -> PackageId {
+        self.package_id
+    }
+
+    /// The packages in the order they are instantiated, dependencies first and ending with the
+    /// planned package.
+    #[must_use]
+    pub fn load_order(&self) -> &[PackageId] {
+        &self.load_order
+    }
+
+    /// The names of the interfaces exported by the package `package_id` that are shadowed by
+    /// trampolines, since packages of the plan import them.
+    pub fn shadowed_interfaces(&self, package_id: PackageId) -> impl Iterator<Item = &str> {
+        self.interfaces
+            .get(&package_id)
+            .into_iter()
+            .flat_map(IndexMap::keys)
+            .map(ForeignInterfacePath::interface_name)
+    }
+
+    /// The functions of the interface `interface` exported by the package `package_id` that are
+    /// shadowed by trampolines.
+    pub fn shadowed_functions(
+        &self,
+        package_id: PackageId,
+        interface: &str,
+    ) -> impl Iterator<Item = &str> {
+        self.interfaces
+            .get(&package_id)
+            .into_iter()
+            .flatten()
+            .filter(move |(path, _)| path.interface_name() == interface)
+            .flat_map(|(_, shadowed)| &shadowed.functions)
+            .map(String::as_str)
+    }
+
+    /// Instantiates the planned package and its dependencies, like
+    /// `CompositionGraph::instantiate`.
+    pub fn execute(
+        &self,
+        linker: &mut component::Linker<D>,
+        store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        self.execute_with(linker, store, engine, InstantiateOptions::new())
+    }
+
+    /// Like `execute`, but with the options of `CompositionGraph::instantiate_with`.
+    pub fn execute_with(
+        &self,
+        linker: &mut component::Linker<D>,
+        mut store: impl AsContextMut<Data = D>,
+        engine: &wasmtime::Engine,
+        options: InstantiateOptions<'_, C>,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        let graph = self.graph;
+        let mut fresh_instances = ShadowInstances::new();
+        let instances = options.instances.unwrap_or(&mut fresh_instances);
+
+        let package =
+            graph
+                .packages
+                .get(self.package_id.id)
+                .ok_or(InstantiateError::PackageNotFound {
+                    id: self.package_id,
+                })?;
+
+        let component = graph
+            .component_cache
+            .get_or_compile(engine, package.bytes())
+            .context(instantiate_error::ComponentCompilationSnafu)?;
+
+        #[cfg(feature = "deterministic")]
+        graph.link_deterministic(linker)?;
+        graph.link_stubs(&self.stubs, linker, engine)?;
+
+        for &shadow_package_id in &self.load_order {
+            if shadow_package_id == self.package_id {
+                break;
+            }
+
+            let shadow_package = graph.packages.get(shadow_package_id.id).ok_or(
+                InstantiateError::PackageNotFound {
+                    id: shadow_package_id,
+                },
+            )?;
+
+            let empty_map = IndexMap::new();
+            let shadow_interfaces = self
+                .interfaces
+                .get(&shadow_package_id)
+                .unwrap_or(&empty_map);
+
+            let component = graph
+                .component_cache
+                .get_or_compile(engine, shadow_package.bytes())
+                .context(instantiate_package_error::ComponentCompilationSnafu);
+
+            let instantiated = match component {
+                Ok(component) => graph.instantiate_shadowed_package(
+                    shadow_package_id,
+                    shadow_package,
+                    linker,
+                    &mut store,
+                    component,
+                    shadow_interfaces,
+                    instances,
+                    options.contexts,
+                ),
+                Err(err) => Err(err),
+            };
+
+            instantiated.with_context(|_err| {
+                instantiate_error::InstantiatePackageDependencySnafu {
+                    name: shadow_package.name().to_string(),
+                    version: shadow_package.version().cloned(),
+                }
+            })?;
+        }
+
+        graph
+            .check_import_types(self.package_id, package)
+            .context(instantiate_error::InterfaceTypeMismatchSnafu)?;
+
+        let started = Instant::now();
+        let _memory_scope = graph.memory_scope(package, &store);
+        let _limit_scope = graph.limit_scope(self.package_id, &store);
+        let instance = linker
+            .instantiate(&mut store, &component)
+            .map_err(InstantiateError::from_instantiation)?;
+
+        instances.record(self.package_id, instance, component);
+        graph.emit_instantiated(self.package_id, package, started);
+
+        Ok(instance)
+    }
+
+    /// Links the planned package and its dependencies without a store, like
+    /// `CompositionGraph::instantiate_pre`.
+    pub fn execute_pre(
+        &self,
+        linker: &mut component::Linker<D>,
+        engine: &wasmtime::Engine,
+        instances: fn(&mut D) -> &mut ShadowInstances,
+    ) -> Result<InstancePre<D>, InstantiateError>
+    where
+        D: 'static,
+        C: Send + Sync + 'static,
+    {
+        let graph = self.graph;
+        let package =
+            graph
+                .packages
+                .get(self.package_id.id)
+                .ok_or(InstantiateError::PackageNotFound {
+                    id: self.package_id,
+                })?;
+
+        let component = graph
+            .component_cache
+            .get_or_compile(engine, package.bytes())
+            .context(instantiate_error::ComponentCompilationSnafu)?;
+
+        #[cfg(feature = "deterministic")]
+        graph.link_deterministic(linker)?;
+        graph.link_stubs(&self.stubs, linker, engine)?;
+
+        for &shadow_package_id in &self.load_order {
+            if shadow_package_id == self.package_id {
+                break;
+            }
+
+            let shadow_package = graph.packages.get(shadow_package_id.id).ok_or(
+                InstantiateError::PackageNotFound {
+                    id: shadow_package_id,
+                },
+            )?;
+
+            let empty_map = IndexMap::new();
+            let shadow_interfaces = self
+                .interfaces
+                .get(&shadow_package_id)
+                .unwrap_or(&empty_map);
 
-                interfaces
-                    .entry(*import_package)
-                    .or_default()
-                    .insert(import.interface_name().to_string());
-            }
+            graph
+                .component_cache
+                .get_or_compile(engine, shadow_package.bytes())
+                .context(instantiate_package_error::ComponentCompilationSnafu)
+                .and_then(|component| {
+                    graph.link_deferred_package(
+                        shadow_package_id,
+                        shadow_package,
+                        linker,
+                        component,
+                        shadow_interfaces,
+                        instances,
+                    )
+                })
+                .with_context(
+                    |_err| instantiate_error::InstantiatePackageDependencySnafu {
+                        name: shadow_package.name().to_string(),
+                        version: shadow_package.version().cloned(),
+                    },
+                )?;
         }
 
-        Ok(load_order.into_iter().chain(load_stack.into_iter().rev()))
+        graph
+            .check_import_types(self.package_id, package)
+            .context(instantiate_error::InterfaceTypeMismatchSnafu)?;
+
+        linker
+            .instantiate_pre(&component)
+            .map_err(InstantiateError::from_instantiation)
     }
 
-    fn instantiate_shadowed_package(
+    /// Like `execute`, but for asynchronous contexts.
+    pub async fn execute_async(
         &self,
-        package: &Package,
         linker: &mut component::Linker<D>,
-        mut store: impl AsContextMut<Data = D>,
+        store: impl AsContextMut<Data = D>,
         engine: &wasmtime::Engine,
-        interfaces: &IndexSet<String>,
-    ) -> Result<(), InstantiatePackageError>
+    ) -> Result<Instance, InstantiateError>
     where
-        D: 'static,
+        D: Send + 'static,
         C: Send + Sync + 'static,
     {
-        let component = Component::new(engine, package.bytes())
-            .context(instantiate_package_error::ComponentInstantiationSnafu)?;
-
-        let shadow_instance = linker
-            .instantiate(&mut store, &component)
-            .context(instantiate_package_error::ComponentInstantiationSnafu)?;
-
-        self.shadow_package(
-            package,
-            Rc::new(shadow_instance),
-            linker,
-            store,
-            interfaces,
-            SyncInstanceShadower,
-        )
+        self.execute_with_async(linker, store, engine, InstantiateOptions::new())
+            .await
     }
 
-    async fn instantiate_shadowed_package_async(
+    /// Like `execute_with`, but for asynchronous contexts.
+    pub async fn execute_with_async(
         &self,
-        package: &Package,
         linker: &mut component::Linker<D>,
         mut store: impl AsContextMut<Data = D>,
         engine: &wasmtime::Engine,
-        interfaces: &IndexSet<String>,
-    ) -> Result<(), InstantiatePackageError>
+        options: InstantiateOptions<'_, C>,
+    ) -> Result<Instance, InstantiateError>
     where
         D: Send + 'static,
         C: Send + Sync + 'static,
     {
-        let component = Component::new(engine, package.bytes())
-            .context(instantiate_package_error::ComponentInstantiationSnafu)?;
+        let graph = self.graph;
+        let mut fresh_instances = ShadowInstances::new();
+        let instances = options.instances.unwrap_or(&mut fresh_instances);
 
-        let shadow_instance = linker
-            .instantiate_async(&mut store, &component)
-            .await
-            .context(instantiate_package_error::ComponentInstantiationSnafu)?;
+        let package =
+            graph
+                .packages
+                .get(self.package_id.id)
+                .ok_or(InstantiateError::PackageNotFound {
+                    id: self.package_id,
+                })?;
 
-        self.shadow_package(
-            package,
-            Rc::new(shadow_instance),
-            linker,
-            store,
-            interfaces,
-            AsyncInstanceShadower,
-        )
-    }
+        let component = graph
+            .component_cache
+            .get_or_compile(engine, package.bytes())
+            .context(instantiate_error::ComponentCompilationSnafu)?;
 
-    fn shadow_package(
-        &self,
-        package: &Package,
-        shadow_instance: Rc<Instance>,
-        linker: &mut component::Linker<D>,
-        mut store: impl AsContextMut<Data = D>,
-        interfaces: &IndexSet<String>,
-        shadower: impl InstanceShadower<D, C>,
-    ) -> Result<(), InstantiatePackageError> {
-        for interface_name in interfaces {
-            let interface_path = ForeignInterfacePath::new(
-                package.name().to_string(),
-                interface_name.to_string(),
-                package.version().cloned(),
-            );
+        #[cfg(feature = "deterministic")]
+        graph.link_deterministic(linker)?;
+        graph.link_stubs(&self.stubs, linker, engine)?;
 
-            let interface_full_name = interface_path.to_string();
+        for &shadow_package_id in &self.load_order {
+            if shadow_package_id == self.package_id {
+                break;
+            }
 
-            let (_, shadow_interface_export_id) = shadow_instance
-                .get_export(&mut store, None, &interface_full_name)
-                .ok_or_else(|| InstantiatePackageError::InstanceMissingInterfaceExport {
-                    interface_name: interface_full_name.to_string(),
-                })?;
+            let shadow_package = graph.packages.get(shadow_package_id.id).ok_or(
+                InstantiateError::PackageNotFound {
+                    id: shadow_package_id,
+                },
+            )?;
 
-            let interface_export =
-                self.exported_interfaces
-                    .get(&interface_path)
-                    .ok_or_else(|| InstantiatePackageError::MissingInterfaceExport {
-                        path: interface_path.clone(),
-                    })?;
+            let empty_map = IndexMap::new();
+            let shadow_interfaces = self
+                .interfaces
+                .get(&shadow_package_id)
+                .unwrap_or(&empty_map);
 
-            let mut front_instance = linker
-                .instance(interface_full_name.as_str())
-                .context(instantiate_package_error::LinkerInstanceSnafu)?;
+            let component = graph
+                .component_cache
+                .get_or_compile(engine, shadow_package.bytes())
+                .context(instantiate_package_error::ComponentCompilationSnafu);
 
-            let interface = &self.types[interface_export.interface];
+            let instantiated = match component {
+                Ok(component) => {
+                    graph
+                        .instantiate_shadowed_package_async(
+                            shadow_package_id,
+                            shadow_package,
+                            linker,
+                            &mut store,
+                            component,
+                            shadow_interfaces,
+                            instances,
+                            options.contexts,
+                        )
+                        .await
+                }
+                Err(err) => Err(err),
+            };
 
-            for (export_name, export_kind) in &interface.exports {
-                let ItemKind::Func(func_id) = export_kind else {
-                    continue;
-                };
+            instantiated.with_context(|_err| {
+                instantiate_error::InstantiatePackageDependencySnafu {
+                    name: shadow_package.name().to_string(),
+                    version: shadow_package.version().cloned(),
+                }
+            })?;
+        }
 
-                let (_, shadow_func_export_id) = shadow_instance
-                    .get_export(&mut store, Some(&shadow_interface_export_id), export_name)
-                    .ok_or_else(
-                        || InstantiatePackageError::InstanceMissingInterfaceFuncExport {
-                            interface_name: interface_full_name.to_string(),
-                            func_name: export_name.to_string(),
-                        },
-                    )?;
+        graph
+            .check_import_types(self.package_id, package)
+            .context(instantiate_error::InterfaceTypeMismatchSnafu)?;
 
-                let shadow_func = shadow_instance
-                    .get_func(&mut store, shadow_func_export_id)
-                    .ok_or_else(|| InstantiatePackageError::ComponentFuncRetrievalError {
-                        interface_name: interface_full_name.to_string(),
-                        func_name: export_name.to_string(),
-                    })?;
+        let started = Instant::now();
+        let _memory_scope = graph.memory_scope(package, &store);
+        let _limit_scope = graph.limit_scope(self.package_id, &store);
+        let instance = linker
+            .instantiate_async(&mut store, &component)
+            .await
+            .map_err(InstantiateError::from_instantiation)?;
 
-                shadower.shadow_func(
-                    &mut front_instance,
-                    export_name,
-                    shadow_func,
-                    interface_path.clone(),
-                    self.types[*func_id].clone(),
-                    &interface_export.trampoline,
-                )?;
-            }
-        }
+        instances.record(self.package_id, instance, component);
+        graph.emit_instantiated(self.package_id, package, started);
 
-        Ok(())
+        Ok(instance)
     }
 }
 
-impl<D, C: Clone> Index<PackageId> for CompositionGraph<D, C> {
-    type Output = Package;
+/// The functions of a package that are shadowed by trampolines, resolved against the package's
+/// compiled component.
+///
+/// Function tables are built when instantiating packages, and can be retrieved for diagnostics
+/// with `CompositionGraph::function_table`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub struct FunctionTable<D, C: Clone = ()> {
+    interfaces: Vec<FunctionTableInterface<D, C>>,
+}
 
-    fn index(&self, index: PackageId) -> &Self::Output {
-        let package = self
-            .packages
-            .get(index.id)
-            .expect("package id out of bounds");
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+struct FunctionTableInterface<D, C: Clone> {
+    name: String,
+    /// The import names the interface is linked under besides `name`.
+    aliases: Vec<String>,
+    /// The resources declared by the interface, proxied for importing packages.
+    resources: Vec<String>,
+    #[derivative(Debug = "ignore")]
+    trampoline: DynInterfaceTrampoline<D, C>,
+    functions: Vec<(Arc<CallMeta>, ComponentExportIndex)>,
+}
 
-        assert_eq!(
-            package.nonce, index.nonce,
-            "package nonce mismatch for id {index:?}"
-        );
+impl<D, C: Clone> FunctionTable<D, C> {
+    /// Iterates over the shadowed functions, grouped by interface.
+    pub fn functions(&self) -> impl Iterator<Item = &CallMeta> {
+        self.interfaces
+            .iter()
+            .flat_map(|interface| interface.functions.iter().map(|(meta, _)| meta.as_ref()))
+    }
+
+    /// Looks up a shadowed function by its interface path and name.
+    #[must_use]
+    pub fn get(&self, interface_path: &ForeignInterfacePath, method: &str) -> Option<&CallMeta> {
+        self.functions()
+            .find(|meta| meta.interface_path() == interface_path && meta.method() == method)
+    }
 
-        &package.package
+    /// Returns the number of shadowed functions.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.interfaces
+            .iter()
+            .map(|interface| interface.functions.len())
+            .sum()
+    }
+
+    /// Returns `true` if no functions are shadowed.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
-#[derive(Debug)]
-struct PackageWrapper {
-    package: Package,
-    nonce: usize,
+/// The shadowed functions of an interface, paired with the guest functions they call.
+pub type ShadowFuncs<'a, D> = std::vec::Drain<'a, (Arc<CallMeta>, ShadowFunc<D>)>;
+
+/// A guest function called by a shadowed function, which is looked up when its package is
+/// instantiated, or on its first call if the package is instantiated lazily.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub enum ShadowFunc<D: 'static> {
+    Instantiated(component::Func),
+    Lazy {
+        #[derivative(Debug = "ignore")]
+        instance: Arc<LazyInstance<D>>,
+        index: ComponentExportIndex,
+    },
 }
 
-impl Deref for PackageWrapper {
-    type Target = Package;
+impl<D: 'static> ShadowFunc<D> {
+    /// Returns the guest function, instantiating its package first if it is instantiated lazily
+    /// and has not been instantiated yet.
+    pub fn resolve(
+        &self,
+        mut store: impl AsContextMut<Data = D>,
+    ) -> Result<component::Func, anyhow::Error> {
+        match self {
+            ShadowFunc::Instantiated(func) => Ok(*func),
+            ShadowFunc::Lazy { instance, index } => {
+                let instance = instance.instance(&mut store)?;
+                instance
+                    .get_func(&mut store, index)
+                    .ok_or_else(|| anyhow::anyhow!("shadowed function not found in its instance"))
+            }
+        }
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.package
+/// A dependency package that is instantiated on the first call into its shadowed interfaces,
+/// see `CompositionGraph::set_lazy_instantiation`.
+pub struct LazyInstance<D: 'static> {
+    package_id: PackageId,
+    name: String,
+    version: Option<Version>,
+    component: Component,
+    /// The linker defining the imports of the package.
+    linker: component::Linker<D>,
+    slot: LazySlot<D>,
+    tracked: Option<TrackedPackage>,
+    limits: Option<Arc<PackageLimits>>,
+    scopes: Option<fn(&D) -> &StoreScopes>,
+    events: Arc<EventSubscribers>,
+}
+
+/// Where the instance of a `LazyInstance` is kept once created.
+enum LazySlot<D> {
+    /// In the lazy instance itself, for packages linked into a single store.
+    Once(OnceLock<Instance>),
+    /// In the `ShadowInstances` of each store, for packages linked by
+    /// `CompositionGraph::instantiate_pre`.
+    PerStore(fn(&mut D) -> &mut ShadowInstances),
+}
+
+impl<D: 'static> LazyInstance<D> {
+    /// Returns the instance of the package, instantiating it in `store` on first use.
+    fn instance(&self, mut store: impl AsContextMut<Data = D>) -> Result<Instance, anyhow::Error> {
+        let recorded = match &self.slot {
+            LazySlot::Once(instance) => instance.get().copied(),
+            LazySlot::PerStore(instances) => {
+                instances(store.as_context_mut().data_mut()).get(self.package_id)
+            }
+        };
+        if let Some(instance) = recorded {
+            return Ok(instance);
+        }
+
+        let started = Instant::now();
+        let scopes = self
+            .scopes
+            .map(|scopes| scopes(store.as_context().data()).clone());
+        let _memory_scope = self
+            .tracked
+            .as_ref()
+            .zip(scopes.as_ref())
+            .map(|(tracked, scopes)| tracked.instantiating(scopes));
+        let _limit_scope = self
+            .limits
+            .clone()
+            .zip(scopes.as_ref())
+            .map(|(limits, scopes)| scopes.limits.enter(limits));
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.component)
+            .map_err(|err| {
+                err.context(format!(
+                    "Failed to lazily instantiate package '{}'",
+                    self.name
+                ))
+            })?;
+        match &self.slot {
+            LazySlot::Once(slot) => {
+                let _ = slot.set(instance);
+            }
+            LazySlot::PerStore(instances) => instances(store.as_context_mut().data_mut()).record(
+                self.package_id,
+                instance,
+                self.component.clone(),
+            ),
+        }
+
+        log_debug!(
+            id:? = self.package_id, package = self.name.as_str(), duration:? = started.elapsed();
+            "Lazily instantiated package"
+        );
+
+        self.events.emit(|| GraphEvent::Instantiated {
+            package: self.package_id,
+            name: self.name.clone(),
+            version: self.version.clone(),
+            duration: started.elapsed(),
+        });
+
+        Ok(instance)
     }
 }
 
+/// The instance of a shadowed package, which is created on first use for lazily instantiated
+/// packages.
+enum ShadowInstance<D: 'static> {
+    Instantiated(Instance),
+    Lazy(Arc<LazyInstance<D>>),
+}
+
 trait InstanceShadower<D, C: Clone> {
-    fn shadow_func(
+    /// Defines all `functions` of an interface in `instance`, resolving the trampoline once for
+    /// the whole interface.
+    fn shadow_interface(
         &self,
         instance: &mut LinkerInstance<D>,
-        export_name: &str,
-        shadow_func: component::Func,
-        interface_path: ForeignInterfacePath,
-        func_ty: wac_types::FuncType,
+        functions: ShadowFuncs<'_, D>,
         trampoline: &DynInterfaceTrampoline<D, C>,
     ) -> Result<(), InstantiatePackageError>;
+
+    /// Defines the resource `name` in `instance` as a proxy of the resource of the shadowed
+    /// package, dropping the proxied handles with the proxies.
+    fn shadow_resource(
+        &self,
+        instance: &mut LinkerInstance<D>,
+        name: &str,
+        resources: PackageResources,
+    ) -> Result<(), InstantiatePackageError>;
 }
 
 #[derive(Copy, Clone, Default, Debug)]
 struct SyncInstanceShadower;
 
 impl<D: 'static, C: Clone + Send + Sync + 'static> InstanceShadower<D, C> for SyncInstanceShadower {
-    fn shadow_func(
+    fn shadow_interface(
         &self,
         instance: &mut LinkerInstance<D>,
-        export_name: &str,
-        shadow_func: component::Func,
-        interface_path: ForeignInterfacePath,
-        func_ty: wac_types::FuncType,
+        mut functions: ShadowFuncs<'_, D>,
         trampoline: &DynInterfaceTrampoline<D, C>,
     ) -> Result<(), InstantiatePackageError> {
-        let fn_export_name = Arc::new(export_name.to_string());
-        let fn_interface_path = Arc::new(interface_path);
-        let fn_ty = Arc::new(func_ty);
-
         match &trampoline {
-            DynInterfaceTrampoline::Sync(trampoline) => {
-                let fn_trampoline = trampoline.clone();
-
-                instance
-                    .func_new(export_name, move |store, arguments, result| {
-                        let mut result = fn_trampoline.bounce(
-                            &shadow_func,
-                            store,
-                            fn_interface_path.as_ref(),
-                            fn_export_name.as_str(),
-                            fn_ty.as_ref(),
-                            arguments,
-                            result,
-                        )?;
+            DynInterfaceTrampoline::Sync(trampoline) => functions.try_for_each(|(meta, func)| {
+                link_sync_func(instance, func, meta, trampoline.clone())
+            }),
 
-                        result.post_return()?;
+            DynInterfaceTrampoline::Async(_trampoline) => {
+                Err(InstantiatePackageError::InvalidTrampolineSynchronicity)
+            }
 
-                        Ok(())
-                    })
-                    .context(instantiate_package_error::LinkFuncInstantiationSnafu)
+            DynInterfaceTrampoline::Passthrough => {
+                functions.try_for_each(|(meta, func)| link_passthrough_func(instance, func, meta))
             }
 
-            DynInterfaceTrampoline::Async(_trampoline) => {
-                Err(InstantiatePackageError::InvalidTrampolineSynchronicity)
+            DynInterfaceTrampoline::Static(trampoline) => {
+                trampoline.link_interface(instance, functions, false)
             }
         }
     }
+
+    fn shadow_resource(
+        &self,
+        instance: &mut LinkerInstance<D>,
+        name: &str,
+        resources: PackageResources,
+    ) -> Result<(), InstantiatePackageError> {
+        instance
+            .resource(
+                name,
+                ResourceType::host::<ShadowedResource>(),
+                move |mut store, rep| match resources.take(rep) {
+                    Some(handle) => handle.resource_drop(&mut store),
+                    None => Ok(()),
+                },
+            )
+            .context(instantiate_package_error::LinkFuncInstantiationSnafu)
+    }
 }
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -627,73 +4826,360 @@ struct AsyncInstanceShadower;
 impl<D: Send + 'static, C: Clone + Send + Sync + 'static> InstanceShadower<D, C>
     for AsyncInstanceShadower
 {
-    fn shadow_func(
+    fn shadow_interface(
         &self,
         instance: &mut LinkerInstance<D>,
-        export_name: &str,
-        shadow_func: component::Func,
-        interface_path: ForeignInterfacePath,
-        func_ty: wac_types::FuncType,
+        mut functions: ShadowFuncs<'_, D>,
         trampoline: &DynInterfaceTrampoline<D, C>,
     ) -> Result<(), InstantiatePackageError> {
-        let fn_export_name = Arc::new(export_name.to_string());
-        let fn_interface_path = Arc::new(interface_path);
-        let fn_ty = Arc::new(func_ty);
+        match &trampoline {
+            DynInterfaceTrampoline::Sync(trampoline) => functions.try_for_each(|(meta, func)| {
+                link_sync_func(instance, func, meta, trampoline.clone())
+            }),
+
+            DynInterfaceTrampoline::Async(trampoline) => functions.try_for_each(|(meta, func)| {
+                link_async_func(instance, func, meta, trampoline.clone())
+            }),
+
+            DynInterfaceTrampoline::Passthrough => functions
+                .try_for_each(|(meta, func)| link_passthrough_async_func(instance, func, meta)),
+
+            DynInterfaceTrampoline::Static(trampoline) => {
+                trampoline.link_interface(instance, functions, true)
+            }
+        }
+    }
+
+    fn shadow_resource(
+        &self,
+        instance: &mut LinkerInstance<D>,
+        name: &str,
+        resources: PackageResources,
+    ) -> Result<(), InstantiatePackageError> {
+        instance
+            .resource_async(
+                name,
+                ResourceType::host::<ShadowedResource>(),
+                move |mut store, rep| {
+                    let handle = resources.take(rep);
+
+                    Box::new(async move {
+                        match handle {
+                            Some(handle) => handle.resource_drop_async::<D>(&mut store).await,
+                            None => Ok(()),
+                        }
+                    })
+                },
+            )
+            .context(instantiate_package_error::LinkFuncInstantiationSnafu)
+    }
+}
+
+/// Defines `meta.method()` in `instance`, calling `shadow_func` through a synchronous trampoline.
+fn link_sync_func<D: 'static, C: Send + Sync + 'static, T: Trampoline<D, C>>(
+    instance: &mut LinkerInstance<D>,
+    shadow_func: ShadowFunc<D>,
+    meta: Arc<CallMeta>,
+    trampoline: InterfaceTrampoline<T, C>,
+) -> Result<(), InstantiatePackageError> {
+    let export = meta.clone();
+
+    instance
+        .func_new(&export.export_name, move |mut store, arguments, result| {
+            let started = meta.start_call(&store);
+            let called = meta.admit(&store).and_then(|admitted| {
+                meta.unwrap_resources(&mut store, arguments)
+                    .and_then(|arguments| {
+                        let shadow_func = shadow_func
+                            .resolve(&mut store)
+                            .map_err(|err| meta.guest_error(err))?;
+                        trampoline
+                            .bounce(
+                                &shadow_func,
+                                store.as_context_mut(),
+                                &meta.interface_path,
+                                &meta.export_name,
+                                &meta.func_ty,
+                                &arguments,
+                                result,
+                                meta.package_metadata(),
+                            )
+                            .map_err(|err| meta.trampoline_error(err))
+                            .and_then(|mut result| result.post_return())
+                    })
+                    .and_then(|()| meta.wrap_resources(&mut store, result))
+                    .inspect(|()| meta.check_latency_budget(&admitted))
+            });
+
+            meta.finish_call(started, &store, &called);
+            called
+        })
+        .context(instantiate_package_error::LinkFuncInstantiationSnafu)
+}
+
+/// Defines `meta.method()` in `instance`, calling `shadow_func` through an asynchronous
+/// trampoline.
+fn link_async_func<D, C, T>(
+    instance: &mut LinkerInstance<D>,
+    shadow_func: ShadowFunc<D>,
+    meta: Arc<CallMeta>,
+    trampoline: InterfaceTrampoline<T, C>,
+) -> Result<(), InstantiatePackageError>
+where
+    D: Send + 'static,
+    C: Send + Sync + 'static,
+    T: AsyncTrampoline<D, C>,
+{
+    let export = meta.clone();
+    // Shared, so that calls only clone a reference instead of the trampoline and its context.
+    let trampoline = Arc::new(trampoline);
+
+    instance
+        .func_new_async(&export.export_name, move |mut store, arguments, result| {
+            let trampoline = trampoline.clone();
+            let shadow_func = shadow_func.clone();
+            let meta = meta.clone();
+
+            Box::new(async move {
+                let started = meta.start_call(&store);
+                let called = async {
+                    let admitted = meta.admit(&store)?;
+                    let arguments = meta.unwrap_resources(&mut store, arguments)?;
+                    let shadow_func = shadow_func
+                        .resolve(&mut store)
+                        .map_err(|err| meta.guest_error(err))?;
+                    let mut bounced = trampoline
+                        .bounce_async(
+                            &shadow_func,
+                            store.as_context_mut(),
+                            &meta.interface_path,
+                            &meta.export_name,
+                            &meta.func_ty,
+                            &arguments,
+                            result,
+                            meta.package_metadata(),
+                        )
+                        .await
+                        .map_err(|err| meta.trampoline_error(err))?;
+
+                    bounced.post_return_async().await?;
+                    meta.wrap_resources(&mut store, result)?;
+                    meta.check_latency_budget(&admitted);
+                    Ok(())
+                }
+                .await;
+
+                meta.finish_call(started, &store, &called);
+                called
+            })
+        })
+        .context(instantiate_package_error::LinkFuncInstantiationSnafu)
+}
+
+/// Defines `meta.method()` in `instance`, calling `shadow_func` directly.
+fn link_passthrough_func<D: 'static>(
+    instance: &mut LinkerInstance<D>,
+    shadow_func: ShadowFunc<D>,
+    meta: Arc<CallMeta>,
+) -> Result<(), InstantiatePackageError> {
+    let export = meta.clone();
+
+    instance
+        .func_new(&export.export_name, move |mut store, arguments, result| {
+            let started = meta.start_call(&store);
+            let called = meta.admit(&store).and_then(|admitted| {
+                meta.unwrap_resources(&mut store, arguments)
+                    .and_then(|arguments| {
+                        let shadow_func = shadow_func
+                            .resolve(&mut store)
+                            .map_err(|err| meta.guest_error(err))?;
+                        shadow_func
+                            .call(&mut store, &arguments, result)
+                            .and_then(|()| shadow_func.post_return(&mut store))
+                            .map_err(|err| meta.guest_error(err))
+                    })
+                    .and_then(|()| meta.wrap_resources(&mut store, result))
+                    .inspect(|()| meta.check_latency_budget(&admitted))
+            });
+
+            meta.finish_call(started, &store, &called);
+            called
+        })
+        .context(instantiate_package_error::LinkFuncInstantiationSnafu)
+}
+
+/// Like `link_passthrough_func`, but for asynchronous calls.
+fn link_passthrough_async_func<D: Send + 'static>(
+    instance: &mut LinkerInstance<D>,
+    shadow_func: ShadowFunc<D>,
+    meta: Arc<CallMeta>,
+) -> Result<(), InstantiatePackageError> {
+    let export = meta.clone();
+
+    instance
+        .func_new_async(&export.export_name, move |mut store, arguments, result| {
+            let shadow_func = shadow_func.clone();
+            let meta = meta.clone();
+
+            Box::new(async move {
+                let started = meta.start_call(&store);
+                let called = async {
+                    let admitted = meta.admit(&store)?;
+                    let arguments = meta.unwrap_resources(&mut store, arguments)?;
+                    let shadow_func = shadow_func
+                        .resolve(&mut store)
+                        .map_err(|err| meta.guest_error(err))?;
+                    shadow_func
+                        .call_async(&mut store, &arguments, result)
+                        .await
+                        .map_err(|err| meta.guest_error(err))?;
+
+                    shadow_func
+                        .post_return_async(&mut store)
+                        .await
+                        .map_err(|err| meta.guest_error(err))?;
+
+                    meta.wrap_resources(&mut store, result)?;
+                    meta.check_latency_budget(&admitted);
+                    Ok(())
+                }
+                .await;
+
+                meta.finish_call(started, &store, &called);
+                called
+            })
+        })
+        .context(instantiate_package_error::LinkFuncInstantiationSnafu)
+}
+
+impl<D, C, T> StaticInterfaceTrampoline<D, C> for InterfaceTrampoline<StaticTrampoline<T>, C>
+where
+    D: 'static,
+    C: Clone + Send + Sync + 'static,
+    T: Trampoline<D, C> + Clone,
+{
+    fn link_interface(
+        &self,
+        instance: &mut LinkerInstance<D>,
+        mut functions: ShadowFuncs<'_, D>,
+        _allow_async: bool,
+    ) -> Result<(), InstantiatePackageError> {
+        functions.try_for_each(|(meta, func)| link_sync_func(instance, func, meta, self.clone()))
+    }
+
+    fn with_context(&self, context: C) -> Arc<dyn StaticInterfaceTrampoline<D, C>> {
+        Arc::new(InterfaceTrampoline::with_context(self, context))
+    }
+}
+
+impl<D, C, T> StaticInterfaceTrampoline<D, C> for InterfaceTrampoline<StaticAsyncTrampoline<T>, C>
+where
+    D: Send + 'static,
+    C: Clone + Send + Sync + 'static,
+    T: AsyncTrampoline<D, C> + Clone,
+{
+    fn link_interface(
+        &self,
+        instance: &mut LinkerInstance<D>,
+        mut functions: ShadowFuncs<'_, D>,
+        allow_async: bool,
+    ) -> Result<(), InstantiatePackageError> {
+        if !allow_async {
+            return Err(InstantiatePackageError::InvalidTrampolineSynchronicity);
+        }
+
+        functions.try_for_each(|(meta, func)| link_async_func(instance, func, meta, self.clone()))
+    }
+
+    fn with_context(&self, context: C) -> Arc<dyn StaticInterfaceTrampoline<D, C>> {
+        Arc::new(InterfaceTrampoline::with_context(self, context))
+    }
+}
+
+/// Options for `CompositionGraph::instantiate_with`.
+#[derive(Derivative)]
+#[derivative(Default(bound = ""), Debug(bound = "C: std::fmt::Debug"))]
+pub struct InstantiateOptions<'a, C> {
+    instances: Option<&'a mut ShadowInstances>,
+    contexts: Option<&'a ContextOverlay<C>>,
+}
+
+impl<'a, C> InstantiateOptions<'a, C> {
+    /// Creates options that instantiate all dependencies anew, with the contexts the packages
+    /// were added with.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reuses and records package instances in `instances`, like
+    /// `CompositionGraph::instantiate_reusing`.
+    #[must_use]
+    pub fn with_instances(mut self, instances: &'a mut ShadowInstances) -> Self {
+        self.instances = Some(instances);
+        self
+    }
+
+    /// Overrides the trampoline contexts of the shadowed interfaces in `contexts`, for this
+    /// instantiation only.
+    #[must_use]
+    pub fn with_contexts(mut self, contexts: &'a ContextOverlay<C>) -> Self {
+        self.contexts = Some(contexts);
+        self
+    }
+}
+
+/// Package instances created in one store, for reuse by later instantiations in that store with
+/// `CompositionGraph::instantiate_reusing`.
+#[derive(Derivative)]
+#[derivative(Clone, Default, Debug)]
+pub struct ShadowInstances {
+    #[derivative(Debug = "ignore")]
+    instances: HashMap<PackageId, (Instance, Component)>,
+    /// The resource handles of the instances held by the packages importing them.
+    resources: Arc<ShadowResources>,
+}
 
-        match &trampoline {
-            DynInterfaceTrampoline::Sync(trampoline) => {
-                let fn_trampoline = trampoline.clone();
+impl ShadowInstances {
+    /// Creates an empty set of instances.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-                instance
-                    .func_new(export_name, move |store, arguments, result| {
-                        let mut result = fn_trampoline.bounce(
-                            &shadow_func,
-                            store,
-                            fn_interface_path.as_ref(),
-                            fn_export_name.as_str(),
-                            fn_ty.as_ref(),
-                            arguments,
-                            result,
-                        )?;
+    /// Returns the recorded instance of a package, if any.
+    #[must_use]
+    pub fn get(&self, package_id: PackageId) -> Option<Instance> {
+        self.instances
+            .get(&package_id)
+            .map(|(instance, _)| *instance)
+    }
 
-                        result.post_return()?;
+    /// Removes the recorded instance of a package, so that it is instantiated again by the next
+    /// instantiation depending on it.
+    pub fn remove(&mut self, package_id: PackageId) -> Option<Instance> {
+        self.instances
+            .remove(&package_id)
+            .map(|(instance, _)| instance)
+    }
 
-                        Ok(())
-                    })
-                    .context(instantiate_package_error::LinkFuncInstantiationSnafu)
-            }
+    /// Returns the number of recorded instances.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
 
-            DynInterfaceTrampoline::Async(trampoline) => {
-                let fn_trampoline = trampoline.clone();
+    /// Returns `true` if no instances are recorded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
 
-                instance
-                    .func_new_async(export_name, move |store, arguments, result| {
-                        let export_name = fn_export_name.clone();
-                        let trampoline = fn_trampoline.clone();
-                        let interface_path = fn_interface_path.clone();
-                        let ty = fn_ty.clone();
-
-                        Box::new(async move {
-                            let mut result = trampoline
-                                .bounce_async(
-                                    &shadow_func,
-                                    store,
-                                    interface_path.as_ref(),
-                                    export_name.as_str(),
-                                    ty.as_ref(),
-                                    arguments,
-                                    result,
-                                )
-                                .await?;
-
-                            result.post_return_async().await?;
-
-                            Ok(())
-                        })
-                    })
-                    .context(instantiate_package_error::LinkFuncInstantiationSnafu)
-            }
-        }
+    /// Records the instance of a package, keeping an instance recorded earlier.
+    fn record(&mut self, package_id: PackageId, instance: Instance, component: Component) {
+        self.instances
+            .entry(package_id)
+            .or_insert((instance, component));
     }
 }
 
@@ -705,7 +5191,7 @@ pub struct PackageId {
 }
 
 #[derive(Derivative)]
-#[derivative(Debug(bound = ""))]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
 struct InterfaceExport<D, C: Clone> {
     package: PackageId,
     interface: InterfaceId,
@@ -714,6 +5200,105 @@ struct InterfaceExport<D, C: Clone> {
     trampoline: DynInterfaceTrampoline<D, C>,
 }
 
+/// How a `CompositionGraph` handles violations of its internal invariants, which indicate a bug in
+/// the graph rather than in its packages or host.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum InvariantPolicy {
+    /// Panic, aborting the current operation.
+    #[default]
+    Panic,
+
+    /// Return an `InternalError` from the current operation. The graph may be left partially
+    /// updated, e.g. with a package that was only partially added.
+    Error,
+}
+
+/// An interface import from one package to another that is part of a package import cycle.
+#[derive(Clone, Debug)]
+pub struct CycleEdge {
+    pub importer: String,
+    pub import: ForeignInterfacePath,
+    pub exporter: String,
+}
+
+impl Display for CycleEdge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' imports '{}' from '{}'",
+            self.importer, self.import, self.exporter
+        )
+    }
+}
+
+/// A non-fatal condition detected by the composition graph, reported to the handler set with
+/// `CompositionGraph::set_warning_handler`.
+#[derive(Clone, Debug)]
+pub enum GraphWarning {
+    /// A deprecated package version was selected to satisfy an import.
+    DeprecatedPackage {
+        importer: String,
+        import: ForeignInterfacePath,
+        version: Version,
+        message: String,
+    },
+
+    /// An added package imports an interface without functions whose name cannot be parsed. The
+    /// import is ignored, since it is never linked.
+    UnparsableImport {
+        package: String,
+        import: String,
+        error: String,
+    },
+
+    /// An added package exports an interface that the import filter skips, so imports of it are
+    /// expected to be provided by the host rather than by the package.
+    SkippedExport {
+        package: String,
+        export: ForeignInterfacePath,
+    },
+}
+
+impl GraphWarning {
+    /// Returns a stable, machine-readable code identifying the warning variant.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            GraphWarning::DeprecatedPackage { .. } => "WCT0501",
+            GraphWarning::UnparsableImport { .. } => "WCT0502",
+            GraphWarning::SkippedExport { .. } => "WCT0503",
+        }
+    }
+}
+
+impl Display for GraphWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphWarning::DeprecatedPackage {
+                importer,
+                import,
+                version,
+                message,
+            } => write!(
+                f,
+                "Package '{importer}' imports '{import}' from deprecated version {version}: {message}"
+            ),
+            GraphWarning::UnparsableImport {
+                package,
+                import,
+                error,
+            } => write!(
+                f,
+                "Package '{package}' import '{import}' is ignored, since it cannot be parsed: {error}"
+            ),
+            GraphWarning::SkippedExport { package, export } => write!(
+                f,
+                "Package '{package}' exports '{export}', which the import filter skips"
+            ),
+        }
+    }
+}
+
 #[derive(Snafu, Debug)]
 #[snafu(module)]
 pub enum AddPackageError {
@@ -728,6 +5313,62 @@ pub enum AddPackageError {
         interface: String,
         source: InterfacePathParseError,
     },
+
+    #[snafu(display("Internal graph error: {message}"))]
+    InternalError { message: String },
+
+    #[snafu(display("Failed to read package"))]
+    ReadError { source: std::io::Error },
+
+    #[cfg(feature = "preinit")]
+    #[snafu(display("Failed to pre-initialize package"))]
+    PreinitializeError { source: PreinitializeError },
+
+    #[snafu(display("Package id '{id:?}' not found"))]
+    PackageNotFound { id: PackageId },
+
+    #[snafu(display("Failed to fetch package '{reference}'"))]
+    FetchError {
+        reference: String,
+        source: anyhow::Error,
+    },
+
+    #[snafu(display("Package {name}@{version} was refused by the package verifier"))]
+    VerificationFailed {
+        name: String,
+        version: Version,
+        source: anyhow::Error,
+    },
+
+    #[snafu(display("Package {name}@{version} is denied by package policy rule '{policy}'"))]
+    DeniedByPackagePolicy {
+        name: String,
+        version: Version,
+        policy: String,
+    },
+}
+
+impl AddPackageError {
+    /// Returns a stable, machine-readable code identifying the error variant.
+    ///
+    /// Codes are never reused or reassigned between releases.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            AddPackageError::DuplicatePackage { .. } => "WCT0001",
+            AddPackageError::PackageParseError { .. } => "WCT0002",
+            AddPackageError::ImportParseError { .. } => "WCT0003",
+            AddPackageError::InternalError { .. } => "WCT0004",
+            AddPackageError::ReadError { .. } => "WCT0005",
+            // WCT0006 was `VerificationError`, which `VerificationFailed` replaces.
+            #[cfg(feature = "preinit")]
+            AddPackageError::PreinitializeError { .. } => "WCT0007",
+            AddPackageError::PackageNotFound { .. } => "WCT0008",
+            AddPackageError::FetchError { .. } => "WCT0009",
+            AddPackageError::VerificationFailed { .. } => "WCT0010",
+            AddPackageError::DeniedByPackagePolicy { .. } => "WCT0011",
+        }
+    }
 }
 
 #[derive(Snafu, Debug)]
@@ -743,29 +5384,154 @@ pub enum InstantiateError {
     InstantiatePackageDependencyError {
         name: String,
         version: Option<Version>,
-        source: InstantiatePackageError,
+        /// Boxed so that results carrying an `InstantiateError` stay small.
+        #[snafu(source(from(InstantiatePackageError, Box::new)))]
+        source: Box<InstantiatePackageError>,
     },
 
     #[snafu(display("Failed to instantiate wasm component"))]
     ComponentInstantiationError { source: anyhow::Error },
+
+    #[snafu(display("Failed to compile wasm component"))]
+    ComponentCompilationError { source: anyhow::Error },
+
+    #[snafu(display("Wasm component trapped during instantiation"))]
+    GuestTrap { source: anyhow::Error },
+
+    #[snafu(display("Imported interface types do not match their exports"))]
+    InterfaceTypeMismatch { source: Box<InterfaceTypeMismatch> },
+
+    #[snafu(display("Failed to parse lazily added package"))]
+    LazyPackageError { source: AddPackageError },
+
+    #[snafu(display(
+        "Lazily added package '{id:?}' has not been parsed, see `CompositionGraph::prepare`"
+    ))]
+    UnpreparedPackage { id: PackageId },
+}
+
+impl InstantiateError {
+    fn from_instantiation(source: anyhow::Error) -> Self {
+        if source.downcast_ref::<Trap>().is_some() {
+            InstantiateError::GuestTrap { source }
+        } else {
+            InstantiateError::ComponentInstantiationError { source }
+        }
+    }
+
+    /// Returns a stable, machine-readable code identifying the error variant.
+    ///
+    /// Variants wrapping another graph error have their own code; the wrapped error's code is
+    /// available from its `source`.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            InstantiateError::PackageNotFound { .. } => "WCT0101",
+            InstantiateError::LoadPackageError { .. } => "WCT0102",
+            InstantiateError::InstantiatePackageDependencyError { .. } => "WCT0103",
+            InstantiateError::ComponentInstantiationError { .. } => "WCT0104",
+            InstantiateError::ComponentCompilationError { .. } => "WCT0105",
+            InstantiateError::GuestTrap { .. } => "WCT0106",
+            InstantiateError::InterfaceTypeMismatch { .. } => "WCT0107",
+            InstantiateError::LazyPackageError { .. } => "WCT0108",
+            InstantiateError::UnpreparedPackage { .. } => "WCT0109",
+        }
+    }
+
+    /// Returns the WebAssembly backtrace of a guest trap that caused the instantiation to fail,
+    /// including traps in the instantiation of package dependencies.
+    ///
+    /// Backtraces are only captured if enabled with `wasmtime::Config::wasm_backtrace`.
+    #[must_use]
+    pub fn wasm_backtrace(&self) -> Option<&WasmBacktrace> {
+        match self {
+            InstantiateError::InstantiatePackageDependencyError { source, .. } => {
+                source.wasm_backtrace()
+            }
+            InstantiateError::ComponentInstantiationError { source }
+            | InstantiateError::ComponentCompilationError { source }
+            | InstantiateError::GuestTrap { source } => source.downcast_ref(),
+            InstantiateError::PackageNotFound { .. }
+            | InstantiateError::LoadPackageError { .. }
+            | InstantiateError::InterfaceTypeMismatch { .. }
+            | InstantiateError::LazyPackageError { .. }
+            | InstantiateError::UnpreparedPackage { .. } => None,
+        }
+    }
 }
 
 #[derive(Snafu, Debug)]
 #[snafu(module)]
 pub enum LoadPackageError {
-    #[snafu(display("Package import cycle detected: {cycle:?}"))]
-    PackageCycle { cycle: Vec<String> },
+    #[snafu(display(
+        "Package import cycle detected: {}",
+        edges.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    ))]
+    PackageCycle {
+        cycle: Vec<String>,
+        edges: Vec<CycleEdge>,
+    },
 
-    #[snafu(display("Package dependency {package_name} not found"))]
-    MissingPackageDependency { package_name: String },
+    #[snafu(display(
+        "Package dependency {package_name} not found, for import '{import}' of package '{importer}'"
+    ))]
+    MissingPackageDependency {
+        package_name: String,
+        importer: String,
+        import: Box<ForeignInterfacePath>,
+    },
 
-    #[snafu(display("Cannot resolve package version for {name}@{version:?}"))]
+    #[snafu(display(
+        "Cannot resolve package version for {name}@{version:?}, for import '{import}' of package \
+         '{importer}'"
+    ))]
     CannotResolvePackageVersion {
         name: String,
         version: Option<Version>,
+        importer: String,
+        import: Box<ForeignInterfacePath>,
+    },
+
+    #[snafu(display("No provider selected among {providers} packages for {name}@{version}"))]
+    AmbiguousPackageProvider {
+        name: String,
+        version: Version,
+        providers: usize,
+    },
+
+    #[snafu(display("Import '{import}' of package '{importer}' is denied by the graph's policy"))]
+    DeniedByPolicy {
+        importer: String,
+        import: Box<ForeignInterfacePath>,
+    },
+
+    #[snafu(display(
+        "Import '{import}' of package '{importer}' resolves to package '{package}', which is \
+         denied by package policy rule '{policy}'"
+    ))]
+    DeniedByPackagePolicy {
+        importer: String,
+        import: Box<ForeignInterfacePath>,
+        package: String,
+        policy: String,
     },
 }
 
+impl LoadPackageError {
+    /// Returns a stable, machine-readable code identifying the error variant.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            LoadPackageError::PackageCycle { .. } => "WCT0201",
+            LoadPackageError::MissingPackageDependency { .. } => "WCT0202",
+            LoadPackageError::CannotResolvePackageVersion { .. } => "WCT0203",
+            LoadPackageError::AmbiguousPackageProvider { .. } => "WCT0204",
+            LoadPackageError::DeniedByPolicy { .. } => "WCT0205",
+            LoadPackageError::DeniedByPackagePolicy { .. } => "WCT0206",
+        }
+    }
+}
+
 #[derive(Snafu, Debug)]
 #[snafu(module)]
 pub enum InstantiatePackageError {
@@ -800,4 +5566,623 @@ pub enum InstantiatePackageError {
 
     #[snafu(display("Missing interface export {path}"))]
     MissingInterfaceExport { path: ForeignInterfacePath },
+
+    #[snafu(display("Failed to compile wasm component"))]
+    ComponentCompilationError { source: anyhow::Error },
+
+    #[snafu(display("Wasm component trapped during instantiation"))]
+    GuestTrap { source: anyhow::Error },
+
+    #[snafu(display("Imported interface types do not match their exports"))]
+    InterfaceTypeMismatch { source: Box<InterfaceTypeMismatch> },
+
+    #[snafu(display("Internal graph error: {message}"))]
+    InternalError { message: String },
+
+    #[snafu(display("Failed to parse lazily added package"))]
+    LazyPackageError { source: AddPackageError },
+
+    #[snafu(display(
+        "Interface '{interface_name}' exports resources, which cannot be shadowed before \
+         instantiation"
+    ))]
+    UnsupportedResourceExport { interface_name: String },
+
+    #[snafu(display("Package id '{id:?}' not found"))]
+    PackageNotFound { id: PackageId },
+}
+
+impl InstantiatePackageError {
+    fn from_instantiation(source: anyhow::Error) -> Self {
+        if source.downcast_ref::<Trap>().is_some() {
+            InstantiatePackageError::GuestTrap { source }
+        } else {
+            InstantiatePackageError::ComponentInstantiationError { source }
+        }
+    }
+
+    /// Returns a stable, machine-readable code identifying the error variant.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            InstantiatePackageError::ComponentInstantiationError { .. } => "WCT0301",
+            InstantiatePackageError::LinkerInstanceError { .. } => "WCT0302",
+            InstantiatePackageError::InstanceMissingInterfaceExport { .. } => "WCT0303",
+            InstantiatePackageError::InstanceMissingInterfaceFuncExport { .. } => "WCT0304",
+            InstantiatePackageError::ComponentFuncRetrievalError { .. } => "WCT0305",
+            InstantiatePackageError::LinkFuncInstantiationError { .. } => "WCT0306",
+            InstantiatePackageError::InvalidTrampolineSynchronicity => "WCT0307",
+            InstantiatePackageError::MissingInterfaceExport { .. } => "WCT0308",
+            InstantiatePackageError::ComponentCompilationError { .. } => "WCT0309",
+            InstantiatePackageError::GuestTrap { .. } => "WCT0310",
+            InstantiatePackageError::InterfaceTypeMismatch { .. } => "WCT0311",
+            InstantiatePackageError::InternalError { .. } => "WCT0312",
+            InstantiatePackageError::LazyPackageError { .. } => "WCT0313",
+            InstantiatePackageError::UnsupportedResourceExport { .. } => "WCT0314",
+            InstantiatePackageError::PackageNotFound { .. } => "WCT0315",
+        }
+    }
+
+    /// Returns the WebAssembly backtrace of a guest trap that caused the error, if any.
+    ///
+    /// Backtraces are only captured if enabled with `wasmtime::Config::wasm_backtrace`.
+    #[must_use]
+    pub fn wasm_backtrace(&self) -> Option<&WasmBacktrace> {
+        match self {
+            InstantiatePackageError::ComponentInstantiationError { source }
+            | InstantiatePackageError::LinkerInstanceError { source }
+            | InstantiatePackageError::LinkFuncInstantiationError { source }
+            | InstantiatePackageError::ComponentCompilationError { source }
+            | InstantiatePackageError::GuestTrap { source } => source.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Checks a package against a package policy and verifier, before it is parsed.
+pub(crate) fn verify_package(
+    package_policy: Option<&PackagePolicy>,
+    package_verifier: Option<&PackageVerifierRef>,
+    name: &str,
+    version: &Version,
+    bytes: &[u8],
+) -> Result<(), AddPackageError> {
+    if let Some(rule) = package_policy.and_then(|policy| policy.denies(name, version)) {
+        return Err(AddPackageError::DeniedByPackagePolicy {
+            name: name.to_string(),
+            version: version.clone(),
+            policy: rule.name.clone(),
+        });
+    }
+
+    let Some(verifier) = package_verifier else {
+        return Ok(());
+    };
+
+    verifier.verify_package(name, version, bytes).context(
+        add_package_error::VerificationFailedSnafu {
+            name,
+            version: version.clone(),
+        },
+    )
+}
+
+fn _assert_graph_send_sync(_graph: &CompositionGraph<(), ()>) -> &(dyn Send + Sync) {
+    unreachable!("only used for compile time assertion");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ComponentFixture, FixtureFunc};
+    use crate::{ErrorClass, Fault, NoopTrampoline, PolicyViolation, TimeoutRule};
+    use std::time::Duration;
+    use wasmtime::{Engine, Store};
+
+    fn kvstore(get: FixtureFunc) -> Vec<u8> {
+        ComponentFixture::new()
+            .export("test:kvstore/store@1.0.0", [("get", get)])
+            .to_bytes()
+            .unwrap()
+    }
+
+    /// A package whose `run` export forwards to the `get` function of the kvstore.
+    fn app() -> Vec<u8> {
+        ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .export(
+                "test:app/run@1.0.0",
+                [(
+                    "run",
+                    FixtureFunc::Forward {
+                        interface: "test:kvstore/store@1.0.0".to_string(),
+                        function: "get".to_string(),
+                    },
+                )],
+            )
+            .to_bytes()
+            .unwrap()
+    }
+
+    fn add(graph: &mut CompositionGraph<()>, name: &str, bytes: Vec<u8>) -> PackageId {
+        graph
+            .add_package(
+                name.to_string(),
+                Version::new(1, 0, 0),
+                bytes,
+                NoopTrampoline,
+            )
+            .unwrap()
+    }
+
+    fn call(instance: &Instance, store: &mut Store<()>, interface: &str, function: &str) -> u32 {
+        let interface = instance.get_export_index(&mut *store, None, interface);
+        let index = instance
+            .get_export_index(&mut *store, interface.as_ref(), function)
+            .unwrap();
+        let func = instance
+            .get_typed_func::<(u32,), (u32,)>(&mut *store, &index)
+            .unwrap();
+
+        func.call(&mut *store, (41,)).unwrap().0
+    }
+
+    fn run(graph: &CompositionGraph<()>, engine: &Engine, app_id: PackageId) -> u32 {
+        let mut store = Store::new(engine, ());
+        let instance = graph
+            .instantiate_isolated(app_id, &component::Linker::new(engine), &mut store, engine)
+            .unwrap();
+
+        call(&instance, &mut store, "test:app/run@1.0.0", "run")
+    }
+
+    #[test]
+    fn test_replace_package() {
+        let engine = Engine::default();
+        let mut graph = CompositionGraph::<()>::new();
+        let kvstore_id = add(
+            &mut graph,
+            "test:kvstore",
+            kvstore(FixtureFunc::Constant(1)),
+        );
+        let app_id = add(&mut graph, "test:app", app());
+        assert_eq!(run(&graph, &engine, app_id), 1);
+
+        graph
+            .replace_package(
+                kvstore_id,
+                kvstore(FixtureFunc::Constant(2)),
+                NoopTrampoline,
+            )
+            .unwrap();
+        assert_eq!(graph[kvstore_id].name(), "test:kvstore");
+        assert_eq!(graph.packages().count(), 2);
+        assert_eq!(run(&graph, &engine, app_id), 2);
+
+        // A failed replacement leaves the package unchanged.
+        assert!(
+            graph
+                .replace_package(kvstore_id, b"not a component".to_vec(), NoopTrampoline)
+                .is_err()
+        );
+        assert_eq!(run(&graph, &engine, app_id), 2);
+    }
+
+    #[test]
+    fn test_pre_release_policy() {
+        let engine = Engine::default();
+        let mut graph = CompositionGraph::<()>::new();
+        add(
+            &mut graph,
+            "test:kvstore",
+            kvstore(FixtureFunc::Constant(1)),
+        );
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0-rc.1", ["get"])
+            .export(
+                "test:app/run@1.0.0",
+                [(
+                    "run",
+                    FixtureFunc::Forward {
+                        interface: "test:kvstore/store@1.0.0-rc.1".to_string(),
+                        function: "get".to_string(),
+                    },
+                )],
+            )
+            .to_bytes()
+            .unwrap();
+        let app_id = add(&mut graph, "test:app", app_bytes);
+
+        assert_eq!(graph.pre_release_policy(), PreReleasePolicy::Exact);
+        assert!(matches!(
+            graph.plan(app_id),
+            Err(InstantiateError::LoadPackageError {
+                source: LoadPackageError::CannotResolvePackageVersion { .. }
+            })
+        ));
+
+        // The policy applies to the packages added before it was set.
+        graph.set_pre_release_policy(PreReleasePolicy::OptIn);
+        assert_eq!(run(&graph, &engine, app_id), 1);
+    }
+
+    #[test]
+    fn test_build_metadata_policy() {
+        let engine = Engine::default();
+        let mut graph = CompositionGraph::<()>::new();
+        let kvstore_bytes = ComponentFixture::new()
+            .export(
+                "test:kvstore/store@1.0.0+signed",
+                [("get", FixtureFunc::Constant(1))],
+            )
+            .to_bytes()
+            .unwrap();
+        graph
+            .add_package(
+                "test:kvstore".to_string(),
+                Version::parse("1.0.0+signed").unwrap(),
+                kvstore_bytes,
+                NoopTrampoline,
+            )
+            .unwrap();
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0+unsigned", ["get"])
+            .export(
+                "test:app/run@1.0.0",
+                [(
+                    "run",
+                    FixtureFunc::Forward {
+                        interface: "test:kvstore/store@1.0.0+unsigned".to_string(),
+                        function: "get".to_string(),
+                    },
+                )],
+            )
+            .to_bytes()
+            .unwrap();
+        let app_id = add(&mut graph, "test:app", app_bytes);
+
+        assert_eq!(graph.build_metadata_policy(), BuildMetadataPolicy::Exact);
+        assert!(graph.plan(app_id).is_err());
+
+        // The policy applies to the packages added before it was set.
+        graph.set_build_metadata_policy(BuildMetadataPolicy::Ignore);
+        assert_eq!(run(&graph, &engine, app_id), 1);
+
+        graph.set_build_metadata_policy(BuildMetadataPolicy::RequireEqual);
+        assert!(graph.plan(app_id).is_err());
+    }
+
+    #[test]
+    fn test_function_table_package_not_found() {
+        let engine = Engine::default();
+        let mut other = CompositionGraph::<()>::new();
+        let stale_id = add(&mut other, "test:kvstore", kvstore(FixtureFunc::Echo));
+
+        let mut graph = CompositionGraph::<()>::new();
+        assert!(matches!(
+            graph.function_table(stale_id, &engine),
+            Err(InstantiatePackageError::PackageNotFound { id }) if id == stale_id
+        ));
+    }
+
+    #[test]
+    fn test_subgraph() {
+        let engine = Engine::default();
+        let mut graph = CompositionGraph::<()>::new();
+        let kvstore_id = add(&mut graph, "test:kvstore", kvstore(FixtureFunc::Echo));
+        let logger_bytes = ComponentFixture::new()
+            .export("test:logger/log@1.0.0", [("log", FixtureFunc::Echo)])
+            .to_bytes()
+            .unwrap();
+        let logger_id = add(&mut graph, "test:logger", logger_bytes);
+        let app_id = add(&mut graph, "test:app", app());
+
+        let subgraph = graph.subgraph(app_id).unwrap();
+        let mut packages = subgraph.packages().collect::<Vec<_>>();
+        packages.sort_unstable();
+        assert_eq!(packages, [kvstore_id, app_id]);
+        assert!(subgraph.package(logger_id).is_none());
+        assert_eq!(run(&subgraph, &engine, app_id), 41);
+
+        let subgraph = graph.subgraph(kvstore_id).unwrap();
+        assert_eq!(subgraph.packages().collect::<Vec<_>>(), [kvstore_id]);
+    }
+
+    #[test]
+    fn test_instantiate_many() {
+        let engine = Engine::default();
+        let mut graph = CompositionGraph::<()>::new();
+        let kvstore_id = add(&mut graph, "test:kvstore", kvstore(FixtureFunc::Counter));
+        let app_id = add(&mut graph, "test:app", app());
+        let other_id = add(&mut graph, "test:other", app());
+
+        let mut store = Store::new(&engine, ());
+        let instances = graph
+            .instantiate_many(
+                &[other_id, app_id, kvstore_id],
+                &component::Linker::new(&engine),
+                &mut store,
+                &engine,
+            )
+            .unwrap();
+        assert_eq!(instances.len(), 3);
+
+        // All roots share the one instance of the kvstore.
+        assert_eq!(
+            call(&instances[0], &mut store, "test:app/run@1.0.0", "run"),
+            1
+        );
+        assert_eq!(
+            call(&instances[1], &mut store, "test:app/run@1.0.0", "run"),
+            2
+        );
+        assert_eq!(
+            call(&instances[2], &mut store, "test:kvstore/store@1.0.0", "get"),
+            3
+        );
+    }
+
+    #[test]
+    fn test_instantiate_many_roots() {
+        let engine = Engine::default();
+        let mut graph = CompositionGraph::<()>::new();
+        let kvstore_id = add(&mut graph, "test:kvstore", kvstore(FixtureFunc::Counter));
+        let app_id = add(&mut graph, "test:app", app());
+        let linker = component::Linker::new(&engine);
+
+        // Every requested root gets an instance, in order, even when repeated.
+        let mut store = Store::new(&engine, ());
+        let instances = graph
+            .instantiate_many(&[app_id, kvstore_id, app_id], &linker, &mut store, &engine)
+            .unwrap();
+        assert_eq!(instances.len(), 3);
+        assert_eq!(
+            call(&instances[2], &mut store, "test:app/run@1.0.0", "run"),
+            1
+        );
+
+        // An id past the packages of the graph is not found.
+        let mut other = CompositionGraph::<()>::new();
+        for name in ["test:a", "test:b"] {
+            add(&mut other, name, kvstore(FixtureFunc::Echo));
+        }
+        let stale_id = add(&mut other, "test:other", kvstore(FixtureFunc::Echo));
+        let mut store = Store::new(&engine, ());
+        assert!(matches!(
+            graph.instantiate_many(&[app_id, stale_id], &linker, &mut store, &engine),
+            Err(InstantiateError::PackageNotFound { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "deterministic")]
+    fn test_deterministic_state() {
+        let bytes = wat::parse_str(
+            r#"(component
+                (import "wasi:random/random@0.2.3" (instance $random
+                    (export "get-random-u64" (func (result u64)))
+                ))
+                (core func $get-random (canon lower (func $random "get-random-u64")))
+                (core module $module
+                    (import "host" "random" (func $random (result i64)))
+                    (func (export "random") (result i64) call $random)
+                )
+                (core instance $instance (instantiate $module
+                    (with "host" (instance (export "random" (func $get-random))))
+                ))
+                (func (export "random") (result u64) (canon lift (core func $instance "random")))
+            )"#,
+        )
+        .unwrap();
+
+        let graph = |deterministic| {
+            let mut graph = CompositionGraph::<DeterministicState>::new();
+            if deterministic {
+                graph.set_deterministic_state(Some(|state| state));
+            }
+            let package_id = graph
+                .add_package(
+                    "test:guest".to_string(),
+                    Version::new(1, 0, 0),
+                    bytes.clone(),
+                    NoopTrampoline,
+                )
+                .unwrap();
+            (graph, package_id)
+        };
+
+        let (ambient, package_id) = graph(false);
+        assert!(!ambient.validate_package(package_id).unwrap().is_resolved());
+
+        let (graph, package_id) = graph(true);
+        assert!(graph.validate_package(package_id).unwrap().is_resolved());
+
+        let engine = Engine::default();
+        let run = |seed| {
+            let mut store = Store::new(&engine, DeterministicState::new(seed));
+            let instance = graph
+                .instantiate(
+                    package_id,
+                    &mut component::Linker::new(&engine),
+                    &mut store,
+                    &engine,
+                )
+                .unwrap();
+            let random = instance
+                .get_typed_func::<(), (u64,)>(&mut store, "random")
+                .unwrap();
+            random.call(&mut store, ()).unwrap().0
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_enforce_timeouts() {
+        let mut config = wasmtime::Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).unwrap();
+
+        let mut graph = CompositionGraph::<StoreScopes>::new();
+        graph.set_store_scopes(Some(|scopes| scopes));
+        graph.set_policy(Some(Policy::new().with_timeout(TimeoutRule {
+            interface: "test:kvstore/*".to_string(),
+            millis: 10,
+        })));
+        let mut add = |name: &str, bytes| {
+            graph
+                .add_package(
+                    name.to_string(),
+                    Version::new(1, 0, 0),
+                    bytes,
+                    NoopTrampoline,
+                )
+                .unwrap()
+        };
+        add("test:kvstore", kvstore(FixtureFunc::Spin));
+        let app_id = add("test:app", app());
+
+        let mut store = Store::new(&engine, StoreScopes::new());
+        graph.enforce_timeouts(&mut store, 1);
+        let instance = graph
+            .instantiate_isolated(
+                app_id,
+                &component::Linker::new(&engine),
+                &mut store,
+                &engine,
+            )
+            .unwrap();
+        let interface = instance.get_export_index(&mut store, None, "test:app/run@1.0.0");
+        let index = instance
+            .get_export_index(&mut store, interface.as_ref(), "run")
+            .unwrap();
+        let run = instance
+            .get_typed_func::<(u32,), (u32,)>(&mut store, &index)
+            .unwrap();
+
+        let ticking = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let ticker = std::thread::spawn({
+            let (engine, ticking) = (engine.clone(), ticking.clone());
+            move || {
+                while ticking.load(std::sync::atomic::Ordering::Relaxed) {
+                    engine.increment_epoch();
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        });
+        let err = run.call(&mut store, (41,)).unwrap_err();
+        ticking.store(false, std::sync::atomic::Ordering::Relaxed);
+        ticker.join().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<PolicyViolation>(),
+            Some(PolicyViolation::DeadlineExceeded { method, timeout, elapsed, .. })
+                if method == "get" && *timeout == Duration::from_millis(10) && elapsed > timeout
+        ));
+        assert_eq!(
+            ErrorClass::of_call_error(&err),
+            ErrorClass::GuestTrap(Trap::Interrupt)
+        );
+        assert_eq!(Fault::of_call_error(&err), Fault::Guest);
+        // The deadline is left as the interrupted call unwinds.
+        assert!(store.data().deadlines.current().is_none());
+    }
+
+    /// Reads at most `chunk` bytes at a time, and then endless `fill` bytes if set, rather than
+    /// reaching the end.
+    #[cfg(feature = "async")]
+    struct ChunkedReader<'a> {
+        bytes: &'a [u8],
+        chunk: usize,
+        fill: Option<u8>,
+    }
+
+    #[cfg(feature = "async")]
+    impl futures_io::AsyncRead for ChunkedReader<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let len = buf.len().min(self.chunk);
+            if let Some(fill) = self.fill
+                && self.bytes.is_empty()
+            {
+                buf[..len].fill(fill);
+                return Poll::Ready(Ok(len));
+            }
+
+            let len = len.min(self.bytes.len());
+            buf[..len].copy_from_slice(&self.bytes[..len]);
+            self.bytes = &self.bytes[len..];
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    /// Completes a future that never waits, as the readers of these tests are always ready.
+    #[cfg(feature = "async")]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+
+        match std::pin::pin!(future).poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future is pending"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_add_package_from_reader() {
+        let bytes = kvstore(FixtureFunc::Constant(7));
+        let mut graph = CompositionGraph::<()>::new();
+        let reader = ChunkedReader {
+            bytes: &bytes,
+            chunk: 7,
+            fill: None,
+        };
+        let kvstore_id = block_on(graph.add_package_from_reader(
+            "test:kvstore".to_string(),
+            Version::new(1, 0, 0),
+            reader,
+            NoopTrampoline,
+        ))
+        .unwrap();
+        assert_eq!(graph.package(kvstore_id).unwrap().bytes(), bytes.as_slice());
+
+        let app_id = add(&mut graph, "test:app", app());
+        assert_eq!(run(&graph, &Engine::default(), app_id), 7);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_add_package_from_reader_invalid() {
+        let mut graph = CompositionGraph::<()>::new();
+        let mut add = |bytes, fill| {
+            let reader = ChunkedReader {
+                bytes,
+                chunk: 1024,
+                fill,
+            };
+            block_on(graph.add_package_from_reader(
+                "test:kvstore".to_string(),
+                Version::new(1, 0, 0),
+                reader,
+                NoopTrampoline,
+            ))
+        };
+
+        // An invalid section fails before the rest of the component is read, which never ends.
+        let header = b"\0asm\x0d\0\x01\0";
+        assert!(matches!(
+            add(header, Some(0xff)),
+            Err(AddPackageError::PackageParseError { .. })
+        ));
+
+        let truncated = kvstore(FixtureFunc::Constant(7));
+        assert!(matches!(
+            add(&truncated[..truncated.len() - 1], None),
+            Err(AddPackageError::PackageParseError { .. })
+        ));
+        assert_eq!(graph.packages().count(), 0);
+    }
 }
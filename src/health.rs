@@ -0,0 +1,149 @@
+use crate::{Diagnostic, ForeignInterfacePath, InFlightCall, RecentCalls};
+use std::time::Duration;
+
+/// A report of the health of a composition graph, returned by `CompositionGraph::health`, for
+/// the readiness and liveness probes of hosting services.
+#[derive(Clone, Debug)]
+pub struct GraphHealth {
+    /// The number of packages in the graph, including lazily added packages.
+    pub packages: usize,
+    /// The number of lazily added packages that have not been parsed yet.
+    pub pending_packages: usize,
+    /// The problems found by `CompositionGraph::validate`.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The recent calls to each shadowed interface, if the graph records call metrics.
+    pub interfaces: Vec<InterfaceHealth>,
+    /// The calls executing in the graph's package instances, if the graph tracks calls in flight.
+    pub in_flight_calls: Vec<InFlightCall>,
+}
+
+/// The recent calls to the functions of a shadowed interface.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InterfaceHealth {
+    pub interface: ForeignInterfacePath,
+    pub recent: RecentCalls,
+}
+
+impl GraphHealth {
+    /// Returns whether all imports resolve and type check, so that the graph's packages can be
+    /// instantiated.
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        !self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    /// Returns whether the graph is ready and no interface's recent error rate exceeds
+    /// `max_error_rate`.
+    #[must_use]
+    pub fn is_healthy(&self, max_error_rate: f64) -> bool {
+        self.is_ready()
+            && self.interfaces.iter().all(|interface| {
+                interface
+                    .recent
+                    .error_rate()
+                    .is_none_or(|rate| rate <= max_error_rate)
+            })
+    }
+
+    /// Returns whether no call has been executing for longer than `max_call_duration`, i.e.
+    /// whether the package instances are still making progress.
+    ///
+    /// Instances are always considered live if the graph does not track calls in flight.
+    #[must_use]
+    pub fn is_live(&self, max_call_duration: Duration) -> bool {
+        self.stalled_calls(max_call_duration).next().is_none()
+    }
+
+    /// Returns the calls that have been executing for longer than `max_call_duration`.
+    pub fn stalled_calls(
+        &self,
+        max_call_duration: Duration,
+    ) -> impl Iterator<Item = &InFlightCall> {
+        self.in_flight_calls
+            .iter()
+            .filter(move |call| call.elapsed > max_call_duration)
+    }
+
+    /// Returns the interfaces whose recent error rate exceeds `max_error_rate`.
+    pub fn failing_interfaces(
+        &self,
+        max_error_rate: f64,
+    ) -> impl Iterator<Item = &InterfaceHealth> {
+        self.interfaces.iter().filter(move |interface| {
+            interface
+                .recent
+                .error_rate()
+                .is_some_and(|rate| rate > max_error_rate)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Severity;
+
+    #[test]
+    fn test_graph_health() {
+        let mut health = GraphHealth {
+            packages: 1,
+            pending_packages: 0,
+            diagnostics: Vec::new(),
+            interfaces: vec![InterfaceHealth {
+                interface: ForeignInterfacePath::new(
+                    "test:kvstore".to_string(),
+                    "store".to_string(),
+                    None,
+                ),
+                recent: RecentCalls {
+                    calls: 4,
+                    errors: 1,
+                },
+            }],
+            in_flight_calls: Vec::new(),
+        };
+
+        assert!(health.is_ready());
+        assert!(health.is_healthy(0.25));
+        assert!(!health.is_healthy(0.1));
+        assert_eq!(health.failing_interfaces(0.1).count(), 1);
+
+        health.diagnostics.push(Diagnostic::new(
+            Severity::Error,
+            "WCT0201",
+            "missing package",
+        ));
+        assert!(!health.is_ready());
+    }
+
+    #[test]
+    fn test_graph_liveness() {
+        let call = |id, elapsed| InFlightCall {
+            id,
+            interface: ForeignInterfacePath::new(
+                "test:kvstore".to_string(),
+                "store".to_string(),
+                None,
+            ),
+            method: "get".to_string(),
+            caller_id: None,
+            correlation_id: id,
+            elapsed: Duration::from_secs(elapsed),
+        };
+        let mut health = GraphHealth {
+            packages: 1,
+            pending_packages: 0,
+            diagnostics: Vec::new(),
+            interfaces: Vec::new(),
+            in_flight_calls: Vec::new(),
+        };
+        assert!(health.is_live(Duration::ZERO));
+
+        health.in_flight_calls = vec![call(1, 1), call(2, 60)];
+        assert!(health.is_live(Duration::from_secs(60)));
+        assert!(!health.is_live(Duration::from_secs(10)));
+
+        let stalled = health.stalled_calls(Duration::from_secs(10));
+        assert_eq!(stalled.map(|call| call.id).collect::<Vec<_>>(), [2]);
+    }
+}
@@ -0,0 +1,422 @@
+//! The HTTP client remote packages are downloaded with.
+
+use anyhow::{Context, bail};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// The number of redirects followed per request.
+pub(crate) const MAX_REDIRECTS: usize = 5;
+
+/// The size in bytes of the largest response body clients read by default.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 256 << 20;
+
+/// The size in bytes of the largest status line and headers `PlainHttpClient` reads.
+const MAX_HEAD_SIZE: u64 = 64 << 10;
+
+/// Sends the HTTP requests downloading remote packages, such as an adapter of the host's HTTP
+/// library.
+///
+/// `HttpsClient` speaks both HTTPS and plain HTTP, and `PlainHttpClient` only plain HTTP, e.g. to
+/// local registries without pulling in a TLS implementation.
+pub trait HttpClient {
+    /// Sends a `GET` request for `url` with `headers`, without following redirects.
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<HttpResponse, anyhow::Error>;
+}
+
+impl<H: HttpClient + ?Sized> HttpClient for &H {
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<HttpResponse, anyhow::Error> {
+        (**self).get(url, headers)
+    }
+}
+
+/// The response to a request sent with an `HttpClient`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Returns the value of the header `name`, compared case-insensitively, if any.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the `Location` of the response, if it is a redirect.
+    pub(crate) fn redirect(&self) -> Option<&str> {
+        matches!(self.status, 301 | 302 | 303 | 307 | 308)
+            .then(|| self.header("location"))
+            .flatten()
+    }
+}
+
+/// An `HttpClient` for `https://` and `http://` URLs, verifying servers against the Mozilla root
+/// certificates.
+#[derive(Clone, Debug)]
+pub struct HttpsClient {
+    agent: ureq::Agent,
+    max_body_size: u64,
+}
+
+impl Default for HttpsClient {
+    fn default() -> Self {
+        Self::with_options(Duration::from_secs(30), DEFAULT_MAX_BODY_SIZE)
+    }
+}
+
+impl HttpsClient {
+    /// Creates a client timing out requests after 30 seconds, and failing requests whose
+    /// response body is larger than `DEFAULT_MAX_BODY_SIZE`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Times out requests after `timeout` instead.
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self::with_options(timeout, self.max_body_size)
+    }
+
+    /// Fails requests whose response body is larger than `bytes` instead.
+    #[must_use]
+    pub fn with_max_body_size(mut self, bytes: u64) -> Self {
+        self.max_body_size = bytes;
+        self
+    }
+
+    fn with_options(timeout: Duration, max_body_size: u64) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .max_redirects(0)
+            .http_status_as_error(false)
+            .timeout_global(Some(timeout))
+            .build()
+            .new_agent();
+
+        Self {
+            agent,
+            max_body_size,
+        }
+    }
+}
+
+impl HttpClient for HttpsClient {
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<HttpResponse, anyhow::Error> {
+        let mut request = self.agent.get(url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let mut response = request.call()?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        // The limit of ureq rejects bodies of exactly the limit, which are allowed.
+        let body = response
+            .body_mut()
+            .with_config()
+            .limit(self.max_body_size.saturating_add(1))
+            .read_to_vec()
+            .with_context(|| format!("failed to read the response body of '{url}'"))?;
+        if body.len() as u64 > self.max_body_size {
+            bail!(
+                "the response body of '{url}' is larger than {} bytes",
+                self.max_body_size
+            );
+        }
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// An `HttpClient` sending plain HTTP/1.0 requests over TCP, for `http://` URLs only.
+#[derive(Clone, Debug)]
+pub struct PlainHttpClient {
+    timeout: Duration,
+    max_body_size: u64,
+}
+
+impl Default for PlainHttpClient {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+impl PlainHttpClient {
+    /// Creates a client timing out connections and reads after 30 seconds, and failing requests
+    /// whose response body is larger than `DEFAULT_MAX_BODY_SIZE`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Times out connections and reads after `timeout` instead.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fails requests whose response body is larger than `bytes` instead.
+    #[must_use]
+    pub fn with_max_body_size(mut self, bytes: u64) -> Self {
+        self.max_body_size = bytes;
+        self
+    }
+}
+
+impl HttpClient for PlainHttpClient {
+    fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<HttpResponse, anyhow::Error> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("'{url}' is not a plain HTTP URL");
+        };
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let path = if path.is_empty() { "/" } else { path };
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{host}:80")
+        };
+
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .with_context(|| format!("'{host}' does not resolve to an address"))?;
+
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        // HTTP/1.0 responses end with the connection and are never chunked.
+        let mut request = format!("GET {path} HTTP/1.0\r\nHost: {host}\r\n");
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        let mut reader = BufReader::new(stream);
+        let mut response = read_head(&mut reader)
+            .with_context(|| format!("invalid HTTP response from '{url}'"))?;
+
+        // The body ends with the connection, but is bounded by its length if the server sent it.
+        let content_length = response
+            .header("content-length")
+            .map(|length| length.parse::<u64>())
+            .transpose()
+            .with_context(|| format!("invalid Content-Length in the response from '{url}'"))?;
+        if content_length.is_some_and(|length| length > self.max_body_size) {
+            bail!(
+                "the response body of '{url}' is larger than {} bytes",
+                self.max_body_size
+            );
+        }
+
+        let limit = content_length.unwrap_or(self.max_body_size.saturating_add(1));
+        reader.take(limit).read_to_end(&mut response.body)?;
+
+        if response.body.len() as u64 > self.max_body_size {
+            bail!(
+                "the response body of '{url}' is larger than {} bytes",
+                self.max_body_size
+            );
+        }
+        if content_length.is_some_and(|length| response.body.len() as u64 != length) {
+            bail!("the response body of '{url}' ended before its Content-Length");
+        }
+
+        Ok(response)
+    }
+}
+
+/// Downloads the body of `url` with `client`, following redirects.
+pub(crate) fn download(client: &impl HttpClient, url: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut url = url.to_string();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let response = client.get(&url, &[])?;
+
+        if let Some(location) = response.redirect() {
+            url = resolve_location(&url, location);
+            continue;
+        }
+        if response.status != 200 {
+            bail!("request for '{url}' failed with status {}", response.status);
+        }
+
+        return Ok(response.body);
+    }
+
+    bail!("request for '{url}' redirected too many times")
+}
+
+/// Resolves the `Location` of a redirect of `url`, which may be relative to its host.
+pub(crate) fn resolve_location(url: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+
+    let origin_len = url
+        .find("://")
+        .and_then(|scheme| url[scheme + 3..].find('/').map(|path| scheme + 3 + path))
+        .unwrap_or(url.len());
+
+    format!("{}{location}", &url[..origin_len])
+}
+
+/// Reads the status line and headers of a response, leaving its body in `reader`.
+fn read_head(reader: &mut impl BufRead) -> Result<HttpResponse, anyhow::Error> {
+    let mut head = reader.take(MAX_HEAD_SIZE);
+    let mut lines = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            bail!("the response has no end of headers");
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_string());
+    }
+
+    let mut lines = lines.into_iter();
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1)?.parse().ok())
+        .context("the response has no status")?;
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AddPackageError, CompositionGraph, NoopTrampoline, Sha256Digest, VerificationError,
+    };
+    use semver::Version;
+    use std::net::TcpListener;
+
+    /// The preamble of an empty component.
+    const EMPTY_COMPONENT: &[u8] = b"\0asm\x0d\x00\x01\x00";
+
+    /// Serves the empty component at `/app.wasm`, redirecting to it from `/latest`, and 16 bytes
+    /// at `/sized`, `/unsized` and `/truncated`, with and without a `Content-Length` and with a
+    /// `Content-Length` of 17.
+    fn serve_component() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let line = BufReader::new(&stream).lines().next().unwrap().unwrap();
+
+                let (status, headers, body): (_, _, &[u8]) = match line.split(' ').nth(1) {
+                    Some("/latest") => ("302 Found", "Location: /app.wasm\r\n", b""),
+                    Some("/app.wasm") => ("200 OK", "", EMPTY_COMPONENT),
+                    Some("/sized") => ("200 OK", "Content-Length: 16\r\n", &[0; 16]),
+                    Some("/unsized") => ("200 OK", "", &[0; 16]),
+                    Some("/truncated") => ("200 OK", "Content-Length: 17\r\n", &[0; 16]),
+                    _ => ("404 Not Found", "", b""),
+                };
+
+                let _ = write!(stream, "HTTP/1.0 {status}\r\n{headers}\r\n");
+                let _ = stream.write_all(body);
+            }
+        });
+
+        host
+    }
+
+    #[test]
+    fn test_add_package_from_url() {
+        let host = serve_component();
+        let digest = Sha256Digest::of(EMPTY_COMPONENT);
+        let mut graph = CompositionGraph::<()>::new();
+        let mut add = |version, path: &str, digest| {
+            graph.add_package_from_url(
+                "test:app".to_string(),
+                Version::new(1, 0, version),
+                PlainHttpClient::new(),
+                &format!("http://{host}{path}"),
+                digest,
+                NoopTrampoline,
+            )
+        };
+
+        add(0, "/latest", digest).unwrap();
+        assert!(matches!(
+            add(1, "/missing.wasm", digest),
+            Err(AddPackageError::FetchError { .. })
+        ));
+
+        let err = add(2, "/app.wasm", Sha256Digest::of(b"")).unwrap_err();
+        let AddPackageError::VerificationFailed { source, .. } = &err else {
+            panic!("unexpected error: {err}");
+        };
+        assert!(matches!(
+            source.downcast_ref(),
+            Some(VerificationError::DigestMismatch { actual, .. }) if *actual == digest
+        ));
+        assert_eq!(err.code(), "WCT0010");
+        assert_eq!(
+            err.to_string(),
+            "Package test:app@1.0.2 was refused by the package verifier"
+        );
+    }
+
+    #[test]
+    fn test_http_body_limits() {
+        let host = serve_component();
+        let url = |path: &str| format!("http://{host}{path}");
+
+        let client = PlainHttpClient::new().with_max_body_size(16);
+        assert_eq!(client.get(&url("/sized"), &[]).unwrap().body.len(), 16);
+        assert_eq!(client.get(&url("/unsized"), &[]).unwrap().body.len(), 16);
+        assert!(client.get(&url("/truncated"), &[]).is_err());
+
+        let client = PlainHttpClient::new().with_max_body_size(15);
+        assert!(client.get(&url("/sized"), &[]).is_err());
+        assert!(client.get(&url("/unsized"), &[]).is_err());
+
+        let client = HttpsClient::new().with_max_body_size(16);
+        assert_eq!(client.get(&url("/sized"), &[]).unwrap().body.len(), 16);
+        assert!(
+            HttpsClient::new()
+                .with_max_body_size(15)
+                .get(&url("/unsized"), &[])
+                .is_err()
+        );
+        assert_eq!(download(&client, &url("/latest")).unwrap(), EMPTY_COMPONENT);
+    }
+}
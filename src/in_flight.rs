@@ -0,0 +1,190 @@
+use crate::scopes::Scope;
+use crate::{ForeignInterfacePath, StoreScopes};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Ids of calls, unique across all `InFlightCalls`, so that callers are identified even if they
+/// are tracked by another graph.
+static NEXT_CALL_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns the correlation id of the innermost call to a shadowed function tracked in flight in
+/// the store `scopes` belong to, if any.
+pub(crate) fn current_correlation_id(scopes: &StoreScopes) -> Option<u64> {
+    scopes.calls.current().map(|frame| frame.correlation_id)
+}
+
+/// The calls to shadowed functions currently executing, tracked by the graph's trampolines once
+/// set with `CompositionGraph::set_in_flight_calls`.
+///
+/// Clones share the same calls, so hosts can keep a clone to inspect a hung composition from
+/// another thread. Callers are tracked in the `StoreScopes` of the store making the calls, set
+/// with `CompositionGraph::set_store_scopes`, and are not identified without them.
+#[derive(Clone, Default, Debug)]
+pub struct InFlightCalls {
+    calls: Arc<Mutex<HashMap<u64, CallEntry>>>,
+}
+
+#[derive(Debug)]
+struct CallEntry {
+    function: Arc<(ForeignInterfacePath, String)>,
+    caller_id: Option<u64>,
+    correlation_id: u64,
+    started: Instant,
+}
+
+/// A call to a shadowed function that has not returned yet.
+#[derive(Clone, Debug)]
+pub struct InFlightCall {
+    /// The id of the call, unique within the process.
+    pub id: u64,
+    pub interface: ForeignInterfacePath,
+    pub method: String,
+    /// The id of the shadowed function call that made this call, if any.
+    pub caller_id: Option<u64>,
+    /// The id of the outermost call of the nested calls this call is part of, shared by all of
+    /// them.
+    pub correlation_id: u64,
+    pub elapsed: Duration,
+}
+
+impl InFlightCalls {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the calls currently executing, in the order they were made.
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<InFlightCall> {
+        let calls = self.calls.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut snapshot = calls
+            .iter()
+            .map(|(id, entry)| InFlightCall {
+                id: *id,
+                interface: entry.function.0.clone(),
+                method: entry.function.1.clone(),
+                caller_id: entry.caller_id,
+                correlation_id: entry.correlation_id,
+                elapsed: entry.started.elapsed(),
+            })
+            .collect::<Vec<_>>();
+        snapshot.sort_unstable_by_key(|call| call.id);
+
+        snapshot
+    }
+
+    /// Returns the handle a shadowed function tracks its calls with.
+    pub(crate) fn function(&self, interface: &ForeignInterfacePath, method: &str) -> InFlightFunc {
+        InFlightFunc {
+            calls: self.clone(),
+            function: Arc::new((interface.clone(), method.to_string())),
+        }
+    }
+}
+
+/// A call in flight in a store, which calls it makes are nested in.
+#[derive(Debug)]
+pub(crate) struct CallFrame {
+    id: u64,
+    correlation_id: u64,
+}
+
+/// A shadowed function tracking its calls in `InFlightCalls`.
+#[derive(Clone, Debug)]
+pub(crate) struct InFlightFunc {
+    calls: InFlightCalls,
+    function: Arc<(ForeignInterfacePath, String)>,
+}
+
+impl InFlightFunc {
+    /// Tracks a call starting at `started` in the store `scopes` belong to, if any, returning its
+    /// id and the scope the calls it makes are nested in.
+    pub(crate) fn enter(
+        &self,
+        started: Instant,
+        scopes: Option<&StoreScopes>,
+    ) -> (u64, Option<Scope<CallFrame>>) {
+        let id = NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed);
+
+        let caller = scopes.and_then(|scopes| scopes.calls.current());
+        let caller_id = caller.as_ref().map(|caller| caller.id);
+        let correlation_id = caller.map_or(id, |caller| caller.correlation_id);
+        let scope = scopes.map(|scopes| {
+            scopes
+                .calls
+                .enter(Arc::new(CallFrame { id, correlation_id }))
+        });
+
+        let mut calls = self
+            .calls
+            .calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        calls.insert(
+            id,
+            CallEntry {
+                function: self.function.clone(),
+                caller_id,
+                correlation_id,
+                started,
+            },
+        );
+
+        (id, scope)
+    }
+
+    pub(crate) fn exit(&self, id: u64) {
+        let mut calls = self
+            .calls
+            .calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        calls.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_flight_calls() {
+        let in_flight = InFlightCalls::new();
+        let path = ForeignInterfacePath::new("test:app".to_string(), "store".to_string(), None);
+        let get = in_flight.function(&path, "get");
+        let set = in_flight.function(&path, "set");
+        let scopes = StoreScopes::new();
+
+        let (outer, outer_scope) = get.enter(Instant::now(), Some(&scopes));
+        let (inner, inner_scope) = set.enter(Instant::now(), Some(&scopes));
+        assert_eq!(current_correlation_id(&scopes), Some(outer));
+
+        // Calls in another store are not nested in the calls of this one.
+        let (other, _) = get.enter(Instant::now(), Some(&StoreScopes::new()));
+
+        let snapshot = in_flight.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!((snapshot[0].id, snapshot[0].caller_id), (outer, None));
+        assert_eq!(
+            (snapshot[1].id, snapshot[1].caller_id),
+            (inner, Some(outer))
+        );
+        assert_eq!(snapshot[1].method, "set");
+        assert_eq!(snapshot[1].correlation_id, outer);
+        assert_eq!(
+            (snapshot[2].caller_id, snapshot[2].correlation_id),
+            (None, other)
+        );
+
+        drop(inner_scope);
+        set.exit(inner);
+        drop(outer_scope);
+        get.exit(outer);
+        get.exit(other);
+        assert!(in_flight.snapshot().is_empty());
+        assert_eq!(current_correlation_id(&scopes), None);
+    }
+}
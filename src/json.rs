@@ -0,0 +1,696 @@
+//! Structured JSON rendering of graph errors and diagnostics, for log pipelines.
+
+#[cfg(feature = "recording")]
+use crate::CallTrace;
+use crate::{
+    AddPackageError, CycleEdge, Diagnostic, GraphHealth, GraphWarning, InstantiateError,
+    InstantiatePackageError, InterfaceTypeMismatch, LoadPackageError, MismatchLocation,
+    VerificationError,
+};
+use serde_json::{Map, Value, json};
+use std::error::Error;
+
+impl AddPackageError {
+    /// Renders the error as a JSON object with its `code`, `kind`, `message`, context `fields`
+    /// and `source` chain.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        match self {
+            AddPackageError::DuplicatePackage { name, version } => error_json(
+                self.code(),
+                "DuplicatePackage",
+                self,
+                json!({ "name": name, "version": version.to_string() }),
+                None,
+            ),
+            AddPackageError::PackageParseError { source } => error_json(
+                self.code(),
+                "PackageParseError",
+                self,
+                json!({}),
+                Some(anyhow_json(source)),
+            ),
+            AddPackageError::ImportParseError { interface, source } => error_json(
+                self.code(),
+                "ImportParseError",
+                self,
+                json!({ "interface": interface }),
+                Some(source_json(source)),
+            ),
+            AddPackageError::InternalError { message } => error_json(
+                self.code(),
+                "InternalError",
+                self,
+                json!({ "message": message }),
+                None,
+            ),
+            AddPackageError::ReadError { source } => error_json(
+                self.code(),
+                "ReadError",
+                self,
+                json!({}),
+                Some(source_json(source)),
+            ),
+            #[cfg(feature = "preinit")]
+            AddPackageError::PreinitializeError { source } => error_json(
+                self.code(),
+                "PreinitializeError",
+                self,
+                json!({}),
+                Some(source_json(source)),
+            ),
+            AddPackageError::PackageNotFound { id } => error_json(
+                self.code(),
+                "PackageNotFound",
+                self,
+                json!({ "id": format!("{id:?}") }),
+                None,
+            ),
+            AddPackageError::DeniedByPackagePolicy {
+                name,
+                version,
+                policy,
+            } => error_json(
+                self.code(),
+                "DeniedByPackagePolicy",
+                self,
+                json!({ "name": name, "version": version.to_string(), "policy": policy }),
+                None,
+            ),
+            AddPackageError::VerificationFailed {
+                name,
+                version,
+                source,
+            } => error_json(
+                self.code(),
+                "VerificationFailed",
+                self,
+                match source.downcast_ref::<VerificationError>() {
+                    Some(VerificationError::DigestMismatch { expected, actual }) => json!({
+                        "name": name,
+                        "version": version.to_string(),
+                        "expected": expected.to_string(),
+                        "actual": actual.to_string(),
+                    }),
+                    _ => json!({ "name": name, "version": version.to_string() }),
+                },
+                Some(anyhow_json(source)),
+            ),
+            AddPackageError::FetchError { reference, source } => error_json(
+                self.code(),
+                "FetchError",
+                self,
+                json!({ "reference": reference }),
+                Some(anyhow_json(source)),
+            ),
+        }
+    }
+}
+
+impl InstantiateError {
+    /// Renders the error as a JSON object with its `code`, `kind`, `message`, context `fields`
+    /// and `source` chain. Wrapped graph errors are rendered with their own codes and fields.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        match self {
+            InstantiateError::PackageNotFound { id } => error_json(
+                self.code(),
+                "PackageNotFound",
+                self,
+                json!({ "id": format!("{id:?}") }),
+                None,
+            ),
+            InstantiateError::LoadPackageError { source } => error_json(
+                self.code(),
+                "LoadPackageError",
+                self,
+                json!({}),
+                Some(source.to_json()),
+            ),
+            InstantiateError::InstantiatePackageDependencyError {
+                name,
+                version,
+                source,
+            } => error_json(
+                self.code(),
+                "InstantiatePackageDependencyError",
+                self,
+                json!({ "name": name, "version": version.as_ref().map(ToString::to_string) }),
+                Some(source.to_json()),
+            ),
+            InstantiateError::ComponentInstantiationError { source } => error_json(
+                self.code(),
+                "ComponentInstantiationError",
+                self,
+                json!({}),
+                Some(anyhow_json(source)),
+            ),
+            InstantiateError::ComponentCompilationError { source } => error_json(
+                self.code(),
+                "ComponentCompilationError",
+                self,
+                json!({}),
+                Some(anyhow_json(source)),
+            ),
+            InstantiateError::GuestTrap { source } => error_json(
+                self.code(),
+                "GuestTrap",
+                self,
+                json!({ "trap": self.trap().map(|trap| trap.to_string()) }),
+                Some(anyhow_json(source)),
+            ),
+            InstantiateError::InterfaceTypeMismatch { source } => error_json(
+                self.code(),
+                "InterfaceTypeMismatch",
+                self,
+                mismatch_fields(source),
+                None,
+            ),
+            InstantiateError::LazyPackageError { source } => error_json(
+                self.code(),
+                "LazyPackageError",
+                self,
+                json!({}),
+                Some(source.to_json()),
+            ),
+            InstantiateError::UnpreparedPackage { id } => error_json(
+                self.code(),
+                "UnpreparedPackage",
+                self,
+                json!({ "id": format!("{id:?}") }),
+                None,
+            ),
+        }
+    }
+}
+
+impl LoadPackageError {
+    /// Renders the error as a JSON object with its `code`, `kind`, `message` and context
+    /// `fields`.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        match self {
+            LoadPackageError::PackageCycle { cycle, edges } => error_json(
+                self.code(),
+                "PackageCycle",
+                self,
+                json!({
+                    "cycle": cycle,
+                    "edges": edges.iter().map(edge_json).collect::<Vec<_>>(),
+                }),
+                None,
+            ),
+            LoadPackageError::MissingPackageDependency {
+                package_name,
+                importer,
+                import,
+            } => error_json(
+                self.code(),
+                "MissingPackageDependency",
+                self,
+                json!({
+                    "package_name": package_name,
+                    "importer": importer,
+                    "import": import.to_string(),
+                }),
+                None,
+            ),
+            LoadPackageError::CannotResolvePackageVersion {
+                name,
+                version,
+                importer,
+                import,
+            } => error_json(
+                self.code(),
+                "CannotResolvePackageVersion",
+                self,
+                json!({
+                    "name": name,
+                    "version": version.as_ref().map(ToString::to_string),
+                    "importer": importer,
+                    "import": import.to_string(),
+                }),
+                None,
+            ),
+            LoadPackageError::AmbiguousPackageProvider {
+                name,
+                version,
+                providers,
+            } => error_json(
+                self.code(),
+                "AmbiguousPackageProvider",
+                self,
+                json!({ "name": name, "version": version.to_string(), "providers": providers }),
+                None,
+            ),
+            LoadPackageError::DeniedByPolicy { importer, import } => error_json(
+                self.code(),
+                "DeniedByPolicy",
+                self,
+                json!({ "importer": importer, "import": import.to_string() }),
+                None,
+            ),
+            LoadPackageError::DeniedByPackagePolicy {
+                importer,
+                import,
+                package,
+                policy,
+            } => error_json(
+                self.code(),
+                "DeniedByPackagePolicy",
+                self,
+                json!({
+                    "importer": importer,
+                    "import": import.to_string(),
+                    "package": package,
+                    "policy": policy,
+                }),
+                None,
+            ),
+        }
+    }
+}
+
+impl InstantiatePackageError {
+    /// Renders the error as a JSON object with its `code`, `kind`, `message`, context `fields`
+    /// and `source` chain.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        match self {
+            InstantiatePackageError::ComponentInstantiationError { source } => error_json(
+                self.code(),
+                "ComponentInstantiationError",
+                self,
+                json!({}),
+                Some(anyhow_json(source)),
+            ),
+            InstantiatePackageError::LinkerInstanceError { source } => error_json(
+                self.code(),
+                "LinkerInstanceError",
+                self,
+                json!({}),
+                Some(anyhow_json(source)),
+            ),
+            InstantiatePackageError::InstanceMissingInterfaceExport { interface_name } => {
+                error_json(
+                    self.code(),
+                    "InstanceMissingInterfaceExport",
+                    self,
+                    json!({ "interface_name": interface_name }),
+                    None,
+                )
+            }
+            InstantiatePackageError::InstanceMissingInterfaceFuncExport {
+                interface_name,
+                func_name,
+            } => error_json(
+                self.code(),
+                "InstanceMissingInterfaceFuncExport",
+                self,
+                json!({ "interface_name": interface_name, "func_name": func_name }),
+                None,
+            ),
+            InstantiatePackageError::ComponentFuncRetrievalError {
+                interface_name,
+                func_name,
+            } => error_json(
+                self.code(),
+                "ComponentFuncRetrievalError",
+                self,
+                json!({ "interface_name": interface_name, "func_name": func_name }),
+                None,
+            ),
+            InstantiatePackageError::LinkFuncInstantiationError { source } => error_json(
+                self.code(),
+                "LinkFuncInstantiationError",
+                self,
+                json!({}),
+                Some(anyhow_json(source)),
+            ),
+            InstantiatePackageError::InvalidTrampolineSynchronicity => error_json(
+                self.code(),
+                "InvalidTrampolineSynchronicity",
+                self,
+                json!({}),
+                None,
+            ),
+            InstantiatePackageError::MissingInterfaceExport { path } => error_json(
+                self.code(),
+                "MissingInterfaceExport",
+                self,
+                json!({ "path": path.to_string() }),
+                None,
+            ),
+            InstantiatePackageError::ComponentCompilationError { source } => error_json(
+                self.code(),
+                "ComponentCompilationError",
+                self,
+                json!({}),
+                Some(anyhow_json(source)),
+            ),
+            InstantiatePackageError::GuestTrap { source } => error_json(
+                self.code(),
+                "GuestTrap",
+                self,
+                json!({ "trap": self.trap().map(|trap| trap.to_string()) }),
+                Some(anyhow_json(source)),
+            ),
+            InstantiatePackageError::InterfaceTypeMismatch { source } => error_json(
+                self.code(),
+                "InterfaceTypeMismatch",
+                self,
+                mismatch_fields(source),
+                None,
+            ),
+            InstantiatePackageError::InternalError { message } => error_json(
+                self.code(),
+                "InternalError",
+                self,
+                json!({ "message": message }),
+                None,
+            ),
+            InstantiatePackageError::UnsupportedResourceExport { interface_name } => error_json(
+                self.code(),
+                "UnsupportedResourceExport",
+                self,
+                json!({ "interface_name": interface_name }),
+                None,
+            ),
+            InstantiatePackageError::PackageNotFound { id } => error_json(
+                self.code(),
+                "PackageNotFound",
+                self,
+                json!({ "id": format!("{id:?}") }),
+                None,
+            ),
+            InstantiatePackageError::LazyPackageError { source } => error_json(
+                self.code(),
+                "LazyPackageError",
+                self,
+                json!({}),
+                Some(source.to_json()),
+            ),
+        }
+    }
+}
+
+impl GraphWarning {
+    /// Renders the warning as a JSON object with its `code`, `kind`, `message` and context
+    /// `fields`.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        let (kind, fields) = match self {
+            GraphWarning::DeprecatedPackage {
+                importer,
+                import,
+                version,
+                message,
+            } => (
+                "DeprecatedPackage",
+                json!({
+                    "importer": importer,
+                    "import": import.to_string(),
+                    "version": version.to_string(),
+                    "message": message,
+                }),
+            ),
+            GraphWarning::UnparsableImport {
+                package,
+                import,
+                error,
+            } => (
+                "UnparsableImport",
+                json!({ "package": package, "import": import, "error": error }),
+            ),
+            GraphWarning::SkippedExport { package, export } => (
+                "SkippedExport",
+                json!({ "package": package, "export": export.to_string() }),
+            ),
+        };
+
+        json!({
+            "code": self.code(),
+            "kind": kind,
+            "message": self.to_string(),
+            "fields": fields,
+        })
+    }
+}
+
+impl Diagnostic {
+    /// Renders the diagnostic as a JSON object.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        json!({
+            "severity": self.severity().to_string(),
+            "code": self.code(),
+            "message": self.message(),
+            "package": self.package().map(|package| format!("{package:?}")),
+            "related_paths": self
+                .related_paths()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            "suggestion": self.suggestion(),
+        })
+    }
+}
+
+impl GraphHealth {
+    /// Renders the report as a JSON object, e.g. for the body of a health check endpoint.
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ready": self.is_ready(),
+            "packages": self.packages,
+            "pending_packages": self.pending_packages,
+            "diagnostics": self
+                .diagnostics
+                .iter()
+                .map(Diagnostic::to_json)
+                .collect::<Vec<_>>(),
+            "interfaces": self
+                .interfaces
+                .iter()
+                .map(|interface| json!({
+                    "interface": interface.interface.to_string(),
+                    "calls": interface.recent.calls,
+                    "errors": interface.recent.errors,
+                    "error_rate": interface.recent.error_rate(),
+                }))
+                .collect::<Vec<_>>(),
+            "in_flight_calls": self
+                .in_flight_calls
+                .iter()
+                .map(|call| json!({
+                    "id": call.id,
+                    "interface": call.interface.to_string(),
+                    "method": call.method,
+                    "caller_id": call.caller_id,
+                    "correlation_id": call.correlation_id,
+                    "elapsed_ms": call.elapsed.as_secs_f64() * 1000.0,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(feature = "recording")]
+impl CallTrace {
+    /// Renders the recorded calls in the Chrome `trace_event` format, as read by
+    /// `chrome://tracing` and Perfetto. Each call is a complete event on the thread it returned on,
+    /// so nested calls are shown nested in their callers.
+    #[must_use]
+    pub fn to_chrome_trace(&self) -> Value {
+        let pid = std::process::id();
+
+        let events = self
+            .events()
+            .into_iter()
+            .map(|event| {
+                json!({
+                    "name": &*event.name,
+                    "cat": &*event.package,
+                    "ph": "X",
+                    "ts": event.start.as_secs_f64() * 1e6,
+                    "dur": event.duration.as_secs_f64() * 1e6,
+                    "pid": pid,
+                    "tid": event.thread,
+                    "args": { "failed": event.failed },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+    }
+}
+
+fn error_json(
+    code: &str,
+    kind: &str,
+    err: &dyn Error,
+    fields: Value,
+    source: Option<Value>,
+) -> Value {
+    let mut object = Map::new();
+    object.insert("code".to_string(), code.into());
+    object.insert("kind".to_string(), kind.into());
+    object.insert("message".to_string(), err.to_string().into());
+    object.insert("fields".to_string(), fields);
+
+    if let Some(source) = source {
+        object.insert("source".to_string(), source);
+    }
+
+    Value::Object(object)
+}
+
+fn edge_json(edge: &CycleEdge) -> Value {
+    json!({
+        "importer": edge.importer,
+        "import": edge.import.to_string(),
+        "exporter": edge.exporter,
+    })
+}
+
+fn mismatch_fields(mismatch: &InterfaceTypeMismatch) -> Value {
+    let mismatches = mismatch.mismatches.iter().map(|mismatch| {
+        let (location, param_index, param_name) = match &mismatch.location {
+            MismatchLocation::Item => ("item", None, None),
+            MismatchLocation::ParamCount => ("param_count", None, None),
+            MismatchLocation::Param { index, name } => ("param", Some(*index), Some(name)),
+            MismatchLocation::Result => ("result", None, None),
+        };
+
+        json!({
+            "item": mismatch.item,
+            "location": location,
+            "param_index": param_index,
+            "param_name": param_name,
+            "expected": mismatch.expected,
+            "found": mismatch.found,
+        })
+    });
+
+    json!({
+        "importer": mismatch.importer,
+        "import": mismatch.import.to_string(),
+        "exporter": mismatch.exporter,
+        "mismatches": mismatches.collect::<Vec<_>>(),
+    })
+}
+
+/// Renders an `anyhow` error chain as nested `message`/`source` objects.
+fn anyhow_json(err: &anyhow::Error) -> Value {
+    chain_json(err.chain().map(ToString::to_string).collect())
+}
+
+/// Renders a foreign error's source chain as nested `message`/`source` objects.
+fn source_json(err: &(dyn Error + 'static)) -> Value {
+    let mut messages = Vec::new();
+    let mut source = Some(err);
+
+    while let Some(err) = source {
+        messages.push(err.to_string());
+        source = err.source();
+    }
+
+    chain_json(messages)
+}
+
+fn chain_json(messages: Vec<String>) -> Value {
+    messages
+        .into_iter()
+        .rev()
+        .fold(None, |source, message| {
+            let mut object = Map::new();
+            object.insert("message".to_string(), message.into());
+
+            if let Some(source) = source {
+                object.insert("source".to_string(), source);
+            }
+
+            Some(Value::Object(object))
+        })
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ForeignInterfacePath;
+    use semver::Version;
+    use wasmtime::Trap;
+
+    #[test]
+    fn test_nested_error_json() {
+        let err = InstantiateError::LoadPackageError {
+            source: LoadPackageError::MissingPackageDependency {
+                package_name: "test:kvstore".to_string(),
+                importer: "test:application@0.4.0".to_string(),
+                import: Box::new(ForeignInterfacePath::new(
+                    "test:kvstore".to_string(),
+                    "store".to_string(),
+                    Some(Version::new(1, 0, 0)),
+                )),
+            },
+        };
+
+        let value = err.to_json();
+        assert_eq!(value["code"], "WCT0102");
+        assert_eq!(value["kind"], "LoadPackageError");
+        assert_eq!(value["message"], "Failed to load package");
+        assert_eq!(value["source"]["code"], "WCT0202");
+        assert_eq!(
+            value["source"]["fields"],
+            json!({
+                "package_name": "test:kvstore",
+                "importer": "test:application@0.4.0",
+                "import": "test:kvstore/store@1.0.0",
+            })
+        );
+    }
+
+    #[cfg(feature = "recording")]
+    #[test]
+    fn test_chrome_trace() {
+        let trace = CallTrace::new();
+        let path = ForeignInterfacePath::new(
+            "test:kvstore".to_string(),
+            "store".to_string(),
+            Some(Version::new(1, 0, 0)),
+        );
+        trace
+            .function(&path, "get")
+            .record(std::time::Instant::now(), false);
+
+        let value = trace.to_chrome_trace();
+        let event = &value["traceEvents"][0];
+        assert_eq!(event["name"], "test:kvstore/store@1.0.0#get");
+        assert_eq!(event["cat"], "test:kvstore@1.0.0");
+        assert_eq!(event["ph"], "X");
+        assert_eq!(event["pid"], std::process::id());
+        assert_eq!(event["args"]["failed"], true);
+        assert!(event["ts"].is_f64() && event["dur"].is_f64());
+    }
+
+    #[test]
+    fn test_anyhow_source_json() {
+        let err = InstantiatePackageError::GuestTrap {
+            source: anyhow::Error::from(Trap::UnreachableCodeReached).context("start failed"),
+        };
+
+        let value = err.to_json();
+        assert_eq!(value["code"], "WCT0310");
+        assert_eq!(
+            value["fields"]["trap"],
+            Trap::UnreachableCodeReached.to_string()
+        );
+        assert_eq!(value["source"]["message"], "start failed");
+        assert_eq!(
+            value["source"]["source"]["message"],
+            Trap::UnreachableCodeReached.to_string()
+        );
+        assert_eq!(value["source"]["source"].get("source"), None);
+    }
+}
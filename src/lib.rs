@@ -1,11 +1,126 @@
 #![cfg(not(target_family = "wasm"))]
 
+mod builder;
+mod cache;
+#[cfg(feature = "compose")]
+mod compose;
+#[cfg(feature = "manifest")]
+mod config;
+#[cfg(feature = "deterministic")]
+mod deterministic;
+mod diagnostic;
+mod digest;
+mod error_class;
+mod error_rates;
+mod events;
 mod filter;
 mod graph;
+mod health;
+#[cfg(feature = "http")]
+mod http;
+mod in_flight;
+#[cfg(feature = "json")]
+mod json;
+mod limits;
+mod logging;
+#[cfg(feature = "manifest")]
+mod manifest;
+mod memory;
+mod metadata;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod mismatch;
+#[cfg(feature = "oci")]
+mod oci;
 mod path;
+mod policy;
+#[cfg(feature = "preinit")]
+mod preinit;
+#[cfg(feature = "recording")]
+mod profile;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod recent;
+#[cfg(feature = "miette")]
+mod report;
+mod resolution;
+mod resolver;
+mod resources;
+#[cfg(feature = "runner")]
+mod runner;
+mod sampling;
+mod scopes;
+mod slow_calls;
+mod source;
+#[cfg(feature = "proptest")]
+pub mod strategies;
+mod stub;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "recording")]
+mod trace;
+mod trace_context;
 mod trampoline;
+mod verify;
+mod wasi_http;
+#[cfg(feature = "watch")]
+mod watch;
 
+pub use builder::*;
+#[cfg(feature = "compose")]
+pub use compose::*;
+#[cfg(feature = "manifest")]
+pub use config::*;
+#[cfg(feature = "deterministic")]
+pub use deterministic::*;
+pub use diagnostic::*;
+pub use digest::*;
+pub use error_class::*;
+pub use error_rates::*;
+pub use events::*;
 pub use filter::*;
 pub use graph::*;
+pub use health::*;
+#[cfg(feature = "http")]
+pub use http::*;
+pub use in_flight::*;
+pub use limits::*;
+#[cfg(feature = "manifest")]
+pub use manifest::*;
+pub use memory::*;
+pub use metadata::*;
+#[cfg(feature = "metrics")]
+pub use metrics::*;
+pub use mismatch::*;
+#[cfg(feature = "oci")]
+pub use oci::*;
 pub use path::*;
+pub use policy::*;
+#[cfg(feature = "preinit")]
+pub use preinit::*;
+#[cfg(feature = "recording")]
+pub use profile::*;
+#[cfg(feature = "prometheus")]
+pub use prometheus::{MetricLabel, MetricLabels};
+pub use recent::*;
+pub use resolution::*;
+pub use resolver::*;
+#[cfg(feature = "runner")]
+pub use runner::GraphRunner;
+#[cfg(feature = "recording")]
+pub use sampling::*;
+pub use scopes::StoreScopes;
+pub use slow_calls::*;
+pub use source::*;
+pub use stub::*;
+#[cfg(feature = "recording")]
+pub use trace::*;
+pub use trace_context::*;
 pub use trampoline::*;
+pub use verify::*;
+pub use wasi_http::*;
+pub use wasm_component_semver::{
+    AlternateStrategy, BuildMetadataPolicy, PreReleasePolicy, ResolutionMode, VersionPriority,
+};
+#[cfg(feature = "watch")]
+pub use watch::*;
@@ -0,0 +1,188 @@
+use crate::StoreScopes;
+use wasmtime::{ResourceLimiter, StoreLimits};
+
+/// Limits on the linear memories and tables of a package, set with
+/// `CompositionGraph::set_package_limits`.
+///
+/// The limits apply while the package is instantiated and during calls to its shadowed
+/// functions, and are enforced by the store's resource limiter, which must be a `PackageLimiter`,
+/// e.g. installed with `Store::limiter`. The package being instantiated or called is tracked in
+/// the `StoreScopes` of the store, set with `CompositionGraph::set_store_scopes`, so growth in the
+/// root package outside of shadowed calls is only limited by the inner limiter.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackageLimits {
+    memory_size: Option<usize>,
+    table_elements: Option<usize>,
+}
+
+impl PackageLimits {
+    /// Creates limits that allow any growth.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits each linear memory of the package to `bytes`.
+    #[must_use]
+    pub fn with_memory_size(mut self, bytes: usize) -> Self {
+        self.memory_size = Some(bytes);
+        self
+    }
+
+    /// Limits each table of the package to `elements`.
+    #[must_use]
+    pub fn with_table_elements(mut self, elements: usize) -> Self {
+        self.table_elements = Some(elements);
+        self
+    }
+
+    /// The size in bytes each linear memory of the package is limited to, if any.
+    #[must_use]
+    pub fn memory_size(&self) -> Option<usize> {
+        self.memory_size
+    }
+
+    /// The number of elements each table of the package is limited to, if any.
+    #[must_use]
+    pub fn table_elements(&self) -> Option<usize> {
+        self.table_elements
+    }
+}
+
+/// A resource limiter enforcing the `PackageLimits` of the package being instantiated or called
+/// in a store, delegating all other limits to an inner limiter.
+///
+/// Growth allowed by the package limits must still be allowed by the inner limiter, so the inner
+/// limiter can bound the store as a whole.
+#[derive(Debug)]
+pub struct PackageLimiter<L = StoreLimits> {
+    scopes: StoreScopes,
+    inner: L,
+}
+
+impl PackageLimiter {
+    /// Creates a limiter enforcing only package limits, for the store `scopes` belong to.
+    #[must_use]
+    pub fn new(scopes: &StoreScopes) -> Self {
+        Self::with_inner(scopes, StoreLimits::default())
+    }
+}
+
+impl<L> PackageLimiter<L> {
+    /// Creates a limiter enforcing package limits for the store `scopes` belong to, and
+    /// delegating to `inner`.
+    #[must_use]
+    pub fn with_inner(scopes: &StoreScopes, inner: L) -> Self {
+        Self {
+            scopes: scopes.clone(),
+            inner,
+        }
+    }
+
+    /// Returns the limiter limits are delegated to.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the limiter limits are delegated to.
+    pub fn inner_mut(&mut self) -> &mut L {
+        &mut self.inner
+    }
+}
+
+impl<L: ResourceLimiter> ResourceLimiter for PackageLimiter<L> {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let exceeds = self
+            .scopes
+            .limits
+            .current()
+            .and_then(|limits| limits.memory_size)
+            .is_some_and(|limit| desired > limit);
+
+        if exceeds {
+            return Ok(false);
+        }
+
+        self.inner.memory_growing(current, desired, maximum)
+    }
+
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.inner.memory_grow_failed(error)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let exceeds = self
+            .scopes
+            .limits
+            .current()
+            .and_then(|limits| limits.table_elements)
+            .is_some_and(|limit| desired > limit);
+
+        if exceeds {
+            return Ok(false);
+        }
+
+        self.inner.table_growing(current, desired, maximum)
+    }
+
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.inner.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.inner.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.inner.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.inner.memories()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const PAGE: usize = 0x10000;
+
+    #[test]
+    fn test_package_limiter() {
+        let scopes = StoreScopes::new();
+        let mut limiter = PackageLimiter::new(&scopes);
+        let limits = Arc::new(
+            PackageLimits::new()
+                .with_memory_size(2 * PAGE)
+                .with_table_elements(10),
+        );
+
+        {
+            let _scope = scopes.limits.enter(limits.clone());
+            assert!(limiter.memory_growing(0, 2 * PAGE, None).unwrap());
+            assert!(!limiter.memory_growing(2 * PAGE, 3 * PAGE, None).unwrap());
+            assert!(!limiter.table_growing(0, 11, None).unwrap());
+
+            // Scopes entered in another store do not limit its limiter.
+            let mut other = PackageLimiter::new(&StoreScopes::new());
+            assert!(other.memory_growing(2 * PAGE, 3 * PAGE, None).unwrap());
+
+            let _nested = scopes.limits.enter(Arc::new(PackageLimits::new()));
+            assert!(limiter.memory_growing(2 * PAGE, 3 * PAGE, None).unwrap());
+        }
+        assert!(limiter.memory_growing(2 * PAGE, 3 * PAGE, None).unwrap());
+        assert!(limiter.table_growing(0, 11, None).unwrap());
+    }
+}
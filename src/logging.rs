@@ -0,0 +1,28 @@
+//! The crate's own operational messages, emitted through `log` with structured fields when the
+//! `log` feature is enabled, and compiled out otherwise.
+
+/// Logs a debug message, e.g. `log_debug!(package:% = label; "Added package")`.
+macro_rules! log_debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!(target: "wasm_component_trampoline", $($arg)+);
+    }};
+}
+
+/// Logs a trace message, like `log_debug`.
+macro_rules! log_trace {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::trace!(target: "wasm_component_trampoline", $($arg)+);
+    }};
+}
+
+/// Logs a warning message, like `log_debug`.
+macro_rules! log_warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!(target: "wasm_component_trampoline", $($arg)+);
+    }};
+}
+
+pub(crate) use {log_debug, log_trace, log_warn};
@@ -0,0 +1,207 @@
+//! Serializable descriptions of composition graphs, so that compositions can be persisted and
+//! version-controlled instead of being built imperatively, see `CompositionGraph::to_manifest`.
+
+use crate::sampling::matches_pattern;
+use crate::{
+    AddPackageError, DynPackageTrampoline, ForeignInterfacePath, ImportFilter, ImportRule,
+    Sha256Digest,
+};
+use semver::Version;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use snafu::Snafu;
+use std::path::PathBuf;
+
+/// The packages and import rules of a composition graph.
+///
+/// Manifests are serializable with serde, and can be read from and written to TOML documents:
+///
+/// ```toml
+/// [[packages]]
+/// name = "test:kvstore"
+/// version = "1.0.0"
+/// path = "kvstore.component.wasm"
+/// digest = "sha256:..."
+///
+/// [[packages]]
+/// name = "test:application"
+/// version = "0.4.0"
+/// path = "application.component.wasm"
+/// trampoline = "logging"
+///
+/// [[import_rules]]
+/// interface = "wasi:*"
+/// rule = "skip"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompositionManifest {
+    pub packages: Vec<PackageManifest>,
+    pub import_rules: Vec<ImportRuleManifest>,
+}
+
+/// A package of a `CompositionManifest`.
+///
+/// The component bytes are located by `path` or `digest`, and checked against `digest` when both
+/// are given. The trampoline is named by an identifier resolved by the `ManifestResolver` the
+/// manifest is loaded with, and packages without one are added with a `NoopTrampoline`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: Version,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Sha256Digest>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trampoline: Option<String>,
+}
+
+/// Applies `rule` to the imports whose path matches the pattern `interface`, where `*` matches
+/// any sequence of characters.
+///
+/// Lists of rules are import filters applying the first `Skip` or `Force` rule matching an import.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportRuleManifest {
+    pub interface: String,
+    pub rule: ImportRule,
+}
+
+impl ImportFilter for ImportRuleManifest {
+    fn filter_rule(&self, import_path: &ForeignInterfacePath) -> ImportRule {
+        if matches_pattern(&self.interface, import_path.as_str()) {
+            self.rule.clone()
+        } else {
+            ImportRule::Include
+        }
+    }
+}
+
+impl CompositionManifest {
+    /// Parses a manifest from a TOML document.
+    pub fn from_toml(document: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(document)
+    }
+
+    /// Formats the manifest as a TOML document.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+}
+
+/// Provides the component bytes and trampolines of the packages of a manifest loaded with
+/// `CompositionGraph::from_manifest`.
+pub trait ManifestResolver<D, C: Clone> {
+    /// Returns the component bytes of `package`, such as by reading its path relative to the
+    /// manifest, or by looking up its digest in a content-addressed store.
+    fn package_bytes(&self, package: &PackageManifest) -> Result<Vec<u8>, anyhow::Error>;
+
+    /// Returns the trampoline identified by `id`, or `None` if there is no such trampoline.
+    fn trampoline(&self, id: &str) -> Option<Box<dyn DynPackageTrampoline<D, C>>>;
+}
+
+/// A manifest that cannot be loaded with `CompositionGraph::from_manifest`.
+#[derive(Snafu, Debug)]
+#[snafu(module, visibility(pub(crate)))]
+pub enum ManifestError {
+    #[snafu(display("Failed to read the component of package '{name}@{version}'"))]
+    ReadPackage {
+        name: String,
+        version: Version,
+        source: anyhow::Error,
+    },
+
+    #[snafu(display("Package '{name}@{version}' has unknown trampoline '{trampoline}'"))]
+    UnknownTrampoline {
+        name: String,
+        version: Version,
+        trampoline: String,
+    },
+
+    #[snafu(display("Failed to add package '{name}@{version}'"))]
+    AddPackage {
+        name: String,
+        version: Version,
+        #[snafu(source(from(AddPackageError, Box::new)))]
+        source: Box<AddPackageError>,
+    },
+}
+
+impl Serialize for Sha256Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sha256Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompositionGraph, NoopTrampoline};
+
+    /// The preamble of an empty component.
+    const EMPTY_COMPONENT: &[u8] = b"\0asm\x0d\x00\x01\x00";
+
+    struct Resolver;
+
+    impl ManifestResolver<(), ()> for Resolver {
+        fn package_bytes(&self, _package: &PackageManifest) -> Result<Vec<u8>, anyhow::Error> {
+            Ok(EMPTY_COMPONENT.to_vec())
+        }
+
+        fn trampoline(&self, id: &str) -> Option<Box<dyn DynPackageTrampoline<(), ()>>> {
+            (id == "noop").then(|| Box::new(NoopTrampoline) as _)
+        }
+    }
+
+    #[test]
+    fn test_composition_manifest() {
+        let manifest = CompositionManifest::from_toml(
+            r#"
+            [[packages]]
+            name = "test:app"
+            version = "1.0.0"
+            path = "app.component.wasm"
+            trampoline = "noop"
+
+            [[import_rules]]
+            interface = "wasi:*"
+            rule = "skip"
+            "#,
+        )
+        .unwrap();
+
+        let graph = CompositionGraph::<()>::from_manifest(&manifest, &Resolver).unwrap();
+        let exported = graph.to_manifest();
+        assert_eq!(exported.import_rules, manifest.import_rules);
+        assert_eq!(
+            exported.packages[0].digest,
+            Some(Sha256Digest::of(EMPTY_COMPONENT))
+        );
+        assert_eq!(
+            CompositionManifest::from_toml(&exported.to_toml().unwrap()).unwrap(),
+            exported
+        );
+
+        let wasi = ForeignInterfacePath::new("wasi:cli".to_string(), "stdout".to_string(), None);
+        assert!(matches!(
+            manifest.import_rules.filter_rule(&wasi),
+            ImportRule::Skip
+        ));
+
+        let mut unknown = manifest;
+        unknown.packages[0].trampoline = Some("missing".to_string());
+        assert!(matches!(
+            CompositionGraph::<()>::from_manifest(&unknown, &Resolver),
+            Err(ManifestError::UnknownTrampoline { .. })
+        ));
+    }
+}
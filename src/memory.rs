@@ -0,0 +1,386 @@
+use crate::scopes::Scope;
+use crate::{ForeignInterfacePath, StoreScopes};
+use semver::Version;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use wasmtime::{ResourceLimiter, StoreLimits};
+
+/// Tracks the growth of the linear memories of the graph's packages, attributing it to the
+/// package being instantiated or to the shadowed function being called when memory grows.
+///
+/// Packages are tracked once the tracker is set with `CompositionGraph::set_memory_tracker`, and
+/// growth is observed by the store's resource limiter, which must be a `MemoryLimiter` of the
+/// same tracker, e.g. installed with `Store::limiter`. Attribution follows the `StoreScopes` of
+/// the store, set with `CompositionGraph::set_store_scopes`, so growth in the root package outside
+/// of shadowed calls is recorded without a package.
+///
+/// Clones share the same usage.
+#[derive(Clone, Debug)]
+pub struct MemoryTracker {
+    epoch: Instant,
+    usage: Arc<Mutex<MemoryUsage>>,
+}
+
+#[derive(Default, Debug)]
+struct MemoryUsage {
+    packages: HashMap<Arc<PackageKey>, PackageEntry>,
+    growths: Vec<MemoryGrowth>,
+}
+
+type PackageKey = (String, Option<Version>);
+
+#[derive(Default, Debug)]
+struct PackageEntry {
+    instantiation: u64,
+    high_water: u64,
+    functions: HashMap<Arc<(ForeignInterfacePath, String)>, FunctionMemory>,
+}
+
+/// The memory growth attributed to a package.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PackageMemory {
+    pub name: String,
+    pub version: Option<Version>,
+    /// The bytes grown while instantiating the package and in calls to its functions.
+    pub grown: u64,
+    /// The bytes grown while instantiating the package.
+    pub instantiation: u64,
+    /// The largest size in bytes reached by a linear memory while attributed to the package.
+    pub high_water: u64,
+    /// The growth attributed to each function of the package, ordered by interface and method.
+    pub functions: Vec<FunctionMemory>,
+}
+
+/// The memory growth attributed to calls to a shadowed function.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FunctionMemory {
+    pub interface: ForeignInterfacePath,
+    pub method: String,
+    /// The bytes grown during calls to the function, excluding the calls they made.
+    pub grown: u64,
+    /// The number of times memory grew during calls to the function.
+    pub growths: u64,
+}
+
+/// A growth of a linear memory observed by a `MemoryLimiter`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoryGrowth {
+    /// The time memory grew, relative to the creation of the tracker.
+    pub at: Duration,
+    /// The name and version of the package the growth is attributed to, if any.
+    pub package: Option<(String, Option<Version>)>,
+    /// The interface and method of the function the growth is attributed to, if it grew during
+    /// a call rather than an instantiation.
+    pub function: Option<(ForeignInterfacePath, String)>,
+    /// The size of the memory before growing, in bytes.
+    pub from: u64,
+    /// The size of the memory after growing, in bytes.
+    pub to: u64,
+}
+
+impl Default for MemoryTracker {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            usage: Arc::default(),
+        }
+    }
+}
+
+impl MemoryTracker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a resource limiter recording memory growth in this tracker, without limiting it,
+    /// for the store `scopes` belong to.
+    #[must_use]
+    pub fn limiter(&self, scopes: &StoreScopes) -> MemoryLimiter {
+        self.limiter_with(scopes, StoreLimits::default())
+    }
+
+    /// Returns a resource limiter recording the memory growth allowed by `inner` in this tracker,
+    /// for the store `scopes` belong to.
+    #[must_use]
+    pub fn limiter_with<L: ResourceLimiter>(
+        &self,
+        scopes: &StoreScopes,
+        inner: L,
+    ) -> MemoryLimiter<L> {
+        MemoryLimiter {
+            tracker: self.clone(),
+            scopes: scopes.clone(),
+            inner,
+        }
+    }
+
+    /// Returns the memory growth attributed to each package, ordered by name and version.
+    #[must_use]
+    pub fn usage(&self) -> Vec<PackageMemory> {
+        let usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut packages = usage
+            .packages
+            .iter()
+            .map(|(key, entry)| {
+                let mut functions = entry.functions.values().cloned().collect::<Vec<_>>();
+                functions.sort_unstable_by(|a, b| {
+                    (&a.interface, &a.method).cmp(&(&b.interface, &b.method))
+                });
+
+                PackageMemory {
+                    name: key.0.clone(),
+                    version: key.1.clone(),
+                    grown: entry.instantiation
+                        + functions.iter().map(|function| function.grown).sum::<u64>(),
+                    instantiation: entry.instantiation,
+                    high_water: entry.high_water,
+                    functions,
+                }
+            })
+            .collect::<Vec<_>>();
+        packages.sort_unstable_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+        packages
+    }
+
+    /// Returns the recorded growths, in the order they happened.
+    #[must_use]
+    pub fn growths(&self) -> Vec<MemoryGrowth> {
+        self.usage
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .growths
+            .clone()
+    }
+
+    /// Removes all recorded usage and growths.
+    pub fn clear(&self) {
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        usage.packages.clear();
+        usage.growths.clear();
+    }
+
+    /// Returns the handle a package attributes its memory growth with.
+    pub(crate) fn package(&self, name: &str, version: Option<&Version>) -> TrackedPackage {
+        let package = Arc::new((name.to_string(), version.cloned()));
+
+        TrackedPackage {
+            instantiation: Arc::new(Attribution {
+                package: package.clone(),
+                function: None,
+            }),
+            package,
+        }
+    }
+
+    fn record(&self, attribution: Option<Arc<Attribution>>, from: usize, to: usize) {
+        let (from, to) = (from as u64, to as u64);
+
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(attribution) = &attribution {
+            let entry = usage
+                .packages
+                .entry(attribution.package.clone())
+                .or_default();
+            entry.high_water = entry.high_water.max(to);
+
+            match &attribution.function {
+                Some(function) => {
+                    let memory =
+                        entry
+                            .functions
+                            .entry(function.clone())
+                            .or_insert_with(|| FunctionMemory {
+                                interface: function.0.clone(),
+                                method: function.1.clone(),
+                                grown: 0,
+                                growths: 0,
+                            });
+                    memory.grown += to - from;
+                    memory.growths += 1;
+                }
+                None => entry.instantiation += to - from,
+            }
+        }
+
+        usage.growths.push(MemoryGrowth {
+            at: self.epoch.elapsed(),
+            package: attribution
+                .as_ref()
+                .map(|attribution| (*attribution.package).clone()),
+            function: attribution
+                .as_ref()
+                .and_then(|attribution| attribution.function.as_deref().cloned()),
+            from,
+            to,
+        });
+    }
+}
+
+/// A resource limiter recording the growth of linear memories in a `MemoryTracker`, delegating
+/// all limits to an inner limiter.
+#[derive(Debug)]
+pub struct MemoryLimiter<L = StoreLimits> {
+    tracker: MemoryTracker,
+    scopes: StoreScopes,
+    inner: L,
+}
+
+impl<L> MemoryLimiter<L> {
+    /// Returns the limiter limits are delegated to.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the limiter limits are delegated to.
+    pub fn inner_mut(&mut self) -> &mut L {
+        &mut self.inner
+    }
+}
+
+impl<L: ResourceLimiter> ResourceLimiter for MemoryLimiter<L> {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let allowed = self.inner.memory_growing(current, desired, maximum)?;
+
+        if allowed {
+            self.tracker
+                .record(self.scopes.memory.current(), current, desired);
+        }
+
+        Ok(allowed)
+    }
+
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.inner.memory_grow_failed(error)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        self.inner.table_growing(current, desired, maximum)
+    }
+
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.inner.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.inner.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.inner.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.inner.memories()
+    }
+}
+
+/// The instantiation or shadowed function memory growth is attributed to.
+#[derive(Debug)]
+pub(crate) struct Attribution {
+    package: Arc<PackageKey>,
+    function: Option<Arc<(ForeignInterfacePath, String)>>,
+}
+
+/// A package attributing its memory growth in a `MemoryTracker`.
+#[derive(Clone, Debug)]
+pub(crate) struct TrackedPackage {
+    package: Arc<PackageKey>,
+    instantiation: Arc<Attribution>,
+}
+
+impl TrackedPackage {
+    /// Attributes memory growth to the instantiation of the package in the store `scopes` belong
+    /// to, until the scope is dropped.
+    pub(crate) fn instantiating(&self, scopes: &StoreScopes) -> Scope<Attribution> {
+        scopes.memory.enter(self.instantiation.clone())
+    }
+
+    /// Returns the handle a shadowed function of the package attributes its memory growth with.
+    pub(crate) fn function(&self, interface: &ForeignInterfacePath, method: &str) -> TrackedFunc {
+        TrackedFunc {
+            attribution: Arc::new(Attribution {
+                package: self.package.clone(),
+                function: Some(Arc::new((interface.clone(), method.to_string()))),
+            }),
+        }
+    }
+}
+
+/// A shadowed function attributing its memory growth in a `MemoryTracker`.
+#[derive(Clone, Debug)]
+pub(crate) struct TrackedFunc {
+    attribution: Arc<Attribution>,
+}
+
+impl TrackedFunc {
+    /// Attributes memory growth to a call to the function in the store `scopes` belong to, until
+    /// the scope is dropped.
+    pub(crate) fn enter(&self, scopes: &StoreScopes) -> Scope<Attribution> {
+        scopes.memory.enter(self.attribution.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: usize = 0x10000;
+
+    #[test]
+    fn test_memory_tracker() {
+        let tracker = MemoryTracker::new();
+        let scopes = StoreScopes::new();
+        let mut limiter = tracker.limiter(&scopes);
+        let path = ForeignInterfacePath::new("test:kvstore".to_string(), "store".to_string(), None);
+        let package = tracker.package("test:kvstore", None);
+        let set = package.function(&path, "set");
+
+        {
+            let _scope = package.instantiating(&scopes);
+            assert!(limiter.memory_growing(0, PAGE, None).unwrap());
+        }
+        {
+            let _scope = set.enter(&scopes);
+            assert!(limiter.memory_growing(PAGE, 3 * PAGE, None).unwrap());
+
+            // Growth in another store is not attributed to calls in this one.
+            let mut other = tracker.limiter(&StoreScopes::new());
+            assert!(other.memory_growing(0, PAGE, None).unwrap());
+        }
+        assert!(limiter.memory_growing(0, PAGE, None).unwrap());
+
+        let usage = tracker.usage();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].name, "test:kvstore");
+        assert_eq!(usage[0].instantiation, PAGE as u64);
+        assert_eq!(usage[0].grown, 3 * PAGE as u64);
+        assert_eq!(usage[0].high_water, 3 * PAGE as u64);
+        assert_eq!(usage[0].functions.len(), 1);
+        assert_eq!(usage[0].functions[0].method, "set");
+        assert_eq!(usage[0].functions[0].grown, 2 * PAGE as u64);
+
+        let growths = tracker.growths();
+        assert_eq!(growths.len(), 4);
+        assert!(growths[1].function.is_some());
+        assert_eq!(growths[2].package, None);
+        assert_eq!(growths[3].package, None);
+
+        tracker.clear();
+        assert!(tracker.usage().is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Metadata attached to a package with `CompositionGraph::add_package_with_metadata`, such as its
+/// author, capabilities, source registry or digest.
+///
+/// Metadata holds string values by key, and at most one value of each type, so hosts can attach
+/// structured metadata like a capability set without encoding it as strings. Trampolines read the
+/// metadata of the package being called with `GuestCallData::callee_metadata`, e.g. for policy
+/// decisions.
+#[derive(Clone, Default)]
+pub struct PackageMetadata {
+    values: BTreeMap<String, String>,
+    typed: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Debug for PackageMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PackageMetadata")
+            .field("values", &self.values)
+            .field("typed", &self.typed.len())
+            .finish()
+    }
+}
+
+impl PackageMetadata {
+    /// Creates metadata without values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of `key`.
+    #[must_use]
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Sets the value of `key`, returning its previous value, if any.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.values.insert(key.into(), value.into())
+    }
+
+    /// Returns the value of `key`, if any.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Returns the values, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Sets the value of type `T`.
+    #[must_use]
+    pub fn with_typed<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.insert_typed(value);
+        self
+    }
+
+    /// Sets the value of type `T`, replacing any previous value of that type.
+    pub fn insert_typed<T: Any + Send + Sync>(&mut self, value: T) {
+        self.typed.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Returns the value of type `T`, if any.
+    #[must_use]
+    pub fn get_typed<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.typed
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompositionGraph, NoopTrampoline};
+    use semver::Version;
+
+    #[derive(Debug, PartialEq)]
+    struct Capabilities(Vec<&'static str>);
+
+    #[test]
+    fn test_package_metadata() {
+        let metadata = PackageMetadata::new()
+            .with("author", "andyl")
+            .with("registry", "ghcr.io")
+            .with_typed(Capabilities(vec!["network"]));
+
+        assert_eq!(metadata.get("author"), Some("andyl"));
+        assert_eq!(metadata.get("digest"), None);
+        assert_eq!(
+            metadata.iter().collect::<Vec<_>>(),
+            [("author", "andyl"), ("registry", "ghcr.io")]
+        );
+        assert_eq!(
+            metadata.get_typed::<Capabilities>(),
+            Some(&Capabilities(vec!["network"]))
+        );
+        assert_eq!(metadata.get_typed::<String>(), None);
+
+        let mut graph = CompositionGraph::<()>::new();
+        let package_id = graph
+            .add_package_with_metadata(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                b"\0asm\x0d\x00\x01\x00".to_vec(),
+                NoopTrampoline,
+                metadata,
+            )
+            .unwrap();
+        let metadata = graph.package_metadata(package_id).unwrap();
+        assert_eq!(metadata.get("registry"), Some("ghcr.io"));
+    }
+}
@@ -0,0 +1,522 @@
+use crate::recent::RecentWindow;
+use crate::{ForeignInterfacePath, RecentCalls};
+use semver::Version;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the call duration histogram buckets, in seconds.
+pub(crate) const DURATION_BUCKETS: [f64; 12] = [
+    0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+
+/// Sub-buckets per power of two of the latency histograms, bounding the relative error of
+/// latency quantiles to 1/8.
+const LATENCY_SUB_BUCKETS: usize = 8;
+
+/// The number of latency histogram buckets: exact buckets for durations below
+/// `LATENCY_SUB_BUCKETS` nanoseconds, then `LATENCY_SUB_BUCKETS` per power of two up to
+/// `u64::MAX` nanoseconds.
+const LATENCY_BUCKETS: usize = 62 * LATENCY_SUB_BUCKETS;
+
+/// The period covered by `CallMetrics::recent_calls`.
+pub const RECENT_CALLS_WINDOW: Duration = Duration::from_secs(60);
+
+thread_local! {
+    /// The fuel consumed by the calls made by each call measuring fuel on this thread, innermost
+    /// last.
+    static FUEL_STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The metrics of each function, by interface path and method.
+type FuncMetricsMap = HashMap<(ForeignInterfacePath, String), Arc<FuncMetrics>>;
+
+/// Counts and durations of the calls to shadowed functions, recorded by the graph's trampolines
+/// once set with `CompositionGraph::set_call_metrics`.
+///
+/// If fuel is enabled in the store's engine, the fuel consumed by each function is recorded too,
+/// excluding the fuel consumed by the shadowed functions it called, so that it is attributed to
+/// the package that consumed it. Nested calls are tracked per thread, like the call stacks of
+/// `CallProfiler`.
+///
+/// Clones share the same metrics, so hosts can keep a clone to read the metrics recorded by the
+/// graph.
+#[derive(Clone, Default, Debug)]
+pub struct CallMetrics {
+    functions: Arc<Mutex<FuncMetricsMap>>,
+}
+
+impl CallMetrics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the metrics of all functions called at least once, ordered by interface and
+    /// method.
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<CallStats> {
+        let functions = self
+            .functions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut stats = functions
+            .iter()
+            .map(|((interface, method), metrics)| CallStats {
+                interface: interface.clone(),
+                method: method.clone(),
+                p50: metrics.latency_quantile(0.5),
+                p95: metrics.latency_quantile(0.95),
+                p99: metrics.latency_quantile(0.99),
+                calls: metrics.calls.load(Ordering::Relaxed),
+                errors: metrics.errors.load(Ordering::Relaxed),
+                duration: Duration::from_nanos(metrics.duration_nanos.load(Ordering::Relaxed)),
+                fuel: metrics.fuel.load(Ordering::Relaxed),
+                duration_buckets: metrics
+                    .duration_buckets
+                    .iter()
+                    .map(|bucket| bucket.load(Ordering::Relaxed))
+                    .collect(),
+            })
+            .filter(|stats| stats.calls > 0)
+            .collect::<Vec<_>>();
+
+        stats.sort_unstable_by(|a, b| {
+            (a.interface.as_str(), &a.method).cmp(&(b.interface.as_str(), &b.method))
+        });
+
+        stats
+    }
+
+    /// Estimates the `quantile` (between 0 and 1) of the durations of the calls to `method` of
+    /// `interface`, or to all methods of `interface` if `method` is `None`. Returns `None` if
+    /// there were no such calls.
+    ///
+    /// Estimates are accurate to within 1/8 of the actual duration.
+    #[must_use]
+    pub fn latency_quantile(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: Option<&str>,
+        quantile: f64,
+    ) -> Option<Duration> {
+        let mut counts = vec![0; LATENCY_BUCKETS];
+
+        self.for_each_function(interface, method, |metrics| {
+            for (count, bucket) in counts.iter_mut().zip(&metrics.latency_buckets[..]) {
+                *count += bucket.load(Ordering::Relaxed);
+            }
+        });
+
+        latency_quantile(&counts, quantile)
+    }
+
+    /// Returns the fraction of failed calls to `method` of `interface`, or to all methods of
+    /// `interface` if `method` is `None`. Returns `None` if there were no such calls.
+    #[must_use]
+    pub fn error_rate(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: Option<&str>,
+    ) -> Option<f64> {
+        let (mut calls, mut errors) = (0, 0);
+
+        self.for_each_function(interface, method, |metrics| {
+            calls += metrics.calls.load(Ordering::Relaxed);
+            errors += metrics.errors.load(Ordering::Relaxed);
+        });
+
+        (calls > 0).then(|| errors as f64 / calls as f64)
+    }
+
+    /// Returns the number of calls to `method` of `interface`, or to all methods of `interface` if
+    /// `method` is `None`, and of those that failed, within about the last
+    /// `RECENT_CALLS_WINDOW`.
+    #[must_use]
+    pub fn recent_calls(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: Option<&str>,
+    ) -> RecentCalls {
+        let mut recent = RecentCalls::default();
+
+        self.for_each_function(interface, method, |metrics| {
+            recent += metrics.recent_calls();
+        });
+
+        recent
+    }
+
+    /// Returns the recent calls of each interface called within about the last
+    /// `RECENT_CALLS_WINDOW`, ordered by interface.
+    pub(crate) fn recent_calls_by_interface(&self) -> Vec<(ForeignInterfacePath, RecentCalls)> {
+        let functions = self
+            .functions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut interfaces = BTreeMap::<&ForeignInterfacePath, RecentCalls>::new();
+        for ((interface, _), metrics) in functions.iter() {
+            *interfaces.entry(interface).or_default() += metrics.recent_calls();
+        }
+
+        interfaces
+            .into_iter()
+            .filter(|(_, recent)| recent.calls > 0)
+            .map(|(interface, recent)| (interface.clone(), recent))
+            .collect()
+    }
+
+    /// Returns the fuel consumed by calls to `method` of `interface`, or to all methods of
+    /// `interface` if `method` is `None`.
+    #[must_use]
+    pub fn interface_fuel(&self, interface: &ForeignInterfacePath, method: Option<&str>) -> u64 {
+        let mut fuel = 0;
+
+        self.for_each_function(interface, method, |metrics| {
+            fuel += metrics.fuel.load(Ordering::Relaxed);
+        });
+
+        fuel
+    }
+
+    /// Returns the fuel consumed by the functions of each package that consumed fuel, ordered by
+    /// name and version.
+    #[must_use]
+    pub fn package_fuel(&self) -> Vec<PackageFuel> {
+        let functions = self
+            .functions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut packages = BTreeMap::<(&str, Option<&Version>), u64>::new();
+        for ((interface, _), metrics) in functions.iter() {
+            *packages
+                .entry((interface.package_name(), interface.version()))
+                .or_default() += metrics.fuel.load(Ordering::Relaxed);
+        }
+
+        packages
+            .into_iter()
+            .filter(|(_, fuel)| *fuel > 0)
+            .map(|((name, version), fuel)| PackageFuel {
+                name: name.to_string(),
+                version: version.cloned(),
+                fuel,
+            })
+            .collect()
+    }
+
+    fn for_each_function(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: Option<&str>,
+        mut f: impl FnMut(&FuncMetrics),
+    ) {
+        let functions = self
+            .functions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        functions
+            .iter()
+            .filter(|((function_interface, function_method), _)| {
+                function_interface == interface
+                    && method.is_none_or(|method| method == function_method)
+            })
+            .for_each(|(_, metrics)| f(metrics));
+    }
+
+    /// Returns the metrics of a function, registering it on first use.
+    pub(crate) fn function(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: &str,
+    ) -> Arc<FuncMetrics> {
+        let mut functions = self
+            .functions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        functions
+            .entry((interface.clone(), method.to_string()))
+            .or_default()
+            .clone()
+    }
+}
+
+/// The metrics of the calls to a shadowed function.
+#[derive(Clone, Debug)]
+pub struct CallStats {
+    /// The path of the interface exporting the function.
+    pub interface: ForeignInterfacePath,
+    pub method: String,
+    pub calls: u64,
+    /// The number of calls that returned an error, from the guest or the trampoline.
+    pub errors: u64,
+    /// The total duration of all calls.
+    pub duration: Duration,
+    /// The fuel consumed by all calls, excluding the shadowed functions they called. Zero if
+    /// fuel is not enabled.
+    pub fuel: u64,
+    /// The number of calls per duration bucket, not including the calls counted by the preceding
+    /// buckets. Calls longer than the last bucket are only counted in `calls`.
+    pub duration_buckets: Vec<u64>,
+    /// The estimated median call duration, see `CallMetrics::latency_quantile`.
+    pub p50: Duration,
+    /// The estimated 95th percentile call duration.
+    pub p95: Duration,
+    /// The estimated 99th percentile call duration.
+    pub p99: Duration,
+}
+
+impl CallStats {
+    /// Returns the fraction of calls that failed.
+    #[must_use]
+    pub fn error_rate(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.calls as f64
+        }
+    }
+}
+
+/// The fuel consumed by the functions of a package.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PackageFuel {
+    pub name: String,
+    pub version: Option<Version>,
+    pub fuel: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct FuncMetrics {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    duration_nanos: AtomicU64,
+    fuel: AtomicU64,
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    latency_buckets: Box<[AtomicU64]>,
+    recent: RecentWindow,
+}
+
+impl Default for FuncMetrics {
+    fn default() -> Self {
+        Self {
+            calls: AtomicU64::default(),
+            errors: AtomicU64::default(),
+            duration_nanos: AtomicU64::default(),
+            fuel: AtomicU64::default(),
+            duration_buckets: Default::default(),
+            latency_buckets: (0..LATENCY_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            recent: RecentWindow::new(RECENT_CALLS_WINDOW),
+        }
+    }
+}
+
+impl FuncMetrics {
+    pub(crate) fn record(&self, started: Instant, succeeded: bool) {
+        self.record_duration(started.elapsed(), succeeded);
+    }
+
+    fn record_duration(&self, duration: Duration, succeeded: bool) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+
+        if !succeeded {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.recent.record(succeeded);
+
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.duration_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.latency_buckets[latency_bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn recent_calls(&self) -> RecentCalls {
+        self.recent.calls()
+    }
+
+    /// Starts measuring the fuel consumed by a call. Must be followed by `exit_fuel` when the
+    /// call returns.
+    pub(crate) fn enter_fuel(&self) {
+        FUEL_STACK.with_borrow_mut(|stack| stack.push(0));
+    }
+
+    /// Records the fuel consumed by a call, given the fuel remaining in the store when it started
+    /// and returned.
+    pub(crate) fn exit_fuel(&self, before: u64, after: u64) {
+        let consumed = before.saturating_sub(after);
+
+        let children = FUEL_STACK.with_borrow_mut(|stack| {
+            let children = stack.pop().unwrap_or_default();
+            if let Some(caller) = stack.last_mut() {
+                *caller += consumed;
+            }
+
+            children
+        });
+
+        self.fuel
+            .fetch_add(consumed.saturating_sub(children), Ordering::Relaxed);
+    }
+
+    fn latency_quantile(&self, quantile: f64) -> Duration {
+        let counts = self
+            .latency_buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+
+        latency_quantile(&counts, quantile).unwrap_or_default()
+    }
+}
+
+/// Returns the latency histogram bucket of a duration in nanoseconds.
+fn latency_bucket(nanos: u64) -> usize {
+    const SUB_BITS: u32 = LATENCY_SUB_BUCKETS.trailing_zeros();
+
+    if nanos < LATENCY_SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+
+    // The most significant bit selects the power of two, and the bits following it the
+    // sub-bucket within it.
+    let msb = u64::BITS - 1 - nanos.leading_zeros();
+    let sub_bucket = (nanos >> (msb - SUB_BITS)) as usize - LATENCY_SUB_BUCKETS;
+
+    (msb - SUB_BITS + 1) as usize * LATENCY_SUB_BUCKETS + sub_bucket
+}
+
+/// Returns the range of durations in nanoseconds counted by a latency histogram bucket.
+fn latency_bucket_range(bucket: usize) -> (u128, u128) {
+    if bucket < LATENCY_SUB_BUCKETS {
+        return (bucket as u128, bucket as u128 + 1);
+    }
+
+    let shift = bucket / LATENCY_SUB_BUCKETS - 1;
+    let sub_bucket = (LATENCY_SUB_BUCKETS + bucket % LATENCY_SUB_BUCKETS) as u128;
+
+    (sub_bucket << shift, (sub_bucket + 1) << shift)
+}
+
+/// Estimates a quantile from latency histogram bucket counts, as the middle of the bucket
+/// containing it.
+fn latency_quantile(counts: &[u64], quantile: f64) -> Option<Duration> {
+    let total = counts.iter().sum::<u64>();
+    if total == 0 {
+        return None;
+    }
+
+    let rank = ((quantile.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+    let mut cumulative = 0;
+
+    let bucket = counts.iter().position(|count| {
+        cumulative += count;
+        cumulative >= rank
+    })?;
+
+    let (start, end) = latency_bucket_range(bucket);
+    let nanos = u64::try_from((start + end) / 2).unwrap_or(u64::MAX);
+
+    Some(Duration::from_nanos(nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn test_call_metrics() {
+        let metrics = CallMetrics::new();
+        let path = ForeignInterfacePath::new(
+            "test:kvstore".to_string(),
+            "store".to_string(),
+            Some(Version::new(1, 0, 0)),
+        );
+
+        let get = metrics.function(&path, "get");
+        get.record(Instant::now(), true);
+        get.record(Instant::now(), false);
+        metrics.function(&path, "get").record(Instant::now(), true);
+        metrics.function(&path, "set");
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].method, "get");
+        assert_eq!(snapshot[0].calls, 3);
+        assert_eq!(snapshot[0].errors, 1);
+        assert!(snapshot[0].duration_buckets.iter().sum::<u64>() <= 3);
+
+        let set = metrics.function(&path, "set");
+        get.enter_fuel();
+        set.enter_fuel();
+        set.exit_fuel(90, 60);
+        get.exit_fuel(100, 50);
+
+        assert_eq!(metrics.interface_fuel(&path, Some("get")), 20);
+        assert_eq!(metrics.interface_fuel(&path, None), 50);
+        assert_eq!(
+            metrics.package_fuel(),
+            [PackageFuel {
+                name: "test:kvstore".to_string(),
+                version: Some(Version::new(1, 0, 0)),
+                fuel: 50,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_latency_quantiles() {
+        let metrics = CallMetrics::new();
+        let path = ForeignInterfacePath::new("test:kvstore".to_string(), "store".to_string(), None);
+
+        let get = metrics.function(&path, "get");
+        for millis in 1..=100 {
+            get.record_duration(Duration::from_millis(millis), millis % 10 != 0);
+        }
+        metrics
+            .function(&path, "set")
+            .record_duration(Duration::from_secs(10), true);
+
+        let quantile = |method, quantile| {
+            metrics
+                .latency_quantile(&path, method, quantile)
+                .unwrap()
+                .as_secs_f64()
+        };
+        let within = |estimate: f64, millis: f64| (estimate * 1000.0 / millis - 1.0).abs() <= 0.125;
+
+        assert!(within(quantile(Some("get"), 0.5), 50.0));
+        assert!(within(quantile(Some("get"), 0.95), 95.0));
+        assert!(within(quantile(Some("get"), 0.99), 99.0));
+        assert!(within(quantile(None, 1.0), 10_000.0));
+        assert_eq!(metrics.latency_quantile(&path, Some("delete"), 0.5), None);
+
+        assert_eq!(metrics.error_rate(&path, Some("get")), Some(0.1));
+        assert_eq!(metrics.error_rate(&path, None), Some(10.0 / 101.0));
+        assert_eq!(
+            metrics.recent_calls(&path, None),
+            RecentCalls {
+                calls: 101,
+                errors: 10
+            }
+        );
+        assert!(within(metrics.snapshot()[0].p95.as_secs_f64(), 95.0));
+
+        for nanos in [0, 7, 8, 1000, u64::MAX] {
+            let (start, end) = latency_bucket_range(latency_bucket(nanos));
+            assert!(start <= nanos as u128 && (nanos as u128) < end);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::ForeignInterfacePath;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::Display;
+use wac_types::{
+    DefinedType, FuncTypeId, InterfaceId, ItemKind, SubtypeChecker, Type, Types, ValueType,
+};
+
+/// The part of an interface item whose type differs between importer and exporter.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum MismatchLocation {
+    /// The item as a whole, e.g. a function missing from the exporter or a differing type
+    /// definition.
+    Item,
+
+    /// The number of function parameters.
+    ParamCount,
+
+    /// A function parameter, by position and name as expected by the importer.
+    Param { index: usize, name: String },
+
+    /// The function result.
+    Result,
+}
+
+/// A difference between the WIT type an importer expects for an interface item, and the type its
+/// exporter provides.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TypeMismatch {
+    /// The name of the interface item, e.g. a function or type name.
+    pub item: String,
+    pub location: MismatchLocation,
+    /// The type expected by the importer.
+    pub expected: String,
+    /// The type provided by the exporter.
+    pub found: String,
+}
+
+impl Display for TypeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            MismatchLocation::Item => write!(f, "'{}'", self.item)?,
+            MismatchLocation::ParamCount => write!(f, "'{}' parameter count", self.item)?,
+            MismatchLocation::Param { index, name } => {
+                write!(f, "'{}' parameter {index} ('{name}')", self.item)?
+            }
+            MismatchLocation::Result => write!(f, "'{}' result", self.item)?,
+        }
+
+        write!(f, ": expected {}, found {}", self.expected, self.found)
+    }
+}
+
+/// The types of an imported interface differ from the interface exported by the package it
+/// resolves to.
+#[derive(Clone, Debug)]
+pub struct InterfaceTypeMismatch {
+    /// The importing package, as `name@version`.
+    pub importer: String,
+    pub import: ForeignInterfacePath,
+    /// The exporting package, as `name@version`.
+    pub exporter: String,
+    pub mismatches: Vec<TypeMismatch>,
+}
+
+impl Display for InterfaceTypeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Package '{}' import '{}' does not match the interface exported by package '{}'",
+            self.importer, self.import, self.exporter
+        )?;
+
+        for mismatch in &self.mismatches {
+            write!(f, "\n  - {mismatch}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for InterfaceTypeMismatch {}
+
+/// Compares the items of an imported interface with those of the exported interface it is linked
+/// against, returning one mismatch per differing item, parameter or result.
+pub(crate) fn interface_mismatches(
+    types: &Types,
+    import: InterfaceId,
+    export: InterfaceId,
+    cache: &mut HashSet<(ItemKind, ItemKind)>,
+) -> Vec<TypeMismatch> {
+    let export_items = &types[export].exports;
+    let mut mismatches = Vec::new();
+
+    for (name, import_kind) in &types[import].exports {
+        let Some(export_kind) = export_items.get(name) else {
+            mismatches.push(TypeMismatch {
+                item: name.clone(),
+                location: MismatchLocation::Item,
+                expected: import_kind.desc(types).to_string(),
+                found: "nothing".to_string(),
+            });
+            continue;
+        };
+
+        let check = SubtypeChecker::new(cache).is_subtype(*export_kind, types, *import_kind, types);
+        let Err(err) = check else {
+            continue;
+        };
+
+        let item_mismatches = match (import_kind, export_kind) {
+            (ItemKind::Func(import_func), ItemKind::Func(export_func)) => {
+                func_mismatches(types, name, *import_func, *export_func)
+            }
+            _ => Vec::new(),
+        };
+
+        // Fall back to the subtype checker's description for differences not covered above, e.g.
+        // in nested type definitions of the same shape.
+        if item_mismatches.is_empty() {
+            mismatches.push(TypeMismatch {
+                item: name.clone(),
+                location: MismatchLocation::Item,
+                expected: item_type(types, import_kind),
+                found: format!("{} ({err:#})", item_type(types, export_kind)),
+            });
+        } else {
+            mismatches.extend(item_mismatches);
+        }
+    }
+
+    mismatches
+}
+
+fn func_mismatches(
+    types: &Types,
+    name: &str,
+    import: FuncTypeId,
+    export: FuncTypeId,
+) -> Vec<TypeMismatch> {
+    let import = &types[import];
+    let export = &types[export];
+    let mut mismatches = Vec::new();
+
+    if import.params.len() != export.params.len() {
+        mismatches.push(TypeMismatch {
+            item: name.to_string(),
+            location: MismatchLocation::ParamCount,
+            expected: import.params.len().to_string(),
+            found: export.params.len().to_string(),
+        });
+    }
+
+    let params = import.params.iter().zip(&export.params).enumerate();
+    for (index, ((import_name, import_ty), (export_name, export_ty))) in params {
+        let expected = format!("{import_name}: {}", wit_type(types, *import_ty));
+        let found = format!("{export_name}: {}", wit_type(types, *export_ty));
+
+        if expected != found {
+            mismatches.push(TypeMismatch {
+                item: name.to_string(),
+                location: MismatchLocation::Param {
+                    index,
+                    name: import_name.clone(),
+                },
+                expected,
+                found,
+            });
+        }
+    }
+
+    let result_type = |result: Option<ValueType>| {
+        result.map_or_else(|| "no result".to_string(), |ty| wit_type(types, ty))
+    };
+    let expected = result_type(import.result);
+    let found = result_type(export.result);
+
+    if expected != found {
+        mismatches.push(TypeMismatch {
+            item: name.to_string(),
+            location: MismatchLocation::Result,
+            expected,
+            found,
+        });
+    }
+
+    mismatches
+}
+
+fn item_type(types: &Types, kind: &ItemKind) -> String {
+    match kind {
+        ItemKind::Type(Type::Value(ty)) | ItemKind::Value(ty) => wit_type(types, *ty),
+        ItemKind::Type(Type::Resource(id)) => {
+            format!("resource {}", types[types.resolve_resource(*id)].name)
+        }
+        kind => kind.desc(types).to_string(),
+    }
+}
+
+/// Renders a value type in WIT syntax. Named types are rendered by their structure, since names
+/// are not part of the type.
+fn wit_type(types: &Types, ty: ValueType) -> String {
+    let resource = |id| &types[types.resolve_resource(id)].name;
+    let optional =
+        |ty: Option<ValueType>| ty.map_or_else(|| "_".to_string(), |ty| wit_type(types, ty));
+    let list = |tys: &mut dyn Iterator<Item = String>| tys.collect::<Vec<_>>().join(", ");
+
+    match ty {
+        ValueType::Primitive(ty) => ty.desc().to_string(),
+        ValueType::Borrow(id) => format!("borrow<{}>", resource(id)),
+        ValueType::Own(id) => format!("own<{}>", resource(id)),
+        ValueType::Defined(id) => match &types[id] {
+            DefinedType::Tuple(tys) => {
+                format!(
+                    "tuple<{}>",
+                    list(&mut tys.iter().map(|ty| wit_type(types, *ty)))
+                )
+            }
+            DefinedType::List(ty) => format!("list<{}>", wit_type(types, *ty)),
+            DefinedType::FixedSizeList(ty, size) => {
+                format!("list<{}, {size}>", wit_type(types, *ty))
+            }
+            DefinedType::Option(ty) => format!("option<{}>", wit_type(types, *ty)),
+            DefinedType::Result { ok, err } => {
+                format!("result<{}, {}>", optional(*ok), optional(*err))
+            }
+            DefinedType::Variant(variant) => format!(
+                "variant {{ {} }}",
+                list(&mut variant.cases.iter().map(|(name, ty)| match ty {
+                    Some(ty) => format!("{name}({})", wit_type(types, *ty)),
+                    None => name.clone(),
+                }))
+            ),
+            DefinedType::Record(record) => format!(
+                "record {{ {} }}",
+                list(
+                    &mut record
+                        .fields
+                        .iter()
+                        .map(|(name, ty)| format!("{name}: {}", wit_type(types, *ty)))
+                )
+            ),
+            DefinedType::Flags(flags) => {
+                format!("flags {{ {} }}", list(&mut flags.0.iter().cloned()))
+            }
+            DefinedType::Enum(cases) => {
+                format!("enum {{ {} }}", list(&mut cases.0.iter().cloned()))
+            }
+            DefinedType::Alias(ty) => wit_type(types, *ty),
+            DefinedType::Stream(ty) => format!("stream<{}>", optional(*ty)),
+            DefinedType::Future(ty) => format!("future<{}>", optional(*ty)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+    use wac_types::{FuncType, Interface, PrimitiveType};
+
+    fn interface(types: &mut Types, funcs: Vec<(&str, FuncType)>) -> InterfaceId {
+        let exports = funcs
+            .into_iter()
+            .map(|(name, func)| (name.to_string(), ItemKind::Func(types.add_func_type(func))))
+            .collect();
+
+        types.add_interface(Interface {
+            id: None,
+            uses: IndexMap::new(),
+            exports,
+        })
+    }
+
+    fn func(params: &[(&str, ValueType)], result: Option<ValueType>) -> FuncType {
+        FuncType {
+            params: params
+                .iter()
+                .map(|(name, ty)| (name.to_string(), *ty))
+                .collect(),
+            result,
+        }
+    }
+
+    #[test]
+    fn test_interface_mismatches() {
+        let mut types = Types::default();
+        let string = ValueType::Primitive(PrimitiveType::String);
+        let u32 = ValueType::Primitive(PrimitiveType::U32);
+        let list = ValueType::Defined(types.add_defined_type(DefinedType::List(string)));
+        let option = ValueType::Defined(types.add_defined_type(DefinedType::Option(string)));
+
+        let import = interface(
+            &mut types,
+            vec![
+                ("get", func(&[("key", string)], Some(option))),
+                ("keys", func(&[], Some(list))),
+                ("clear", func(&[], None)),
+            ],
+        );
+        let export = interface(
+            &mut types,
+            vec![
+                ("get", func(&[("key", u32)], Some(string))),
+                ("keys", func(&[], Some(list))),
+            ],
+        );
+
+        let mismatches = interface_mismatches(&types, import, export, &mut HashSet::new());
+        assert_eq!(
+            mismatches,
+            [
+                TypeMismatch {
+                    item: "get".to_string(),
+                    location: MismatchLocation::Param {
+                        index: 0,
+                        name: "key".to_string(),
+                    },
+                    expected: "key: string".to_string(),
+                    found: "key: u32".to_string(),
+                },
+                TypeMismatch {
+                    item: "get".to_string(),
+                    location: MismatchLocation::Result,
+                    expected: "option<string>".to_string(),
+                    found: "string".to_string(),
+                },
+                TypeMismatch {
+                    item: "clear".to_string(),
+                    location: MismatchLocation::Item,
+                    expected: "function".to_string(),
+                    found: "nothing".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "'get' parameter 0 ('key'): expected key: string, found key: u32"
+        );
+    }
+}
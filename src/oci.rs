@@ -0,0 +1,604 @@
+//! Pulling components distributed as OCI artifacts from container registries, following the OCI
+//! distribution specification.
+
+use crate::http::{MAX_REDIRECTS, resolve_location};
+use crate::{HttpClient, HttpResponse, HttpsClient, PackageSource, PlainHttpClient, Sha256Digest};
+use serde_json::Value;
+use snafu::{ResultExt, Snafu};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// The media types of the manifests requested from registries.
+const MANIFEST_MEDIA_TYPES: &str = "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+/// The media type of the layers holding components.
+const WASM_MEDIA_TYPE: &str = "application/wasm";
+
+/// A package source pulling components from OCI registries, by references such as
+/// `ghcr.io/org/pkg:1.2.3` or `ghcr.io/org/pkg@sha256:...`.
+///
+/// The component is the `application/wasm` layer of the referenced manifest, or its only layer.
+/// Its bytes are checked against the digest of the layer, and the manifest against the digest of
+/// the reference, if any. Registries requiring a token are authenticated with anonymously, or with
+/// the credentials set with `with_credentials`, which are only ever sent over HTTPS.
+///
+/// Requests are sent with an `HttpClient`, over HTTPS unless the source uses plain HTTP, e.g. for
+/// local registries.
+#[derive(Clone, Debug)]
+pub struct OciSource<H = HttpsClient> {
+    client: H,
+    plain_http: bool,
+    credentials: Option<(String, String)>,
+}
+
+impl Default for OciSource {
+    fn default() -> Self {
+        Self::new(HttpsClient::new())
+    }
+}
+
+impl OciSource {
+    /// Creates a source pulling from registries over HTTPS with an `HttpsClient`.
+    #[must_use]
+    pub fn https() -> Self {
+        Self::default()
+    }
+}
+
+impl OciSource<PlainHttpClient> {
+    /// Creates a source pulling from registries over plain HTTP, such as a local registry.
+    #[must_use]
+    pub fn plain_http() -> Self {
+        Self::new(PlainHttpClient::new()).with_plain_http(true)
+    }
+}
+
+impl<H: HttpClient> OciSource<H> {
+    /// Creates a source sending its requests over HTTPS with `client`.
+    pub fn new(client: H) -> Self {
+        Self {
+            client,
+            plain_http: false,
+            credentials: None,
+        }
+    }
+
+    /// Sends requests over plain HTTP instead of HTTPS.
+    #[must_use]
+    pub fn with_plain_http(mut self, plain_http: bool) -> Self {
+        self.plain_http = plain_http;
+        self
+    }
+
+    /// Authenticates with `username` and `password` when registries require a token.
+    ///
+    /// Pulls fail with `InsecureCredentials` rather than send the credentials to a token endpoint
+    /// over plain HTTP.
+    #[must_use]
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Pulls the component referenced by `reference`.
+    pub fn pull(&self, reference: &str) -> Result<Vec<u8>, OciError> {
+        let reference = reference.parse::<OciReference>()?;
+        let scheme = if self.plain_http { "http" } else { "https" };
+        let base = format!(
+            "{scheme}://{}/v2/{}",
+            reference.registry, reference.repository
+        );
+        let mut token = None;
+
+        let manifest_url = format!("{base}/manifests/{}", reference.tag_or_digest());
+        let manifest = self.get(
+            &manifest_url,
+            Some(MANIFEST_MEDIA_TYPES),
+            &mut token,
+            &reference,
+        )?;
+
+        if let Some(digest) = &reference.digest {
+            verify_digest(digest, &manifest.body)?;
+        }
+
+        let layer_digest = wasm_layer_digest(&manifest.body)?;
+        let blob_url = format!("{base}/blobs/{layer_digest}");
+        let blob = self.get(&blob_url, None, &mut token, &reference)?;
+        verify_digest(&layer_digest, &blob.body)?;
+
+        Ok(blob.body)
+    }
+
+    /// Sends a `GET` request for `url`, authenticating with a token if the registry requires one
+    /// and following redirects.
+    fn get(
+        &self,
+        url: &str,
+        accept: Option<&str>,
+        token: &mut Option<String>,
+        reference: &OciReference,
+    ) -> Result<HttpResponse, OciError> {
+        let mut url = url.to_string();
+        let mut authorized = true;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let response = self.send(&url, accept, token.as_deref().filter(|_| authorized))?;
+
+            if response.status == 401 && authorized && token.is_none() {
+                let challenge = response.header("www-authenticate").unwrap_or_default();
+                *token = Some(self.token(challenge, reference)?);
+                continue;
+            }
+
+            if let Some(location) = response.redirect() {
+                // Redirects lead to blob storage, which rejects the registry's token.
+                url = resolve_location(&url, location);
+                authorized = false;
+                continue;
+            }
+
+            if response.status != 200 {
+                return oci_error::StatusSnafu {
+                    url,
+                    status: response.status,
+                }
+                .fail();
+            }
+
+            return Ok(response);
+        }
+
+        oci_error::TooManyRedirectsSnafu { url }.fail()
+    }
+
+    fn send(
+        &self,
+        url: &str,
+        accept: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<HttpResponse, OciError> {
+        let authorization = token.map(|token| format!("Bearer {token}"));
+        let headers = accept
+            .map(|accept| ("Accept", accept))
+            .into_iter()
+            .chain(
+                authorization
+                    .as_deref()
+                    .map(|value| ("Authorization", value)),
+            )
+            .collect::<Vec<_>>();
+
+        self.client
+            .get(url, &headers)
+            .context(oci_error::RequestSnafu { url })
+    }
+
+    /// Requests a pull token for the repository of `reference` from the realm of the Bearer
+    /// challenge among the `WWW-Authenticate` `challenges` of a registry.
+    fn token(&self, challenges: &str, reference: &OciReference) -> Result<String, OciError> {
+        let unsupported = || OciError::UnsupportedAuth {
+            challenge: challenges.to_string(),
+        };
+        let parsed = http_auth::parse_challenges(challenges).map_err(|_| unsupported())?;
+        let challenge = parsed
+            .iter()
+            .find(|challenge| challenge.scheme.eq_ignore_ascii_case("bearer"))
+            .ok_or_else(unsupported)?;
+
+        let mut realm = None;
+        let mut query = String::new();
+        let mut has_scope = false;
+        for (name, value) in &challenge.params {
+            let value = value.to_unescaped();
+
+            if name.eq_ignore_ascii_case("realm") {
+                realm = Some(value);
+            } else if name.eq_ignore_ascii_case("service") || name.eq_ignore_ascii_case("scope") {
+                has_scope |= name.eq_ignore_ascii_case("scope");
+                let _ = write!(
+                    query,
+                    "&{}={}",
+                    name.to_ascii_lowercase(),
+                    percent_encode(&value)
+                );
+            }
+        }
+        if !has_scope {
+            let scope = format!("repository:{}:pull", reference.repository);
+            let _ = write!(query, "&scope={}", percent_encode(&scope));
+        }
+
+        let realm = realm.ok_or_else(unsupported)?;
+        let url = format!("{realm}?{}", query.trim_start_matches('&'));
+
+        if self.credentials.is_some() && !url.starts_with("https://") {
+            return oci_error::InsecureCredentialsSnafu { url }.fail();
+        }
+
+        let basic = self
+            .credentials
+            .as_ref()
+            .map(|(username, password)| http_auth::basic::encode_credentials(username, password));
+        let headers = basic
+            .as_deref()
+            .map(|value| ("Authorization", value))
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let response = self
+            .client
+            .get(&url, &headers)
+            .context(oci_error::RequestSnafu { url: url.clone() })?;
+        if response.status != 200 {
+            return oci_error::StatusSnafu {
+                url,
+                status: response.status,
+            }
+            .fail();
+        }
+
+        let body = serde_json::from_slice::<Value>(&response.body)
+            .context(oci_error::InvalidJsonSnafu { url: url.clone() })?;
+        body.get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or(OciError::MissingToken { url })
+    }
+}
+
+impl<H: HttpClient> PackageSource for OciSource<H> {
+    fn fetch(&self, reference: &str) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(self.pull(reference)?)
+    }
+}
+
+/// A component that cannot be pulled by an `OciSource`.
+#[derive(Snafu, Debug)]
+#[snafu(module, visibility(pub(crate)))]
+pub enum OciError {
+    #[snafu(display("Invalid OCI reference '{reference}'"))]
+    InvalidReference { reference: String },
+
+    #[snafu(display("Request for '{url}' failed"))]
+    Request { url: String, source: anyhow::Error },
+
+    #[snafu(display("Request for '{url}' failed with status {status}"))]
+    Status { url: String, status: u16 },
+
+    #[snafu(display("Request for '{url}' redirected too many times"))]
+    TooManyRedirects { url: String },
+
+    #[snafu(display("Unsupported registry authentication challenge '{challenge}'"))]
+    UnsupportedAuth { challenge: String },
+
+    #[snafu(display("Refusing to send credentials to '{url}' over plain HTTP"))]
+    InsecureCredentials { url: String },
+
+    #[snafu(display("The token response of '{url}' has no token"))]
+    MissingToken { url: String },
+
+    #[snafu(display("The response of '{url}' is not valid JSON"))]
+    InvalidJson {
+        url: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Invalid manifest: {reason}"))]
+    InvalidManifest { reason: String },
+
+    #[snafu(display("Unsupported digest '{digest}', expected a SHA-256 digest"))]
+    UnsupportedDigest { digest: String },
+
+    #[snafu(display("Digest mismatch: expected {expected}, got {actual}"))]
+    DigestMismatch {
+        expected: Sha256Digest,
+        actual: Sha256Digest,
+    },
+}
+
+/// A parsed reference to an artifact in an OCI registry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct OciReference {
+    registry: String,
+    repository: String,
+    tag: Option<String>,
+    digest: Option<String>,
+}
+
+impl OciReference {
+    fn tag_or_digest(&self) -> &str {
+        self.digest
+            .as_deref()
+            .or(self.tag.as_deref())
+            .unwrap_or("latest")
+    }
+}
+
+impl FromStr for OciReference {
+    type Err = OciError;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        let invalid = || OciError::InvalidReference {
+            reference: reference.to_string(),
+        };
+
+        let (name, digest) = match reference.split_once('@') {
+            Some((name, digest)) => (name, Some(digest.to_string())),
+            None => (reference, None),
+        };
+
+        // The registry is the first component if it looks like a host, like Docker resolves
+        // references.
+        let (registry, path) = match name.split_once('/') {
+            Some((host, path))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), path.to_string())
+            }
+            Some(_) => ("registry-1.docker.io".to_string(), name.to_string()),
+            None => (
+                "registry-1.docker.io".to_string(),
+                format!("library/{name}"),
+            ),
+        };
+
+        let last_segment = path.rfind('/').map_or(0, |index| index + 1);
+        let (repository, tag) = match path[last_segment..].split_once(':') {
+            Some((_, tag)) => (
+                path[..path.len() - tag.len() - 1].to_string(),
+                Some(tag.to_string()),
+            ),
+            None => (path, None),
+        };
+
+        if repository.is_empty()
+            || repository.split('/').any(str::is_empty)
+            || tag.as_deref().is_some_and(str::is_empty)
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+}
+
+/// Returns the digest of the component layer of `manifest`.
+fn wasm_layer_digest(manifest: &[u8]) -> Result<String, OciError> {
+    let invalid = |reason: &str| OciError::InvalidManifest {
+        reason: reason.to_string(),
+    };
+
+    let manifest = serde_json::from_slice::<Value>(manifest)
+        .map_err(|err| invalid(&format!("not valid JSON: {err}")))?;
+    let layers = manifest
+        .get("layers")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("no layers, manifest lists are not supported"))?;
+
+    let layer = layers
+        .iter()
+        .find(|layer| layer.get("mediaType").and_then(Value::as_str) == Some(WASM_MEDIA_TYPE))
+        .or(match layers.as_slice() {
+            [layer] => Some(layer),
+            _ => None,
+        })
+        .ok_or_else(|| invalid("no application/wasm layer"))?;
+
+    layer
+        .get("digest")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| invalid("the component layer has no digest"))
+}
+
+fn verify_digest(digest: &str, bytes: &[u8]) -> Result<(), OciError> {
+    let expected = digest
+        .strip_prefix("sha256:")
+        .and_then(|hex| Sha256Digest::from_str(hex).ok())
+        .ok_or_else(|| OciError::UnsupportedDigest {
+            digest: digest.to_string(),
+        })?;
+    let actual = Sha256Digest::of(bytes);
+
+    if expected != actual {
+        return oci_error::DigestMismatchSnafu { expected, actual }.fail();
+    }
+
+    Ok(())
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompositionGraph, NoopTrampoline};
+    use semver::Version;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// The preamble of an empty component.
+    const EMPTY_COMPONENT: &[u8] = b"\0asm\x0d\x00\x01\x00";
+
+    /// Serves a registry requiring a token, which redirects blob requests. Repository
+    /// `test/basic` requires Basic authentication, and the token endpoint of `test/tokenless`
+    /// returns no token.
+    fn serve_registry() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let digest = Sha256Digest::of(EMPTY_COMPONENT).to_string();
+        let manifest = format!(
+            r#"{{"layers":[{{"mediaType":"application/wasm","digest":"{digest}","size":8}}]}}"#
+        );
+        let challenge = format!(r#"Bearer realm="http://{host}/token",service="test""#);
+        let tokenless = format!(r#"bearer realm="http://{host}/tokenless", scope="pull""#);
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines().map(Result::unwrap);
+                let path = lines.next().unwrap().split(' ').nth(1).unwrap().to_string();
+                let headers = lines
+                    .take_while(|line| !line.is_empty())
+                    .collect::<Vec<_>>();
+                let authorized = headers
+                    .iter()
+                    .any(|header| header == "Authorization: Bearer t");
+
+                let (status, headers, body): (_, _, &[u8]) = match path.as_str() {
+                    "/token?service=test&scope=repository%3Atest%2Fapp%3Apull" => {
+                        ("200 OK", String::new(), br#"{"token":"t"}"#)
+                    }
+                    "/tokenless?scope=pull" => ("200 OK", String::new(), b"{}"),
+                    _ if path.starts_with("/v2/test/basic/") => (
+                        "401 Unauthorized",
+                        "WWW-Authenticate: Basic realm=\"test\"\r\n".to_string(),
+                        b"",
+                    ),
+                    _ if path.starts_with("/v2/test/tokenless/") => (
+                        "401 Unauthorized",
+                        format!("WWW-Authenticate: {tokenless}\r\n"),
+                        b"",
+                    ),
+                    _ if path.starts_with("/v2/") && !authorized => (
+                        "401 Unauthorized",
+                        format!("WWW-Authenticate: {challenge}\r\n"),
+                        b"",
+                    ),
+                    _ if path.starts_with("/v2/test/app/manifests/") => {
+                        ("200 OK", String::new(), manifest.as_bytes())
+                    }
+                    _ if path == format!("/v2/test/app/blobs/{digest}") => (
+                        "307 Temporary Redirect",
+                        "Location: /storage/app\r\n".to_string(),
+                        b"",
+                    ),
+                    "/storage/app" => ("200 OK", String::new(), EMPTY_COMPONENT),
+                    _ => ("404 Not Found", String::new(), b""),
+                };
+
+                let _ = write!(stream, "HTTP/1.0 {status}\r\n{headers}\r\n");
+                let _ = stream.write_all(body);
+            }
+        });
+
+        host
+    }
+
+    #[test]
+    fn test_oci_source() {
+        let host = serve_registry();
+        let source = OciSource::plain_http();
+
+        let mut graph = CompositionGraph::<()>::new();
+        graph
+            .add_package_from_source(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                &source,
+                &format!("{host}/test/app:1.0.0"),
+                NoopTrampoline,
+            )
+            .unwrap();
+        assert_eq!(graph.packages().count(), 1);
+
+        assert!(matches!(
+            source.pull(&format!("{host}/test/missing:1.0.0")),
+            Err(OciError::Status { status: 404, .. })
+        ));
+        let wrong_digest = format!("{host}/test/app@{}", Sha256Digest::of(b""));
+        assert!(matches!(
+            source.pull(&wrong_digest),
+            Err(OciError::DigestMismatch { .. })
+        ));
+
+        assert!(matches!(
+            source.pull(&format!("{host}/test/basic:1.0.0")),
+            Err(OciError::UnsupportedAuth { .. })
+        ));
+        assert!(matches!(
+            source.pull(&format!("{host}/test/tokenless:1.0.0")),
+            Err(OciError::MissingToken { .. })
+        ));
+
+        let with_credentials = OciSource::plain_http().with_credentials("user", "pass");
+        assert!(matches!(
+            with_credentials.pull(&format!("{host}/test/app:1.0.0")),
+            Err(OciError::InsecureCredentials { url }) if url.starts_with("http://")
+        ));
+    }
+
+    #[test]
+    fn test_oci_manifest() {
+        let digest = Sha256Digest::of(EMPTY_COMPONENT).to_string();
+        let manifest = format!(
+            r#"{{"layers":[{{"mediaType":"text/plain","digest":"sha256:00"}},{{"mediaType":"application/wasm","digest":"{digest}"}}]}}"#
+        );
+        assert_eq!(wasm_layer_digest(manifest.as_bytes()).unwrap(), digest);
+
+        assert!(matches!(
+            wasm_layer_digest(
+                br#"{"layers":[{"mediaType":"text/plain"},{"mediaType":"text/plain"}]}"#
+            ),
+            Err(OciError::InvalidManifest { .. })
+        ));
+        assert!(matches!(
+            wasm_layer_digest(b"not json"),
+            Err(OciError::InvalidManifest { .. })
+        ));
+        assert!(matches!(
+            verify_digest("md5:00", EMPTY_COMPONENT),
+            Err(OciError::UnsupportedDigest { .. })
+        ));
+    }
+
+    #[test]
+    fn test_oci_reference() {
+        assert_eq!(
+            "ghcr.io/org/pkg:1.2.3".parse::<OciReference>().unwrap(),
+            OciReference {
+                registry: "ghcr.io".to_string(),
+                repository: "org/pkg".to_string(),
+                tag: Some("1.2.3".to_string()),
+                digest: None,
+            }
+        );
+        assert_eq!(
+            "alpine".parse::<OciReference>().unwrap().repository,
+            "library/alpine"
+        );
+        assert_eq!(
+            "localhost:5000/pkg@sha256:00"
+                .parse::<OciReference>()
+                .unwrap(),
+            OciReference {
+                registry: "localhost:5000".to_string(),
+                repository: "pkg".to_string(),
+                tag: None,
+                digest: Some("sha256:00".to_string()),
+            }
+        );
+        assert!("ghcr.io/".parse::<OciReference>().is_err());
+    }
+}
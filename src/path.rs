@@ -1,14 +1,27 @@
+use derivative::Derivative;
 use semver::Version;
 use snafu::{ResultExt, Snafu};
 use std::fmt::Display;
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
 /// A fully-qualified path to a WIT interface, with an optional version.
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[derive(Clone, Derivative)]
+#[derivative(Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct ForeignInterfacePath {
     package_name: String,
     interface_name: String,
     version: Option<Version>,
+    /// The canonical `package/interface@version` rendering, built on first use so that
+    /// displaying the path, and its clones, does not format it again.
+    #[derivative(
+        PartialEq = "ignore",
+        PartialOrd = "ignore",
+        Ord = "ignore",
+        Hash = "ignore",
+        Debug = "ignore"
+    )]
+    rendered: OnceLock<Arc<str>>,
 }
 
 impl ForeignInterfacePath {
@@ -23,9 +36,26 @@ impl ForeignInterfacePath {
             package_name,
             interface_name,
             version,
+            rendered: OnceLock::new(),
         }
     }
 
+    /// Returns the canonical `package/interface@version` rendering of the path, as used by
+    /// `Display`, formatting it only the first time.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        self.rendered.get_or_init(|| {
+            let rendered = match &self.version {
+                Some(version) => {
+                    format!("{}/{}@{version}", self.package_name, self.interface_name)
+                }
+                None => format!("{}/{}", self.package_name, self.interface_name),
+            };
+
+            rendered.into()
+        })
+    }
+
     /// Returns the package name component of the interface path.
     #[must_use]
     pub fn package_name(&self) -> &str {
@@ -57,15 +87,7 @@ impl From<ForeignInterfacePath> for InterfacePath {
 
 impl Display for ForeignInterfacePath {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}/{}{}",
-            self.package_name,
-            self.interface_name,
-            self.version
-                .as_ref()
-                .map_or(String::new(), |v| format!("@{v}"))
-        )
+        f.write_str(self.as_str())
     }
 }
 
@@ -114,11 +136,11 @@ impl InterfacePath {
     /// otherwise returns `None`.
     #[must_use]
     pub fn into_foreign(self) -> Option<ForeignInterfacePath> {
-        Some(ForeignInterfacePath {
-            package_name: self.package_name?,
-            interface_name: self.interface_name,
-            version: self.version,
-        })
+        Some(ForeignInterfacePath::new(
+            self.package_name?,
+            self.interface_name,
+            self.version,
+        ))
     }
 }
 
@@ -266,6 +288,24 @@ mod tests {
         assert_eq!(fp_string, path.to_string());
     }
 
+    #[test]
+    fn test_foreign_interface_path_as_str() {
+        for package in [PACKAGE, PACKAGE_WITHOUT_VERSION] {
+            let path = InterfacePath::from_str(package)
+                .unwrap()
+                .into_foreign()
+                .unwrap();
+            let clone = path.clone();
+
+            assert_eq!(path.as_str(), package);
+            // Whether a path was rendered yet does not affect equality, and clones keep the
+            // rendering.
+            assert_eq!(clone, path);
+            assert_eq!(clone.as_str(), package);
+            assert_eq!(path.clone().as_str(), package);
+        }
+    }
+
     #[test]
     fn test_interface_path_parsing() {
         let path = InterfacePath::from_str(PACKAGE).unwrap();
@@ -0,0 +1,780 @@
+use crate::ForeignInterfacePath;
+use crate::sampling::matches_pattern;
+use derivative::Derivative;
+use semver::{Version, VersionReq};
+use snafu::Snafu;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use wasmtime::component::Val;
+
+/// The string redacted arguments and results are replaced with.
+pub const REDACTED: &str = "<redacted>";
+
+/// A policy describing the interface edges allowed between packages, and the rate limits,
+/// latency budgets, timeouts and redactions of the calls to shadowed interfaces, once set with
+/// `CompositionGraph::set_policy`.
+///
+/// Rules match package names and interface paths with patterns, where `*` matches any sequence
+/// of characters like in `CallSampling`. With the `policy` feature, policies can be loaded from
+/// TOML documents with `Policy::from_toml`:
+///
+/// ```toml
+/// [[edges]]
+/// caller = "test:application"
+/// callee = "test:kvstore/*"
+///
+/// [[rate_limits]]
+/// interface = "test:kvstore/store@*"
+/// calls_per_second = 100.0
+/// burst = 10
+///
+/// [[latency_budgets]]
+/// interface = "test:kvstore/*"
+/// millis = 250
+///
+/// [[timeouts]]
+/// interface = "test:kvstore/*"
+/// millis = 1000
+///
+/// [[redactions]]
+/// interface = "test:kvstore/store@*"
+/// method = "set"
+/// arguments = [1]
+/// ```
+///
+/// Clones share the same rate limits.
+#[derive(Clone, Default, Derivative)]
+#[derivative(Debug)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Policy {
+    edges: Vec<EdgeRule>,
+    rate_limits: Vec<RateLimitRule>,
+    latency_budgets: Vec<LatencyBudgetRule>,
+    timeouts: Vec<TimeoutRule>,
+    redactions: Vec<RedactionRule>,
+    #[derivative(Debug = "ignore")]
+    #[cfg_attr(feature = "policy", serde(skip))]
+    limiters: Arc<Mutex<HashMap<ForeignInterfacePath, Arc<RateLimiter>>>>,
+}
+
+/// Allows packages whose name matches `caller` to import the interfaces whose path matches
+/// `callee`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct EdgeRule {
+    pub caller: String,
+    pub callee: String,
+}
+
+/// Limits the calls to each interface matching `interface` to `calls_per_second`, allowing
+/// bursts of up to `burst` calls.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct RateLimitRule {
+    pub interface: String,
+    pub calls_per_second: f64,
+    /// Defaults to one second of calls.
+    #[cfg_attr(feature = "policy", serde(default))]
+    pub burst: Option<u32>,
+}
+
+/// Reports the calls to the interfaces matching `interface` that take longer than `millis`.
+///
+/// Budgets are checked when calls return, so calls over budget still complete and are only
+/// logged as warnings. To interrupt long-running guests, use a `TimeoutRule` instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct LatencyBudgetRule {
+    pub interface: String,
+    pub millis: u64,
+}
+
+/// Interrupts the calls to the interfaces matching `interface` that are still running after
+/// `millis`, failing them with `PolicyViolation::DeadlineExceeded`.
+///
+/// Timeouts are checked on the epoch deadlines of stores enforcing them with
+/// `CompositionGraph::enforce_timeouts`, so calls overrun their timeout by up to the time between
+/// two checks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct TimeoutRule {
+    pub interface: String,
+    pub millis: u64,
+}
+
+/// Redacts arguments, and optionally the results, of the functions matching `method` in the
+/// interfaces matching `interface`, in the values logged by trampolines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct RedactionRule {
+    pub interface: String,
+    /// Defaults to all functions.
+    #[cfg_attr(feature = "policy", serde(default = "any_method"))]
+    pub method: String,
+    /// The indices of the redacted arguments.
+    #[cfg_attr(feature = "policy", serde(default))]
+    pub arguments: Vec<usize>,
+    #[cfg_attr(feature = "policy", serde(default))]
+    pub results: bool,
+}
+
+#[cfg(feature = "policy")]
+fn any_method() -> String {
+    "*".to_string()
+}
+
+/// A deny list of packages, once set with `CompositionGraph::set_package_policy`, such as to ban
+/// releases with known vulnerabilities.
+///
+/// Denied packages cannot be added to the graph, and imports resolving to a denied package fail
+/// to resolve, so packages added before the policy is set are denied as well. Rules are named,
+/// and errors name the rule that denied the package. With the `policy` feature, package policies
+/// can be loaded from TOML documents with `PackagePolicy::from_toml`:
+///
+/// ```toml
+/// [[deny]]
+/// name = "kvstore-advisory"
+/// package = "test:kvstore"
+/// versions = "<2.1.0"
+/// reason = "Leaks keys across tenants"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct PackagePolicy {
+    deny: Vec<PackageDenyRule>,
+}
+
+/// Denies the packages whose name matches `package` at the versions matching `versions`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "policy",
+    derive(serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct PackageDenyRule {
+    /// The name of the rule, reported by the errors of the packages it denies.
+    pub name: String,
+    pub package: String,
+    /// Defaults to all versions.
+    #[cfg_attr(feature = "policy", serde(default))]
+    pub versions: VersionReq,
+    #[cfg_attr(feature = "policy", serde(default))]
+    pub reason: Option<String>,
+}
+
+impl PackagePolicy {
+    /// Creates a policy allowing all packages.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a package policy from a TOML document.
+    #[cfg(feature = "policy")]
+    pub fn from_toml(document: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(document)
+    }
+
+    /// Denies the packages matching `rule`, in addition to the rules added before.
+    #[must_use]
+    pub fn with_deny(mut self, rule: PackageDenyRule) -> Self {
+        self.deny.push(rule);
+        self
+    }
+
+    /// Returns the first rule denying the package `name` at `version`, if any.
+    #[must_use]
+    pub fn denies(&self, name: &str, version: &Version) -> Option<&PackageDenyRule> {
+        self.deny
+            .iter()
+            .find(|rule| matches_pattern(&rule.package, name) && rule.versions.matches(version))
+    }
+}
+
+/// A call to a shadowed function rejected by the graph's `Policy`, carried by the call's error.
+#[derive(Snafu, Clone, Debug, PartialEq)]
+pub enum PolicyViolation {
+    #[snafu(display(
+        "Call to '{interface}#{method}' exceeded the rate limit of {calls_per_second} calls per \
+         second"
+    ))]
+    RateLimited {
+        interface: Box<ForeignInterfacePath>,
+        method: String,
+        calls_per_second: f64,
+    },
+
+    #[snafu(display(
+        "Call to '{interface}#{method}' was interrupted after {elapsed:?}, exceeding its timeout \
+         of {timeout:?}"
+    ))]
+    DeadlineExceeded {
+        interface: Box<ForeignInterfacePath>,
+        method: String,
+        timeout: Duration,
+        elapsed: Duration,
+    },
+}
+
+impl Policy {
+    /// Creates a policy allowing all edges and calls.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a policy from a TOML document.
+    #[cfg(feature = "policy")]
+    pub fn from_toml(document: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(document)
+    }
+
+    /// Allows the edges of `rule`. Once a policy has edge rules, only the edges they match are
+    /// allowed.
+    #[must_use]
+    pub fn with_edge(mut self, rule: EdgeRule) -> Self {
+        self.edges.push(rule);
+        self
+    }
+
+    /// Limits the rate of calls with `rule`, unless a rule added before matches.
+    #[must_use]
+    pub fn with_rate_limit(mut self, rule: RateLimitRule) -> Self {
+        self.rate_limits.push(rule);
+        self
+    }
+
+    /// Budgets the latency of calls with `rule`, unless a rule added before matches.
+    #[must_use]
+    pub fn with_latency_budget(mut self, rule: LatencyBudgetRule) -> Self {
+        self.latency_budgets.push(rule);
+        self
+    }
+
+    /// Interrupts calls with `rule`, unless a rule added before matches.
+    #[must_use]
+    pub fn with_timeout(mut self, rule: TimeoutRule) -> Self {
+        self.timeouts.push(rule);
+        self
+    }
+
+    /// Redacts logged values with `rule`, in addition to the rules added before.
+    #[must_use]
+    pub fn with_redaction(mut self, rule: RedactionRule) -> Self {
+        self.redactions.push(rule);
+        self
+    }
+
+    /// Returns whether the package named `caller` may import the interface at `callee`.
+    #[must_use]
+    pub fn allows_edge(&self, caller: &str, callee: &ForeignInterfacePath) -> bool {
+        self.edges.is_empty()
+            || self.edges.iter().any(|rule| {
+                matches_pattern(&rule.caller, caller)
+                    && matches_pattern(&rule.callee, callee.as_str())
+            })
+    }
+
+    /// Returns the rate limit of the calls to `interface`, if any.
+    #[must_use]
+    pub fn rate_limit(&self, interface: &ForeignInterfacePath) -> Option<&RateLimitRule> {
+        self.rate_limits
+            .iter()
+            .find(|rule| matches_pattern(&rule.interface, interface.as_str()))
+    }
+
+    /// Returns the latency budget of the calls to `interface`, if any.
+    #[must_use]
+    pub fn latency_budget(&self, interface: &ForeignInterfacePath) -> Option<Duration> {
+        self.latency_budgets
+            .iter()
+            .find(|rule| matches_pattern(&rule.interface, interface.as_str()))
+            .map(|rule| Duration::from_millis(rule.millis))
+    }
+
+    /// Returns the timeout of the calls to `interface`, if any.
+    #[must_use]
+    pub fn timeout(&self, interface: &ForeignInterfacePath) -> Option<Duration> {
+        self.timeouts
+            .iter()
+            .find(|rule| matches_pattern(&rule.interface, interface.as_str()))
+            .map(|rule| Duration::from_millis(rule.millis))
+    }
+
+    /// Returns `arguments` with the arguments of `interface#method` redacted by the policy
+    /// replaced with `REDACTED`, such as for logging them.
+    #[must_use]
+    pub fn redact_arguments<'a>(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: &str,
+        arguments: &'a [Val],
+    ) -> Cow<'a, [Val]> {
+        let mut arguments = Cow::Borrowed(arguments);
+
+        for rule in self.redactions(interface, method) {
+            for &index in &rule.arguments {
+                if let Some(argument) = arguments.to_mut().get_mut(index) {
+                    *argument = Val::String(REDACTED.to_string());
+                }
+            }
+        }
+
+        arguments
+    }
+
+    /// Like `redact_arguments`, but for the results of `interface#method`, which are redacted
+    /// all at once.
+    #[must_use]
+    pub fn redact_results<'a>(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: &str,
+        results: &'a [Val],
+    ) -> Cow<'a, [Val]> {
+        if self.redactions(interface, method).any(|rule| rule.results) {
+            Cow::Owned(vec![Val::String(REDACTED.to_string()); results.len()])
+        } else {
+            Cow::Borrowed(results)
+        }
+    }
+
+    fn redactions(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: &str,
+    ) -> impl Iterator<Item = &RedactionRule> {
+        self.redactions.iter().filter(move |rule| {
+            matches_pattern(&rule.interface, interface.as_str())
+                && matches_pattern(&rule.method, method)
+        })
+    }
+
+    /// Returns the handle a shadowed function enforces the policy with, if its calls are rate
+    /// limited, or have a latency budget or timeout. All functions of an interface share its rate limit.
+    pub(crate) fn function(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: &str,
+    ) -> Option<PolicedFunc> {
+        let limiter = self.rate_limit(interface).map(|rule| {
+            let mut limiters = self.limiters.lock().unwrap_or_else(PoisonError::into_inner);
+
+            limiters
+                .entry(interface.clone())
+                .or_insert_with(|| Arc::new(RateLimiter::new(rule)))
+                .clone()
+        });
+        let latency_budget = self.latency_budget(interface);
+        let timeout = self.timeout(interface);
+
+        if limiter.is_none() && latency_budget.is_none() && timeout.is_none() {
+            return None;
+        }
+
+        Some(PolicedFunc {
+            function: Arc::new((interface.clone(), method.to_string())),
+            limiter,
+            latency_budget,
+            timeout,
+        })
+    }
+}
+
+/// A token bucket limiting the rate of calls to an interface.
+#[derive(Debug)]
+struct RateLimiter {
+    calls_per_second: f64,
+    burst: f64,
+    /// The calls currently allowed, and when they were last refilled.
+    tokens: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(rule: &RateLimitRule) -> Self {
+        let burst = rule
+            .burst
+            .map_or(rule.calls_per_second.ceil().max(1.0), f64::from);
+
+        Self {
+            calls_per_second: rule.calls_per_second,
+            burst,
+            tokens: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        let (available, refilled) = &mut *tokens;
+
+        let now = Instant::now();
+        let refill = now.duration_since(*refilled).as_secs_f64() * self.calls_per_second;
+        *available = (*available + refill.max(0.0)).min(self.burst);
+        *refilled = now;
+
+        if *available < 1.0 {
+            return false;
+        }
+
+        *available -= 1.0;
+        true
+    }
+}
+
+/// A shadowed function enforcing the rate limits, latency budgets and timeouts of a `Policy`.
+#[derive(Clone, Debug)]
+pub(crate) struct PolicedFunc {
+    function: Arc<(ForeignInterfacePath, String)>,
+    limiter: Option<Arc<RateLimiter>>,
+    latency_budget: Option<Duration>,
+    timeout: Option<Duration>,
+}
+
+impl PolicedFunc {
+    /// Admits a call, returning when it started if it has a latency budget or timeout.
+    pub(crate) fn admit(&self) -> Result<Option<Instant>, PolicyViolation> {
+        if let Some(limiter) = &self.limiter
+            && !limiter.try_acquire()
+        {
+            return Err(PolicyViolation::RateLimited {
+                interface: Box::new(self.function.0.clone()),
+                method: self.function.1.clone(),
+                calls_per_second: limiter.calls_per_second,
+            });
+        }
+
+        Ok(self.latency_budget.or(self.timeout).map(|_| Instant::now()))
+    }
+
+    /// Returns the deadline of a call admitted at `started`, if it has a timeout.
+    pub(crate) fn deadline(&self, started: Option<Instant>) -> Option<CallDeadline> {
+        Some(CallDeadline {
+            function: self.function.clone(),
+            timeout: self.timeout?,
+            started: started?,
+        })
+    }
+
+    /// Returns how long a call admitted at `started` took, if it exceeded its latency budget,
+    /// along with the budget.
+    pub(crate) fn over_budget(&self, started: Option<Instant>) -> Option<(Duration, Duration)> {
+        let (budget, started) = (self.latency_budget?, started?);
+        let elapsed = started.elapsed();
+
+        (elapsed > budget).then_some((elapsed, budget))
+    }
+}
+
+/// The deadline of a call to a shadowed function with a timeout, entered in the `StoreScopes` of
+/// the store making the call.
+#[derive(Debug)]
+pub(crate) struct CallDeadline {
+    function: Arc<(ForeignInterfacePath, String)>,
+    timeout: Duration,
+    started: Instant,
+}
+
+impl CallDeadline {
+    /// Returns the violation of the call if it is running past its timeout.
+    pub(crate) fn exceeded(&self) -> Option<PolicyViolation> {
+        let elapsed = self.started.elapsed();
+
+        (elapsed > self.timeout).then(|| PolicyViolation::DeadlineExceeded {
+            interface: Box::new(self.function.0.clone()),
+            method: self.function.1.clone(),
+            timeout: self.timeout,
+            elapsed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ComponentFixture, FixtureFunc};
+    use crate::{AddPackageError, CompositionGraph, LoadPackageError, NoopTrampoline};
+
+    fn kvstore() -> ForeignInterfacePath {
+        ForeignInterfacePath::new("test:kvstore".to_string(), "store".to_string(), None)
+    }
+
+    fn logger() -> ForeignInterfacePath {
+        ForeignInterfacePath::new("test:logger".to_string(), "log".to_string(), None)
+    }
+
+    #[test]
+    fn test_edges() {
+        let policy = Policy::new().with_edge(EdgeRule {
+            caller: "test:app*".to_string(),
+            callee: "test:kvstore/*".to_string(),
+        });
+
+        assert!(policy.allows_edge("test:application", &kvstore()));
+        assert!(!policy.allows_edge("test:application", &logger()));
+        assert!(!policy.allows_edge("test:other", &kvstore()));
+        assert!(Policy::new().allows_edge("test:other", &logger()));
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let policy = Policy::new().with_rate_limit(RateLimitRule {
+            interface: "test:kvstore/*".to_string(),
+            calls_per_second: 0.0,
+            burst: Some(2),
+        });
+
+        let store = policy.function(&kvstore(), "get").unwrap();
+        assert!(store.admit().is_ok());
+        // Functions of the same interface share its rate limit.
+        assert!(policy.function(&kvstore(), "set").unwrap().admit().is_ok());
+        assert!(matches!(
+            store.admit(),
+            Err(PolicyViolation::RateLimited { .. })
+        ));
+        assert!(policy.function(&logger(), "log").is_none());
+    }
+
+    #[test]
+    fn test_latency_budgets() {
+        let budgeted = Policy::new()
+            .with_latency_budget(LatencyBudgetRule {
+                interface: "test:logger/*".to_string(),
+                millis: 1000,
+            })
+            .function(&logger(), "log")
+            .unwrap();
+        let admitted = budgeted.admit().unwrap();
+        assert!(admitted.is_some());
+        assert_eq!(budgeted.over_budget(admitted), None);
+        let (elapsed, budget) = budgeted
+            .over_budget(admitted.map(|started| started - Duration::from_secs(2)))
+            .unwrap();
+        assert!(elapsed >= Duration::from_secs(2));
+        assert_eq!(budget, Duration::from_secs(1));
+        assert!(budgeted.deadline(admitted).is_none());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let timed = Policy::new()
+            .with_timeout(TimeoutRule {
+                interface: "test:logger/*".to_string(),
+                millis: 1000,
+            })
+            .function(&logger(), "log")
+            .unwrap();
+        let admitted = timed.admit().unwrap();
+        assert_eq!(timed.deadline(admitted).unwrap().exceeded(), None);
+        let exceeded = timed
+            .deadline(admitted.map(|started| started - Duration::from_secs(2)))
+            .unwrap()
+            .exceeded();
+        assert!(matches!(
+            exceeded,
+            Some(PolicyViolation::DeadlineExceeded { timeout, elapsed, .. })
+                if timeout == Duration::from_secs(1) && elapsed >= Duration::from_secs(2)
+        ));
+    }
+
+    #[test]
+    fn test_redactions() {
+        let policy = Policy::new().with_redaction(RedactionRule {
+            interface: "test:kvstore/*".to_string(),
+            method: "set".to_string(),
+            arguments: vec![1, 5],
+            results: false,
+        });
+
+        let arguments = [
+            Val::String("key".to_string()),
+            Val::String("secret".to_string()),
+        ];
+        assert_eq!(
+            policy
+                .redact_arguments(&kvstore(), "set", &arguments)
+                .as_ref(),
+            [
+                Val::String("key".to_string()),
+                Val::String(REDACTED.to_string())
+            ]
+        );
+        assert!(matches!(
+            policy.redact_arguments(&kvstore(), "get", &arguments),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(
+            policy.redact_results(&kvstore(), "set", &[Val::U32(1)]),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[cfg(feature = "policy")]
+    #[test]
+    fn test_policy_from_toml() {
+        let policy = Policy::from_toml(
+            r#"
+            [[latency_budgets]]
+            interface = "test:kvstore/*"
+            millis = 250
+
+            [[timeouts]]
+            interface = "test:kvstore/*"
+            millis = 1000
+
+            [[redactions]]
+            interface = "test:kvstore/*"
+            results = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy.latency_budget(&kvstore()),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(policy.timeout(&kvstore()), Some(Duration::from_secs(1)));
+        assert!(policy.allows_edge("test:application", &kvstore()));
+        assert_eq!(
+            policy
+                .redact_results(&kvstore(), "get", &[Val::U32(1)])
+                .as_ref(),
+            [Val::String(REDACTED.to_string())]
+        );
+
+        assert!(Policy::from_toml("[[latency_budgets]]\ninterface = \"*\"\nseconds = 1").is_err());
+    }
+
+    #[cfg(feature = "policy")]
+    #[test]
+    fn test_package_policy_from_toml() {
+        let policy = PackagePolicy::from_toml(
+            r#"
+            [[deny]]
+            name = "kvstore-advisory"
+            package = "test:kvstore"
+            versions = "<2.1.0"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy
+                .denies("test:kvstore", &Version::new(2, 0, 5))
+                .map(|rule| rule.name.as_str()),
+            Some("kvstore-advisory")
+        );
+        assert!(
+            policy
+                .denies("test:kvstore", &Version::new(2, 1, 0))
+                .is_none()
+        );
+    }
+
+    fn kvstore_bytes() -> Vec<u8> {
+        ComponentFixture::new()
+            .export("test:kvstore/store@2.0.0", [("get", FixtureFunc::Echo)])
+            .to_bytes()
+            .unwrap()
+    }
+
+    fn kvstore_advisory() -> PackagePolicy {
+        PackagePolicy::new().with_deny(PackageDenyRule {
+            name: "kvstore-advisory".to_string(),
+            package: "test:kvstore".to_string(),
+            versions: "<2.1.0".parse().unwrap(),
+            reason: None,
+        })
+    }
+
+    #[test]
+    fn test_package_policy_denies_resolution() {
+        let app = ComponentFixture::new()
+            .import("test:kvstore/store@2.0.0", ["get"])
+            .to_bytes()
+            .unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        graph
+            .add_package(
+                "test:kvstore".to_string(),
+                Version::new(2, 0, 0),
+                kvstore_bytes(),
+                NoopTrampoline,
+            )
+            .unwrap();
+        let app = graph
+            .add_package(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                app,
+                NoopTrampoline,
+            )
+            .unwrap();
+        assert!(graph.validate_package(app).unwrap().is_resolved());
+
+        // Packages added before the policy is set are denied as well.
+        graph.set_package_policy(Some(kvstore_advisory()));
+
+        let report = graph.validate_package(app).unwrap();
+        assert!(matches!(
+            &report.unresolved[..],
+            [LoadPackageError::DeniedByPackagePolicy { package, policy, .. }]
+                if package == "test:kvstore@2.0.0" && policy == "kvstore-advisory"
+        ));
+    }
+
+    #[test]
+    fn test_package_policy_denies_add() {
+        let mut graph = CompositionGraph::<()>::new();
+        graph.set_package_policy(Some(kvstore_advisory()));
+
+        let err = graph
+            .add_package(
+                "test:kvstore".to_string(),
+                Version::new(2, 0, 1),
+                kvstore_bytes(),
+                NoopTrampoline,
+            )
+            .unwrap_err();
+        assert!(matches!(err, AddPackageError::DeniedByPackagePolicy { .. }));
+        assert!(err.to_string().contains("'kvstore-advisory'"));
+
+        graph
+            .add_package(
+                "test:kvstore".to_string(),
+                Version::new(2, 1, 0),
+                kvstore_bytes(),
+                NoopTrampoline,
+            )
+            .unwrap();
+    }
+}
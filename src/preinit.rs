@@ -0,0 +1,513 @@
+//! Pre-initialization of packages, in the style of Wizer: the init export of a package is run
+//! once when the package is added, and the initialized memories and globals are snapshotted into
+//! the component, so that instances start out initialized instead of paying for the
+//! initialization in every store.
+
+use crate::verify::sections;
+use snafu::{IntoError, ResultExt, Snafu};
+use std::convert::Infallible;
+use wasm_encoder::reencode::{self, Reencode, utils};
+use wasm_encoder::{
+    ComponentSectionId, ConstExpr, DataSection, ExportKind, ExportSection, GlobalSection, Ieee32,
+    Ieee64, MemorySection, RawSection,
+};
+use wasmparser::{ExternalKind, Payload, TypeRef};
+use wasmtime::{Engine, Instance, Linker, Module, Store, Val};
+
+/// Zero bytes between non-zero bytes of a snapshotted memory shorter than this are kept in the
+/// same data segment, rather than splitting the memory into many tiny segments.
+const MAX_SEGMENT_GAP: usize = 64;
+
+/// An error pre-initializing a package.
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum PreinitializeError {
+    #[snafu(display("Failed to parse package"))]
+    ParseError {
+        source: wasmparser::BinaryReaderError,
+    },
+
+    #[snafu(display("No core module of the package exports the init function '{init_export}'"))]
+    MissingInitExport { init_export: String },
+
+    #[snafu(display(
+        "The core module exporting '{init_export}' imports {kind} '{module}#{name}', which \
+         cannot be snapshotted"
+    ))]
+    UnsupportedImport {
+        init_export: String,
+        module: String,
+        name: String,
+        kind: &'static str,
+    },
+
+    #[snafu(display(
+        "The core module exporting '{init_export}' has a start function, which would run again \
+         when the snapshot is instantiated"
+    ))]
+    UnsupportedStart { init_export: String },
+
+    #[snafu(display(
+        "Memory {index} of the core module exporting '{init_export}' is shared, which cannot be \
+         snapshotted"
+    ))]
+    UnsupportedMemory { init_export: String, index: u32 },
+
+    #[snafu(display(
+        "Global {index} of the core module exporting '{init_export}' is a mutable reference, \
+         which cannot be snapshotted"
+    ))]
+    UnsupportedGlobal { init_export: String, index: u32 },
+
+    #[snafu(display("Failed to run the init function '{init_export}'"))]
+    InitError {
+        init_export: String,
+        source: anyhow::Error,
+    },
+
+    #[snafu(display("Failed to encode the snapshot"))]
+    EncodeError { source: reencode::Error },
+}
+
+/// Runs the init export `init_export` of the component `bytes` once, and returns the component
+/// with the memories and globals it initialized snapshotted into it.
+///
+/// The init export must be a parameterless function without results, lifted from a core function
+/// exported under the same name by a top-level core module of the component, which is how
+/// `wit-bindgen` lifts exports, e.g. `init` or `my:package/setup#init`. Only that module is run
+/// and snapshotted, so the init function must not call imports; calling one traps. The module
+/// must not import memories, tables or globals, nor have a start function. Tables are not
+/// snapshotted, so the init function must not modify them.
+///
+/// The returned component no longer needs the init export to be called, but still exports it;
+/// calling it again initializes the instance again.
+pub fn preinitialize(bytes: &[u8], init_export: &str) -> Result<Vec<u8>, PreinitializeError> {
+    let init_module = || preinitialize_error::MissingInitExportSnafu { init_export };
+    let sections = sections(bytes)
+        .context(preinitialize_error::ParseSnafu)?
+        .ok_or_else(|| init_module().build())?;
+
+    let mut found = None;
+    for (index, section) in sections.iter().enumerate() {
+        if section.id == ComponentSectionId::CoreModule as u8
+            && let Some(layout) = ModuleLayout::of(section.contents, init_export)?
+        {
+            found = Some((index, layout));
+            break;
+        }
+    }
+    let (module_index, layout) = found.ok_or_else(|| init_module().build())?;
+    let module = sections[module_index].contents;
+
+    let snapshot = Snapshot::take(module, &layout, init_export)?;
+    let module = snapshot
+        .apply(module)
+        .context(preinitialize_error::EncodeSnafu)?;
+
+    let mut component = wasm_encoder::Component::new();
+    for (index, section) in sections.iter().enumerate() {
+        let data = if index == module_index {
+            module.as_slice()
+        } else {
+            section.contents
+        };
+        component.section(&RawSection {
+            id: section.id,
+            data,
+        });
+    }
+
+    Ok(component.finish())
+}
+
+/// The memories and globals defined by the core module exporting the init function.
+struct ModuleLayout {
+    /// Whether each memory is 64-bit.
+    memories: Vec<bool>,
+    /// Whether each global is mutable.
+    mutable_globals: Vec<bool>,
+}
+
+impl ModuleLayout {
+    /// Returns the layout of the core `module`, or `None` if it does not export `init_export`.
+    fn of(module: &[u8], init_export: &str) -> Result<Option<Self>, PreinitializeError> {
+        let mut layout = Self {
+            memories: Vec::new(),
+            mutable_globals: Vec::new(),
+        };
+        let mut exports_init = false;
+        let mut imports = Vec::new();
+        let mut has_start = false;
+        let mut shared_memory = None;
+
+        for payload in wasmparser::Parser::new(0).parse_all(module) {
+            match payload.context(preinitialize_error::ParseSnafu)? {
+                Payload::ImportSection(section) => {
+                    for import in section {
+                        let import = import.context(preinitialize_error::ParseSnafu)?;
+                        imports.push((
+                            import.module.to_string(),
+                            import.name.to_string(),
+                            import.ty,
+                        ));
+                    }
+                }
+                Payload::MemorySection(section) => {
+                    for (memory, index) in section.into_iter().zip(0u32..) {
+                        let memory = memory.context(preinitialize_error::ParseSnafu)?;
+                        if memory.shared {
+                            shared_memory.get_or_insert(index);
+                        }
+                        layout.memories.push(memory.memory64);
+                    }
+                }
+                Payload::GlobalSection(section) => {
+                    for global in section {
+                        let global = global.context(preinitialize_error::ParseSnafu)?;
+                        layout.mutable_globals.push(global.ty.mutable);
+                    }
+                }
+                Payload::ExportSection(section) => {
+                    for export in section {
+                        let export = export.context(preinitialize_error::ParseSnafu)?;
+                        exports_init |=
+                            export.name == init_export && export.kind == ExternalKind::Func;
+                    }
+                }
+                Payload::StartSection { .. } => has_start = true,
+                _ => {}
+            }
+        }
+
+        if !exports_init {
+            return Ok(None);
+        }
+
+        // Only functions are imported, so the indices of memories and globals start at zero.
+        for (module, name, ty) in imports {
+            let kind = match ty {
+                TypeRef::Func(_) => continue,
+                TypeRef::Table(_) => "table",
+                TypeRef::Memory(_) => "memory",
+                TypeRef::Global(_) => "global",
+                TypeRef::Tag(_) => "tag",
+            };
+
+            return preinitialize_error::UnsupportedImportSnafu {
+                init_export,
+                module,
+                name,
+                kind,
+            }
+            .fail();
+        }
+
+        if has_start {
+            return preinitialize_error::UnsupportedStartSnafu { init_export }.fail();
+        }
+
+        if let Some(index) = shared_memory {
+            return preinitialize_error::UnsupportedMemorySnafu { init_export, index }.fail();
+        }
+
+        Ok(Some(layout))
+    }
+}
+
+fn memory_export(index: u32) -> String {
+    format!("__wct_memory_{index}")
+}
+
+fn global_export(index: u32) -> String {
+    format!("__wct_global_{index}")
+}
+
+/// Re-encodes a core module exporting all of its memories and mutable globals, so they can be
+/// read after running the init function.
+struct Instrument<'a> {
+    layout: &'a ModuleLayout,
+}
+
+impl Reencode for Instrument<'_> {
+    type Error = Infallible;
+
+    fn parse_export_section(
+        &mut self,
+        exports: &mut ExportSection,
+        section: wasmparser::ExportSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        utils::parse_export_section(self, exports, section)?;
+
+        for index in (0..).take(self.layout.memories.len()) {
+            exports.export(&memory_export(index), ExportKind::Memory, index);
+        }
+        for (index, _) in (0..).zip(&self.layout.mutable_globals).filter(|(_, m)| **m) {
+            exports.export(&global_export(index), ExportKind::Global, index);
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of the memories and mutable globals of a core module after running its init
+/// function.
+struct Snapshot {
+    /// The size in pages of each memory.
+    memory_sizes: Vec<u64>,
+    /// The memory index, offset and contents of each run of non-zero bytes of the memories.
+    segments: Vec<(u32, ConstExpr, Vec<u8>)>,
+    /// The value of each mutable global.
+    globals: Vec<Option<ConstExpr>>,
+    /// The index of the next global re-encoded by `apply`.
+    next_global: usize,
+    segments_written: bool,
+}
+
+impl Snapshot {
+    fn take(
+        module: &[u8],
+        layout: &ModuleLayout,
+        init_export: &str,
+    ) -> Result<Self, PreinitializeError> {
+        let init_error = |source: anyhow::Error| {
+            preinitialize_error::InitSnafu { init_export }.into_error(source)
+        };
+
+        let mut instrumented = wasm_encoder::Module::new();
+        Instrument { layout }
+            .parse_core_module(&mut instrumented, wasmparser::Parser::new(0), module)
+            .context(preinitialize_error::EncodeSnafu)?;
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, instrumented.finish()).map_err(init_error)?;
+        let mut linker = Linker::new(&engine);
+        linker
+            .define_unknown_imports_as_traps(&module)
+            .map_err(init_error)?;
+
+        let mut store = Store::new(&engine, ());
+        let instance: Instance = linker
+            .instantiate(&mut store, &module)
+            .map_err(init_error)?;
+        instance
+            .get_typed_func::<(), ()>(&mut store, init_export)
+            .and_then(|init| init.call(&mut store, ()))
+            .map_err(init_error)?;
+
+        let mut memory_sizes = Vec::with_capacity(layout.memories.len());
+        let mut segments = Vec::new();
+        for (memory64, index) in layout.memories.iter().zip(0..) {
+            let memory = instance
+                .get_memory(&mut store, &memory_export(index))
+                .expect("memories are exported by the instrumentation");
+            memory_sizes.push(memory.size(&store));
+
+            for (start, bytes) in non_zero_runs(memory.data(&store)) {
+                let offset = if *memory64 {
+                    ConstExpr::i64_const(start as i64)
+                } else {
+                    ConstExpr::i32_const(start as i32)
+                };
+                segments.push((index, offset, bytes.to_vec()));
+            }
+        }
+
+        let mut globals = Vec::with_capacity(layout.mutable_globals.len());
+        for (mutable, index) in layout.mutable_globals.iter().zip(0..) {
+            if !mutable {
+                globals.push(None);
+                continue;
+            }
+
+            let global = instance
+                .get_global(&mut store, &global_export(index))
+                .expect("mutable globals are exported by the instrumentation");
+            let value = match global.get(&mut store) {
+                Val::I32(value) => ConstExpr::i32_const(value),
+                Val::I64(value) => ConstExpr::i64_const(value),
+                Val::F32(bits) => ConstExpr::f32_const(Ieee32::new(bits)),
+                Val::F64(bits) => ConstExpr::f64_const(Ieee64::new(bits)),
+                Val::V128(value) => ConstExpr::v128_const(value.as_u128() as i128),
+                _ => {
+                    return preinitialize_error::UnsupportedGlobalSnafu { init_export, index }
+                        .fail();
+                }
+            };
+            globals.push(Some(value));
+        }
+
+        Ok(Self {
+            memory_sizes,
+            segments,
+            globals,
+            next_global: 0,
+            segments_written: false,
+        })
+    }
+
+    /// Re-encodes the core `module` with the snapshotted state as its initial state.
+    fn apply(mut self, module: &[u8]) -> Result<Vec<u8>, reencode::Error> {
+        let mut snapshot = wasm_encoder::Module::new();
+        self.parse_core_module(&mut snapshot, wasmparser::Parser::new(0), module)?;
+        Ok(snapshot.finish())
+    }
+
+    fn write_segments(&mut self, data: &mut DataSection) {
+        self.segments_written = true;
+        for (memory, offset, bytes) in &self.segments {
+            data.active(*memory, offset, bytes.iter().copied());
+        }
+    }
+}
+
+/// Returns the offset and bytes of the runs of non-zero bytes of `memory`, merging runs separated
+/// by fewer than `MAX_SEGMENT_GAP` zero bytes.
+fn non_zero_runs(memory: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+
+    for (offset, _) in memory.iter().enumerate().filter(|(_, byte)| **byte != 0) {
+        match runs.last_mut() {
+            Some((_, end)) if offset - *end < MAX_SEGMENT_GAP => *end = offset + 1,
+            _ => runs.push((offset, offset + 1)),
+        }
+    }
+
+    runs.into_iter()
+        .map(|(start, end)| (start, &memory[start..end]))
+        .collect()
+}
+
+impl Reencode for Snapshot {
+    type Error = Infallible;
+
+    fn parse_memory_section(
+        &mut self,
+        memories: &mut MemorySection,
+        section: wasmparser::MemorySectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        for (memory, size) in section.into_iter().zip(self.memory_sizes.clone()) {
+            let mut memory = self.memory_type(memory?)?;
+            memory.minimum = memory.minimum.max(size);
+            memories.memory(memory);
+        }
+
+        Ok(())
+    }
+
+    fn parse_global(
+        &mut self,
+        globals: &mut GlobalSection,
+        global: wasmparser::Global<'_>,
+    ) -> Result<(), reencode::Error> {
+        let index = self.next_global;
+        self.next_global += 1;
+
+        match self.globals.get(index).cloned().flatten() {
+            Some(value) => {
+                globals.global(self.global_type(global.ty)?, &value);
+                Ok(())
+            }
+            None => utils::parse_global(self, globals, global),
+        }
+    }
+
+    fn parse_data(
+        &mut self,
+        data: &mut DataSection,
+        datum: wasmparser::Data<'_>,
+    ) -> Result<(), reencode::Error> {
+        // The snapshot already holds the contents of active segments, so they are emptied. They
+        // are kept as passive segments to preserve the indices of the other segments.
+        match datum.kind {
+            wasmparser::DataKind::Passive => utils::parse_data(self, data, datum),
+            wasmparser::DataKind::Active { .. } => {
+                data.passive([]);
+                Ok(())
+            }
+        }
+    }
+
+    fn parse_data_section(
+        &mut self,
+        data: &mut DataSection,
+        section: wasmparser::DataSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        utils::parse_data_section(self, data, section)?;
+        self.write_segments(data);
+        Ok(())
+    }
+
+    fn data_count(&mut self, count: u32) -> Result<u32, reencode::Error> {
+        Ok(count + self.segments.len() as u32)
+    }
+
+    fn intersperse_section_hook(
+        &mut self,
+        module: &mut wasm_encoder::Module,
+        after: Option<wasm_encoder::SectionId>,
+        before: Option<wasm_encoder::SectionId>,
+    ) -> Result<(), reencode::Error> {
+        // Modules without a data section get one holding the snapshot after their last section.
+        if before.is_none() && !self.segments_written && !self.segments.is_empty() {
+            let mut data = DataSection::new();
+            self.write_segments(&mut data);
+            module.section(&data);
+        }
+
+        utils::intersperse_section_hook(self, module, after, before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::component::{Component, Linker};
+
+    const COUNTER_WAT: &str = r#"(component
+        (core module $module
+            (memory (export "memory") 1)
+            (global $base (mut i32) (i32.const 0))
+            (data (i32.const 8) "\01")
+            (func (export "init")
+                (i32.store (i32.const 1024) (i32.const 40))
+                (global.set $base (i32.const 1))
+            )
+            (func (export "get") (result i32)
+                (i32.add
+                    (i32.add (i32.load (i32.const 1024)) (global.get $base))
+                    (i32.load8_u (i32.const 8))
+                )
+            )
+        )
+        (core instance $instance (instantiate $module))
+        (func (export "init") (canon lift (core func $instance "init")))
+        (func (export "get") (result u32) (canon lift (core func $instance "get")))
+    )"#;
+
+    #[test]
+    fn test_preinitialize() {
+        let bytes = wat::parse_str(COUNTER_WAT).unwrap();
+        let preinitialized = preinitialize(&bytes, "init").unwrap();
+
+        let engine = Engine::default();
+        let get = |bytes: &[u8]| {
+            let component = Component::new(&engine, bytes).unwrap();
+            let mut store = Store::new(&engine, ());
+            let instance = Linker::new(&engine)
+                .instantiate(&mut store, &component)
+                .unwrap();
+            let get = instance
+                .get_typed_func::<(), (u32,)>(&mut store, "get")
+                .unwrap();
+            get.call(&mut store, ()).unwrap().0
+        };
+
+        assert_eq!(get(&bytes), 1);
+        assert_eq!(get(&preinitialized), 42);
+
+        assert!(matches!(
+            preinitialize(&bytes, "missing"),
+            Err(PreinitializeError::MissingInitExport { .. })
+        ));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::ForeignInterfacePath;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Attributes the time spent in calls to shadowed functions to interfaces, call edges and call
+/// stacks, recorded by the graph's trampolines once set with `CompositionGraph::set_call_profiler`.
+///
+/// Nested calls are tracked on a call stack per thread. The profile is exact for synchronous
+/// calls, while asynchronous calls of different stores interleaved on a thread may be attributed
+/// to each other's callers.
+///
+/// Clones share the same profile.
+#[derive(Clone, Default, Debug)]
+pub struct CallProfiler {
+    profile: Arc<Mutex<Profile>>,
+}
+
+#[derive(Default, Debug)]
+struct Profile {
+    /// Exclusive time, by folded call stack.
+    stacks: HashMap<String, Duration>,
+    interfaces: HashMap<Arc<str>, InterfaceTime>,
+    edges: HashMap<(Arc<str>, Arc<str>), Duration>,
+}
+
+/// The time spent in the functions of an interface.
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+pub struct InterfaceTime {
+    pub interface: String,
+    /// The time spent in calls to the interface, including the calls they made. Calls nested in
+    /// a call to the same interface are counted once.
+    pub inclusive: Duration,
+    /// The time spent in calls to the interface, excluding the calls they made.
+    pub exclusive: Duration,
+}
+
+/// The time spent in calls from the functions of one interface to those of another.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EdgeTime {
+    pub caller: String,
+    pub callee: String,
+    /// The time spent in the callee's calls, including the calls they made.
+    pub inclusive: Duration,
+}
+
+impl CallProfiler {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renders the exclusive time per call stack in the folded stack format read by flame graph
+    /// tools such as `inferno-flamegraph`, in nanoseconds. Frames are named
+    /// `interface#method`, outermost first.
+    #[must_use]
+    pub fn folded_stacks(&self) -> String {
+        let profile = self.profile.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut stacks = profile.stacks.iter().collect::<Vec<_>>();
+        stacks.sort_unstable();
+
+        let mut folded = String::new();
+        for (stack, time) in stacks {
+            let _ = writeln!(folded, "{stack} {}", time.as_nanos());
+        }
+
+        folded
+    }
+
+    /// Returns the time spent per interface, ordered by interface.
+    #[must_use]
+    pub fn interface_times(&self) -> Vec<InterfaceTime> {
+        let profile = self.profile.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut times = profile.interfaces.values().cloned().collect::<Vec<_>>();
+        times.sort_unstable_by(|a, b| a.interface.cmp(&b.interface));
+
+        times
+    }
+
+    /// Returns the time spent per caller and callee interface, ordered by caller and callee.
+    #[must_use]
+    pub fn edge_times(&self) -> Vec<EdgeTime> {
+        let profile = self.profile.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut times = profile
+            .edges
+            .iter()
+            .map(|((caller, callee), inclusive)| EdgeTime {
+                caller: caller.to_string(),
+                callee: callee.to_string(),
+                inclusive: *inclusive,
+            })
+            .collect::<Vec<_>>();
+        times.sort_unstable_by(|a, b| (&a.caller, &a.callee).cmp(&(&b.caller, &b.callee)));
+
+        times
+    }
+
+    /// Returns the handle a shadowed function records its calls with.
+    pub(crate) fn function(&self, interface: &ForeignInterfacePath, method: &str) -> ProfiledFunc {
+        ProfiledFunc {
+            profiler: self.clone(),
+            interface: Arc::from(interface.as_str()),
+            frame: Arc::from(format!("{interface}#{method}")),
+        }
+    }
+}
+
+thread_local! {
+    static CALL_STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Frame {
+    interface: Arc<str>,
+    name: Arc<str>,
+    started: Instant,
+    /// The inclusive time of the calls made by this call.
+    children: Duration,
+}
+
+/// A shadowed function recording its calls in a `CallProfiler`.
+#[derive(Clone, Debug)]
+pub(crate) struct ProfiledFunc {
+    profiler: CallProfiler,
+    interface: Arc<str>,
+    frame: Arc<str>,
+}
+
+impl ProfiledFunc {
+    pub(crate) fn enter(&self, started: Instant) {
+        CALL_STACK.with_borrow_mut(|stack| {
+            stack.push(Frame {
+                interface: self.interface.clone(),
+                name: self.frame.clone(),
+                started,
+                children: Duration::ZERO,
+            });
+        });
+    }
+
+    pub(crate) fn exit(&self, started: Instant) {
+        let inclusive = started.elapsed();
+
+        CALL_STACK.with_borrow_mut(|stack| {
+            let Some(index) = stack.iter().rposition(|frame| {
+                Arc::ptr_eq(&frame.name, &self.frame) && frame.started == started
+            }) else {
+                return;
+            };
+
+            let frame = stack.remove(index);
+            let callers = &mut stack[..index];
+            let exclusive = inclusive.saturating_sub(frame.children);
+
+            let mut folded = String::new();
+            for caller in callers.iter() {
+                folded.push_str(&caller.name);
+                folded.push(';');
+            }
+            folded.push_str(&frame.name);
+
+            let is_recursive = callers
+                .iter()
+                .any(|caller| caller.interface == frame.interface);
+
+            let mut profile = self
+                .profiler
+                .profile
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            *profile.stacks.entry(folded).or_default() += exclusive;
+
+            let time = profile
+                .interfaces
+                .entry(frame.interface.clone())
+                .or_insert_with(|| InterfaceTime {
+                    interface: frame.interface.to_string(),
+                    ..InterfaceTime::default()
+                });
+            time.exclusive += exclusive;
+            if !is_recursive {
+                time.inclusive += inclusive;
+            }
+
+            if let Some(caller) = callers.last_mut() {
+                caller.children += inclusive;
+
+                if caller.interface != frame.interface {
+                    *profile
+                        .edges
+                        .entry((caller.interface.clone(), frame.interface.clone()))
+                        .or_default() += inclusive;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(interface: &str) -> ForeignInterfacePath {
+        ForeignInterfacePath::new("test:app".to_string(), interface.to_string(), None)
+    }
+
+    #[test]
+    fn test_call_profiler() {
+        let profiler = CallProfiler::new();
+        let handle = profiler.function(&path("handler"), "handle");
+        let get = profiler.function(&path("store"), "get");
+
+        let handle_started = Instant::now();
+        handle.enter(handle_started);
+        for _ in 0..2 {
+            let get_started = Instant::now();
+            get.enter(get_started);
+            get.exit(get_started);
+        }
+        handle.exit(handle_started);
+
+        let folded = profiler.folded_stacks();
+        let stacks = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stacks,
+            [
+                "test:app/handler#handle",
+                "test:app/handler#handle;test:app/store#get"
+            ]
+        );
+
+        let interfaces = profiler.interface_times();
+        assert_eq!(interfaces.len(), 2);
+        assert!(interfaces[0].inclusive >= interfaces[1].inclusive);
+        assert_eq!(interfaces[1].inclusive, interfaces[1].exclusive);
+
+        let edges = profiler.edge_times();
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].caller, "test:app/handler");
+        assert_eq!(edges[0].callee, "test:app/store");
+        assert_eq!(edges[0].inclusive, interfaces[1].inclusive);
+    }
+}
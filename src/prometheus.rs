@@ -0,0 +1,397 @@
+//! Prometheus text exposition of call metrics and memory usage, for hosts scraping composition
+//! metrics.
+
+use crate::metrics::DURATION_BUCKETS;
+use crate::{CallMetrics, CallStats, MemoryTracker};
+use semver::Version;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+/// The value of labels whose other values are dropped by `MetricLabels::with_max_values`.
+const OTHER_LABEL_VALUE: &str = "other";
+
+/// A dimension of call metrics that can be exported as a label.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MetricLabel {
+    /// The `package` label, with the name of the package exporting the function.
+    Package,
+
+    /// Whether the `package` label includes the package version, as `name@version`.
+    Version,
+
+    /// The `interface` label, with the name of the interface exporting the function.
+    Interface,
+
+    /// The `method` label, with the name of the function.
+    Method,
+}
+
+/// The labels of the call metrics encoded by `CallMetrics::encode_prometheus_with`.
+///
+/// Series of functions that only differ by dropped labels are summed, so large compositions can
+/// be exported at the granularity of packages or interfaces. The number of values of a label can
+/// also be capped, keeping the values with the most calls and summing the others into one
+/// `other` value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MetricLabels {
+    labels: HashSet<MetricLabel>,
+    max_values: HashMap<MetricLabel, usize>,
+}
+
+impl Default for MetricLabels {
+    /// All labels, without caps.
+    fn default() -> Self {
+        Self::new([
+            MetricLabel::Package,
+            MetricLabel::Version,
+            MetricLabel::Interface,
+            MetricLabel::Method,
+        ])
+    }
+}
+
+impl MetricLabels {
+    /// Creates labels exporting the given dimensions, without caps.
+    #[must_use]
+    pub fn new(labels: impl IntoIterator<Item = MetricLabel>) -> Self {
+        Self {
+            labels: labels.into_iter().collect(),
+            max_values: HashMap::new(),
+        }
+    }
+
+    /// Caps the number of values of `label` to `max_values`, including the `other` value.
+    #[must_use]
+    pub fn with_max_values(mut self, label: MetricLabel, max_values: usize) -> Self {
+        self.max_values.insert(label, max_values);
+        self
+    }
+
+    /// Returns the label values of a function's metrics, as `(name, value)` pairs.
+    fn values(&self, stats: &CallStats) -> Vec<(&'static str, String)> {
+        let path = &stats.interface;
+        let mut values = Vec::new();
+
+        if self.labels.contains(&MetricLabel::Package) {
+            let version = path
+                .version()
+                .filter(|_| self.labels.contains(&MetricLabel::Version));
+            values.push(("package", package(path.package_name(), version)));
+        }
+        if self.labels.contains(&MetricLabel::Interface) {
+            values.push(("interface", path.interface_name().to_string()));
+        }
+        if self.labels.contains(&MetricLabel::Method) {
+            values.push(("method", stats.method.clone()));
+        }
+
+        values
+    }
+
+    /// Returns the series of `snapshot` by label set, summing the metrics of the functions with
+    /// the same labels.
+    fn series(&self, snapshot: Vec<CallStats>) -> Vec<(String, CallStats)> {
+        let mut labelled = snapshot
+            .into_iter()
+            .map(|stats| (self.values(&stats), stats))
+            .collect::<Vec<_>>();
+
+        for (label, name) in [
+            (MetricLabel::Package, "package"),
+            (MetricLabel::Interface, "interface"),
+            (MetricLabel::Method, "method"),
+        ] {
+            let Some(max_values) = self.max_values.get(&label) else {
+                continue;
+            };
+
+            let mut calls = HashMap::<&str, u64>::new();
+            for (values, stats) in &labelled {
+                if let Some((_, value)) = values.iter().find(|(label, _)| *label == name) {
+                    *calls.entry(value).or_default() += stats.calls;
+                }
+            }
+            if calls.len() <= *max_values {
+                continue;
+            }
+
+            let mut ranked = calls.into_iter().collect::<Vec<_>>();
+            ranked.sort_unstable_by(|(a, a_calls), (b, b_calls)| {
+                b_calls.cmp(a_calls).then_with(|| a.cmp(b))
+            });
+            let kept = ranked
+                .into_iter()
+                .take(max_values.saturating_sub(1))
+                .map(|(value, _)| value.to_string())
+                .collect::<HashSet<_>>();
+
+            for (values, _) in &mut labelled {
+                for (label, value) in values.iter_mut() {
+                    if *label == name && !kept.contains(value) {
+                        *value = OTHER_LABEL_VALUE.to_string();
+                    }
+                }
+            }
+        }
+
+        let mut series = BTreeMap::<String, CallStats>::new();
+        for (values, stats) in labelled {
+            let labels = values
+                .iter()
+                .map(|(label, value)| format!("{label}=\"{}\"", escape(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            match series.get_mut(&labels) {
+                Some(total) => {
+                    total.calls += stats.calls;
+                    total.errors += stats.errors;
+                    total.duration += stats.duration;
+                    total.fuel += stats.fuel;
+                    for (total, count) in total
+                        .duration_buckets
+                        .iter_mut()
+                        .zip(&stats.duration_buckets)
+                    {
+                        *total += count;
+                    }
+                }
+                None => {
+                    series.insert(labels, stats);
+                }
+            }
+        }
+
+        series.into_iter().collect()
+    }
+}
+
+impl CallMetrics {
+    /// Renders the metrics in the Prometheus text exposition format, labelled by the `package`,
+    /// `interface` and `method` of each function.
+    #[must_use]
+    pub fn encode_prometheus(&self) -> String {
+        self.encode_prometheus_with(&MetricLabels::default())
+    }
+
+    /// Like `encode_prometheus`, but with the labels selected by `labels`.
+    #[must_use]
+    pub fn encode_prometheus_with(&self, labels: &MetricLabels) -> String {
+        let series = labels.series(self.snapshot());
+        let mut text = String::new();
+
+        text.push_str("# HELP component_trampoline_calls_total Calls to shadowed functions.\n");
+        text.push_str("# TYPE component_trampoline_calls_total counter\n");
+        for (labels, stats) in &series {
+            let _ = writeln!(
+                text,
+                "component_trampoline_calls_total{{{labels}}} {}",
+                stats.calls
+            );
+        }
+
+        text.push_str(
+            "# HELP component_trampoline_call_errors_total Calls to shadowed functions that \
+             failed.\n",
+        );
+        text.push_str("# TYPE component_trampoline_call_errors_total counter\n");
+        for (labels, stats) in &series {
+            let _ = writeln!(
+                text,
+                "component_trampoline_call_errors_total{{{labels}}} {}",
+                stats.errors
+            );
+        }
+
+        text.push_str(
+            "# HELP component_trampoline_call_fuel_consumed_total Fuel consumed by calls to \
+             shadowed functions, excluding the shadowed functions they called.\n",
+        );
+        text.push_str("# TYPE component_trampoline_call_fuel_consumed_total counter\n");
+        for (labels, stats) in &series {
+            let _ = writeln!(
+                text,
+                "component_trampoline_call_fuel_consumed_total{{{labels}}} {}",
+                stats.fuel
+            );
+        }
+
+        text.push_str(
+            "# HELP component_trampoline_call_duration_seconds Durations of calls to shadowed \
+             functions.\n",
+        );
+        text.push_str("# TYPE component_trampoline_call_duration_seconds histogram\n");
+        for (labels, stats) in &series {
+            let mut cumulative = 0;
+            // Bucket labels follow the series labels, if any.
+            let bucket_labels = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{labels},")
+            };
+
+            for (bound, count) in DURATION_BUCKETS.iter().zip(&stats.duration_buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "component_trampoline_call_duration_seconds_bucket{{{bucket_labels}le=\"{bound}\"}} \
+                     {cumulative}"
+                );
+            }
+
+            let _ = writeln!(
+                text,
+                "component_trampoline_call_duration_seconds_bucket{{{bucket_labels}le=\"+Inf\"}} {}",
+                stats.calls
+            );
+            let _ = writeln!(
+                text,
+                "component_trampoline_call_duration_seconds_sum{{{labels}}} {}",
+                stats.duration.as_secs_f64()
+            );
+            let _ = writeln!(
+                text,
+                "component_trampoline_call_duration_seconds_count{{{labels}}} {}",
+                stats.calls
+            );
+        }
+
+        text
+    }
+}
+
+impl MemoryTracker {
+    /// Renders the memory usage in the Prometheus text exposition format, labelled by the
+    /// `package` of each package, and the `interface` and `method` of each function.
+    #[must_use]
+    pub fn encode_prometheus(&self) -> String {
+        let usage = self.usage();
+        let mut text = String::new();
+
+        let labels = usage
+            .iter()
+            .map(|memory| {
+                format!(
+                    "package=\"{}\"",
+                    escape(&package(&memory.name, memory.version.as_ref()))
+                )
+            })
+            .collect::<Vec<_>>();
+
+        text.push_str(
+            "# HELP component_trampoline_memory_high_water_bytes Largest size of a linear memory \
+             attributed to a package.\n",
+        );
+        text.push_str("# TYPE component_trampoline_memory_high_water_bytes gauge\n");
+        for (memory, labels) in usage.iter().zip(&labels) {
+            let _ = writeln!(
+                text,
+                "component_trampoline_memory_high_water_bytes{{{labels}}} {}",
+                memory.high_water
+            );
+        }
+
+        text.push_str(
+            "# HELP component_trampoline_instantiation_memory_grown_bytes_total Linear memory \
+             grown while instantiating a package.\n",
+        );
+        text.push_str(
+            "# TYPE component_trampoline_instantiation_memory_grown_bytes_total counter\n",
+        );
+        for (memory, labels) in usage.iter().zip(&labels) {
+            let _ = writeln!(
+                text,
+                "component_trampoline_instantiation_memory_grown_bytes_total{{{labels}}} {}",
+                memory.instantiation
+            );
+        }
+
+        text.push_str(
+            "# HELP component_trampoline_call_memory_grown_bytes_total Linear memory grown in \
+             calls to shadowed functions.\n",
+        );
+        text.push_str("# TYPE component_trampoline_call_memory_grown_bytes_total counter\n");
+        for (memory, labels) in usage.iter().zip(&labels) {
+            for function in &memory.functions {
+                let _ = writeln!(
+                    text,
+                    "component_trampoline_call_memory_grown_bytes_total{{{labels},interface=\"{}\",\
+                     method=\"{}\"}} {}",
+                    escape(function.interface.interface_name()),
+                    escape(&function.method),
+                    function.grown
+                );
+            }
+        }
+
+        text
+    }
+}
+
+/// Formats a package label value as `name@version`.
+fn package(name: &str, version: Option<&Version>) -> String {
+    match version {
+        Some(version) => format!("{name}@{version}"),
+        None => name.to_string(),
+    }
+}
+
+/// Escapes a label value, as required by the text exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ForeignInterfacePath;
+    use semver::Version;
+    use std::time::Instant;
+
+    #[test]
+    fn test_encode_prometheus() {
+        let metrics = CallMetrics::new();
+        let path = ForeignInterfacePath::new(
+            "test:kvstore".to_string(),
+            "store".to_string(),
+            Some(Version::new(1, 0, 0)),
+        );
+
+        metrics.function(&path, "get").record(Instant::now(), false);
+
+        let text = metrics.encode_prometheus();
+        let labels = r#"package="test:kvstore@1.0.0",interface="store",method="get""#;
+
+        assert!(text.contains(&format!("component_trampoline_calls_total{{{labels}}} 1\n")));
+        assert!(text.contains(&format!(
+            "component_trampoline_call_errors_total{{{labels}}} 1\n"
+        )));
+        assert!(text.contains(&format!(
+            "component_trampoline_call_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 1\n"
+        )));
+        assert!(text.contains(&format!(
+            "component_trampoline_call_duration_seconds_bucket{{{labels},le=\"5\"}} 1\n"
+        )));
+
+        metrics.function(&path, "get").record(Instant::now(), true);
+        metrics.function(&path, "set").record(Instant::now(), true);
+        metrics
+            .function(&path, "delete")
+            .record(Instant::now(), true);
+
+        let labels = MetricLabels::new([MetricLabel::Package, MetricLabel::Method])
+            .with_max_values(MetricLabel::Method, 2);
+        let text = metrics.encode_prometheus_with(&labels);
+
+        assert!(text.contains(
+            "component_trampoline_calls_total{package=\"test:kvstore\",method=\"other\"} 2\n"
+        ));
+        assert!(text.contains(
+            "component_trampoline_call_errors_total{package=\"test:kvstore\",method=\"get\"} 1\n"
+        ));
+        assert!(!text.contains("interface="));
+    }
+}
@@ -0,0 +1,103 @@
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The number of slots of the rolling windows of recent calls.
+const RECENT_SLOTS: u64 = 6;
+
+/// The number of calls to shadowed functions within about the last window, such as
+/// `RECENT_CALLS_WINDOW`.
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+pub struct RecentCalls {
+    pub calls: u64,
+    /// The number of calls that returned an error, from the guest or the trampoline.
+    pub errors: u64,
+}
+
+impl RecentCalls {
+    /// Returns the fraction of calls that failed, or `None` if there were no calls.
+    #[must_use]
+    pub fn error_rate(&self) -> Option<f64> {
+        (self.calls > 0).then(|| self.errors as f64 / self.calls as f64)
+    }
+}
+
+impl AddAssign for RecentCalls {
+    fn add_assign(&mut self, other: Self) {
+        self.calls += other.calls;
+        self.errors += other.errors;
+    }
+}
+
+/// Counts the calls and errors within about the last window, in slots each counting a fraction
+/// of the window.
+#[derive(Debug)]
+pub(crate) struct RecentWindow {
+    created: Instant,
+    slot_period: Duration,
+    slots: [RecentSlot; RECENT_SLOTS as usize],
+}
+
+/// The calls of one period of a rolling window of recent calls.
+#[derive(Default, Debug)]
+struct RecentSlot {
+    /// The period counted by the slot, numbered from 1 since the creation of the window, or 0
+    /// if unused.
+    period: AtomicU64,
+    calls: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl RecentWindow {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            created: Instant::now(),
+            slot_period: (window / RECENT_SLOTS as u32).max(Duration::from_nanos(1)),
+            slots: Default::default(),
+        }
+    }
+
+    pub(crate) fn record(&self, succeeded: bool) {
+        let period = self.period();
+        let slot = &self.slots[(period % RECENT_SLOTS) as usize];
+        let slot_period = slot.period.load(Ordering::Relaxed);
+
+        // Counts racing with the reset of an expired slot may be lost, which is acceptable for
+        // an estimate of recent calls.
+        if slot_period != period
+            && slot
+                .period
+                .compare_exchange(slot_period, period, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            slot.calls.store(0, Ordering::Relaxed);
+            slot.errors.store(0, Ordering::Relaxed);
+        }
+
+        slot.calls.fetch_add(1, Ordering::Relaxed);
+        if !succeeded {
+            slot.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn calls(&self) -> RecentCalls {
+        let oldest = self.period().saturating_sub(RECENT_SLOTS);
+
+        self.slots
+            .iter()
+            .filter(|slot| slot.period.load(Ordering::Relaxed) > oldest)
+            .map(|slot| RecentCalls {
+                calls: slot.calls.load(Ordering::Relaxed),
+                errors: slot.errors.load(Ordering::Relaxed),
+            })
+            .fold(RecentCalls::default(), |mut total, recent| {
+                total += recent;
+                total
+            })
+    }
+
+    fn period(&self) -> u64 {
+        let period = self.created.elapsed().as_nanos() / self.slot_period.as_nanos();
+        u64::try_from(period).unwrap_or(u64::MAX - 1) + 1
+    }
+}
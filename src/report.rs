@@ -0,0 +1,261 @@
+//! Integration with `miette` for rich terminal rendering of graph errors and diagnostics.
+
+use crate::{
+    AddPackageError, Diagnostic, InstantiateError, InstantiatePackageError, LoadPackageError,
+    Severity,
+};
+use miette::{LabeledSpan, SourceCode};
+use std::fmt::Display;
+
+type Labels<'a> = Option<Box<dyn Iterator<Item = LabeledSpan> + 'a>>;
+
+impl miette::Diagnostic for AddPackageError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(AddPackageError::code(self)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        help(Diagnostic::from(self))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        match self {
+            AddPackageError::DuplicatePackage { name, .. }
+            | AddPackageError::VerificationFailed { name, .. }
+            | AddPackageError::DeniedByPackagePolicy { name, .. } => Some(name),
+            AddPackageError::ImportParseError { interface, .. } => Some(interface),
+            AddPackageError::PackageParseError { .. }
+            | AddPackageError::InternalError { .. }
+            | AddPackageError::ReadError { .. }
+            | AddPackageError::PackageNotFound { .. }
+            | AddPackageError::FetchError { .. } => None,
+            #[cfg(feature = "preinit")]
+            AddPackageError::PreinitializeError { .. } => None,
+        }
+    }
+
+    fn labels(&self) -> Labels<'_> {
+        match self {
+            AddPackageError::DuplicatePackage { name, .. } => {
+                label(name, "already added with this version")
+            }
+            AddPackageError::VerificationFailed { name, .. } => {
+                label(name, "refused by the package verifier")
+            }
+            AddPackageError::DeniedByPackagePolicy { name, .. } => {
+                label(name, "denied by the package policy")
+            }
+            AddPackageError::ImportParseError { interface, .. } => {
+                label(interface, "not a valid interface path")
+            }
+            AddPackageError::PackageParseError { .. }
+            | AddPackageError::InternalError { .. }
+            | AddPackageError::ReadError { .. }
+            | AddPackageError::PackageNotFound { .. }
+            | AddPackageError::FetchError { .. } => None,
+            #[cfg(feature = "preinit")]
+            AddPackageError::PreinitializeError { .. } => None,
+        }
+    }
+}
+
+impl miette::Diagnostic for InstantiateError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(InstantiateError::code(self)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        help(Diagnostic::from(self))
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn miette::Diagnostic> {
+        match self {
+            InstantiateError::LoadPackageError { source } => Some(source),
+            InstantiateError::InstantiatePackageDependencyError { source, .. } => {
+                Some(source.as_ref())
+            }
+            InstantiateError::LazyPackageError { source } => Some(source),
+            InstantiateError::PackageNotFound { .. }
+            | InstantiateError::ComponentInstantiationError { .. }
+            | InstantiateError::ComponentCompilationError { .. }
+            | InstantiateError::GuestTrap { .. }
+            | InstantiateError::InterfaceTypeMismatch { .. }
+            | InstantiateError::UnpreparedPackage { .. } => None,
+        }
+    }
+}
+
+impl miette::Diagnostic for LoadPackageError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(LoadPackageError::code(self)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        help(Diagnostic::from(self))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        match self {
+            LoadPackageError::MissingPackageDependency { package_name, .. } => Some(package_name),
+            LoadPackageError::CannotResolvePackageVersion { name, .. }
+            | LoadPackageError::AmbiguousPackageProvider { name, .. } => Some(name),
+            LoadPackageError::DeniedByPolicy { importer, .. }
+            | LoadPackageError::DeniedByPackagePolicy { importer, .. } => Some(importer),
+            LoadPackageError::PackageCycle { .. } => None,
+        }
+    }
+
+    fn labels(&self) -> Labels<'_> {
+        match self {
+            LoadPackageError::MissingPackageDependency { package_name, .. } => {
+                label(package_name, "no package with this name in the graph")
+            }
+            LoadPackageError::CannotResolvePackageVersion { name, .. } => {
+                label(name, "no version of this package satisfies the import")
+            }
+            LoadPackageError::AmbiguousPackageProvider { name, .. } => {
+                label(name, "provided by multiple packages")
+            }
+            LoadPackageError::DeniedByPolicy { importer, .. } => {
+                label(importer, "not allowed to import the interface")
+            }
+            LoadPackageError::DeniedByPackagePolicy { importer, .. } => {
+                label(importer, "imports a package denied by the package policy")
+            }
+            LoadPackageError::PackageCycle { .. } => None,
+        }
+    }
+}
+
+impl miette::Diagnostic for InstantiatePackageError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(InstantiatePackageError::code(self)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        help(Diagnostic::from(self))
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn miette::Diagnostic> {
+        match self {
+            InstantiatePackageError::LazyPackageError { source } => Some(source),
+            _ => None,
+        }
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        match self {
+            InstantiatePackageError::InstanceMissingInterfaceExport { interface_name }
+            | InstantiatePackageError::InstanceMissingInterfaceFuncExport {
+                interface_name, ..
+            }
+            | InstantiatePackageError::ComponentFuncRetrievalError { interface_name, .. }
+            | InstantiatePackageError::UnsupportedResourceExport { interface_name } => {
+                Some(interface_name)
+            }
+            _ => None,
+        }
+    }
+
+    fn labels(&self) -> Labels<'_> {
+        match self {
+            InstantiatePackageError::InstanceMissingInterfaceExport { interface_name } => {
+                label(interface_name, "not exported by the instance")
+            }
+            InstantiatePackageError::InstanceMissingInterfaceFuncExport {
+                interface_name,
+                func_name,
+            }
+            | InstantiatePackageError::ComponentFuncRetrievalError {
+                interface_name,
+                func_name,
+            } => label(
+                interface_name,
+                &format!("function '{func_name}' is missing from this interface"),
+            ),
+            InstantiatePackageError::UnsupportedResourceExport { interface_name } => {
+                label(interface_name, "exports resources")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl miette::Diagnostic for Diagnostic {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(Diagnostic::code(self)))
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(match Diagnostic::severity(self) {
+            Severity::Note => miette::Severity::Advice,
+            Severity::Warning => miette::Severity::Warning,
+            Severity::Error => miette::Severity::Error,
+        })
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.suggestion()
+            .map(|suggestion| Box::new(suggestion) as Box<dyn Display>)
+    }
+}
+
+fn help<'a>(diagnostic: Diagnostic) -> Option<Box<dyn Display + 'a>> {
+    diagnostic
+        .suggestion()
+        .map(|suggestion| Box::new(suggestion.to_string()) as Box<dyn Display>)
+}
+
+fn label<'a>(source: &str, label: &str) -> Labels<'a> {
+    Some(Box::new(std::iter::once(LabeledSpan::at(
+        0..source.len(),
+        label,
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ForeignInterfacePath;
+    use miette::Diagnostic as MietteDiagnostic;
+
+    fn path() -> Box<ForeignInterfacePath> {
+        Box::new(ForeignInterfacePath::new(
+            "test:kvstore".to_string(),
+            "store".to_string(),
+            None,
+        ))
+    }
+
+    #[test]
+    fn test_error_labels() {
+        let err = LoadPackageError::MissingPackageDependency {
+            package_name: "test:kvstore".to_string(),
+            importer: "test:application@0.4.0".to_string(),
+            import: path(),
+        };
+
+        let code = MietteDiagnostic::code(&err).unwrap();
+        assert_eq!(code.to_string(), "WCT0202");
+        assert!(err.help().is_some());
+
+        let labels = err.labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].offset(), 0);
+        assert_eq!(labels[0].len(), "test:kvstore".len());
+    }
+
+    #[test]
+    fn test_nested_diagnostic_source() {
+        let err = InstantiateError::LoadPackageError {
+            source: LoadPackageError::MissingPackageDependency {
+                package_name: "test:kvstore".to_string(),
+                importer: "test:application@0.4.0".to_string(),
+                import: path(),
+            },
+        };
+
+        let source = err.diagnostic_source().unwrap();
+        assert_eq!(source.code().unwrap().to_string(), "WCT0202");
+    }
+}
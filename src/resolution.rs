@@ -0,0 +1,97 @@
+use crate::{CycleEdge, ForeignInterfacePath, LoadPackageError, PackageId};
+use semver::Version;
+
+/// A report of resolving the dependencies of a package without instantiating it, returned by
+/// `CompositionGraph::validate_package`.
+#[derive(Debug)]
+pub struct ResolutionReport {
+    /// The validated package.
+    pub package: PackageId,
+    /// The packages in the order `instantiate` would instantiate them, ending with the validated
+    /// package, or empty if any import is unresolved or the packages form a cycle.
+    pub load_order: Vec<PackageId>,
+    /// The resolved imports of the validated package and its transitive dependencies.
+    pub edges: Vec<ResolvedEdge>,
+    /// The imports that cannot be resolved, such as those of missing packages or versions.
+    pub unresolved: Vec<LoadPackageError>,
+    /// The import cycles among the dependencies, each as the edges along the cycle.
+    pub cycles: Vec<Vec<CycleEdge>>,
+}
+
+/// An import of a package resolved to the package providing it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResolvedEdge {
+    pub importer: PackageId,
+    pub import: ForeignInterfacePath,
+    /// The version of the providing package the import resolved to.
+    pub version: Version,
+    pub provider: PackageId,
+}
+
+impl ResolutionReport {
+    /// Returns whether all imports resolve without cycles, so that the package can be loaded.
+    #[must_use]
+    pub fn is_resolved(&self) -> bool {
+        self.unresolved.is_empty() && self.cycles.is_empty()
+    }
+
+    /// Returns the resolved imports of the package `importer`.
+    pub fn edges_of(&self, importer: PackageId) -> impl Iterator<Item = &ResolvedEdge> {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.importer == importer)
+    }
+
+    /// Returns the dependency packages that cannot be found, by name.
+    pub fn missing_packages(&self) -> impl Iterator<Item = &str> {
+        self.unresolved.iter().filter_map(|err| match err {
+            LoadPackageError::MissingPackageDependency { package_name, .. } => {
+                Some(package_name.as_str())
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompositionGraph, NoopTrampoline};
+
+    #[test]
+    fn test_resolution_report() {
+        let mut graph = CompositionGraph::<()>::new();
+        let package = graph
+            .add_package(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                b"\0asm\x0d\x00\x01\x00".to_vec(),
+                NoopTrampoline,
+            )
+            .unwrap();
+
+        let report = graph.validate_package(package).unwrap();
+        assert!(report.is_resolved());
+        assert_eq!(report.load_order, vec![package]);
+        assert_eq!(report.edges_of(package).count(), 0);
+
+        let report = ResolutionReport {
+            load_order: Vec::new(),
+            unresolved: vec![LoadPackageError::MissingPackageDependency {
+                package_name: "test:kvstore".to_string(),
+                importer: "test:app@1.0.0".to_string(),
+                import: Box::new(ForeignInterfacePath::new(
+                    "test:kvstore".to_string(),
+                    "store".to_string(),
+                    None,
+                )),
+            }],
+            ..report
+        };
+        assert!(!report.is_resolved());
+        assert_eq!(
+            report.missing_packages().collect::<Vec<_>>(),
+            ["test:kvstore"]
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! On-demand resolution of the packages that no package of a graph provides, such as from a warg
+//! component registry.
+//!
+//! The crate does not ship a registry client: hosts implement `PackageResolver` over the client
+//! of their registry, such as `warg-client`, which brings its own asynchronous runtime and
+//! keyring dependencies.
+
+use crate::{AddPackageError, DynPackageTrampoline, ForeignInterfacePath, InstantiateError};
+use semver::Version;
+use snafu::Snafu;
+use std::future::Future;
+use std::pin::Pin;
+
+/// The boxed future returned by `PackageResolver::resolve`.
+pub type ResolveFuture<'a, D, C> =
+    Pin<Box<dyn Future<Output = Result<Option<ResolvedPackage<D, C>>, anyhow::Error>> + Send + 'a>>;
+
+/// Fetches the packages that imports of a graph depend on but no package provides, for
+/// `CompositionGraph::resolve_missing_async` and `CompositionGraph::instantiate_resolving_async`.
+///
+/// Resolvers are typically clients of a component registry, such as a warg registry, that look up
+/// the package by name and download a release matching the version of the import.
+pub trait PackageResolver<D, C: Clone = ()>: Send + Sync {
+    /// Resolves the package `missing.name`, returning `None` if the resolver does not know it.
+    fn resolve<'a>(&'a self, missing: &'a MissingPackage) -> ResolveFuture<'a, D, C>;
+}
+
+/// A package dependency that no package of the graph provides.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingPackage {
+    /// The name of the missing package, such as `wasi:http`.
+    pub name: String,
+    /// The version of the first import of the package, if it is versioned.
+    pub version: Option<Version>,
+    /// The package importing the missing package, as `name@version`.
+    pub importer: String,
+    /// The first import of the missing package.
+    pub import: ForeignInterfacePath,
+}
+
+/// A package fetched by a `PackageResolver`, added to the graph with `add_package`.
+pub struct ResolvedPackage<D, C: Clone = ()> {
+    pub version: Version,
+    pub bytes: Vec<u8>,
+    pub trampoline: Box<dyn DynPackageTrampoline<D, C> + Send + Sync>,
+}
+
+impl<D, C: Clone> ResolvedPackage<D, C> {
+    pub fn new(
+        version: Version,
+        bytes: impl Into<Vec<u8>>,
+        trampoline: impl DynPackageTrampoline<D, C> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            version,
+            bytes: bytes.into(),
+            trampoline: Box::new(trampoline),
+        }
+    }
+}
+
+impl<D, C: Clone> std::fmt::Debug for ResolvedPackage<D, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResolvedPackage")
+            .field("version", &self.version)
+            .field("bytes", &self.bytes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Missing packages that cannot be resolved by `CompositionGraph::resolve_missing_async`, or
+/// packages that fail to instantiate once resolved by
+/// `CompositionGraph::instantiate_resolving_async`.
+#[derive(Snafu, Debug)]
+#[snafu(module, visibility(pub(crate)))]
+pub enum ResolveError {
+    #[snafu(display("Failed to resolve the dependencies of the package"))]
+    Dependencies { source: InstantiateError },
+
+    #[snafu(display("Failed to resolve package '{name}'"))]
+    Resolver { name: String, source: anyhow::Error },
+
+    #[snafu(display("Failed to add resolved package '{name}@{version}'"))]
+    AddPackage {
+        name: String,
+        version: Version,
+        source: AddPackageError,
+    },
+
+    #[snafu(display("Failed to instantiate the package"))]
+    Instantiate { source: InstantiateError },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ComponentFixture, FixtureFunc};
+    use crate::{CompositionGraph, LoadPackageError, NoopTrampoline};
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+    use wasmtime::component::Linker;
+    use wasmtime::{Config, Engine, Store};
+
+    struct FixtureResolver;
+
+    impl PackageResolver<()> for FixtureResolver {
+        fn resolve<'a>(&'a self, missing: &'a MissingPackage) -> ResolveFuture<'a, (), ()> {
+            Box::pin(async move {
+                if missing.name != "test:kvstore" {
+                    return Ok(None);
+                }
+
+                let bytes = ComponentFixture::new()
+                    .export("test:kvstore/store@1.0.0", [("get", FixtureFunc::Echo)])
+                    .to_bytes()?;
+
+                Ok(Some(ResolvedPackage::new(
+                    Version::new(1, 0, 0),
+                    bytes,
+                    NoopTrampoline,
+                )))
+            })
+        }
+    }
+
+    /// Completes a future that never waits, as the resolver and the graph's instantiation
+    /// without yielding trampolines only await ready futures.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = Context::from_waker(Waker::noop());
+
+        match pin!(future).poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future is pending"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_missing() {
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .import("test:logger/log@1.0.0", ["log"])
+            .export("test:app/run@1.0.0", [("run", FixtureFunc::Echo)])
+            .to_bytes()
+            .unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        let app = graph
+            .add_package(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                app_bytes,
+                NoopTrampoline,
+            )
+            .unwrap();
+
+        let added = block_on(graph.resolve_missing_async(app, &FixtureResolver)).unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(graph[added[0]].name(), "test:kvstore");
+        assert!(
+            block_on(graph.resolve_missing_async(app, &FixtureResolver))
+                .unwrap()
+                .is_empty()
+        );
+
+        let report = graph.validate_package(app).unwrap();
+        assert_eq!(
+            report.missing_packages().collect::<Vec<_>>(),
+            ["test:logger"]
+        );
+    }
+    #[test]
+    fn test_instantiate_resolving() {
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .export("test:app/run@1.0.0", [("run", FixtureFunc::Echo)])
+            .to_bytes()
+            .unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        let app = graph
+            .add_package(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                app_bytes,
+                NoopTrampoline,
+            )
+            .unwrap();
+
+        let mut config = Config::new();
+        config.async_support(true);
+        let engine = Engine::new(&config).unwrap();
+        let mut linker = Linker::new(&engine);
+        let mut store = Store::new(&engine, ());
+
+        assert!(matches!(
+            graph.plan(app),
+            Err(InstantiateError::LoadPackageError {
+                source: LoadPackageError::MissingPackageDependency { .. }
+            })
+        ));
+        block_on(graph.instantiate_resolving_async(
+            app,
+            &FixtureResolver,
+            &mut linker,
+            &mut store,
+            &engine,
+        ))
+        .unwrap();
+        assert_eq!(graph.packages().count(), 2);
+    }
+}
@@ -0,0 +1,360 @@
+//! Proxying of the resources exported by shadowed packages, so that resource handles can cross
+//! trampolined edges like any other value.
+//!
+//! Importing packages are linked against host resources standing in for the resources of the
+//! shadowed package, whose handles are kept in a table shared by the packages instantiated in a
+//! store. Handles are swapped for their proxies when returned by shadowed functions, and back
+//! when passed to them.
+
+use crate::PackageId;
+use slab::Slab;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex, PoisonError};
+use wac_types::{DefinedType, FuncType, ItemKind, Type, Types, ValueType};
+use wasmtime::AsContextMut;
+use wasmtime::component::{Resource, ResourceAny, ResourceType, Val};
+
+/// The host resource type standing in for the resources of shadowed packages.
+pub(crate) struct ShadowedResource;
+
+/// The handles of the resources of shadowed packages held for importing packages, in one store.
+#[derive(Default, Debug)]
+pub(crate) struct ShadowResources {
+    /// The handles, and the packages owning them, by the rep of their proxies.
+    handles: Mutex<Slab<(PackageId, ResourceAny)>>,
+    /// The resource types exported by shadowed packages, and the packages exporting them.
+    exported: Mutex<Vec<(ResourceType, PackageId)>>,
+}
+
+/// The resources of a shadowed package, as seen by the functions proxying them.
+#[derive(Clone, Debug)]
+pub(crate) struct PackageResources {
+    package: PackageId,
+    resources: Arc<ShadowResources>,
+}
+
+impl ShadowResources {
+    pub(crate) fn package(self: &Arc<Self>, package: PackageId) -> PackageResources {
+        PackageResources {
+            package,
+            resources: self.clone(),
+        }
+    }
+}
+
+impl PackageResources {
+    /// Records that handles of `ty` returned by the package are proxied.
+    pub(crate) fn export_type(&self, ty: ResourceType) {
+        let mut exported = self
+            .resources
+            .exported
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if !exported.iter().any(|(exported, _)| *exported == ty) {
+            exported.push((ty, self.package));
+        }
+    }
+
+    /// Removes the handle proxied by the owned proxy `rep`, once the importing package dropped
+    /// it.
+    pub(crate) fn take(&self, rep: u32) -> Option<ResourceAny> {
+        let mut handles = self
+            .resources
+            .handles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        handles.try_remove(rep as usize).map(|(_, handle)| handle)
+    }
+
+    /// Replaces the proxies of the package's handles in `arguments` with the handles, taking
+    /// ownership of the owned ones.
+    pub(crate) fn unwrap_arguments<'a>(
+        &self,
+        mut store: impl AsContextMut,
+        arguments: &'a [Val],
+    ) -> anyhow::Result<Cow<'a, [Val]>> {
+        let mut arguments = Cow::Borrowed(arguments);
+        let proxy = ResourceType::host::<ShadowedResource>();
+
+        for index in 0..arguments.len() {
+            if !contains_resource(&arguments[index], &mut |handle| handle.ty() == proxy) {
+                continue;
+            }
+
+            for_each_resource(&mut arguments.to_mut()[index], &mut |handle| {
+                if handle.ty() != proxy {
+                    return Ok(());
+                }
+
+                let owned = handle.owned();
+                let rep = handle
+                    .try_into_resource::<ShadowedResource>(&mut store)?
+                    .rep();
+                let mut handles = self
+                    .resources
+                    .handles
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+
+                match handles.get(rep as usize) {
+                    // Proxies of other packages are passed through, since the package imports
+                    // their resources like the caller.
+                    Some((package, _)) if *package != self.package => {
+                        *handle = if owned {
+                            Resource::<ShadowedResource>::new_own(rep)
+                        } else {
+                            Resource::<ShadowedResource>::new_borrow(rep)
+                        }
+                        .try_into_resource_any(&mut store)?;
+                    }
+                    Some(_) if owned => *handle = handles.remove(rep as usize).1,
+                    Some((_, proxied)) => *handle = *proxied,
+                    None => anyhow::bail!("unknown resource handle {rep}"),
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(arguments)
+    }
+
+    /// Replaces the handles of the package's resources in `results` with proxies.
+    pub(crate) fn wrap_results(
+        &self,
+        mut store: impl AsContextMut,
+        results: &mut [Val],
+    ) -> anyhow::Result<()> {
+        let exported = self
+            .resources
+            .exported
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        for result in results {
+            for_each_resource(result, &mut |handle| {
+                if !exported
+                    .iter()
+                    .any(|(ty, package)| *ty == handle.ty() && *package == self.package)
+                {
+                    return Ok(());
+                }
+
+                let rep = self
+                    .resources
+                    .handles
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert((self.package, *handle));
+                let rep = u32::try_from(rep)?;
+
+                *handle =
+                    Resource::<ShadowedResource>::new_own(rep).try_into_resource_any(&mut store)?;
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the names of the resources declared by an interface, excluding those it uses from
+/// other interfaces.
+pub(crate) fn declared_resources<'a>(
+    types: &'a Types,
+    exports: impl IntoIterator<Item = (&'a String, &'a ItemKind)>,
+) -> impl Iterator<Item = &'a str> {
+    exports
+        .into_iter()
+        .filter_map(move |(name, kind)| match kind {
+            ItemKind::Type(Type::Resource(id)) if types[*id].alias.is_none() => Some(name.as_str()),
+            _ => None,
+        })
+}
+
+/// Returns whether the parameters or results of `func` contain resource handles.
+pub(crate) fn func_has_resources(types: &Types, func: &FuncType) -> bool {
+    func.params
+        .values()
+        .chain(&func.result)
+        .any(|ty| has_resources(types, *ty))
+}
+
+fn has_resources(types: &Types, ty: ValueType) -> bool {
+    match ty {
+        ValueType::Primitive(_) => false,
+        ValueType::Borrow(_) | ValueType::Own(_) => true,
+        ValueType::Defined(id) => match &types[id] {
+            DefinedType::Tuple(tys) => tys.iter().any(|ty| has_resources(types, *ty)),
+            DefinedType::List(ty)
+            | DefinedType::FixedSizeList(ty, _)
+            | DefinedType::Option(ty)
+            | DefinedType::Alias(ty) => has_resources(types, *ty),
+            DefinedType::Result { ok, err } => {
+                ok.iter().chain(err).any(|ty| has_resources(types, *ty))
+            }
+            DefinedType::Variant(variant) => variant
+                .cases
+                .values()
+                .flatten()
+                .any(|ty| has_resources(types, *ty)),
+            DefinedType::Record(record) => {
+                record.fields.values().any(|ty| has_resources(types, *ty))
+            }
+            DefinedType::Flags(_)
+            | DefinedType::Enum(_)
+            | DefinedType::Stream(_)
+            | DefinedType::Future(_) => false,
+        },
+    }
+}
+
+fn contains_resource(value: &Val, predicate: &mut impl FnMut(&ResourceAny) -> bool) -> bool {
+    match value {
+        Val::Resource(handle) => predicate(handle),
+        Val::List(values) | Val::Tuple(values) => values
+            .iter()
+            .any(|value| contains_resource(value, predicate)),
+        Val::Record(fields) => fields
+            .iter()
+            .any(|(_, value)| contains_resource(value, predicate)),
+        Val::Variant(_, Some(value))
+        | Val::Option(Some(value))
+        | Val::Result(Ok(Some(value)) | Err(Some(value))) => contains_resource(value, predicate),
+        _ => false,
+    }
+}
+
+fn for_each_resource(
+    value: &mut Val,
+    f: &mut impl FnMut(&mut ResourceAny) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    match value {
+        Val::Resource(handle) => f(handle),
+        Val::List(values) | Val::Tuple(values) => values
+            .iter_mut()
+            .try_for_each(|value| for_each_resource(value, f)),
+        Val::Record(fields) => fields
+            .iter_mut()
+            .try_for_each(|(_, value)| for_each_resource(value, f)),
+        Val::Variant(_, Some(value))
+        | Val::Option(Some(value))
+        | Val::Result(Ok(Some(value)) | Err(Some(value))) => for_each_resource(value, f),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CompositionGraph, NoopTrampoline};
+    use semver::Version;
+    use wasmtime::component::{Linker, Val};
+    use wasmtime::{Config, Engine, Store};
+
+    /// Exports a `counter` resource holding the number it was constructed with.
+    const COUNTER_WAT: &str = r#"
+        (component
+          (type $counter (resource (rep i32)))
+          (core func $new (canon resource.new $counter))
+          (core module $m
+            (import "" "new" (func $new (param i32) (result i32)))
+            (func (export "ctor") (param i32) (result i32) local.get 0 call $new)
+            (func (export "get") (param i32) (result i32) local.get 0))
+          (core instance $i (instantiate $m (with "" (instance (export "new" (func $new))))))
+          (func $ctor (param "start" u32) (result (own $counter))
+            (canon lift (core func $i "ctor")))
+          (func $get (param "self" (borrow $counter)) (result u32)
+            (canon lift (core func $i "get")))
+          (component $api
+            (import "counter" (type $c (sub resource)))
+            (import "ctor" (func $ctor (param "start" u32) (result (own $c))))
+            (import "get" (func $get (param "self" (borrow $c)) (result u32)))
+            (export $e "counter" (type $c))
+            (export "[constructor]counter" (func $ctor)
+              (func (param "start" u32) (result (own $e))))
+            (export "[method]counter.get" (func $get)
+              (func (param "self" (borrow $e)) (result u32))))
+          (instance $api (instantiate $api
+            (with "counter" (type $counter))
+            (with "ctor" (func $ctor))
+            (with "get" (func $get))))
+          (export "test:counter/api@1.0.0" (instance $api)))
+    "#;
+
+    /// Constructs a counter, reads it and drops it.
+    const APP_WAT: &str = r#"
+        (component
+          (import "test:counter/api@1.0.0" (instance $api
+            (export "counter" (type $c (sub resource)))
+            (export "[constructor]counter" (func (param "start" u32) (result (own $c))))
+            (export "[method]counter.get" (func (param "self" (borrow $c)) (result u32)))))
+          (alias export $api "counter" (type $counter))
+          (core func $ctor (canon lower (func $api "[constructor]counter")))
+          (core func $get (canon lower (func $api "[method]counter.get")))
+          (core func $drop (canon resource.drop $counter))
+          (core module $m
+            (import "" "ctor" (func $ctor (param i32) (result i32)))
+            (import "" "get" (func $get (param i32) (result i32)))
+            (import "" "drop" (func $drop (param i32)))
+            (func (export "run") (param i32) (result i32) (local $h i32) (local $v i32)
+              local.get 0 call $ctor local.set $h
+              local.get $h call $get local.set $v
+              local.get $h call $drop
+              local.get $v))
+          (core instance $i (instantiate $m (with "" (instance
+            (export "ctor" (func $ctor))
+            (export "get" (func $get))
+            (export "drop" (func $drop))))))
+          (func $run (param "x" u32) (result u32) (canon lift (core func $i "run")))
+          (instance $run (export "run" (func $run)))
+          (export "test:app/run@1.0.0" (instance $run)))
+    "#;
+
+    #[test]
+    fn test_resource_proxies() {
+        let mut config = Config::new();
+        config.async_support(false);
+        let engine = Engine::new(&config).unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        let version = Version::new(1, 0, 0);
+        graph
+            .add_package(
+                "test:counter".to_string(),
+                version.clone(),
+                wat::parse_str(COUNTER_WAT).unwrap(),
+                NoopTrampoline,
+            )
+            .unwrap();
+        let app_id = graph
+            .add_package(
+                "test:app".to_string(),
+                version,
+                wat::parse_str(APP_WAT).unwrap(),
+                NoopTrampoline,
+            )
+            .unwrap();
+
+        let mut linker = Linker::new(&engine);
+        let mut store = Store::new(&engine, ());
+        let instance = graph
+            .instantiate(app_id, &mut linker, &mut store, &engine)
+            .unwrap();
+
+        let run = instance.get_export_index(&mut store, None, "test:app/run@1.0.0");
+        let run = instance
+            .get_export_index(&mut store, run.as_ref(), "run")
+            .unwrap();
+        let run = instance.get_func(&mut store, run).unwrap();
+
+        for value in [7, 11] {
+            let mut results = [Val::U32(0)];
+            run.call(&mut store, &[Val::U32(value)], &mut results)
+                .unwrap();
+            run.post_return(&mut store).unwrap();
+            assert_eq!(results, [Val::U32(value)]);
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! A harness bundling the engine, linker and graph of hosts that compose packages loaded from
+//! component files, so that they only define their host interfaces and trampolines.
+
+use crate::{CompositionGraph, Diagnostic, DynPackageTrampoline, InstantiateError, PackageId};
+use anyhow::{Context, ensure};
+use semver::Version;
+use std::path::{Path, PathBuf};
+use wasmtime::component::{Func, Instance, Linker, Val};
+use wasmtime::{AsContextMut, Config, Engine, Store};
+
+/// Composes a graph of packages loaded from component files, and instantiates and calls them.
+///
+/// The linker is shared by all instantiations, so host interfaces are added to it once with
+/// `GraphRunner::linker_mut`. Each instantiation links the shadowed interfaces into its own clone
+/// of the linker, so packages can be instantiated any number of times. Instantiation is synchronous unless the runner is created with
+/// `GraphRunner::new_async`.
+pub struct GraphRunner<D: 'static, C: Clone = ()> {
+    engine: Engine,
+    linker: Linker<D>,
+    graph: CompositionGraph<D, C>,
+    package_dir: Option<PathBuf>,
+}
+
+impl<D: 'static, C: Clone + Send + Sync + 'static> GraphRunner<D, C> {
+    /// Creates a runner with a synchronous engine.
+    pub fn new() -> anyhow::Result<Self> {
+        let mut config = Config::new();
+        config.wasm_component_model(true);
+        config.async_support(false);
+
+        Self::with_config(&config)
+    }
+
+    /// Creates a runner with an asynchronous engine, for graphs of asynchronous trampolines.
+    pub fn new_async() -> anyhow::Result<Self> {
+        let mut config = Config::new();
+        config.wasm_component_model(true);
+        config.async_support(true);
+
+        Self::with_config(&config)
+    }
+
+    /// Creates a runner with an engine configured by `config`.
+    pub fn with_config(config: &Config) -> anyhow::Result<Self> {
+        let engine = Engine::new(config)?;
+        let linker = Linker::new(&engine);
+
+        Ok(Self {
+            engine,
+            linker,
+            graph: CompositionGraph::new(),
+            package_dir: None,
+        })
+    }
+
+    /// Resolves the relative paths of the component files added with
+    /// `GraphRunner::add_package_file` against `dir`, such as a build artifacts directory.
+    #[must_use]
+    pub fn with_package_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.package_dir = Some(dir.into());
+        self
+    }
+
+    #[must_use]
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    #[must_use]
+    pub fn linker(&self) -> &Linker<D> {
+        &self.linker
+    }
+
+    /// Returns the linker, to add the host interfaces imported by the packages.
+    pub fn linker_mut(&mut self) -> &mut Linker<D> {
+        &mut self.linker
+    }
+
+    #[must_use]
+    pub fn graph(&self) -> &CompositionGraph<D, C> {
+        &self.graph
+    }
+
+    /// Returns the graph, to configure it or add packages from bytes.
+    pub fn graph_mut(&mut self) -> &mut CompositionGraph<D, C> {
+        &mut self.graph
+    }
+
+    /// Creates a store for instantiating packages, holding `data`.
+    #[must_use]
+    pub fn new_store(&self, data: D) -> Store<D> {
+        Store::new(&self.engine, data)
+    }
+
+    /// Adds the package in the component file at `path` to the graph.
+    pub fn add_package_file(
+        &mut self,
+        name: impl Into<String>,
+        version: Version,
+        path: impl AsRef<Path>,
+        trampoline: impl DynPackageTrampoline<D, C>,
+    ) -> anyhow::Result<PackageId> {
+        let path = match &self.package_dir {
+            Some(dir) => dir.join(path),
+            None => path.as_ref().to_path_buf(),
+        };
+        let name = name.into();
+
+        let bytes = std::fs::read(&path).with_context(|| {
+            format!(
+                "failed to read the component of '{name}@{version}' from '{}'",
+                path.display()
+            )
+        })?;
+
+        self.graph
+            .add_package(name, version, bytes, trampoline)
+            .context("failed to add package")
+    }
+
+    /// Checks the composition of the graph, returning its warnings, or an error listing its
+    /// diagnostics if any is an error.
+    pub fn validate(&self) -> anyhow::Result<Vec<Diagnostic>> {
+        let diagnostics = self.graph.validate();
+
+        ensure!(
+            !diagnostics.iter().any(Diagnostic::is_error),
+            "graph validation failed:\n{}",
+            diagnostics
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+
+        Ok(diagnostics)
+    }
+
+    /// Instantiates the package `package_id` and its dependencies in `store`, isolated from other
+    /// instantiations like `CompositionGraph::instantiate_isolated`.
+    pub fn instantiate(
+        &self,
+        package_id: PackageId,
+        store: impl AsContextMut<Data = D>,
+    ) -> Result<Instance, InstantiateError> {
+        self.graph
+            .instantiate_isolated(package_id, &self.linker, store, &self.engine)
+    }
+
+    /// Like `instantiate`, but for asynchronous runners.
+    pub async fn instantiate_async(
+        &self,
+        package_id: PackageId,
+        store: impl AsContextMut<Data = D>,
+    ) -> Result<Instance, InstantiateError>
+    where
+        D: Send,
+    {
+        self.graph
+            .instantiate_isolated_async(package_id, &self.linker, store, &self.engine)
+            .await
+    }
+
+    /// Instantiates the package `package_id` like `instantiate`, then binds its exports with
+    /// `bindings`, such as the `new` function generated by `wasmtime::component::bindgen!` for a
+    /// world.
+    pub fn instantiate_typed<T>(
+        &self,
+        package_id: PackageId,
+        store: &mut Store<D>,
+        bindings: impl FnOnce(&mut Store<D>, &Instance) -> wasmtime::Result<T>,
+    ) -> anyhow::Result<T> {
+        let instance = self.instantiate(package_id, &mut *store)?;
+        bindings(store, &instance)
+    }
+
+    /// Like `instantiate_typed`, but for asynchronous runners.
+    pub async fn instantiate_typed_async<T>(
+        &self,
+        package_id: PackageId,
+        store: &mut Store<D>,
+        bindings: impl FnOnce(&mut Store<D>, &Instance) -> wasmtime::Result<T>,
+    ) -> anyhow::Result<T>
+    where
+        D: Send,
+    {
+        let instance = self.instantiate_async(package_id, &mut *store).await?;
+        bindings(store, &instance)
+    }
+
+    /// Returns the function `function` exported by `instance`, such as to read its parameter
+    /// types.
+    ///
+    /// The function is named as `interface#function`, such as
+    /// `test:application/greeter@0.4.0#hello`, or as `function` if it is exported directly by the
+    /// component.
+    pub fn func(
+        &self,
+        store: impl AsContextMut<Data = D>,
+        instance: &Instance,
+        function: &str,
+    ) -> anyhow::Result<Func> {
+        exported_func(store, instance, function)
+    }
+
+    /// Calls the function `function` exported by `instance` with `args`, returning its results.
+    ///
+    /// The function is named as with `GraphRunner::func`.
+    pub fn call(
+        &self,
+        mut store: impl AsContextMut<Data = D>,
+        instance: &Instance,
+        function: &str,
+        args: &[Val],
+    ) -> anyhow::Result<Vec<Val>> {
+        let func = exported_func(&mut store, instance, function)?;
+
+        let mut results = vec![Val::Bool(false); func.results(&store).len()];
+        func.call(&mut store, args, &mut results)?;
+        func.post_return(&mut store)?;
+
+        Ok(results)
+    }
+
+    /// Like `call`, but for asynchronous runners.
+    pub async fn call_async(
+        &self,
+        mut store: impl AsContextMut<Data = D>,
+        instance: &Instance,
+        function: &str,
+        args: &[Val],
+    ) -> anyhow::Result<Vec<Val>>
+    where
+        D: Send,
+    {
+        let func = exported_func(&mut store, instance, function)?;
+
+        let mut results = vec![Val::Bool(false); func.results(&store).len()];
+        func.call_async(&mut store, args, &mut results).await?;
+        func.post_return_async(&mut store).await?;
+
+        Ok(results)
+    }
+}
+
+/// Returns the function of `instance` named `interface#function` or `function`.
+fn exported_func<D>(
+    mut store: impl AsContextMut<Data = D>,
+    instance: &Instance,
+    function: &str,
+) -> anyhow::Result<Func> {
+    let index = match function.split_once('#') {
+        Some((interface, function)) => {
+            let interface = instance
+                .get_export_index(&mut store, None, interface)
+                .with_context(|| format!("the component does not export '{interface}'"))?;
+            instance.get_export_index(&mut store, Some(&interface), function)
+        }
+        None => instance.get_export_index(&mut store, None, function),
+    }
+    .with_context(|| format!("the component does not export '{function}'"))?;
+
+    instance
+        .get_func(&mut store, index)
+        .with_context(|| format!("'{function}' is not a function"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoopTrampoline;
+    use crate::testing::{ComponentFixture, FixtureFunc};
+
+    /// A runner of an app forwarding its calls to a kvstore echoing its argument, added from
+    /// files written to a temporary `name` directory, which is removed once they are added.
+    fn runner(name: &str) -> (GraphRunner<()>, PackageId) {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let store_bytes = ComponentFixture::new()
+            .export("test:kvstore/store@1.0.0", [("get", FixtureFunc::Echo)])
+            .to_bytes()
+            .unwrap();
+        std::fs::write(dir.join("kvstore.component.wasm"), store_bytes).unwrap();
+
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .export(
+                "test:app/run@1.0.0",
+                [(
+                    "run",
+                    FixtureFunc::Forward {
+                        interface: "test:kvstore/store@1.0.0".to_string(),
+                        function: "get".to_string(),
+                    },
+                )],
+            )
+            .to_bytes()
+            .unwrap();
+        std::fs::write(dir.join("app.component.wasm"), app_bytes).unwrap();
+
+        let mut runner = GraphRunner::<()>::new().unwrap().with_package_dir(&dir);
+        let version = Version::new(1, 0, 0);
+        runner
+            .add_package_file(
+                "test:kvstore",
+                version.clone(),
+                "kvstore.component.wasm",
+                NoopTrampoline,
+            )
+            .unwrap();
+        let app_id = runner
+            .add_package_file("test:app", version, "app.component.wasm", NoopTrampoline)
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        (runner, app_id)
+    }
+
+    #[test]
+    fn test_graph_runner() {
+        let (runner, app_id) = runner("graph-runner");
+        assert!(runner.validate().unwrap().is_empty());
+
+        let mut store = runner.new_store(());
+        let instance = runner.instantiate(app_id, &mut store).unwrap();
+        let results = runner
+            .call(
+                &mut store,
+                &instance,
+                "test:app/run@1.0.0#run",
+                &[Val::U32(7)],
+            )
+            .unwrap();
+        assert_eq!(results, [Val::U32(7)]);
+
+        // Instantiating again does not redefine the shadowed interfaces in the runner's linker.
+        let mut other_store = runner.new_store(());
+        runner.instantiate(app_id, &mut other_store).unwrap();
+        runner.instantiate(app_id, &mut store).unwrap();
+    }
+
+    #[test]
+    fn test_add_missing_package_file() {
+        let (mut runner, _) = runner("graph-runner-missing-file");
+
+        assert!(
+            runner
+                .add_package_file(
+                    "test:missing",
+                    Version::new(1, 0, 0),
+                    "missing.component.wasm",
+                    NoopTrampoline
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_call_missing_function() {
+        let (runner, app_id) = runner("graph-runner-missing-function");
+
+        let mut store = runner.new_store(());
+        let instance = runner.instantiate(app_id, &mut store).unwrap();
+        assert!(
+            runner
+                .call(&mut store, &instance, "test:app/run@1.0.0#stop", &[])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,162 @@
+#[cfg(feature = "recording")]
+use crate::ForeignInterfacePath;
+
+#[cfg(feature = "recording")]
+/// Which calls to a shadowed function are recorded.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub enum SampleRate {
+    /// Record all calls.
+    #[default]
+    Always,
+
+    /// Record no calls.
+    Never,
+
+    /// Record the given fraction of calls, between 0 and 1, spread evenly over the calls.
+    Ratio(f64),
+
+    /// Record only the calls that failed.
+    ErrorsOnly,
+}
+
+#[cfg(feature = "recording")]
+/// The sampling of the calls recorded by a `CallTrace`, set with `CallTrace::with_sampling`.
+///
+/// Interfaces are sampled at the rate of the first override whose pattern matches their path,
+/// such as `wasi:*` or `*/store@1.*`, where `*` matches any sequence of characters, or at the
+/// default rate if none matches.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct CallSampling {
+    default: SampleRate,
+    overrides: Vec<(String, SampleRate)>,
+}
+
+#[cfg(feature = "recording")]
+impl CallSampling {
+    /// Creates a sampling recording calls at `default` rate.
+    #[must_use]
+    pub fn new(default: SampleRate) -> Self {
+        Self {
+            default,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Samples the interfaces matching `pattern` at `rate` instead, unless they match an
+    /// override added before.
+    #[must_use]
+    pub fn with_override(mut self, pattern: impl Into<String>, rate: SampleRate) -> Self {
+        self.overrides.push((pattern.into(), rate));
+        self
+    }
+
+    /// Returns the rate the calls to the functions of `interface` are sampled at.
+    #[must_use]
+    pub fn rate(&self, interface: &ForeignInterfacePath) -> SampleRate {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| matches_pattern(pattern, interface.as_str()))
+            .map_or(self.default, |(_, rate)| *rate)
+    }
+}
+
+#[cfg(feature = "recording")]
+impl SampleRate {
+    /// Returns whether to record the `index`th call to a function, counting from 0.
+    pub(crate) fn samples(self, index: u64, failed: bool) -> bool {
+        match self {
+            SampleRate::Always => true,
+            SampleRate::Never => false,
+            SampleRate::ErrorsOnly => failed,
+            SampleRate::Ratio(ratio) => {
+                // Records a call whenever the expected number of recorded calls reaches the next
+                // integer, so that sampled calls are evenly spaced.
+                let ratio = ratio.clamp(0.0, 1.0);
+                ((index + 1) as f64 * ratio).floor() > (index as f64 * ratio).floor()
+            }
+        }
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any sequence of characters.
+pub(crate) fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` in the pattern, and of the text it was matched against.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("wasi:*", "wasi:http/types@0.2.3"));
+        assert!(matches_pattern("*/store@1.*", "test:kvstore/store@1.2.0"));
+        assert!(matches_pattern("test:*/*", "test:kvstore/store"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("*/store@1.*", "test:kvstore/store@2.0.0"));
+        assert!(!matches_pattern("test:kvstore", "test:kvstore/store"));
+    }
+
+    #[test]
+    #[cfg(feature = "recording")]
+    fn test_call_sampling() {
+        use semver::Version;
+
+        let sampling = CallSampling::new(SampleRate::Ratio(0.25))
+            .with_override("wasi:*", SampleRate::Never)
+            .with_override("*/store@1.*", SampleRate::ErrorsOnly);
+
+        let path = |package: &str, interface: &str| {
+            ForeignInterfacePath::new(
+                package.to_string(),
+                interface.to_string(),
+                Some(Version::new(1, 2, 0)),
+            )
+        };
+
+        assert_eq!(
+            sampling.rate(&path("wasi:http", "types")),
+            SampleRate::Never
+        );
+        assert_eq!(
+            sampling.rate(&path("test:kvstore", "store")),
+            SampleRate::ErrorsOnly
+        );
+        assert_eq!(
+            sampling.rate(&path("test:kvstore", "cache")),
+            SampleRate::Ratio(0.25)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "recording")]
+    fn test_sample_rate() {
+        let sampled = (0..100)
+            .filter(|index| SampleRate::Ratio(0.25).samples(*index, false))
+            .count();
+        assert_eq!(sampled, 25);
+        assert!(SampleRate::ErrorsOnly.samples(0, true));
+        assert!(!SampleRate::ErrorsOnly.samples(0, false));
+    }
+}
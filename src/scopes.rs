@@ -0,0 +1,103 @@
+use crate::PackageLimits;
+use crate::in_flight::CallFrame;
+use crate::memory::Attribution;
+use crate::policy::CallDeadline;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// The packages being instantiated and the shadowed functions being called in a store, which the
+/// store's resource limiter reads to enforce `PackageLimits` or attribute memory growth in a
+/// `MemoryTracker`, and which identify the callers of calls tracked by `InFlightCalls`. Calls
+/// with a policy timeout also enter their deadline, which `CompositionGraph::enforce_timeouts`
+/// checks.
+///
+/// Keep one `StoreScopes` per store in its data, and tell the graph where to find it with
+/// `CompositionGraph::set_store_scopes`. Scopes are entered and left by the graph around
+/// instantiations and calls in the store, including across the await points of asynchronous
+/// calls, so they follow the store rather than the thread or task executing it.
+///
+/// Clones share the same scopes, so the limiter of the store can hold a clone.
+#[derive(Clone, Default, Debug)]
+pub struct StoreScopes {
+    pub(crate) limits: ScopeStack<PackageLimits>,
+    pub(crate) memory: ScopeStack<Attribution>,
+    pub(crate) calls: ScopeStack<CallFrame>,
+    pub(crate) deadlines: ScopeStack<CallDeadline>,
+}
+
+impl StoreScopes {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// The values of the scopes entered in a store, innermost last.
+#[derive(Debug)]
+pub(crate) struct ScopeStack<T>(Arc<Mutex<Vec<Arc<T>>>>);
+
+impl<T> Clone for ScopeStack<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for ScopeStack<T> {
+    fn default() -> Self {
+        Self(Arc::default())
+    }
+}
+
+impl<T> ScopeStack<T> {
+    /// Makes `value` the innermost value until the scope is dropped.
+    pub(crate) fn enter(&self, value: Arc<T>) -> Scope<T> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(value.clone());
+
+        Scope {
+            stack: self.clone(),
+            value,
+        }
+    }
+
+    /// Returns the value of the innermost scope, if any.
+    pub(crate) fn current(&self) -> Option<Arc<T>> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .last()
+            .cloned()
+    }
+
+    /// Returns the first result of `f` for the values of the scopes, outermost first.
+    pub(crate) fn find_map<R>(&self, f: impl FnMut(&Arc<T>) -> Option<R>) -> Option<R> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find_map(f)
+    }
+}
+
+/// A scope entered in a `ScopeStack`, left when dropped.
+///
+/// Scopes may be left in any order, as concurrent calls in the same store finish.
+#[derive(Debug)]
+pub(crate) struct Scope<T> {
+    stack: ScopeStack<T>,
+    value: Arc<T>,
+}
+
+impl<T> Drop for Scope<T> {
+    fn drop(&mut self) {
+        let mut stack = self.stack.0.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(index) = stack
+            .iter()
+            .rposition(|value| Arc::ptr_eq(value, &self.value))
+        {
+            stack.remove(index);
+        }
+    }
+}
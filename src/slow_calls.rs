@@ -0,0 +1,134 @@
+use crate::ForeignInterfacePath;
+use crate::sampling::matches_pattern;
+use derivative::Derivative;
+use std::sync::Arc;
+use std::time::Duration;
+
+type SlowCallCallback = Arc<dyn Fn(&SlowCall) + Send + Sync>;
+
+/// Invokes a callback for the calls to shadowed functions that take longer than a threshold,
+/// once set with `CompositionGraph::set_slow_call_detector`.
+///
+/// Interfaces use the threshold of the first override whose pattern matches their path, where
+/// `*` matches any sequence of characters like in `CallSampling`, or the default threshold if
+/// none matches. Calls are checked when they return, so hung calls are only visible in
+/// `InFlightCalls`.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub struct SlowCallDetector {
+    threshold: Option<Duration>,
+    overrides: Vec<(String, Option<Duration>)>,
+    #[derivative(Debug = "ignore")]
+    callback: SlowCallCallback,
+}
+
+/// A call to a shadowed function that exceeded its `SlowCallDetector` threshold.
+#[derive(Clone, Debug)]
+pub struct SlowCall {
+    pub interface: ForeignInterfacePath,
+    pub method: String,
+    pub elapsed: Duration,
+    pub threshold: Duration,
+    pub failed: bool,
+}
+
+impl SlowCallDetector {
+    /// Creates a detector invoking `callback` for the calls taking longer than `threshold`, or
+    /// for no calls if `threshold` is `None`, unless overridden.
+    pub fn new(
+        threshold: Option<Duration>,
+        callback: impl Fn(&SlowCall) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            threshold,
+            overrides: Vec::new(),
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// Uses `threshold` for the interfaces matching `pattern` instead, unless they match an
+    /// override added before. A `None` threshold disables detection for them.
+    #[must_use]
+    pub fn with_override(
+        mut self,
+        pattern: impl Into<String>,
+        threshold: Option<Duration>,
+    ) -> Self {
+        self.overrides.push((pattern.into(), threshold));
+        self
+    }
+
+    /// Returns the threshold of the calls to the functions of `interface`, if they are checked.
+    #[must_use]
+    pub fn threshold(&self, interface: &ForeignInterfacePath) -> Option<Duration> {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| matches_pattern(pattern, interface.as_str()))
+            .map_or(self.threshold, |(_, threshold)| *threshold)
+    }
+
+    /// Returns the handle a shadowed function checks its calls with, if they are checked.
+    pub(crate) fn function(
+        &self,
+        interface: &ForeignInterfacePath,
+        method: &str,
+    ) -> Option<SlowCallFunc> {
+        Some(SlowCallFunc {
+            threshold: self.threshold(interface)?,
+            function: Arc::new((interface.clone(), method.to_string())),
+            callback: self.callback.clone(),
+        })
+    }
+}
+
+/// A shadowed function checking its calls with a `SlowCallDetector`.
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
+pub(crate) struct SlowCallFunc {
+    threshold: Duration,
+    function: Arc<(ForeignInterfacePath, String)>,
+    #[derivative(Debug = "ignore")]
+    callback: SlowCallCallback,
+}
+
+impl SlowCallFunc {
+    pub(crate) fn check(&self, elapsed: Duration, succeeded: bool) {
+        if elapsed <= self.threshold {
+            return;
+        }
+
+        (self.callback)(&SlowCall {
+            interface: self.function.0.clone(),
+            method: self.function.1.clone(),
+            elapsed,
+            threshold: self.threshold,
+            failed: !succeeded,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_slow_call_detector() {
+        let slow_calls = Arc::new(Mutex::new(Vec::new()));
+        let detector = SlowCallDetector::new(Some(Duration::from_millis(100)), {
+            let slow_calls = slow_calls.clone();
+            move |call: &SlowCall| slow_calls.lock().unwrap().push(call.method.clone())
+        })
+        .with_override("wasi:*", None);
+
+        let path = ForeignInterfacePath::new("test:kvstore".to_string(), "store".to_string(), None);
+        let wasi = ForeignInterfacePath::new("wasi:io".to_string(), "streams".to_string(), None);
+        assert!(detector.function(&wasi, "read").is_none());
+
+        let get = detector.function(&path, "get").unwrap();
+        get.check(Duration::from_millis(50), true);
+        get.check(Duration::from_millis(150), false);
+
+        assert_eq!(*slow_calls.lock().unwrap(), ["get"]);
+    }
+}
@@ -0,0 +1,12 @@
+/// A source of component bytes, such as a registry, that packages are added from with
+/// `CompositionGraph::add_package_from_source`.
+pub trait PackageSource {
+    /// Fetches the component bytes identified by `reference`, such as a registry reference.
+    fn fetch(&self, reference: &str) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+impl<S: PackageSource + ?Sized> PackageSource for &S {
+    fn fetch(&self, reference: &str) -> Result<Vec<u8>, anyhow::Error> {
+        (**self).fetch(reference)
+    }
+}
@@ -0,0 +1,107 @@
+//! Strategies generating interface paths and import rules for property tests, with the
+//! `proptest` feature.
+
+use crate::{ForeignInterfacePath, ImportRule, InterfacePath};
+use proptest::arbitrary::Arbitrary;
+use proptest::prelude::*;
+pub use wasm_component_semver::strategies::{release_version, version, version_map};
+
+/// Generates kebab-case WIT identifiers.
+pub fn identifier() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9]{0,5}(-[a-z][a-z0-9]{0,5}){0,2}"
+}
+
+/// Generates WIT package names, as `namespace:name`.
+pub fn package_name() -> impl Strategy<Value = String> {
+    (identifier(), identifier()).prop_map(|(namespace, name)| format!("{namespace}:{name}"))
+}
+
+/// Generates foreign interface paths, with or without a version generated by `version`.
+pub fn foreign_interface_path() -> impl Strategy<Value = ForeignInterfacePath> {
+    (
+        package_name(),
+        identifier(),
+        proptest::option::of(version()),
+    )
+        .prop_map(|(package_name, interface_name, version)| {
+            ForeignInterfacePath::new(package_name, interface_name, version)
+        })
+}
+
+/// Generates interface paths, which are local without a version or foreign as with
+/// `foreign_interface_path`.
+pub fn interface_path() -> impl Strategy<Value = InterfacePath> {
+    prop_oneof![
+        1 => identifier().prop_map(|interface_name| InterfacePath::new(None, interface_name, None)),
+        3 => foreign_interface_path().prop_map(InterfacePath::from),
+    ]
+}
+
+impl Arbitrary for ImportRule {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with((): Self::Parameters) -> Self::Strategy {
+        prop_oneof![
+            Just(ImportRule::Skip),
+            Just(ImportRule::Include),
+            Just(ImportRule::Force),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for ForeignInterfacePath {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with((): Self::Parameters) -> Self::Strategy {
+        foreign_interface_path().boxed()
+    }
+}
+
+impl Arbitrary for InterfacePath {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with((): Self::Parameters) -> Self::Strategy {
+        interface_path().boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "regex")]
+    use crate::{ImportFilter, RegexMatchFilter};
+    use std::str::FromStr;
+
+    proptest! {
+        #[test]
+        fn test_interface_path_parsing(path in any::<InterfacePath>()) {
+            prop_assert_eq!(InterfacePath::from_str(&path.to_string()).unwrap(), path.clone());
+
+            if let Some(foreign) = path.clone().into_foreign() {
+                prop_assert_eq!(foreign.as_str(), path.to_string());
+            }
+        }
+
+        #[cfg(feature = "regex")]
+        #[test]
+        fn test_regex_match_filter(
+            package_name in package_name(),
+            rule in any::<ImportRule>(),
+            other in any::<ForeignInterfacePath>(),
+        ) {
+            let regex = regex::Regex::new(&format!("^{}/", regex::escape(&package_name))).unwrap();
+            let filter = RegexMatchFilter::new(regex, rule.clone());
+
+            let path = ForeignInterfacePath::new(package_name.clone(), "store".to_string(), None);
+            prop_assert_eq!(format!("{:?}", filter.filter_rule(&path)), format!("{rule:?}"));
+
+            if other.package_name() != package_name {
+                prop_assert!(matches!(filter.filter_rule(&other), ImportRule::Include));
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Stubs for imports that no package of a graph provides, so that partially assembled graphs can
+//! be instantiated, e.g. for testing.
+
+use crate::ForeignInterfacePath;
+use wasmtime::component::types::{ComponentFunc, ComponentInstance, ComponentItem};
+use wasmtime::component::{LinkerInstance, Type, Val};
+
+/// How the functions of imports that no package of the graph provides behave, once stubbed with
+/// `CompositionGraph::set_import_stubs`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImportStub {
+    /// Trap with an error naming the unresolved import and the called function.
+    #[default]
+    Trap,
+
+    /// Return the default value of the result type: zero, `false`, empty strings, lists and
+    /// flags, `none`, `ok` results, and the first case of variants and enums. Functions returning
+    /// resources, futures or streams trap like `Trap`.
+    Default,
+}
+
+impl ImportStub {
+    /// Defines the functions of `interface`, the type of the unresolved import `import`, that are
+    /// not defined in `instance` yet, skipping the names in `defined`.
+    pub(crate) fn define<D: 'static>(
+        self,
+        instance: &mut LinkerInstance<'_, D>,
+        engine: &wasmtime::Engine,
+        import: &ForeignInterfacePath,
+        interface: &ComponentInstance,
+        defined: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        for (name, item) in interface.exports(engine) {
+            let ComponentItem::ComponentFunc(func) = item else {
+                continue;
+            };
+            if defined.iter().any(|defined| defined == name) {
+                continue;
+            }
+
+            let results = match self {
+                ImportStub::Trap => None,
+                ImportStub::Default => default_results(&func),
+            };
+            let import = import.clone();
+            let function = name.to_string();
+
+            instance.func_new(name, move |_store, _params, values| match &results {
+                Some(results) => {
+                    values.clone_from_slice(results);
+                    Ok(())
+                }
+                None => Err(anyhow::anyhow!(
+                    "function '{function}' of '{import}' is a stub, since no package provides \
+                     the interface"
+                )),
+            })?;
+            defined.push(name.to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the default values of the results of `func`, if all of them have one.
+fn default_results(func: &ComponentFunc) -> Option<Vec<Val>> {
+    func.results().map(|ty| default_value(&ty)).collect()
+}
+
+/// Returns the default value of `ty`, if it has one.
+fn default_value(ty: &Type) -> Option<Val> {
+    Some(match ty {
+        Type::Bool => Val::Bool(false),
+        Type::S8 => Val::S8(0),
+        Type::U8 => Val::U8(0),
+        Type::S16 => Val::S16(0),
+        Type::U16 => Val::U16(0),
+        Type::S32 => Val::S32(0),
+        Type::U32 => Val::U32(0),
+        Type::S64 => Val::S64(0),
+        Type::U64 => Val::U64(0),
+        Type::Float32 => Val::Float32(0.0),
+        Type::Float64 => Val::Float64(0.0),
+        Type::Char => Val::Char('\0'),
+        Type::String => Val::String(String::new()),
+        Type::List(_) => Val::List(Vec::new()),
+        Type::Record(record) => Val::Record(
+            record
+                .fields()
+                .map(|field| Some((field.name.to_string(), default_value(&field.ty)?)))
+                .collect::<Option<_>>()?,
+        ),
+        Type::Tuple(tuple) => Val::Tuple(
+            tuple
+                .types()
+                .map(|ty| default_value(&ty))
+                .collect::<Option<_>>()?,
+        ),
+        Type::Variant(variant) => {
+            let case = variant.cases().next()?;
+            let payload = match &case.ty {
+                Some(ty) => Some(Box::new(default_value(ty)?)),
+                None => None,
+            };
+
+            Val::Variant(case.name.to_string(), payload)
+        }
+        Type::Enum(enum_) => Val::Enum(enum_.names().next()?.to_string()),
+        Type::Option(_) => Val::Option(None),
+        Type::Result(result) => {
+            let payload = match result.ok() {
+                Some(ty) => Some(Box::new(default_value(&ty)?)),
+                None => None,
+            };
+
+            Val::Result(Ok(payload))
+        }
+        Type::Flags(_) => Val::Flags(Vec::new()),
+        Type::Own(_) | Type::Borrow(_) | Type::Future(_) | Type::Stream(_) | Type::ErrorContext => {
+            return None;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ComponentFixture, FixtureFunc};
+    use crate::{CompositionGraph, InstantiateError, NoopTrampoline};
+    use semver::Version;
+    use wasmtime::component::Linker;
+    use wasmtime::{Engine, Store};
+
+    #[test]
+    fn test_import_stubs() {
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .export(
+                "test:app/run@1.0.0",
+                [(
+                    "run",
+                    FixtureFunc::Forward {
+                        interface: "test:kvstore/store@1.0.0".to_string(),
+                        function: "get".to_string(),
+                    },
+                )],
+            )
+            .to_bytes()
+            .unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        let app_id = graph
+            .add_package(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                app_bytes,
+                NoopTrampoline,
+            )
+            .unwrap();
+        assert!(!graph.validate().is_empty());
+
+        let engine = Engine::default();
+        let run = |graph: &CompositionGraph<()>| {
+            let mut store = Store::new(&engine, ());
+            let instance =
+                graph.instantiate_isolated(app_id, &Linker::new(&engine), &mut store, &engine)?;
+            let interface = instance.get_export_index(&mut store, None, "test:app/run@1.0.0");
+            let index = instance
+                .get_export_index(&mut store, interface.as_ref(), "run")
+                .unwrap();
+            let func = instance
+                .get_typed_func::<(u32,), (u32,)>(&mut store, &index)
+                .unwrap();
+
+            Ok::<_, InstantiateError>(func.call(&mut store, (41,)).map(|(result,)| result))
+        };
+
+        assert!(matches!(
+            run(&graph),
+            Err(InstantiateError::LoadPackageError { .. })
+        ));
+
+        graph.set_import_stubs(Some(ImportStub::Trap));
+        assert!(graph.validate().is_empty());
+        let err = run(&graph).unwrap().unwrap_err();
+        assert!(format!("{err:?}").contains("is a stub"));
+
+        graph.set_import_stubs(Some(ImportStub::Default));
+        assert_eq!(run(&graph).unwrap().unwrap(), 0);
+    }
+}
@@ -0,0 +1,500 @@
+//! Helpers for testing graphs and trampolines with tiny components synthesized in memory, so that
+//! tests do not need a `wasm32` build pipeline, and for comparing recorded calls against golden
+//! files.
+
+#[cfg(feature = "testing")]
+use crate::TraceEvent;
+use indexmap::IndexMap;
+#[cfg(feature = "testing")]
+use regex::Regex;
+#[cfg(feature = "testing")]
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The environment variable that makes `assert_golden` write the actual output to the golden
+/// file instead of comparing them, when set to `1`.
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// The behavior of a function of a `ComponentFixture`.
+///
+/// All fixture functions, exported or imported, have the WIT type `func(value: u32) -> u32`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FixtureFunc {
+    /// Returns its argument.
+    Echo,
+
+    /// Returns the given value.
+    Constant(u32),
+
+    /// Returns the number of calls to the function in the instance, including this one.
+    Counter,
+
+    /// Traps.
+    Trap,
+
+    /// Loops until interrupted, such as by an epoch deadline.
+    Spin,
+
+    /// Returns the result of calling `function` of the imported `interface` with its argument.
+    Forward { interface: String, function: String },
+}
+
+/// A component synthesized from WAT, exporting and importing interfaces of `FixtureFunc`s.
+///
+/// Interfaces are named by their full path, such as `test:kvstore/store@1.0.0`, so fixtures can
+/// be added to a `CompositionGraph` like any other package.
+#[derive(Clone, Default, Debug)]
+pub struct ComponentFixture {
+    imports: IndexMap<String, Vec<String>>,
+    exports: IndexMap<String, Vec<(String, FixtureFunc)>>,
+}
+
+impl ComponentFixture {
+    /// Creates a fixture without imports or exports.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Imports `functions` of `interface`.
+    #[must_use]
+    pub fn import<S: Into<String>>(
+        mut self,
+        interface: impl Into<String>,
+        functions: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.imports
+            .entry(interface.into())
+            .or_default()
+            .extend(functions.into_iter().map(Into::into));
+        self
+    }
+
+    /// Exports `functions` of `interface`, with their behaviors.
+    #[must_use]
+    pub fn export<S: Into<String>>(
+        mut self,
+        interface: impl Into<String>,
+        functions: impl IntoIterator<Item = (S, FixtureFunc)>,
+    ) -> Self {
+        self.exports.entry(interface.into()).or_default().extend(
+            functions
+                .into_iter()
+                .map(|(function, behavior)| (function.into(), behavior)),
+        );
+        self
+    }
+
+    /// Renders the component in the WebAssembly text format.
+    ///
+    /// Forwarding functions whose target is not imported reference an undefined function.
+    #[must_use]
+    pub fn to_wat(&self) -> String {
+        let mut wat = String::from("(component\n");
+
+        for (i, (interface, functions)) in self.imports.iter().enumerate() {
+            let _ = writeln!(wat, "  (import {interface:?} (instance $import{i}");
+            for function in functions {
+                let _ = writeln!(
+                    wat,
+                    "    (export {function:?} (func (param \"value\" u32) (result u32)))"
+                );
+            }
+            wat.push_str("  ))\n");
+
+            for (j, function) in functions.iter().enumerate() {
+                let _ = writeln!(
+                    wat,
+                    "  (core func $import{i}-{j} (canon lower (func $import{i} {function:?})))"
+                );
+            }
+        }
+
+        wat.push_str("  (core module $module\n");
+        for (i, (_, functions)) in self.imports.iter().enumerate() {
+            for j in 0..functions.len() {
+                let _ = writeln!(
+                    wat,
+                    "    (import \"imports\" \"import{i}-{j}\" (func $import{i}-{j} (param i32) \
+                     (result i32)))"
+                );
+            }
+        }
+        for (e, (_, functions)) in self.exports.iter().enumerate() {
+            for (k, (_, behavior)) in functions.iter().enumerate() {
+                let body = match behavior {
+                    FixtureFunc::Echo => "local.get 0".to_string(),
+                    FixtureFunc::Constant(value) => format!("i32.const {value}"),
+                    FixtureFunc::Counter => {
+                        let _ =
+                            writeln!(wat, "    (global $counter{e}-{k} (mut i32) (i32.const 0))");
+                        format!(
+                            "global.get $counter{e}-{k} i32.const 1 i32.add \
+                             global.set $counter{e}-{k} global.get $counter{e}-{k}"
+                        )
+                    }
+                    FixtureFunc::Trap => "unreachable".to_string(),
+                    FixtureFunc::Spin => "loop br 0 end unreachable".to_string(),
+                    FixtureFunc::Forward {
+                        interface,
+                        function,
+                    } => {
+                        let target =
+                            self.imports
+                                .get_full(interface)
+                                .and_then(|(i, _, functions)| {
+                                    let j = functions.iter().position(|name| name == function)?;
+                                    Some(format!("$import{i}-{j}"))
+                                });
+
+                        format!(
+                            "local.get 0 call {}",
+                            target.unwrap_or_else(|| "$missing-import".to_string())
+                        )
+                    }
+                };
+
+                let _ = writeln!(
+                    wat,
+                    "    (func (export \"export{e}-{k}\") (param i32) (result i32) {body})"
+                );
+            }
+        }
+        wat.push_str("  )\n");
+
+        if self.imports.is_empty() {
+            wat.push_str("  (core instance $instance (instantiate $module))\n");
+        } else {
+            wat.push_str("  (core instance $imports\n");
+            for (i, (_, functions)) in self.imports.iter().enumerate() {
+                for j in 0..functions.len() {
+                    let _ = writeln!(wat, "    (export \"import{i}-{j}\" (func $import{i}-{j}))");
+                }
+            }
+            wat.push_str("  )\n");
+            wat.push_str(
+                "  (core instance $instance (instantiate $module (with \"imports\" (instance \
+                 $imports))))\n",
+            );
+        }
+
+        for (e, (interface, functions)) in self.exports.iter().enumerate() {
+            for k in 0..functions.len() {
+                let _ = writeln!(
+                    wat,
+                    "  (func $export{e}-{k} (param \"value\" u32) (result u32) (canon lift (core \
+                     func $instance \"export{e}-{k}\")))"
+                );
+            }
+
+            let _ = writeln!(wat, "  (instance $export{e}");
+            for (k, (function, _)) in functions.iter().enumerate() {
+                let _ = writeln!(wat, "    (export {function:?} (func $export{e}-{k}))");
+            }
+            wat.push_str("  )\n");
+            let _ = writeln!(wat, "  (export {interface:?} (instance $export{e}))");
+        }
+
+        wat.push_str(")\n");
+        wat
+    }
+
+    /// Assembles the component into its binary format, as added to a `CompositionGraph`.
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        for (interface, functions) in &self.exports {
+            for (function, behavior) in functions {
+                if let FixtureFunc::Forward {
+                    interface: target_interface,
+                    function: target_function,
+                } = behavior
+                {
+                    let is_imported = self
+                        .imports
+                        .get(target_interface)
+                        .is_some_and(|functions| functions.contains(target_function));
+
+                    anyhow::ensure!(
+                        is_imported,
+                        "function '{function}' of '{interface}' forwards to \
+                         '{target_function}' of '{target_interface}', which is not imported"
+                    );
+                }
+            }
+        }
+
+        Ok(wat::parse_str(self.to_wat())?)
+    }
+}
+
+/// Renders the calls recorded by a `CallTrace` for comparison against golden files with
+/// `assert_golden`, normalized so that they are stable across runs.
+///
+/// Each call is rendered on its own line, in the order the calls returned, without their
+/// timestamps and durations. Threads are numbered in the order they first appear in the session,
+/// and the replacements are applied to the rendered session in the order they were added, to
+/// mask other values that vary across runs, such as ids in function names.
+#[cfg(feature = "testing")]
+#[derive(Clone, Default, Debug)]
+pub struct GoldenCalls {
+    replacements: Vec<(Regex, String)>,
+}
+
+#[cfg(feature = "testing")]
+impl GoldenCalls {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the matches of `regex` in the rendered session with `replacement`, which can
+    /// refer to capture groups like in `Regex::replace_all`.
+    #[must_use]
+    pub fn with_replacement(mut self, regex: Regex, replacement: impl Into<String>) -> Self {
+        self.replacements.push((regex, replacement.into()));
+        self
+    }
+
+    /// Renders `events`, normalized.
+    #[must_use]
+    pub fn render(&self, events: &[TraceEvent]) -> String {
+        let mut threads = HashMap::new();
+        let mut session = String::new();
+
+        for event in events {
+            let next_thread = threads.len() + 1;
+            let thread = *threads.entry(event.thread).or_insert(next_thread);
+            let outcome = if event.failed { "failed" } else { "ok" };
+
+            let _ = writeln!(
+                session,
+                "{} ({}) thread {thread} {outcome}",
+                event.name, event.package
+            );
+        }
+
+        for (regex, replacement) in &self.replacements {
+            session = regex
+                .replace_all(&session, replacement.as_str())
+                .into_owned();
+        }
+
+        session
+    }
+
+    /// Asserts that `events`, normalized, match the golden file at `path`, as with
+    /// `assert_golden`.
+    #[track_caller]
+    pub fn assert_matches(&self, path: impl AsRef<Path>, events: &[TraceEvent]) {
+        assert_golden(path, &self.render(events));
+    }
+}
+
+/// Asserts that `actual` matches the content of the golden file at `path`, panicking with a
+/// line diff between them otherwise.
+///
+/// When the `UPDATE_GOLDEN` environment variable is set to `1`, writes `actual` to the golden
+/// file instead, creating it if needed.
+#[track_caller]
+pub fn assert_golden(path: impl AsRef<Path>, actual: &str) {
+    let path = path.as_ref();
+
+    if std::env::var(UPDATE_GOLDEN_ENV).is_ok_and(|update| update == "1") {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap_or_else(|err| {
+                panic!(
+                    "failed to create the directory of '{}': {err}",
+                    path.display()
+                )
+            });
+        }
+
+        std::fs::write(path, actual).unwrap_or_else(|err| {
+            panic!("failed to write golden file '{}': {err}", path.display())
+        });
+        return;
+    }
+
+    let expected = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        panic!(
+            "failed to read golden file '{}': {err}\n\
+             rerun with {UPDATE_GOLDEN_ENV}=1 to create it",
+            path.display()
+        )
+    });
+
+    if expected != actual {
+        panic!(
+            "output does not match golden file '{}':\n{}\n\
+             rerun with {UPDATE_GOLDEN_ENV}=1 to update it",
+            path.display(),
+            diff_lines(&expected, actual)
+        );
+    }
+}
+
+/// Returns a line diff from `expected` to `actual`, where removed lines are prefixed with `-`,
+/// added lines with `+`, and unchanged lines with a space.
+#[must_use]
+pub fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    // The length of the longest common subsequence of the suffixes of the lines, so that the
+    // diff can be walked from the start.
+    let mut common = vec![vec![0_usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            let _ = writeln!(diff, " {}", expected[i]);
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            let _ = writeln!(diff, "-{}", expected[i]);
+            i += 1;
+        } else {
+            let _ = writeln!(diff, "+{}", actual[j]);
+            j += 1;
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompositionGraph, NoopTrampoline};
+    use semver::Version;
+    use wasmtime::component::Linker;
+    use wasmtime::{Engine, Store};
+
+    #[test]
+    fn test_component_fixtures() {
+        let store_bytes = ComponentFixture::new()
+            .export(
+                "test:kvstore/store@1.0.0",
+                [("get", FixtureFunc::Counter), ("echo", FixtureFunc::Echo)],
+            )
+            .to_bytes()
+            .unwrap();
+        let app_bytes = ComponentFixture::new()
+            .import("test:kvstore/store@1.0.0", ["get"])
+            .export(
+                "test:app/run@1.0.0",
+                [(
+                    "run",
+                    FixtureFunc::Forward {
+                        interface: "test:kvstore/store@1.0.0".to_string(),
+                        function: "get".to_string(),
+                    },
+                )],
+            )
+            .to_bytes()
+            .unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        let version = Version::new(1, 0, 0);
+        graph
+            .add_package(
+                "test:kvstore".to_string(),
+                version.clone(),
+                store_bytes,
+                NoopTrampoline,
+            )
+            .unwrap();
+        let app_id = graph
+            .add_package("test:app".to_string(), version, app_bytes, NoopTrampoline)
+            .unwrap();
+
+        let engine = Engine::default();
+        let mut linker = Linker::new(&engine);
+        let mut store = Store::new(&engine, ());
+        let instance = graph
+            .instantiate(app_id, &mut linker, &mut store, &engine)
+            .unwrap();
+
+        let interface = instance
+            .get_export_index(&mut store, None, "test:app/run@1.0.0")
+            .unwrap();
+        let run = instance
+            .get_export_index(&mut store, Some(&interface), "run")
+            .unwrap();
+        let run = instance
+            .get_typed_func::<(u32,), (u32,)>(&mut store, run)
+            .unwrap();
+
+        for expected in 1..=2 {
+            assert_eq!(run.call(&mut store, (7,)).unwrap(), (expected,));
+            run.post_return(&mut store).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fixture_missing_import() {
+        // Forwarding requires the fixture to import the interface.
+        let missing = ComponentFixture::new().export(
+            "test:app/run@1.0.0",
+            [(
+                "run",
+                FixtureFunc::Forward {
+                    interface: "test:kvstore/store@1.0.0".to_string(),
+                    function: "get".to_string(),
+                },
+            )],
+        );
+        assert!(missing.to_bytes().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn test_golden_calls() {
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let event = |name: &str, thread, failed| TraceEvent {
+            name: Arc::from(name),
+            package: Arc::from("test:kvstore@1.0.0"),
+            thread,
+            start: Duration::from_millis(thread),
+            duration: Duration::from_millis(1),
+            failed,
+        };
+        let events = [
+            event("test:kvstore/store@1.0.0#get-42", 7, false),
+            event("test:kvstore/store@1.0.0#set-43", 3, true),
+            event("test:kvstore/store@1.0.0#get-44", 7, false),
+        ];
+
+        let golden = GoldenCalls::new().with_replacement(Regex::new(r"-\d+ ").unwrap(), "-<id> ");
+        let session = golden.render(&events);
+        assert_eq!(
+            session,
+            "test:kvstore/store@1.0.0#get-<id> (test:kvstore@1.0.0) thread 1 ok\n\
+             test:kvstore/store@1.0.0#set-<id> (test:kvstore@1.0.0) thread 2 failed\n\
+             test:kvstore/store@1.0.0#get-<id> (test:kvstore@1.0.0) thread 1 ok\n"
+        );
+
+        let path = std::env::temp_dir().join(format!("golden-calls-{}.txt", std::process::id()));
+        std::fs::write(&path, &session).unwrap();
+        golden.assert_matches(&path, &events);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(diff_lines("a\nb\nc\n", "a\nc\nd\n"), " a\n-b\n c\n+d\n");
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{CallSampling, ForeignInterfacePath, SampleRate};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// A small number identifying the current thread in traces, since `ThreadId` has no stable
+    /// numeric representation.
+    static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A timeline of the calls to shadowed functions, recorded by the graph's trampolines once set
+/// with `CompositionGraph::set_call_trace`.
+///
+/// With the `json` feature, the timeline can be exported for trace viewers with
+/// `CallTrace::to_chrome_trace`. Calls are recorded until the trace is cleared, and can be
+/// sampled with `CallTrace::with_sampling` to bound the overhead of recording.
+///
+/// Clones share the same timeline.
+#[derive(Clone, Debug)]
+pub struct CallTrace {
+    epoch: Instant,
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+    sampling: Arc<CallSampling>,
+}
+
+/// A call to a shadowed function in a `CallTrace`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+    /// The called function, as `interface#method`.
+    pub name: Arc<str>,
+    /// The package exporting the function, as `name@version`.
+    pub package: Arc<str>,
+    /// The number of the thread the call returned on.
+    pub thread: u64,
+    /// The time the call started, relative to the creation of the trace.
+    pub start: Duration,
+    pub duration: Duration,
+    pub failed: bool,
+}
+
+impl Default for CallTrace {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            events: Arc::default(),
+            sampling: Arc::default(),
+        }
+    }
+}
+
+impl CallTrace {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records only the calls sampled by `sampling`, in the packages instantiated after it is
+    /// set.
+    #[must_use]
+    pub fn with_sampling(mut self, sampling: CallSampling) -> Self {
+        self.sampling = Arc::new(sampling);
+        self
+    }
+
+    /// Returns the sampling of the recorded calls.
+    #[must_use]
+    pub fn sampling(&self) -> &CallSampling {
+        &self.sampling
+    }
+
+    /// Returns the recorded calls, in the order they returned.
+    #[must_use]
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Removes all recorded calls.
+    pub fn clear(&self) {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Returns the handle a shadowed function records its calls with.
+    pub(crate) fn function(&self, interface: &ForeignInterfacePath, method: &str) -> TracedFunc {
+        let package = match interface.version() {
+            Some(version) => format!("{}@{version}", interface.package_name()),
+            None => interface.package_name().to_string(),
+        };
+
+        TracedFunc {
+            trace: self.clone(),
+            name: Arc::from(format!("{interface}#{method}")),
+            package: Arc::from(package),
+            rate: self.sampling.rate(interface),
+            calls: Arc::default(),
+        }
+    }
+}
+
+/// A shadowed function recording its calls in a `CallTrace`.
+#[derive(Clone, Debug)]
+pub(crate) struct TracedFunc {
+    trace: CallTrace,
+    name: Arc<str>,
+    package: Arc<str>,
+    rate: SampleRate,
+    /// The number of calls to the function, for sampling.
+    calls: Arc<AtomicU64>,
+}
+
+impl TracedFunc {
+    pub(crate) fn record(&self, started: Instant, succeeded: bool) {
+        let index = self.calls.fetch_add(1, Ordering::Relaxed);
+        if !self.rate.samples(index, !succeeded) {
+            return;
+        }
+
+        let event = TraceEvent {
+            name: self.name.clone(),
+            package: self.package.clone(),
+            thread: THREAD_ID.with(|id| *id),
+            start: started.saturating_duration_since(self.trace.epoch),
+            duration: started.elapsed(),
+            failed: !succeeded,
+        };
+
+        self.trace
+            .events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn test_call_trace() {
+        let trace = CallTrace::new();
+        let path = ForeignInterfacePath::new(
+            "test:kvstore".to_string(),
+            "store".to_string(),
+            Some(Version::new(1, 0, 0)),
+        );
+        let get = trace.function(&path, "get");
+
+        get.record(Instant::now(), true);
+        get.record(Instant::now(), false);
+
+        let events = trace.events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(&*events[0].name, "test:kvstore/store@1.0.0#get");
+        assert_eq!(&*events[0].package, "test:kvstore@1.0.0");
+        assert_eq!(events[0].thread, events[1].thread);
+        assert!(events[0].start <= events[1].start);
+        assert!(!events[0].failed && events[1].failed);
+
+        trace.clear();
+        assert!(trace.events().is_empty());
+
+        let sampled = CallTrace::new().with_sampling(
+            CallSampling::new(SampleRate::Always)
+                .with_override("test:kvstore/*", SampleRate::ErrorsOnly),
+        );
+        let get = sampled.function(&path, "get");
+
+        get.record(Instant::now(), true);
+        get.record(Instant::now(), false);
+        assert_eq!(sampled.events().len(), 1);
+        assert!(sampled.events()[0].failed);
+    }
+}
@@ -0,0 +1,115 @@
+//! Propagation of the host's trace context into guests, through an interface guests can import
+//! to correlate their logs with the host's spans.
+
+use crate::{StoreScopes, in_flight};
+use std::cell::RefCell;
+use std::sync::Arc;
+use wasmtime::component::Linker;
+
+/// The name of the interface providing the trace context to guests, defined by
+/// `TRACE_CONTEXT_WIT`.
+pub const TRACE_CONTEXT_INTERFACE: &str = "trampoline:trace-context/context@0.1.0";
+
+/// The WIT definition of `TRACE_CONTEXT_INTERFACE`, for guests to import.
+pub const TRACE_CONTEXT_WIT: &str = "package trampoline:trace-context@0.1.0;
+
+interface context {
+    /// The W3C `traceparent` of the host span the call is part of, if any.
+    traceparent: func() -> option<string>;
+
+    /// The id shared by the nested calls to shadowed functions the call is part of, if calls
+    /// are tracked in flight.
+    correlation-id: func() -> option<u64>;
+}
+";
+
+thread_local! {
+    static TRACEPARENT: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// Sets the W3C `traceparent` provided to guests for the calls made on the current thread, until
+/// the returned guard is dropped, which restores the previous one.
+///
+/// Like the call stacks of `CallProfiler`, the trace context is tracked per thread, so
+/// asynchronous hosts should enter it within the poll of the guest call it applies to.
+#[must_use = "the trace context is reset when the guard is dropped"]
+pub fn enter_trace_context(traceparent: impl Into<Arc<str>>) -> TraceContextGuard {
+    let previous = TRACEPARENT.replace(Some(traceparent.into()));
+
+    TraceContextGuard { previous }
+}
+
+/// Returns the W3C `traceparent` set on the current thread with `enter_trace_context`, if any.
+#[must_use]
+pub fn current_traceparent() -> Option<Arc<str>> {
+    TRACEPARENT.with_borrow(Clone::clone)
+}
+
+/// Restores the previous trace context of the thread when dropped.
+#[derive(Debug)]
+pub struct TraceContextGuard {
+    previous: Option<Arc<str>>,
+}
+
+impl Drop for TraceContextGuard {
+    fn drop(&mut self) {
+        TRACEPARENT.set(self.previous.take());
+    }
+}
+
+/// Defines `TRACE_CONTEXT_INTERFACE` in `linker`, so that guests importing it can read the trace
+/// context of their calls.
+///
+/// Graph packages importing the interface should skip it with the graph's import filter, since
+/// it is provided by the host rather than by a package.
+///
+/// Correlation ids are read from the `StoreScopes` of the calling store, found with `scopes` like
+/// `CompositionGraph::set_store_scopes`, and are `None` without them.
+pub fn add_trace_context_to_linker<D: 'static>(
+    linker: &mut Linker<D>,
+    scopes: Option<fn(&D) -> &StoreScopes>,
+) -> anyhow::Result<()> {
+    let mut instance = linker.instance(TRACE_CONTEXT_INTERFACE)?;
+
+    instance.func_wrap("traceparent", |_store, ()| {
+        Ok((current_traceparent().map(|traceparent| traceparent.to_string()),))
+    })?;
+    instance.func_wrap("correlation-id", move |store, ()| {
+        Ok((scopes.and_then(|scopes| in_flight::current_correlation_id(scopes(store.data()))),))
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::Engine;
+
+    #[test]
+    fn test_trace_context() {
+        assert_eq!(current_traceparent(), None);
+
+        let outer = enter_trace_context("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01");
+        {
+            let _inner =
+                enter_trace_context("00-0af7651916cd43dd8448eb211c80319c-00f067aa0ba902b7-01");
+            assert!(
+                current_traceparent()
+                    .unwrap()
+                    .ends_with("00f067aa0ba902b7-01")
+            );
+        }
+        assert!(
+            current_traceparent()
+                .unwrap()
+                .ends_with("b7ad6b7169203331-01")
+        );
+
+        drop(outer);
+        assert_eq!(current_traceparent(), None);
+
+        let mut linker = Linker::<()>::new(&Engine::default());
+        add_trace_context_to_linker(&mut linker, None).unwrap();
+    }
+}
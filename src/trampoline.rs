@@ -1,11 +1,14 @@
 use crate::path::ForeignInterfacePath;
+use crate::{InstantiatePackageError, PackageMetadata, ShadowFuncs};
 use derivative::Derivative;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt::{Debug, Display};
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::sync::Arc;
 use wac_types::FuncType;
-use wasmtime::component::{Func, Val};
+use wasmtime::component::{Func, LinkerInstance, Val};
 use wasmtime::{AsContext, AsContextMut, StoreContext, StoreContextMut};
 
 /// A trampoline is a mechanism to intercept WASM component function calls when switching
@@ -35,13 +38,14 @@ fn _assert_trampoline_object_safe(_object: &dyn Trampoline<()>) {
     unreachable!("only used for compile time assertion");
 }
 
+/// The boxed future returned by `AsyncTrampoline::bounce_async`, which implementations can name
+/// as their return type.
+pub type AsyncBounce<'c, D, C> =
+    Pin<Box<dyn Future<Output = Result<AsyncGuestResult<'c, D, C>, anyhow::Error>> + Send + 'c>>;
+
 /// Like `Trampoline`, but for asynchronous WASM function calls.
 pub trait AsyncTrampoline<D: Send, C: Send + Sync = ()>: Send + Sync + 'static {
-    fn bounce_async<'c>(
-        &'c self,
-        call: AsyncGuestCall<'c, D, C>,
-    ) -> Pin<Box<dyn Future<Output = Result<AsyncGuestResult<'c, D, C>, anyhow::Error>> + Send + 'c>>
-    {
+    fn bounce_async<'c>(&'c self, call: AsyncGuestCall<'c, D, C>) -> AsyncBounce<'c, D, C> {
         Box::pin(async move { call.call_async().await })
     }
 }
@@ -49,11 +53,7 @@ pub trait AsyncTrampoline<D: Send, C: Send + Sync = ()>: Send + Sync + 'static {
 impl<D: Send + 'static, C: Send + Sync + 'static> AsyncTrampoline<D, C>
     for Arc<dyn AsyncTrampoline<D, C>>
 {
-    fn bounce_async<'c>(
-        &'c self,
-        call: AsyncGuestCall<'c, D, C>,
-    ) -> Pin<Box<dyn Future<Output = Result<AsyncGuestResult<'c, D, C>, anyhow::Error>> + Send + 'c>>
-    {
+    fn bounce_async<'c>(&'c self, call: AsyncGuestCall<'c, D, C>) -> AsyncBounce<'c, D, C> {
         Box::pin(async move { self.deref().bounce_async(call).await })
     }
 }
@@ -72,6 +72,7 @@ pub struct GuestCallData<'c, D: 'static, C> {
     ty: &'c FuncType,
     arguments: &'c [Val],
     results: &'c mut [Val],
+    callee: Option<&'c PackageMetadata>,
 }
 
 impl<D: 'static, C> GuestCallData<'_, D, C> {
@@ -114,6 +115,136 @@ impl<D: 'static, C> GuestCallData<'_, D, C> {
     pub fn arguments(&self) -> &[Val] {
         self.arguments
     }
+
+    /// Returns the metadata of the package exporting the function being called, if it was added
+    /// with `CompositionGraph::add_package_with_metadata`.
+    ///
+    /// The calling package is not known, since all packages importing an interface call the same
+    /// shadowed functions.
+    #[must_use]
+    pub fn callee_metadata(&self) -> Option<&PackageMetadata> {
+        self.callee
+    }
+
+    /// Returns an empty `Val` buffer for use as scratch space, e.g. for the arguments or results
+    /// of nested calls. Buffers are pooled per thread, so their allocations are reused across
+    /// calls.
+    #[must_use]
+    pub fn scratch_vals(&self) -> ScratchVals {
+        ScratchVals::take()
+    }
+
+    fn call_error(
+        &self,
+        err: anyhow::Error,
+        fault: fn(ForeignInterfacePath, String) -> CallError,
+    ) -> anyhow::Error {
+        with_call_error(err, || fault(self.path.clone(), self.method.to_string()))
+    }
+}
+
+/// The maximum number of idle scratch buffers pooled per thread.
+const MAX_IDLE_SCRATCH_VALS: usize = 16;
+
+thread_local! {
+    static SCRATCH_VALS: RefCell<Vec<Vec<Val>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A scratch `Val` buffer taken from the current thread's pool, which is cleared and returned to
+/// the pool when dropped.
+#[derive(Debug, Default)]
+pub struct ScratchVals {
+    buffer: Vec<Val>,
+}
+
+impl ScratchVals {
+    fn take() -> Self {
+        let buffer = SCRATCH_VALS.with_borrow_mut(Vec::pop).unwrap_or_default();
+
+        Self { buffer }
+    }
+}
+
+impl Deref for ScratchVals {
+    type Target = Vec<Val>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for ScratchVals {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl Drop for ScratchVals {
+    fn drop(&mut self) {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+
+        SCRATCH_VALS.with_borrow_mut(|pool| {
+            if pool.len() < MAX_IDLE_SCRATCH_VALS && buffer.capacity() > 0 {
+                pool.push(buffer);
+            }
+        });
+    }
+}
+
+/// Identifies the side of a trampolined guest call that failed.
+///
+/// Errors returned by shadowed guest functions carry a `CallError` as context, which can be
+/// retrieved with `anyhow::Error::downcast_ref`. For nested calls, the innermost failing call is
+/// reported.
+#[derive(Clone, Debug)]
+pub enum CallError {
+    /// The called guest function failed, e.g. because it trapped. The guest needs fixing.
+    Guest {
+        interface: ForeignInterfacePath,
+        method: String,
+    },
+
+    /// The trampoline failed, without the guest function failing. The host needs fixing.
+    Trampoline {
+        interface: ForeignInterfacePath,
+        method: String,
+    },
+}
+
+impl CallError {
+    pub(crate) fn guest(interface: ForeignInterfacePath, method: String) -> Self {
+        CallError::Guest { interface, method }
+    }
+
+    pub(crate) fn trampoline(interface: ForeignInterfacePath, method: String) -> Self {
+        CallError::Trampoline { interface, method }
+    }
+}
+
+impl Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallError::Guest { interface, method } => {
+                write!(f, "Guest function '{interface}#{method}' failed")
+            }
+            CallError::Trampoline { interface, method } => {
+                write!(f, "Trampoline for '{interface}#{method}' failed")
+            }
+        }
+    }
+}
+
+/// Attaches a `CallError` to `err`, unless it already carries one from a nested call.
+pub(crate) fn with_call_error(
+    err: anyhow::Error,
+    call_error: impl FnOnce() -> CallError,
+) -> anyhow::Error {
+    if err.downcast_ref::<CallError>().is_some() {
+        err
+    } else {
+        err.context(call_error())
+    }
 }
 
 /// A guest call to a WASM component function, which must be executed synchronously.
@@ -131,7 +262,8 @@ impl<'c, D: 'static, C> GuestCall<'c, D, C> {
     /// the call.
     pub fn call(mut self) -> Result<GuestResult<'c, D, C>, anyhow::Error> {
         self.function
-            .call(&mut self.data.store, self.data.arguments, self.data.results)?;
+            .call(&mut self.data.store, self.data.arguments, self.data.results)
+            .map_err(|err| self.data.call_error(err, CallError::guest))?;
 
         Ok(GuestResult { context: self.data })
     }
@@ -167,7 +299,8 @@ impl<'c, D: Send, C> AsyncGuestCall<'c, D, C> {
     pub async fn call_async(mut self) -> Result<AsyncGuestResult<'c, D, C>, anyhow::Error> {
         self.function
             .call_async(&mut self.data.store, self.data.arguments, self.data.results)
-            .await?;
+            .await
+            .map_err(|err| self.data.call_error(err, CallError::guest))?;
 
         Ok(AsyncGuestResult { context: self.data })
     }
@@ -201,7 +334,10 @@ impl<D: 'static, C> GuestResult<'_, D, C> {
     }
 
     pub(crate) fn post_return(&mut self) -> Result<(), anyhow::Error> {
-        self.context.function.post_return(&mut self.context.store)
+        self.context
+            .function
+            .post_return(&mut self.context.store)
+            .map_err(|err| self.context.call_error(err, CallError::guest))
     }
 }
 
@@ -236,6 +372,7 @@ impl<D: Send + 'static, C> AsyncGuestResult<'_, D, C> {
             .function
             .post_return_async(&mut self.context.store)
             .await
+            .map_err(|err| self.context.call_error(err, CallError::guest))
     }
 }
 
@@ -339,6 +476,18 @@ pub struct InterfaceTrampoline<T, C> {
 }
 
 impl<T, C> InterfaceTrampoline<T, C> {
+    /// Returns a copy of the trampoline that uses `context` instead of its own context.
+    #[must_use]
+    pub fn with_context(&self, context: C) -> Self
+    where
+        T: Clone,
+    {
+        Self {
+            trampoline: self.trampoline.clone(),
+            context,
+        }
+    }
+
     /// Runs the specified function with the given arguments and results, using the trampoline for
     /// execution interception.
     #[allow(clippy::too_many_arguments)]
@@ -351,6 +500,7 @@ impl<T, C> InterfaceTrampoline<T, C> {
         ty: &'c FuncType,
         arguments: &'c [Val],
         results: &'c mut [Val],
+        callee: Option<&'c PackageMetadata>,
     ) -> Result<GuestResult<'c, D, C>, anyhow::Error>
     where
         T: Trampoline<D, C>,
@@ -365,6 +515,7 @@ impl<T, C> InterfaceTrampoline<T, C> {
                 ty,
                 arguments,
                 results,
+                callee,
             },
         })
     }
@@ -380,6 +531,7 @@ impl<T, C> InterfaceTrampoline<T, C> {
         ty: &'c FuncType,
         arguments: &'c [Val],
         results: &'c mut [Val],
+        callee: Option<&'c PackageMetadata>,
     ) -> Result<AsyncGuestResult<'c, D, C>, anyhow::Error>
     where
         D: Send + 'static,
@@ -397,6 +549,7 @@ impl<T, C> InterfaceTrampoline<T, C> {
                     ty,
                     arguments,
                     results,
+                    callee,
                 },
             })
             .await
@@ -409,6 +562,142 @@ impl<T, C> InterfaceTrampoline<T, C> {
 pub enum DynInterfaceTrampoline<D, C: Clone> {
     Sync(InterfaceTrampoline<Arc<dyn Trampoline<D, C>>, C>),
     Async(InterfaceTrampoline<Arc<dyn AsyncTrampoline<D, C>>, C>),
+
+    /// Calls are passed through to the guest without a trampoline, for both synchronous and
+    /// asynchronous calls.
+    Passthrough,
+
+    /// A trampoline of a statically known type, which is only type-erased when linking. Calls are
+    /// dispatched to it without going through a `dyn Trampoline`.
+    Static(Arc<dyn StaticInterfaceTrampoline<D, C>>),
+}
+
+impl<D, C: Clone> DynInterfaceTrampoline<D, C> {
+    /// Returns a copy of the trampoline that uses `context` instead of its own context.
+    /// `Passthrough` trampolines have no context, and are returned unchanged.
+    #[must_use]
+    pub fn with_context(&self, context: C) -> Self {
+        match self {
+            DynInterfaceTrampoline::Sync(trampoline) => {
+                DynInterfaceTrampoline::Sync(trampoline.with_context(context))
+            }
+            DynInterfaceTrampoline::Async(trampoline) => {
+                DynInterfaceTrampoline::Async(trampoline.with_context(context))
+            }
+            DynInterfaceTrampoline::Passthrough => DynInterfaceTrampoline::Passthrough,
+            DynInterfaceTrampoline::Static(trampoline) => {
+                DynInterfaceTrampoline::Static(trampoline.with_context(context))
+            }
+        }
+    }
+}
+
+/// Trampoline contexts overriding those of the graph's packages for a single instantiation, keyed
+/// by the path of the interface they apply to.
+///
+/// Overlays allow one graph to serve instantiations with request-scoped contexts, e.g. a tenant
+/// id, without changing the contexts the packages were added with.
+#[derive(Derivative)]
+#[derivative(
+    Clone(bound = "C: Clone"),
+    Default(bound = ""),
+    Debug(bound = "C: Debug")
+)]
+pub struct ContextOverlay<C> {
+    contexts: HashMap<ForeignInterfacePath, C>,
+}
+
+impl<C> ContextOverlay<C> {
+    /// Creates an overlay that overrides no contexts.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the context of the interface at `path`.
+    pub fn set(&mut self, path: ForeignInterfacePath, context: C) {
+        self.contexts.insert(path, context);
+    }
+
+    /// Like `set`, but consumes and returns the overlay.
+    #[must_use]
+    pub fn with(mut self, path: ForeignInterfacePath, context: C) -> Self {
+        self.set(path, context);
+        self
+    }
+
+    /// Returns the overriding context of the interface at `path`, if any.
+    #[must_use]
+    pub fn get(&self, path: &ForeignInterfacePath) -> Option<&C> {
+        self.contexts.get(path)
+    }
+
+    /// Removes the overriding context of the interface at `path`.
+    pub fn remove(&mut self, path: &ForeignInterfacePath) -> Option<C> {
+        self.contexts.remove(path)
+    }
+}
+
+/// An interface trampoline of a statically known type, which links shadowed functions with
+/// closures specialized for it.
+///
+/// Implemented for `InterfaceTrampoline`s of `StaticTrampoline` and `StaticAsyncTrampoline`.
+pub trait StaticInterfaceTrampoline<D, C>: Send + Sync {
+    /// Defines all `functions` of an interface in `instance`, calling the guest functions through
+    /// the trampoline.
+    ///
+    /// Fails with `InvalidTrampolineSynchronicity` if the trampoline is asynchronous and
+    /// `allow_async` is `false`.
+    fn link_interface(
+        &self,
+        instance: &mut LinkerInstance<D>,
+        functions: ShadowFuncs<'_, D>,
+        allow_async: bool,
+    ) -> Result<(), InstantiatePackageError>;
+
+    /// Returns a copy of the trampoline that uses `context` instead of its own context.
+    fn with_context(&self, context: C) -> Arc<dyn StaticInterfaceTrampoline<D, C>>;
+}
+
+/// Wraps a synchronous trampoline so that a `PackageTrampoline` of it dispatches calls statically,
+/// rather than through `Arc<dyn Trampoline>`.
+///
+/// Hosts with a single trampoline type can use it to avoid a virtual call per guest call.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct StaticTrampoline<T>(pub T);
+
+impl<D, C, T: Trampoline<D, C>> Trampoline<D, C> for StaticTrampoline<T> {
+    fn bounce<'c>(
+        &self,
+        call: GuestCall<'c, D, C>,
+    ) -> Result<GuestResult<'c, D, C>, anyhow::Error> {
+        self.0.bounce(call)
+    }
+}
+
+/// Like `StaticTrampoline`, but for asynchronous trampolines.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct StaticAsyncTrampoline<T>(pub T);
+
+impl<D: Send, C: Send + Sync, T: AsyncTrampoline<D, C>> AsyncTrampoline<D, C>
+    for StaticAsyncTrampoline<T>
+{
+    fn bounce_async<'c>(&'c self, call: AsyncGuestCall<'c, D, C>) -> AsyncBounce<'c, D, C> {
+        self.0.bounce_async(call)
+    }
+}
+
+/// A package trampoline that passes all calls through to the guest without interception.
+///
+/// Calls to packages using it skip building a `GuestCall` and dispatching it to a trampoline,
+/// which makes it the cheapest way to link interfaces that need no interception.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct NoopTrampoline;
+
+impl<D, C: Clone> DynPackageTrampoline<D, C> for NoopTrampoline {
+    fn interface_trampoline(&self, _interface_name: &str) -> DynInterfaceTrampoline<D, C> {
+        DynInterfaceTrampoline::Passthrough
+    }
 }
 
 /// A package-level trampoline factory for each interface name.
@@ -416,6 +705,12 @@ pub trait DynPackageTrampoline<D, C: Clone> {
     fn interface_trampoline(&self, interface_name: &str) -> DynInterfaceTrampoline<D, C>;
 }
 
+impl<D, C: Clone, T: DynPackageTrampoline<D, C> + ?Sized> DynPackageTrampoline<D, C> for Box<T> {
+    fn interface_trampoline(&self, interface_name: &str) -> DynInterfaceTrampoline<D, C> {
+        (**self).interface_trampoline(interface_name)
+    }
+}
+
 impl<D, C: Clone> DynPackageTrampoline<D, C> for PackageTrampoline<Arc<dyn Trampoline<D, C>>, C> {
     fn interface_trampoline(&self, interface_name: &str) -> DynInterfaceTrampoline<D, C> {
         DynInterfaceTrampoline::Sync(self.interface_trampoline(interface_name))
@@ -429,3 +724,25 @@ impl<D, C: Clone> DynPackageTrampoline<D, C>
         DynInterfaceTrampoline::Async(self.interface_trampoline(interface_name))
     }
 }
+
+impl<D, C, T> DynPackageTrampoline<D, C> for PackageTrampoline<StaticTrampoline<T>, C>
+where
+    C: Clone,
+    T: Clone,
+    InterfaceTrampoline<StaticTrampoline<T>, C>: StaticInterfaceTrampoline<D, C> + 'static,
+{
+    fn interface_trampoline(&self, interface_name: &str) -> DynInterfaceTrampoline<D, C> {
+        DynInterfaceTrampoline::Static(Arc::new(self.interface_trampoline(interface_name)))
+    }
+}
+
+impl<D, C, T> DynPackageTrampoline<D, C> for PackageTrampoline<StaticAsyncTrampoline<T>, C>
+where
+    C: Clone,
+    T: Clone,
+    InterfaceTrampoline<StaticAsyncTrampoline<T>, C>: StaticInterfaceTrampoline<D, C> + 'static,
+{
+    fn interface_trampoline(&self, interface_name: &str) -> DynInterfaceTrampoline<D, C> {
+        DynInterfaceTrampoline::Static(Arc::new(self.interface_trampoline(interface_name)))
+    }
+}
@@ -0,0 +1,446 @@
+//! Verification of the component bytes of packages against expected digests and embedded
+//! signatures, for `CompositionGraph::add_package_verified` and the package verifier of a graph.
+
+use crate::Sha256Digest;
+use semver::Version;
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
+use std::fmt::Debug;
+use std::sync::Arc;
+use wasmparser::{BinaryReader, BinaryReaderError, Chunk, CustomSectionReader, Parser, Payload};
+
+/// The name of the custom section holding the signature of a component, such as a sigstore
+/// bundle.
+pub const SIGNATURE_SECTION: &str = "signature";
+
+/// The length of the preamble of a component binary, i.e. its magic number, version and layer.
+const PREAMBLE_LEN: usize = 8;
+
+/// The id of custom sections in component binaries.
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// Verifies the signatures embedded in the `SIGNATURE_SECTION` of components, such as against
+/// a sigstore trust root.
+pub trait SignatureVerifier: Send + Sync {
+    /// Verifies that `signature` signs `payload`, which is the component without its signature
+    /// section.
+    fn verify_signature(&self, signature: &[u8], payload: &[u8]) -> Result<(), anyhow::Error>;
+}
+
+impl<F: Fn(&[u8], &[u8]) -> Result<(), anyhow::Error> + Send + Sync> SignatureVerifier for F {
+    fn verify_signature(&self, signature: &[u8], payload: &[u8]) -> Result<(), anyhow::Error> {
+        self(signature, payload)
+    }
+}
+
+/// Checks every package added to a graph before it is parsed, once set with
+/// `CompositionGraph::set_package_verifier`, such as enforcing cosign signatures or a policy of
+/// trusted publishers.
+///
+/// The verifier is called by `add_package` and the methods built on it, by `add_package_lazy`,
+/// and by `replace_package`. Returning an error refuses the package with
+/// `AddPackageError::VerificationFailed`.
+pub trait PackageVerifier: Send + Sync {
+    /// Verifies the component `bytes` of the package `name` at `version`.
+    fn verify_package(
+        &self,
+        name: &str,
+        version: &Version,
+        bytes: &[u8],
+    ) -> Result<(), anyhow::Error>;
+}
+
+impl<F: Fn(&str, &Version, &[u8]) -> Result<(), anyhow::Error> + Send + Sync> PackageVerifier
+    for F
+{
+    fn verify_package(
+        &self,
+        name: &str,
+        version: &Version,
+        bytes: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        self(name, version, bytes)
+    }
+}
+
+/// The checks a package must pass to be added with `CompositionGraph::add_package_verified`.
+#[derive(Clone, Default)]
+pub struct PackageVerification {
+    digest: Option<Sha256Digest>,
+    signature_verifier: Option<Arc<dyn SignatureVerifier>>,
+}
+
+impl Debug for PackageVerification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PackageVerification")
+            .field("digest", &self.digest)
+            .field("signed", &self.signature_verifier.is_some())
+            .finish()
+    }
+}
+
+impl PackageVerification {
+    /// Creates a verification accepting any package.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires the component bytes to have the digest `digest`.
+    #[must_use]
+    pub fn with_digest(mut self, digest: Sha256Digest) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    /// Requires the component to embed a signature in its `SIGNATURE_SECTION` that `verifier`
+    /// accepts.
+    #[must_use]
+    pub fn with_signature_verifier(mut self, verifier: impl SignatureVerifier + 'static) -> Self {
+        self.signature_verifier = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Verifies the component `bytes`.
+    pub fn verify(&self, bytes: &[u8]) -> Result<(), VerificationError> {
+        if let Some(expected) = self.digest {
+            let actual = Sha256Digest::of(bytes);
+            if actual != expected {
+                return Err(VerificationError::DigestMismatch { expected, actual });
+            }
+        }
+
+        if let Some(verifier) = &self.signature_verifier {
+            let (signature, payload) = split_signature(bytes)?;
+            verifier
+                .verify_signature(signature, &payload)
+                .map_err(|source| VerificationError::InvalidSignature { source })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Verifies every package alike, such as against the signature verifier of a trust root.
+impl PackageVerifier for PackageVerification {
+    fn verify_package(
+        &self,
+        _name: &str,
+        _version: &Version,
+        bytes: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        Ok(self.verify(bytes)?)
+    }
+}
+
+/// A package refused by its `PackageVerification`.
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum VerificationError {
+    #[snafu(display("Package digest {actual} does not match the expected digest {expected}"))]
+    DigestMismatch {
+        expected: Sha256Digest,
+        actual: Sha256Digest,
+    },
+
+    #[snafu(display("Package has no '{SIGNATURE_SECTION}' custom section"))]
+    MissingSignature,
+
+    #[snafu(display("Package has more than one '{SIGNATURE_SECTION}' custom section"))]
+    DuplicateSignature,
+
+    #[snafu(display("Package is not a component binary"))]
+    NotAComponent,
+
+    #[snafu(display("Package is not a well-formed component binary"))]
+    Malformed { source: BinaryReaderError },
+
+    #[snafu(display("Package signature is invalid"))]
+    InvalidSignature { source: anyhow::Error },
+}
+
+/// Splits the component `bytes` into the contents of its top-level `SIGNATURE_SECTION` and the
+/// component without that section.
+fn split_signature(bytes: &[u8]) -> Result<(&[u8], Vec<u8>), VerificationError> {
+    let sections = sections(bytes)
+        .context(verification_error::MalformedSnafu)?
+        .context(verification_error::NotAComponentSnafu)?;
+
+    let mut payload = bytes[..PREAMBLE_LEN].to_vec();
+    let mut signature = None;
+
+    for section in sections {
+        if section.id == CUSTOM_SECTION_ID {
+            let reader = CustomSectionReader::new(BinaryReader::new(
+                section.contents,
+                section.contents_offset,
+            ))
+            .context(verification_error::MalformedSnafu)?;
+
+            if reader.name() == SIGNATURE_SECTION {
+                ensure!(
+                    signature.is_none(),
+                    verification_error::DuplicateSignatureSnafu
+                );
+                signature = Some(reader.data());
+                continue;
+            }
+        }
+
+        payload.extend_from_slice(section.bytes);
+    }
+
+    let signature = signature.context(verification_error::MissingSignatureSnafu)?;
+
+    Ok((signature, payload))
+}
+
+/// A top-level section of a component binary.
+pub(crate) struct Section<'a> {
+    pub(crate) id: u8,
+    /// The encoded section, including its id and size.
+    pub(crate) bytes: &'a [u8],
+    pub(crate) contents: &'a [u8],
+    /// The offset of `contents` in the component binary.
+    pub(crate) contents_offset: usize,
+}
+
+/// Returns the top-level sections of the component `bytes`, or `None` if they are not a
+/// component binary, such as a core module.
+///
+/// Nested core modules and components are returned as single sections without being parsed.
+pub(crate) fn sections(bytes: &[u8]) -> Result<Option<Vec<Section<'_>>>, BinaryReaderError> {
+    if !Parser::is_component(bytes) {
+        return Ok(None);
+    }
+
+    let mut parser = Parser::new(0);
+    let mut sections = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let (consumed, payload) = match parser.parse(&bytes[offset..], true)? {
+            Chunk::Parsed { consumed, payload } => (consumed, payload),
+            // Not returned once all bytes are given, as the end of the input is then an error.
+            Chunk::NeedMoreData(_) => return Ok(None),
+        };
+        let start = offset;
+        offset += consumed;
+
+        if let Payload::End(_) = payload {
+            return Ok(Some(sections));
+        }
+
+        let Some((id, range)) = payload.as_section() else {
+            continue;
+        };
+        // The parser only consumes the header of nested core modules and components, and
+        // resumes after their contents.
+        offset = offset.max(range.end);
+
+        sections.push(Section {
+            id,
+            bytes: &bytes[start..offset],
+            contents_offset: range.start,
+            contents: &bytes[range],
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AddPackageError, CompositionGraph, NoopTrampoline};
+    use semver::Version;
+
+    /// The preamble of an empty component.
+    const EMPTY_COMPONENT: &[u8] = b"\0asm\x0d\x00\x01\x00";
+
+    fn custom_section(name: &str, data: &[u8]) -> Vec<u8> {
+        let contents = [&[name.len() as u8], name.as_bytes(), data].concat();
+        [
+            &[CUSTOM_SECTION_ID, contents.len() as u8],
+            contents.as_slice(),
+        ]
+        .concat()
+    }
+
+    /// A verification accepting signatures that are the digest of the payload.
+    fn signed_by_digest() -> PackageVerification {
+        PackageVerification::new().with_signature_verifier(|signature: &[u8], payload: &[u8]| {
+            anyhow::ensure!(signature == Sha256Digest::of(payload).as_bytes());
+            Ok(())
+        })
+    }
+
+    /// The empty component signed with the digest of `payload`.
+    fn signed(payload: &[u8]) -> Vec<u8> {
+        let digest = Sha256Digest::of(payload);
+        [
+            EMPTY_COMPONENT,
+            &custom_section(SIGNATURE_SECTION, digest.as_bytes()),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_digest_verification() {
+        let digest = Sha256Digest::of(EMPTY_COMPONENT);
+        let verification = PackageVerification::new().with_digest(digest);
+
+        assert!(verification.verify(EMPTY_COMPONENT).is_ok());
+        assert!(matches!(
+            verification.verify(&signed(EMPTY_COMPONENT)),
+            Err(VerificationError::DigestMismatch { expected, .. }) if expected == digest
+        ));
+    }
+
+    #[test]
+    fn test_signature_verification() {
+        let verification = signed_by_digest();
+        assert!(verification.verify(&signed(EMPTY_COMPONENT)).is_ok());
+        assert!(matches!(
+            verification.verify(EMPTY_COMPONENT),
+            Err(VerificationError::MissingSignature)
+        ));
+
+        // The payload keeps the other custom sections.
+        let producers = custom_section("producers", b"x");
+        let component = [signed(EMPTY_COMPONENT).as_slice(), &producers].concat();
+        assert!(matches!(
+            verification.verify(&component),
+            Err(VerificationError::InvalidSignature { .. })
+        ));
+
+        let payload = [EMPTY_COMPONENT, &producers].concat();
+        let component = [signed(&payload).as_slice(), &producers].concat();
+        assert!(verification.verify(&component).is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_signature() {
+        let signature = custom_section(
+            SIGNATURE_SECTION,
+            Sha256Digest::of(EMPTY_COMPONENT).as_bytes(),
+        );
+        let component = [EMPTY_COMPONENT, &signature, &signature].concat();
+
+        assert!(matches!(
+            signed_by_digest().verify(&component),
+            Err(VerificationError::DuplicateSignature)
+        ));
+    }
+
+    #[test]
+    fn test_malformed_signed_component() {
+        let verification = signed_by_digest();
+        let verify = |bytes: &[u8]| verification.verify(bytes).unwrap_err();
+
+        // Core modules and other preambles are not components.
+        assert!(matches!(
+            verify(b"\0asm\x01\x00\x00\x00"),
+            VerificationError::NotAComponent
+        ));
+        assert!(matches!(
+            verify(b"\0asm\x0d\x00\x02\x00"),
+            VerificationError::NotAComponent
+        ));
+        assert!(matches!(verify(b"\0asm"), VerificationError::NotAComponent));
+
+        // A section size with bits beyond 32 bits set is not truncated.
+        let overflowing = [
+            EMPTY_COMPONENT,
+            &[CUSTOM_SECTION_ID, 0xff, 0xff, 0xff, 0xff, 0x7f],
+        ]
+        .concat();
+        assert!(matches!(
+            verify(&overflowing),
+            VerificationError::Malformed { .. }
+        ));
+
+        // Sections may not extend past the end of the component.
+        let signed = signed(EMPTY_COMPONENT);
+        assert!(matches!(
+            verify(&signed[..signed.len() - 1]),
+            VerificationError::Malformed { .. }
+        ));
+
+        // Nor may custom section names.
+        let truncated_name = [EMPTY_COMPONENT, &[CUSTOM_SECTION_ID, 1, 9]].concat();
+        assert!(matches!(
+            verify(&truncated_name),
+            VerificationError::Malformed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_add_package_verified() {
+        let mut graph = CompositionGraph::<()>::new();
+        let version = Version::new(1, 0, 0);
+        let signed = signed(EMPTY_COMPONENT);
+        let verification = signed_by_digest();
+
+        let mismatch = graph.add_package_verified(
+            "test:signed".to_string(),
+            version.clone(),
+            signed.clone(),
+            &verification
+                .clone()
+                .with_digest(Sha256Digest::of(EMPTY_COMPONENT)),
+            NoopTrampoline,
+        );
+        assert!(matches!(
+            mismatch,
+            Err(AddPackageError::VerificationFailed { source, .. })
+                if matches!(
+                    source.downcast_ref(),
+                    Some(VerificationError::DigestMismatch { .. })
+                )
+        ));
+
+        graph
+            .add_package_verified(
+                "test:signed".to_string(),
+                version,
+                signed,
+                &verification,
+                NoopTrampoline,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_package_verifier() {
+        let mut graph = CompositionGraph::<()>::new();
+        graph.set_package_verifier(|name: &str, _: &Version, _: &[u8]| {
+            anyhow::ensure!(name.starts_with("trusted:"), "untrusted publisher");
+            Ok(())
+        });
+
+        let mut add = |name: &str| {
+            graph.add_package(
+                name.to_string(),
+                Version::new(1, 0, 0),
+                EMPTY_COMPONENT,
+                NoopTrampoline,
+            )
+        };
+        add("trusted:app").unwrap();
+
+        let err = add("test:app").unwrap_err();
+        assert!(
+            matches!(&err, AddPackageError::VerificationFailed { name, .. } if name == "test:app")
+        );
+        assert_eq!(err.code(), "WCT0010");
+        assert!(
+            graph
+                .add_package_lazy(
+                    "test:lazy".to_string(),
+                    Version::new(1, 0, 0),
+                    EMPTY_COMPONENT,
+                    NoopTrampoline,
+                )
+                .is_err()
+        );
+        assert_eq!(graph.packages().count(), 1);
+    }
+}
@@ -0,0 +1,119 @@
+//! Helpers for trampolines intercepting the `wasi:http` handler interfaces exported by packages,
+//! such as HTTP middleware wrapping a handler or an outgoing HTTP client.
+//!
+//! The requests and responses of `wasi:http` are resources, which are proxied across shadowed
+//! edges like the resources of any other interface, so trampolines see the handles of the
+//! exporting package.
+
+use crate::{ForeignInterfacePath, GuestCallData};
+use wasmtime::component::{ResourceAny, Val};
+
+/// The name of the `wasi:http` package.
+pub const WASI_HTTP_PACKAGE: &str = "wasi:http";
+
+/// The name of the function of the `wasi:http` handler interfaces handling a request.
+pub const WASI_HTTP_HANDLE_METHOD: &str = "handle";
+
+/// A `wasi:http` interface handling requests.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum WasiHttpHandler {
+    /// `wasi:http/outgoing-handler`, sending the requests of the importing package.
+    Outgoing,
+
+    /// `wasi:http/incoming-handler`, serving the requests forwarded by the importing package.
+    Incoming,
+}
+
+impl WasiHttpHandler {
+    /// Returns the handler interface at `path`, of any `wasi:http` version.
+    #[must_use]
+    pub fn from_path(path: &ForeignInterfacePath) -> Option<Self> {
+        if path.package_name() != WASI_HTTP_PACKAGE {
+            return None;
+        }
+
+        match path.interface_name() {
+            "outgoing-handler" => Some(Self::Outgoing),
+            "incoming-handler" => Some(Self::Incoming),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the interface within `WASI_HTTP_PACKAGE`.
+    #[must_use]
+    pub fn interface_name(self) -> &'static str {
+        match self {
+            Self::Outgoing => "outgoing-handler",
+            Self::Incoming => "incoming-handler",
+        }
+    }
+}
+
+/// The request passed to a `wasi:http` handler.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WasiHttpRequest {
+    pub handler: WasiHttpHandler,
+    /// The `outgoing-request` or `incoming-request` handle.
+    pub request: ResourceAny,
+    /// The `response-outparam` handle, for incoming requests.
+    pub response_out: Option<ResourceAny>,
+}
+
+impl<D: 'static, C> GuestCallData<'_, D, C> {
+    /// Returns the request of the call, if it is a call to the `handle` function of a
+    /// `wasi:http` handler interface.
+    #[must_use]
+    pub fn wasi_http_request(&self) -> Option<WasiHttpRequest> {
+        let handler = WasiHttpHandler::from_path(self.interface())?;
+        if self.method() != WASI_HTTP_HANDLE_METHOD {
+            return None;
+        }
+
+        let resource = |index: usize| match self.arguments().get(index) {
+            Some(Val::Resource(handle)) => Some(*handle),
+            _ => None,
+        };
+
+        Some(WasiHttpRequest {
+            handler,
+            request: resource(0)?,
+            response_out: match handler {
+                WasiHttpHandler::Outgoing => None,
+                WasiHttpHandler::Incoming => Some(resource(1)?),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn test_wasi_http_handler() {
+        let path = |package: &str, interface: &str| {
+            ForeignInterfacePath::new(
+                package.to_string(),
+                interface.to_string(),
+                Some(Version::new(0, 2, 6)),
+            )
+        };
+
+        for handler in [WasiHttpHandler::Outgoing, WasiHttpHandler::Incoming] {
+            assert_eq!(
+                WasiHttpHandler::from_path(&path(WASI_HTTP_PACKAGE, handler.interface_name())),
+                Some(handler)
+            );
+        }
+
+        assert_eq!(
+            WasiHttpHandler::from_path(&path(WASI_HTTP_PACKAGE, "types")),
+            None
+        );
+        assert_eq!(
+            WasiHttpHandler::from_path(&path("test:http", "outgoing-handler")),
+            None
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Hot reloading of packages from component files, for development loops that rebuild components
+//! while the host keeps running.
+
+use crate::{AddPackageError, CompositionGraph, DynPackageTrampoline, PackageId};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+type TrampolineFactory<D, C> = Box<dyn Fn() -> Box<dyn DynPackageTrampoline<D, C>> + Send + Sync>;
+
+/// Watches the component files of the packages of a graph, replacing the packages with
+/// `CompositionGraph::replace_package` when their files change.
+///
+/// Files are polled for changes to their modification time or size, with `poll` or `wait`, so
+/// the watcher works without platform file notifications. Each replacement raises a
+/// `GraphEvent::PackageReplaced` event to the graph's subscribers, and is returned as a
+/// `WatchEvent`, so the host can re-instantiate the packages depending on it. Files that are
+/// missing, such as while a build rewrites them, are checked again on the next poll.
+pub struct GraphWatcher<D, C: Clone = ()> {
+    graph: CompositionGraph<D, C>,
+    wasm_dir: PathBuf,
+    files: Vec<WatchedFile<D, C>>,
+}
+
+struct WatchedFile<D, C: Clone> {
+    package: PackageId,
+    path: PathBuf,
+    stamp: Option<FileStamp>,
+    trampoline: TrampolineFactory<D, C>,
+}
+
+/// The modification time and size a file had when it was last read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// A change to a watched component file, returned by `GraphWatcher::poll`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WatchEvent {
+    /// The package was replaced by the changed component.
+    Replaced { package: PackageId, path: PathBuf },
+
+    /// The changed component could not replace the package, which is left unchanged until the
+    /// file changes again.
+    Failed {
+        package: PackageId,
+        path: PathBuf,
+        error: AddPackageError,
+    },
+}
+
+impl<D, C: Clone> std::fmt::Debug for GraphWatcher<D, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GraphWatcher")
+            .field("wasm_dir", &self.wasm_dir)
+            .field(
+                "files",
+                &self
+                    .files
+                    .iter()
+                    .map(|file| (file.package, &file.path))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl<D, C: Clone> GraphWatcher<D, C> {
+    /// Creates a watcher of component files in `wasm_dir`, replacing packages of `graph`.
+    pub fn new(graph: CompositionGraph<D, C>, wasm_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            graph,
+            wasm_dir: wasm_dir.into(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Watches the component file at `path`, relative to the watched directory, replacing the
+    /// package `package` with a clone of `trampoline` when it changes.
+    ///
+    /// The file is considered unchanged until its modification time or size differs from when it
+    /// is watched, so the package should have been added from the current file.
+    pub fn watch<T>(&mut self, package: PackageId, path: impl AsRef<Path>, trampoline: T)
+    where
+        T: DynPackageTrampoline<D, C> + Clone + Send + Sync + 'static,
+    {
+        let path = self.wasm_dir.join(path);
+        let stamp = FileStamp::of(&path);
+
+        self.files.retain(|file| file.package != package);
+        self.files.push(WatchedFile {
+            package,
+            path,
+            stamp,
+            trampoline: Box::new(move || Box::new(trampoline.clone())),
+        });
+    }
+
+    /// Stops watching the component file of the package `package`.
+    pub fn unwatch(&mut self, package: PackageId) {
+        self.files.retain(|file| file.package != package);
+    }
+
+    /// Replaces the packages whose component files changed since they were last read, returning
+    /// the replacements, in the order the files were watched.
+    pub fn poll(&mut self) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+
+        for file in &mut self.files {
+            let Some(stamp) = FileStamp::of(&file.path) else {
+                continue;
+            };
+            if file.stamp == Some(stamp) {
+                continue;
+            }
+            file.stamp = Some(stamp);
+
+            let replaced = std::fs::read(&file.path)
+                .map_err(|source| AddPackageError::ReadError { source })
+                .and_then(|bytes| {
+                    self.graph
+                        .replace_package(file.package, bytes, (file.trampoline)())
+                });
+
+            events.push(match replaced {
+                Ok(()) => WatchEvent::Replaced {
+                    package: file.package,
+                    path: file.path.clone(),
+                },
+                Err(error) => WatchEvent::Failed {
+                    package: file.package,
+                    path: file.path.clone(),
+                    error,
+                },
+            });
+        }
+
+        events
+    }
+
+    /// Polls the watched files every `interval` until any of them changes, returning the
+    /// replacements like `poll`.
+    pub fn wait(&mut self, interval: Duration) -> Vec<WatchEvent> {
+        loop {
+            let events = self.poll();
+            if !events.is_empty() {
+                return events;
+            }
+
+            std::thread::sleep(interval);
+        }
+    }
+
+    #[must_use]
+    pub fn graph(&self) -> &CompositionGraph<D, C> {
+        &self.graph
+    }
+
+    /// Returns the graph, to configure it or add packages.
+    pub fn graph_mut(&mut self) -> &mut CompositionGraph<D, C> {
+        &mut self.graph
+    }
+
+    /// Stops watching, returning the graph.
+    #[must_use]
+    pub fn into_graph(self) -> CompositionGraph<D, C> {
+        self.graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GraphEvent, NoopTrampoline};
+    use semver::Version;
+    use std::fs::File;
+
+    /// The preamble of an empty component.
+    const EMPTY_COMPONENT: &[u8] = b"\0asm\x0d\x00\x01\x00";
+
+    #[test]
+    fn test_graph_watcher() {
+        let dir = std::env::temp_dir().join(format!("graph-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.component.wasm");
+        std::fs::write(&path, EMPTY_COMPONENT).unwrap();
+
+        let mut graph = CompositionGraph::<()>::new();
+        let package = graph
+            .add_package(
+                "test:app".to_string(),
+                Version::new(1, 0, 0),
+                EMPTY_COMPONENT,
+                NoopTrampoline,
+            )
+            .unwrap();
+        let events = graph.subscribe_events();
+
+        let mut watcher = GraphWatcher::new(graph, &dir);
+        watcher.watch(package, "app.component.wasm", NoopTrampoline);
+        assert!(watcher.poll().is_empty());
+
+        let touch = |bytes: &[u8], modified: u64| {
+            std::fs::write(&path, bytes).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))
+                .unwrap();
+        };
+
+        touch(EMPTY_COMPONENT, 1);
+        assert!(matches!(
+            watcher.poll()[..],
+            [WatchEvent::Replaced { package: replaced, .. }] if replaced == package
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(GraphEvent::PackageReplaced { .. })
+        ));
+        assert!(watcher.poll().is_empty());
+
+        touch(b"not a component", 2);
+        assert!(matches!(
+            watcher.wait(Duration::from_millis(1))[..],
+            [WatchEvent::Failed {
+                error: AddPackageError::PackageParseError { .. },
+                ..
+            }]
+        ));
+        assert!(watcher.poll().is_empty());
+        assert_eq!(watcher.into_graph().packages().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
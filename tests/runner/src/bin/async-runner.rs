@@ -17,16 +17,13 @@ mod runner {
     use regex::Regex;
     use runner::cli::Args;
     use semver::Version;
-    use std::path::PathBuf;
     use std::pin::Pin;
     use std::sync::Arc;
-    use tokio::fs;
     use wasm_component_trampoline::{
-        AsyncGuestCall, AsyncGuestResult, AsyncTrampoline, CompositionGraph, ImportRule,
+        AsyncGuestCall, AsyncGuestResult, AsyncTrampoline, GraphRunner, ImportRule,
         PackageTrampoline, RegexMatchFilter,
     };
     use wasmtime::component::HasSelf;
-    use wasmtime::{Config, Engine, Store, component::Linker};
 
     wasmtime::component::bindgen!({
         path: "../wasm/application/wit",
@@ -102,114 +99,84 @@ mod runner {
         }
     }
 
-    async fn add_package(
-        graph: &mut CompositionGraph<AppData>,
-        wasm_dir: &PathBuf,
-        path: &str,
-        name: &str,
-        version: Version,
-    ) -> Result<wasm_component_trampoline::PackageId, wasm_component_trampoline::AddPackageError>
-    {
-        eprintln!("Loading {path} component...");
-
-        let wasm_file = format!("{path}.component.wasm").to_string();
-        let pkg_bytes = fs::read(wasm_dir.join(&wasm_file))
-            .await
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Failed to read {}/{wasm_file} Make sure it's been compiled",
-                    wasm_dir.display()
-                )
-            });
-
-        let trampoline: Arc<dyn AsyncTrampoline<AppData, ()>> = Arc::new(PassthroughTrampoline {});
-        let pkg = PackageTrampoline::with_default_context(trampoline, ());
-
-        let ret = graph.add_package(name.to_string(), version, pkg_bytes, pkg);
-        eprintln!("{name} component loaded successfully.");
-        ret
-    }
-
     pub async fn main() -> anyhow::Result<()> {
         let args = Args::parse();
 
-        // Configure the WebAssembly engine
-        let mut config = Config::new();
-        config.wasm_component_model(true);
-        config.async_support(true);
-
-        let engine = Engine::new(&config)?;
-        let mut linker: Linker<AppData> = Linker::new(&engine);
-        let mut store = Store::new(&engine, AppData::default());
+        let mut runner = GraphRunner::<AppData>::new_async()?.with_package_dir(&args.wasm_dir);
+        let mut store = runner.new_store(AppData::default());
 
         // Add host interfaces to the linker.
         logger::test::logging::system::add_to_linker::<_, HasSelf<_>>(
-            &mut linker,
+            runner.linker_mut(),
             |ctx: &mut _| &mut ctx.host,
         )?;
 
-        // Create our composition graph
-        let mut graph = CompositionGraph::<AppData>::new();
+        // Configure our composition graph
+        let graph = runner.graph_mut();
 
         graph.set_import_filter(RegexMatchFilter::new(
             Regex::new(r"^test:logging/system")?,
             ImportRule::Skip,
         ));
 
-        // Load the logger component
-        add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "logger",
-            "test:logging",
-            Version::new(1, 1, 1),
-        )
-        .await?;
+        graph.set_warning_handler(|warning| eprintln!("Graph warning: {warning}"));
 
-        add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "logger",
+        let trampoline = || {
+            let trampoline: Arc<dyn AsyncTrampoline<AppData, ()>> = Arc::new(PassthroughTrampoline);
+            PackageTrampoline::with_default_context(trampoline, ())
+        };
+
+        // Load the logger component
+        runner.add_package_file(
             "test:logging",
             Version::new(1, 1, 1),
-        )
-        .await
-        .expect_err("Duplicate logger component should not be allowed");
+            "logger.component.wasm",
+            trampoline(),
+        )?;
+        runner
+            .add_package_file(
+                "test:logging",
+                Version::new(1, 1, 1),
+                "logger.component.wasm",
+                trampoline(),
+            )
+            .expect_err("Duplicate logger component should not be allowed");
 
         // Load the KV store component
-        let _kvstore_id = add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "kvstore",
+        runner.add_package_file(
             "test:kvstore",
             Version::new(2, 1, 6),
-        )
-        .await?;
+            "kvstore.component.wasm",
+            trampoline(),
+        )?;
 
         // Load the application component
-        let app_id = add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "application",
+        let app_id = runner.add_package_file(
             "test:application",
             Version::new(0, 4, 0),
-        )
-        .await?;
+            "application.component.wasm",
+            trampoline(),
+        )?;
+
+        // Check the composition before instantiating it
+        for diagnostic in runner.validate()? {
+            eprintln!("Graph diagnostic: {diagnostic}");
+        }
 
         // Instantiate the components
         eprintln!("Instantiating components...");
         if args.verbose {
-            eprintln!("graph: {graph:#?}");
+            eprintln!("graph: {:#?}", runner.graph());
         }
 
-        let instance = graph
-            .instantiate_async(app_id, &mut linker, &mut store, &engine)
+        let application = runner
+            .instantiate_typed_async(app_id, &mut store, |store, instance| {
+                Application::new(store, instance)
+            })
             .await?;
 
         eprintln!("Components instantiated successfully.");
 
-        let application = Application::new(&mut store, &instance)?;
-
         application
             .test_application_greeter()
             .call_set_name(&mut store, "Dave")
@@ -15,17 +15,15 @@ mod runner {
     use anyhow::Error;
     use clap::Parser;
     use semver::Version;
-    use std::path::PathBuf;
 
     use regex::Regex;
     use runner::cli::Args;
     use std::sync::Arc;
-    use tokio::fs;
     use wasm_component_trampoline::{
-        CompositionGraph, GuestCall, GuestResult, ImportRule, RegexMatchFilter, Trampoline,
+        GraphRunner, GuestCall, GuestResult, ImportRule, PackageTrampoline, RegexMatchFilter,
+        Trampoline,
     };
     use wasmtime::component::HasSelf;
-    use wasmtime::{Config, Engine, Store, component::Linker};
 
     wasmtime::component::bindgen!({
         path: "../wasm/application/wit",
@@ -60,7 +58,8 @@ mod runner {
     }
 
     // Simple async trampoline that just passes calls through
-    struct PassthroughTrampoline {}
+    struct PassthroughTrampoline;
+
     impl Trampoline<AppData, ()> for PassthroughTrampoline {
         fn bounce<'c>(
             &self,
@@ -90,112 +89,82 @@ mod runner {
         }
     }
 
-    async fn add_package(
-        graph: &mut CompositionGraph<AppData>,
-        wasm_dir: &PathBuf,
-        path: &str,
-        name: &str,
-        version: Version,
-    ) -> Result<wasm_component_trampoline::PackageId, wasm_component_trampoline::AddPackageError>
-    {
-        eprintln!("Loading {path} component...");
-
-        let wasm_file = format!("{path}.component.wasm").to_string();
-        let pkg_bytes = fs::read(wasm_dir.join(&wasm_file))
-            .await
-            .unwrap_or_else(|_| {
-                panic!(
-                    "failed to read {}/{wasm_file}; make sure it's been compiled!",
-                    wasm_dir.display()
-                )
-            });
-
-        let trampoline: Arc<dyn Trampoline<AppData, ()>> = Arc::new(PassthroughTrampoline {});
-        let pkg =
-            wasm_component_trampoline::PackageTrampoline::with_default_context(trampoline, ());
-
-        let ret = graph.add_package(name.to_string(), version, pkg_bytes, pkg);
-        eprintln!("{name} component loaded successfully.");
-        ret
-    }
-
     pub async fn main() -> anyhow::Result<()> {
         let args = Args::parse();
 
-        // Configure the WebAssembly engine
-        let mut config = Config::new();
-        config.wasm_component_model(true);
-        config.async_support(false);
-
-        let engine = Engine::new(&config)?;
-        let mut linker: Linker<AppData> = Linker::new(&engine);
-        let mut store = Store::new(&engine, AppData::default());
+        let mut runner = GraphRunner::<AppData>::new()?.with_package_dir(&args.wasm_dir);
+        let mut store = runner.new_store(AppData::default());
 
         // Add host interfaces to the linker.
         logger::test::logging::system::add_to_linker::<_, HasSelf<_>>(
-            &mut linker,
+            runner.linker_mut(),
             |ctx: &mut _| &mut ctx.host,
         )?;
 
-        // Create our composition graph
-        let mut graph = CompositionGraph::<AppData>::new();
+        // Configure our composition graph
+        let graph = runner.graph_mut();
 
         graph.set_import_filter(RegexMatchFilter::new(
             Regex::new(r"^test:logging/system")?,
             ImportRule::Skip,
         ));
 
+        graph.set_warning_handler(|warning| eprintln!("Graph warning: {warning}"));
+
+        let trampoline = || {
+            let trampoline: Arc<dyn Trampoline<AppData, ()>> = Arc::new(PassthroughTrampoline);
+            PackageTrampoline::with_default_context(trampoline, ())
+        };
+
         // Load the logger component
-        add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "logger",
-            "test:logging",
-            Version::new(1, 1, 1),
-        )
-        .await?;
-        add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "logger",
+        runner.add_package_file(
             "test:logging",
             Version::new(1, 1, 1),
-        )
-        .await
-        .expect_err("Duplicate logger component should not be allowed");
+            "logger.component.wasm",
+            trampoline(),
+        )?;
+        runner
+            .add_package_file(
+                "test:logging",
+                Version::new(1, 1, 1),
+                "logger.component.wasm",
+                trampoline(),
+            )
+            .expect_err("Duplicate logger component should not be allowed");
 
         // Load the KV store component
-        let _kvstore_id = add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "kvstore",
+        runner.add_package_file(
             "test:kvstore",
             Version::new(2, 1, 6),
-        )
-        .await?;
+            "kvstore.component.wasm",
+            trampoline(),
+        )?;
 
         // Load the application component
-        let app_id = add_package(
-            &mut graph,
-            &args.wasm_dir,
-            "application",
+        let app_id = runner.add_package_file(
             "test:application",
             Version::new(0, 4, 0),
-        )
-        .await?;
+            "application.component.wasm",
+            trampoline(),
+        )?;
+
+        // Check the composition before instantiating it
+        for diagnostic in runner.validate()? {
+            eprintln!("Graph diagnostic: {diagnostic}");
+        }
 
         // Instantiate the components
         eprintln!("Instantiating components...");
         if args.verbose {
-            eprintln!("graph: {graph:#?}");
+            eprintln!("graph: {:#?}", runner.graph());
         }
 
-        let instance = graph.instantiate(app_id, &mut linker, &mut store, &engine)?;
+        let application = runner.instantiate_typed(app_id, &mut store, |store, instance| {
+            Application::new(store, instance)
+        })?;
 
         eprintln!("Components instantiated successfully.");
 
-        let application = Application::new(&mut store, &instance)?;
-
         application
             .test_application_greeter()
             .call_set_name(&mut store, "Dave")?;
@@ -0,0 +1,2088 @@
+//! A specialized map for semantic versions with alternate version lookup support.
+//!
+//! This module best approximates the behavior of WASM component loading in `wasmtime`,
+//! such as in `wasmtime::component::Linker`.
+//!
+//! This module provides `VersionMap<T>`, which stores values indexed by semantic versions
+//! and supports fallback lookups through version alternates (e.g., 1.2.3 can be found
+//! via 1.0.0 if it's the latest patch for major version 1).
+
+use derivative::Derivative;
+use semver::{BuildMetadata, Version};
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, btree_map};
+#[cfg(feature = "borsh")]
+use std::io::{Read, Write};
+use std::ops::{Bound, RangeBounds};
+
+/// A map that stores values indexed by semantic versions with support for alternate lookups.
+///
+/// The `VersionMap` maintains a primary mapping from versions to values, and a secondary
+/// mapping that groups versions by their "alternate" keys for fallback lookups.
+///
+/// # Alternate Lookup Logic
+///
+/// With the default `AlternateStrategy::CaretCompatible` strategy:
+///
+/// - For major versions > 0: alternate is `major.*.*`
+/// - For minor versions > 0 (when major is 0): alternate is `0.minor.*`
+/// - Otherwise: alternate is `0.0.patch`
+/// - Pre-release versions have no alternates, and by default must be matched exactly (see
+///   `PreReleasePolicy`)
+///
+/// Other strategies can be selected with `VersionMap::with_strategy`.
+///
+/// # Example
+///
+/// ```rust
+/// use semver::Version;
+/// # use wasm_component_semver::VersionMap;
+///
+/// let mut map = VersionMap::new();
+/// map.insert(Version::new(1, 0, 1), "v1.0.1");
+/// map.insert(Version::new(1, 2, 0), "v1.2.0");
+///
+/// // Exact lookups
+/// assert_eq!(map.get_exact(&Version::new(1, 0, 1)), Some(&"v1.0.1"));
+///
+/// // Alternate lookups (finds latest patch for major version 1)
+/// assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&"v1.2.0"));
+/// ```
+#[derive(Clone, Derivative, Debug)]
+#[derivative(Default(bound = ""))]
+pub struct VersionMap<T> {
+    /// Primary storage mapping versions to values
+    versions: BTreeMap<WrappedVersion, T>,
+    /// Secondary mapping for alternate version lookups
+    alternates: HashMap<Version, BTreeSet<WrappedVersion>>,
+    /// How alternate keys are computed for release versions
+    strategy: AlternateStrategy,
+    /// How pre-release version requests are resolved
+    pre_release_policy: PreReleasePolicy,
+    /// How build metadata on version requests is matched
+    build_metadata_policy: BuildMetadataPolicy,
+    /// Which candidate version is selected by lookups
+    resolution_mode: ResolutionMode,
+    /// Preference among the acceptable candidates of a lookup
+    priority: Option<VersionPriority>,
+    /// Deprecation messages for stored versions
+    deprecations: HashMap<Version, String>,
+}
+
+/// Determines which stored versions can satisfy a request for a different version, by mapping
+/// each release version to an "alternate" key. Versions sharing a key are interchangeable, and
+/// the latest of them is selected.
+///
+/// Pre-release versions never have alternate keys; see `PreReleasePolicy` instead.
+#[derive(Copy, Clone, Default, Debug)]
+pub enum AlternateStrategy {
+    /// No alternates: every request must match a stored version exactly.
+    Exact,
+
+    /// Caret (`^`) compatibility, as used by the `wasmtime` component linker: the same major
+    /// version for `1.0.0` and above, the same minor version for `0.x`, and the same patch version
+    /// for `0.0.x`.
+    #[default]
+    CaretCompatible,
+
+    /// The same major version, including major version `0`.
+    LatestInMajor,
+
+    /// A custom alternate key function. Returning `None` opts the version out of alternate lookup.
+    Custom(fn(&Version) -> Option<Version>),
+}
+
+impl AlternateStrategy {
+    /// Computes the alternate key for `version` under this strategy, or `None` if the version has
+    /// no alternates.
+    #[must_use]
+    pub fn alternate(&self, version: &Version) -> Option<Version> {
+        if !version.pre.is_empty() {
+            return None;
+        }
+
+        match self {
+            AlternateStrategy::Exact => None,
+            AlternateStrategy::CaretCompatible => version_alternate(version),
+            AlternateStrategy::LatestInMajor => Some(Version::new(version.major, 0, 0)),
+            AlternateStrategy::Custom(alternate) => alternate(version),
+        }
+    }
+}
+
+/// Controls which of the candidate versions for a request is selected.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub enum ResolutionMode {
+    /// Select the latest candidate.
+    #[default]
+    Latest,
+
+    /// Select the lowest candidate that is at least the requested version, akin to cargo's
+    /// `-Z minimal-versions`. Candidates older than the requested version are never selected, and
+    /// requests without a version select the lowest stored version.
+    ///
+    /// This is useful to verify that declared version requirements are accurate, rather than
+    /// satisfied by newer versions by accident.
+    Minimal,
+}
+
+/// Ranks acceptable versions during lookups: candidates with a higher priority are selected over
+/// those with a lower priority, and the `ResolutionMode` only decides between candidates of equal
+/// priority.
+///
+/// # Examples
+///
+/// ```rust
+/// use semver::Version;
+/// # use wasm_component_semver::VersionMap;
+///
+/// // Prefer internally-signed builds over newer unsigned ones.
+/// let map = VersionMap::from_iter([
+///     (Version::parse("1.1.0+signed").unwrap(), "signed"),
+///     (Version::parse("1.2.0").unwrap(), "unsigned"),
+/// ])
+/// .with_priority(|version| i32::from(version.build.as_str() == "signed"));
+///
+/// assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&"signed"));
+/// assert_eq!(map.get_or_latest(None), Some(&"signed"));
+/// ```
+pub type VersionPriority = fn(&Version) -> i32;
+
+/// Controls how build metadata (`+build`) on a requested version affects lookups.
+///
+/// Versions differing only by build metadata are stored as distinct entries under every policy.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub enum BuildMetadataPolicy {
+    /// A request with build metadata only matches that exact version, without alternate lookup.
+    /// Requests without build metadata match versions regardless of their build metadata.
+    #[default]
+    Exact,
+
+    /// Build metadata on the request is ignored, and candidates are selected regardless of their
+    /// build metadata.
+    Ignore,
+
+    /// Only versions with the same build metadata as the request are candidates. A request without
+    /// build metadata only matches versions without build metadata.
+    RequireEqual,
+
+    /// Like `Ignore`, but if any candidate has the same build metadata as the request, only those
+    /// candidates are considered.
+    PreferMatching,
+}
+
+/// Controls whether pre-release versions can be selected by alternate lookup.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub enum PreReleasePolicy {
+    /// Pre-release versions are only ever matched exactly, and pre-release requests never use
+    /// alternate lookup. This mirrors the `wasmtime` component linker.
+    #[default]
+    Exact,
+
+    /// A pre-release request opts in to pre-releases, with cargo-like semantics: the request
+    /// `1.0.0-rc.1` is satisfied by any release compatible with `1.0.0`, and by any pre-release of
+    /// exactly `1.0.0` that is at least `1.0.0-rc.1`. The latest of these is selected.
+    ///
+    /// Requests without a pre-release identifier never select pre-release versions.
+    OptIn,
+}
+
+impl<T> VersionMap<T> {
+    /// Creates a new empty `VersionMap`.
+    pub fn new() -> Self {
+        Self {
+            versions: BTreeMap::new(),
+            alternates: HashMap::new(),
+            strategy: AlternateStrategy::default(),
+            pre_release_policy: PreReleasePolicy::default(),
+            build_metadata_policy: BuildMetadataPolicy::default(),
+            resolution_mode: ResolutionMode::default(),
+            priority: None,
+            deprecations: HashMap::new(),
+        }
+    }
+
+    /// Sets the alternate lookup strategy, returning the updated map.
+    #[must_use]
+    pub fn with_strategy(mut self, strategy: AlternateStrategy) -> Self {
+        self.set_strategy(strategy);
+        self
+    }
+
+    /// Returns the alternate lookup strategy.
+    pub fn strategy(&self) -> AlternateStrategy {
+        self.strategy
+    }
+
+    /// Sets the alternate lookup strategy, re-indexing all stored versions.
+    pub fn set_strategy(&mut self, strategy: AlternateStrategy) {
+        self.strategy = strategy;
+        self.alternates.clear();
+
+        for version in self.versions.keys() {
+            if let Some(alternate) = strategy.alternate(&version.inner) {
+                self.alternates
+                    .entry(alternate)
+                    .or_default()
+                    .insert(version.clone());
+            }
+        }
+    }
+
+    /// Sets the policy for resolving pre-release version requests, returning the updated map.
+    #[must_use]
+    pub fn with_pre_release_policy(mut self, policy: PreReleasePolicy) -> Self {
+        self.pre_release_policy = policy;
+        self
+    }
+
+    /// Returns the policy for resolving pre-release version requests.
+    pub fn pre_release_policy(&self) -> PreReleasePolicy {
+        self.pre_release_policy
+    }
+
+    /// Sets the policy for resolving pre-release version requests.
+    pub fn set_pre_release_policy(&mut self, policy: PreReleasePolicy) {
+        self.pre_release_policy = policy;
+    }
+
+    /// Sets the resolution mode, returning the updated map.
+    #[must_use]
+    pub fn with_resolution_mode(mut self, mode: ResolutionMode) -> Self {
+        self.resolution_mode = mode;
+        self
+    }
+
+    /// Returns the resolution mode.
+    pub fn resolution_mode(&self) -> ResolutionMode {
+        self.resolution_mode
+    }
+
+    /// Sets the resolution mode.
+    pub fn set_resolution_mode(&mut self, mode: ResolutionMode) {
+        self.resolution_mode = mode;
+    }
+
+    /// Sets the priority function used to rank acceptable candidates, returning the updated map.
+    #[must_use]
+    pub fn with_priority(mut self, priority: VersionPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Returns the priority function used to rank acceptable candidates, if any.
+    pub fn priority(&self) -> Option<VersionPriority> {
+        self.priority
+    }
+
+    /// Sets or clears the priority function used to rank acceptable candidates.
+    pub fn set_priority(&mut self, priority: Option<VersionPriority>) {
+        self.priority = priority;
+    }
+
+    /// Sets the policy for matching build metadata on version requests, returning the updated map.
+    #[must_use]
+    pub fn with_build_metadata_policy(mut self, policy: BuildMetadataPolicy) -> Self {
+        self.build_metadata_policy = policy;
+        self
+    }
+
+    /// Returns the policy for matching build metadata on version requests.
+    pub fn build_metadata_policy(&self) -> BuildMetadataPolicy {
+        self.build_metadata_policy
+    }
+
+    /// Sets the policy for matching build metadata on version requests.
+    pub fn set_build_metadata_policy(&mut self, policy: BuildMetadataPolicy) {
+        self.build_metadata_policy = policy;
+    }
+
+    #[cfg(any(feature = "borsh", feature = "serde"))]
+    fn from_versions(versions: BTreeMap<WrappedVersion, T>) -> Self {
+        let mut map = Self {
+            versions,
+            ..Self::new()
+        };
+        map.set_strategy(AlternateStrategy::default());
+        map
+    }
+
+    /// Attempts to insert a version-value pair, returning an error if the version already exists.
+    pub fn try_insert(&mut self, version: Version, value: T) -> Result<(), (Version, T)> {
+        let version: WrappedVersion = version.into();
+
+        if self.versions.contains_key(&version) {
+            return Err((version.into(), value));
+        }
+
+        if let Some(alternate) = self.strategy.alternate(&version.inner) {
+            self.alternates
+                .entry(alternate)
+                .or_default()
+                .insert(version.clone());
+        }
+
+        self.versions.insert(version, value);
+
+        Ok(())
+    }
+
+    /// Returns a mutable reference to the value stored for exactly `version`, inserting the result
+    /// of `f` first if the version is not present.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.get_or_insert_with(Version::new(1, 2, 0), Vec::new).push("first");
+    /// map.get_or_insert_with(Version::new(1, 2, 0), Vec::new).push("second");
+    ///
+    /// assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&vec!["first", "second"]));
+    /// ```
+    pub fn get_or_insert_with<F>(&mut self, version: Version, f: F) -> &mut T
+    where
+        F: FnOnce() -> T,
+    {
+        match self.versions.entry(version.into()) {
+            btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            btree_map::Entry::Vacant(entry) => {
+                if let Some(alternate) = self.strategy.alternate(&entry.key().inner) {
+                    self.alternates
+                        .entry(alternate)
+                        .or_default()
+                        .insert(entry.key().clone());
+                }
+
+                entry.insert(f())
+            }
+        }
+    }
+
+    /// Inserts a version-value pair, returning the previous value if the version existed.
+    ///
+    /// Updates the alternates mapping appropriately.
+    pub fn insert(&mut self, version: Version, value: T) -> Option<T> {
+        let version: WrappedVersion = version.into();
+
+        if let Some(alternate) = self.strategy.alternate(&version.inner) {
+            self.alternates
+                .entry(alternate)
+                .or_default()
+                .insert(version.clone());
+        }
+
+        self.versions.insert(version, value)
+    }
+
+    /// Gets a value by version, using alternate lookup if exact match is not found.
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.insert(Version::new(0, 0, 9), "v0.0.9");
+    /// map.insert(Version::new(0, 1, 1), "v0.1.1");
+    /// map.insert(Version::new(1, 2, 1), "v1.2.1");
+    ///
+    /// // Get latest patch
+    /// assert_eq!(map.get(&Version::new(0, 0, 9)), Some(&"v0.0.9"));
+    ///
+    /// // Get latest minor
+    /// assert_eq!(map.get(&Version::new(0, 1, 0)), Some(&"v0.1.1"));
+    ///
+    /// // Get latest major
+    /// assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&"v1.2.1"));
+    pub fn get(&self, version: &Version) -> Option<&T> {
+        self.lookup(version).map(|(_, v)| v)
+    }
+
+    /// Like `get`, but returns the resolved version and value as a tuple.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.insert(Version::new(0, 0, 9), "v0.0.9");
+    /// map.insert(Version::new(0, 1, 1), "v0.1.1");
+    /// map.insert(Version::new(1, 2, 1), "v1.2.1");
+    ///
+    /// // Get latest patch
+    /// assert_eq!(map.get_version(&Version::new(0, 0, 9)), Some((&Version::new(0, 0, 9), &"v0.0.9")));
+    ///
+    /// // Get latest minor
+    /// assert_eq!(map.get_version(&Version::new(0, 1, 0)), Some((&Version::new(0, 1, 1), &"v0.1.1")));
+    ///
+    /// // Get latest major
+    /// assert_eq!(map.get_version(&Version::new(1, 0, 0)), Some((&Version::new(1, 2, 1), &"v1.2.1")));
+    pub fn get_version(&self, version: &Version) -> Option<(&Version, &T)> {
+        self.lookup(version).map(|(k, v)| (k.borrow(), v))
+    }
+
+    /// Gets a value by version or returns the latest version if no specific version is provided.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.insert(Version::new(0, 0, 9), "v0.0.9");
+    /// map.insert(Version::new(0, 1, 0), "v0.1.0");
+    /// map.insert(Version::new(0, 1, 1), "v0.1.1");
+    /// map.insert(Version::new(0, 5, 1), "v0.5.1");
+    /// map.insert(Version::new(1, 0, 0), "v1.0.0");
+    /// map.insert(Version::new(1, 2, 0), "v1.2.0");
+    ///
+    /// // Get latest patch
+    /// assert_eq!(map.get_or_latest(Some(&Version::new(0, 0, 9))), Some(&"v0.0.9"));
+    ///
+    /// // Get latest minor
+    /// assert_eq!(map.get_or_latest(Some(&Version::new(0, 1, 0))), Some(&"v0.1.1"));
+    ///
+    /// // Get latest major
+    /// assert_eq!(map.get_or_latest(Some(&Version::new(1, 0, 0))), Some(&"v1.2.0"));
+    ///
+    /// // Get the latest version
+    /// assert_eq!(map.get_or_latest(None), Some(&"v1.2.0"));
+    /// ```
+    pub fn get_or_latest(&self, version: Option<&Version>) -> Option<&T> {
+        self.get_or_latest_version(version).map(|(_, value)| value)
+    }
+
+    /// Gets a value by version or returns the latest version and its associated value
+    /// if no specific version is provided.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.insert(Version::new(0, 0, 9), "v0.0.9");
+    /// map.insert(Version::new(0, 1, 0), "v0.1.0");
+    /// map.insert(Version::new(0, 1, 1), "v0.1.1");
+    /// map.insert(Version::new(0, 5, 1), "v0.5.1");
+    /// map.insert(Version::new(1, 0, 0), "v1.0.0");
+    /// map.insert(Version::new(1, 2, 0), "v1.2.0");
+    ///
+    /// // Get latest patch
+    /// assert_eq!(map.get_or_latest_version(Some(&Version::new(0, 0, 9))), Some((&Version::new(0, 0, 9), &"v0.0.9")));
+    ///
+    /// // Get latest minor
+    /// assert_eq!(map.get_or_latest_version(Some(&Version::new(0, 1, 0))), Some((&Version::new(0, 1, 1), &"v0.1.1")));
+    ///
+    /// // Get latest major
+    /// assert_eq!(map.get_or_latest_version(Some(&Version::new(1, 0, 0))), Some((&Version::new(1, 2, 0), &"v1.2.0")));
+    ///
+    /// // Get the latest version
+    /// assert_eq!(map.get_or_latest_version(None), Some((&Version::new(1, 2, 0), &"v1.2.0")));
+    /// ```
+    pub fn get_or_latest_version(&self, version: Option<&Version>) -> Option<(&Version, &T)> {
+        match version {
+            Some(v) => self.get_version(v),
+            None => self
+                .select(self.versions.keys())
+                .and_then(|version| self.versions.get_key_value(version))
+                .map(|(k, v)| (k.borrow(), v)),
+        }
+    }
+
+    /// Returns the latest version and its associated value.
+    pub fn get_latest(&self) -> Option<(&Version, &T)> {
+        self.versions.last_key_value().map(|(k, v)| (k.borrow(), v))
+    }
+
+    /// Gets a value by exact version match only, without alternate lookup.
+    pub fn get_exact(&self, version: &Version) -> Option<&T> {
+        self.versions.get(version)
+    }
+
+    /// Gets a mutable value by exact version match only, without alternate lookup.
+    pub fn get_exact_mut(&mut self, version: &Version) -> Option<&mut T> {
+        self.versions.get_mut(version)
+    }
+
+    /// Returns the newest release version that is caret-compatible with `version`, along with its
+    /// value.
+    ///
+    /// Compatibility follows cargo's caret rules: the same major version for `1.0.0` and above,
+    /// the same minor version for `0.x`, and the same patch version for `0.0.x`. Pre-release and
+    /// build metadata on `version` are ignored, and pre-release versions are never returned. This
+    /// is independent of the map's lookup policies.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.insert(Version::new(0, 1, 4), "v0.1.4");
+    /// map.insert(Version::new(0, 2, 0), "v0.2.0");
+    /// map.insert(Version::new(1, 0, 0), "v1.0.0");
+    /// map.insert(Version::new(1, 3, 2), "v1.3.2");
+    /// map.insert(Version::parse("1.4.0-rc.1").unwrap(), "v1.4.0-rc.1");
+    ///
+    /// assert_eq!(
+    ///     map.get_latest_compatible(&Version::new(1, 0, 0)),
+    ///     Some((&Version::new(1, 3, 2), &"v1.3.2"))
+    /// );
+    /// assert_eq!(
+    ///     map.get_latest_compatible(&Version::new(0, 1, 0)),
+    ///     Some((&Version::new(0, 1, 4), &"v0.1.4"))
+    /// );
+    /// assert_eq!(map.get_latest_compatible(&Version::new(0, 3, 0)), None);
+    /// ```
+    pub fn get_latest_compatible(&self, version: &Version) -> Option<(&Version, &T)> {
+        let (lower, upper) = caret_bounds(version);
+
+        self.versions
+            .range::<Version, _>((Bound::Included(&lower), upper.as_ref()))
+            .rev()
+            .find(|(k, _)| k.inner.pre.is_empty())
+            .map(|(k, v)| (k.borrow(), v))
+    }
+
+    /// Returns an iterator over the versions and values within `range`, in ascending version
+    /// order. No alternate lookup is performed; only versions stored in the map are returned.
+    ///
+    /// Pre-release versions sort before their release, so the range `1.0.0..2.0.0` includes
+    /// `2.0.0-alpha`. Use `2.0.0-0` as the exclusive upper bound to stop before any `2.x`
+    /// pre-release.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.insert(Version::new(0, 9, 0), "v0.9.0");
+    /// map.insert(Version::new(1, 0, 0), "v1.0.0");
+    /// map.insert(Version::new(1, 4, 2), "v1.4.2");
+    /// map.insert(Version::parse("2.0.0-rc.1").unwrap(), "v2.0.0-rc.1");
+    /// map.insert(Version::new(2, 0, 0), "v2.0.0");
+    ///
+    /// // All versions within major 1
+    /// let major_1 = Version::new(1, 0, 0)..Version::parse("2.0.0-0").unwrap();
+    /// let values: Vec<_> = map.range(major_1).map(|(_, value)| *value).collect();
+    /// assert_eq!(values, ["v1.0.0", "v1.4.2"]);
+    ///
+    /// // Newest version at or below 1.4.0
+    /// assert_eq!(
+    ///     map.range(..=Version::new(1, 4, 0)).next_back(),
+    ///     Some((&Version::new(1, 0, 0), &"v1.0.0"))
+    /// );
+    /// ```
+    pub fn range<R>(&self, range: R) -> impl DoubleEndedIterator<Item = (&Version, &T)>
+    where
+        R: RangeBounds<Version>,
+    {
+        self.versions
+            .range::<Version, R>(range)
+            .map(|(k, v)| (k.borrow(), v))
+    }
+
+    /// Like `range`, but yields mutable references to the values.
+    pub fn range_mut<R>(&mut self, range: R) -> impl DoubleEndedIterator<Item = (&Version, &mut T)>
+    where
+        R: RangeBounds<Version>,
+    {
+        self.versions
+            .range_mut::<Version, R>(range)
+            .map(|(k, v)| (k.borrow(), v))
+    }
+
+    /// Returns the stored versions that could satisfy a request for `version` under the map's
+    /// current lookup policies, in ascending version order.
+    ///
+    /// `get` selects the candidate with the highest priority, if a priority function is set.
+    /// Among candidates of equal priority it selects the last candidate, or, in
+    /// `ResolutionMode::Minimal`, the first candidate that is not older than `version`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let map = VersionMap::from_iter([
+    ///     (Version::new(0, 9, 0), "v0.9.0"),
+    ///     (Version::new(1, 0, 0), "v1.0.0"),
+    ///     (Version::new(1, 2, 0), "v1.2.0"),
+    ///     (Version::new(2, 0, 0), "v2.0.0"),
+    /// ]);
+    ///
+    /// let candidates: Vec<_> = map.candidates(&Version::new(1, 1, 0)).collect();
+    /// assert_eq!(candidates, [&Version::new(1, 0, 0), &Version::new(1, 2, 0)]);
+    /// ```
+    pub fn candidates(&self, version: &Version) -> impl DoubleEndedIterator<Item = &Version> {
+        self.candidate_keys(version).into_iter().map(Borrow::borrow)
+    }
+
+    fn candidate_keys(&self, version: &Version) -> Vec<&WrappedVersion> {
+        let build_policy = self.build_metadata_policy;
+
+        if !version.build.is_empty() && build_policy == BuildMetadataPolicy::Exact {
+            return self
+                .versions
+                .get_key_value(version)
+                .map(|(k, _)| k)
+                .into_iter()
+                .collect();
+        }
+
+        let request = Version {
+            build: BuildMetadata::EMPTY,
+            ..version.clone()
+        };
+        let release = Version::new(version.major, version.minor, version.patch);
+
+        let alternate = match self.pre_release_policy {
+            _ if request.pre.is_empty() => self.strategy.alternate(&request),
+            PreReleasePolicy::Exact => None,
+            PreReleasePolicy::OptIn => self.strategy.alternate(&release),
+        };
+
+        let mut candidates: Vec<_> = alternate
+            .and_then(|alternate| self.alternates.get(&alternate))
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if !request.pre.is_empty() && self.pre_release_policy == PreReleasePolicy::OptIn {
+            candidates.extend(
+                self.versions
+                    .range::<Version, _>((Bound::Included(&request), Bound::Excluded(&release)))
+                    .map(|(k, _)| k),
+            );
+        }
+
+        // Versions that differ from the request only by build metadata sort directly after it.
+        candidates.extend(
+            self.versions
+                .range::<Version, _>(&request..)
+                .map(|(k, _)| k)
+                .take_while(|k| k.inner.cmp_precedence(&request).is_eq()),
+        );
+
+        match build_policy {
+            BuildMetadataPolicy::Exact | BuildMetadataPolicy::Ignore => {}
+            BuildMetadataPolicy::RequireEqual => {
+                candidates.retain(|k| k.inner.build == version.build);
+            }
+            BuildMetadataPolicy::PreferMatching => {
+                if candidates.iter().any(|k| k.inner.build == version.build) {
+                    candidates.retain(|k| k.inner.build == version.build);
+                }
+            }
+        }
+
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    fn lookup(&self, version: &Version) -> Option<(&WrappedVersion, &T)> {
+        let candidates = self.candidate_keys(version);
+
+        let acceptable = candidates.into_iter().filter(|candidate| {
+            self.resolution_mode == ResolutionMode::Latest
+                || candidate.inner.cmp_precedence(version).is_ge()
+        });
+
+        self.select(acceptable)
+            .and_then(|version| self.versions.get_key_value(version))
+    }
+
+    /// Selects among acceptable versions, given in ascending order.
+    fn select<'a>(
+        &self,
+        acceptable: impl Iterator<Item = &'a WrappedVersion>,
+    ) -> Option<&'a WrappedVersion> {
+        let priority = self.priority.unwrap_or(|_| 0);
+
+        // `max_by_key` keeps the last and `min_by_key` the first of equally ranked versions.
+        match self.resolution_mode {
+            ResolutionMode::Latest => acceptable.max_by_key(|version| priority(&version.inner)),
+            ResolutionMode::Minimal => {
+                acceptable.min_by_key(|version| Reverse(priority(&version.inner)))
+            }
+        }
+    }
+
+    pub fn remove(&mut self, version: &Version) -> Option<T> {
+        if let Some(alternate) = self.strategy.alternate(version)
+            && let Some(set) = self.alternates.get_mut(&alternate)
+        {
+            set.remove(version);
+            if set.is_empty() {
+                self.alternates.remove(&alternate);
+            }
+        }
+
+        self.deprecations.remove(version);
+        self.versions.remove(version)
+    }
+
+    /// Marks a stored version as deprecated with the given message, replacing any previous
+    /// message. Deprecated versions are still selected by lookups.
+    ///
+    /// Returns `false`, and does nothing, if the version is not stored in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut map = VersionMap::new();
+    /// map.insert(Version::new(1, 0, 0), "v1.0.0");
+    ///
+    /// assert!(map.deprecate(&Version::new(1, 0, 0), "upgrade to 2.x"));
+    /// assert!(!map.deprecate(&Version::new(1, 1, 0), "not stored"));
+    ///
+    /// assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&"v1.0.0"));
+    /// assert_eq!(map.deprecation(&Version::new(1, 0, 0)), Some("upgrade to 2.x"));
+    /// ```
+    pub fn deprecate(&mut self, version: &Version, message: impl Into<String>) -> bool {
+        if !self.versions.contains_key(version) {
+            return false;
+        }
+
+        self.deprecations.insert(version.clone(), message.into());
+        true
+    }
+
+    /// Removes the deprecation marker from a version, returning its message.
+    pub fn undeprecate(&mut self, version: &Version) -> Option<String> {
+        self.deprecations.remove(version)
+    }
+
+    /// Returns the deprecation message of a stored version, if it has been deprecated.
+    ///
+    /// This is an exact lookup; pass the version returned by `get_version` to check the version a
+    /// lookup actually selected.
+    pub fn deprecation(&self, version: &Version) -> Option<&str> {
+        self.deprecations.get(version).map(String::as_str)
+    }
+
+    /// Merges all versions from `other` into this map, keeping this map's lookup policies.
+    ///
+    /// When both maps contain the same version, `on_conflict` is called with the version, this
+    /// map's value, and `other`'s value, and its result is stored. Deprecation markers from
+    /// `other` replace those in this map.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let mut catalog = VersionMap::from_iter([
+    ///     (Version::new(1, 0, 0), "base-1.0.0"),
+    ///     (Version::new(1, 1, 0), "base-1.1.0"),
+    /// ]);
+    /// let tenant = VersionMap::from_iter([
+    ///     (Version::new(1, 1, 0), "tenant-1.1.0"),
+    ///     (Version::new(2, 0, 0), "tenant-2.0.0"),
+    /// ]);
+    ///
+    /// // Layer the tenant catalog over the base catalog.
+    /// catalog.merge(tenant, |_version, _base, tenant| tenant);
+    ///
+    /// assert_eq!(catalog.get_exact(&Version::new(1, 0, 0)), Some(&"base-1.0.0"));
+    /// assert_eq!(catalog.get_exact(&Version::new(1, 1, 0)), Some(&"tenant-1.1.0"));
+    /// assert_eq!(catalog.get(&Version::new(2, 0, 0)), Some(&"tenant-2.0.0"));
+    /// ```
+    pub fn merge<F>(&mut self, mut other: VersionMap<T>, mut on_conflict: F)
+    where
+        F: FnMut(&Version, T, T) -> T,
+    {
+        let deprecations = std::mem::take(&mut other.deprecations);
+
+        for (version, value) in other {
+            let value = match self.versions.remove(&version) {
+                Some(existing) => on_conflict(&version, existing, value),
+                None => value,
+            };
+
+            self.insert(version, value);
+        }
+
+        self.deprecations.extend(deprecations);
+    }
+
+    /// Compares the stored versions of this map against `other`, returning the changes needed to
+    /// turn this map into `other`, in ascending version order.
+    ///
+    /// Lookup policies are not compared.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::{VersionChange, VersionMap};
+    ///
+    /// let old = VersionMap::from_iter([
+    ///     (Version::new(1, 0, 0), "sha256:aaa"),
+    ///     (Version::new(1, 1, 0), "sha256:bbb"),
+    /// ]);
+    /// let new = VersionMap::from_iter([
+    ///     (Version::new(1, 1, 0), "sha256:ccc"),
+    ///     (Version::new(1, 2, 0), "sha256:ddd"),
+    /// ]);
+    ///
+    /// let v = |minor| Version::new(1, minor, 0);
+    /// assert_eq!(
+    ///     old.diff(&new),
+    ///     [
+    ///         VersionChange::Removed(&v(0), &"sha256:aaa"),
+    ///         VersionChange::Changed(&v(1), &"sha256:bbb", &"sha256:ccc"),
+    ///         VersionChange::Added(&v(2), &"sha256:ddd"),
+    ///     ]
+    /// );
+    /// ```
+    pub fn diff<'a>(&'a self, other: &'a VersionMap<T>) -> Vec<VersionChange<'a, T>>
+    where
+        T: PartialEq,
+    {
+        let mut changes = Vec::new();
+        let mut old = self.iter().peekable();
+        let mut new = other.iter().peekable();
+
+        loop {
+            let change = match (old.peek(), new.peek()) {
+                (Some((old_version, _)), Some((new_version, _))) => {
+                    match old_version.cmp(new_version) {
+                        std::cmp::Ordering::Less => old.next().map(VersionChange::removed),
+                        std::cmp::Ordering::Greater => new.next().map(VersionChange::added),
+                        std::cmp::Ordering::Equal => {
+                            let (version, old_value) = old.next().unwrap();
+                            let (_, new_value) = new.next().unwrap();
+
+                            (old_value != new_value)
+                                .then_some(VersionChange::Changed(version, old_value, new_value))
+                        }
+                    }
+                }
+                (Some(_), None) => old.next().map(VersionChange::removed),
+                (None, Some(_)) => new.next().map(VersionChange::added),
+                (None, None) => break,
+            };
+
+            changes.extend(change);
+        }
+
+        changes
+    }
+
+    /// Returns the number of versions stored in the map.
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// Returns `true` if the map contains no versions.
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    /// Returns an iterator over the stored versions and values, in ascending version order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.versions.iter(),
+        }
+    }
+
+    /// Like `iter`, but yields mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            inner: self.versions.iter_mut(),
+        }
+    }
+}
+
+impl<T> FromIterator<(Version, T)> for VersionMap<T> {
+    /// Builds a map from version-value pairs. Later values replace earlier values for the same
+    /// version, as with `insert`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use semver::Version;
+    /// # use wasm_component_semver::VersionMap;
+    ///
+    /// let map: VersionMap<_> = [
+    ///     (Version::new(1, 0, 0), "v1.0.0"),
+    ///     (Version::new(1, 1, 0), "v1.1.0"),
+    /// ]
+    /// .into_iter()
+    /// .collect();
+    ///
+    /// assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&"v1.1.0"));
+    /// ```
+    fn from_iter<I: IntoIterator<Item = (Version, T)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<T> Extend<(Version, T)> for VersionMap<T> {
+    fn extend<I: IntoIterator<Item = (Version, T)>>(&mut self, iter: I) {
+        for (version, value) in iter {
+            self.insert(version, value);
+        }
+    }
+}
+
+impl<T> IntoIterator for VersionMap<T> {
+    type Item = (Version, T);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.versions.into_iter(),
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a VersionMap<T> {
+    type Item = (&'a Version, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut VersionMap<T> {
+    type Item = (&'a Version, &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// A difference between two `VersionMap`s, as returned by `VersionMap::diff`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum VersionChange<'a, T> {
+    /// The version is only present in the other map.
+    Added(&'a Version, &'a T),
+
+    /// The version is only present in this map.
+    Removed(&'a Version, &'a T),
+
+    /// The version is present in both maps with different values, given as `(old, new)`.
+    Changed(&'a Version, &'a T, &'a T),
+}
+
+impl<T> Clone for VersionChange<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for VersionChange<'_, T> {}
+
+impl<'a, T> VersionChange<'a, T> {
+    fn added((version, value): (&'a Version, &'a T)) -> Self {
+        VersionChange::Added(version, value)
+    }
+
+    fn removed((version, value): (&'a Version, &'a T)) -> Self {
+        VersionChange::Removed(version, value)
+    }
+
+    /// Returns the version that changed.
+    pub fn version(&self) -> &'a Version {
+        match self {
+            VersionChange::Added(version, _)
+            | VersionChange::Removed(version, _)
+            | VersionChange::Changed(version, _, _) => version,
+        }
+    }
+}
+
+/// An owning iterator over the versions and values of a `VersionMap`, in ascending version order.
+#[derive(Debug)]
+pub struct IntoIter<T> {
+    inner: btree_map::IntoIter<WrappedVersion, T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (Version, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (k.inner, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, v)| (k.inner, v))
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+/// An iterator over the versions and values of a `VersionMap`, in ascending version order.
+#[derive(Clone, Debug)]
+pub struct Iter<'a, T> {
+    inner: btree_map::Iter<'a, WrappedVersion, T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a Version, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (k.borrow(), v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, v)| (k.borrow(), v))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+/// A mutable iterator over the versions and values of a `VersionMap`, in ascending version order.
+#[derive(Debug)]
+pub struct IterMut<'a, T> {
+    inner: btree_map::IterMut<'a, WrappedVersion, T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (&'a Version, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (k.borrow(), v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for IterMut<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, v)| (k.borrow(), v))
+    }
+}
+
+impl<T> ExactSizeIterator for IterMut<'_, T> {}
+
+#[cfg(feature = "borsh")]
+/// Only the stored versions and their values are serialized. Deprecations, the alternate
+/// strategy, the pre-release, build metadata and resolution settings, and the priority function
+/// are not, and deserialized maps use the defaults of `VersionMap::new`.
+impl<T: borsh::BorshSerialize> borsh::BorshSerialize for VersionMap<T> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.versions.serialize(writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+/// Restores the stored versions and their values with the default settings of `VersionMap::new`
+/// and no deprecations; apply any other settings to the deserialized map.
+impl<T: borsh::BorshDeserialize> borsh::BorshDeserialize for VersionMap<T> {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let versions: BTreeMap<WrappedVersion, T> =
+            borsh::BorshDeserialize::deserialize_reader(reader)?;
+        Ok(Self::from_versions(versions))
+    }
+}
+
+#[cfg(feature = "serde")]
+/// Only the stored versions and their values are serialized. Deprecations, the alternate
+/// strategy, the pre-release, build metadata and resolution settings, and the priority function
+/// are not, and deserialized maps use the defaults of `VersionMap::new`.
+impl<T: serde::Serialize> serde::Serialize for VersionMap<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.versions.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+/// Restores the stored versions and their values with the default settings of `VersionMap::new`
+/// and no deprecations; apply any other settings to the deserialized map.
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for VersionMap<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let versions: BTreeMap<WrappedVersion, T> = serde::Deserialize::deserialize(deserializer)?;
+        Ok(Self::from_versions(versions))
+    }
+}
+
+/// Computes the alternate version key for fallback lookups.
+///
+/// This function implements the `AlternateStrategy::CaretCompatible` lookup logic:
+/// - Pre-release versions return `None` (no alternates)
+/// - Major versions > 0: return `major.0.0`
+/// - Minor versions > 0 (when major is 0): return `0.minor.0`
+/// - Otherwise: return `0.0.patch`
+fn version_alternate(version: &Version) -> Option<Version> {
+    // Pre-release versions don't have alternates
+    if !version.pre.is_empty() {
+        None
+    } else if version.major > 0 {
+        Some(Version::new(version.major, 0, 0))
+    } else if version.minor > 0 {
+        Some(Version::new(0, version.minor, 0))
+    } else {
+        Some(Version::new(0, 0, version.patch))
+    }
+}
+
+/// Computes the range of release versions that are caret-compatible with `version`, as an
+/// inclusive lower bound and an exclusive upper bound.
+fn caret_bounds(version: &Version) -> (Version, Bound<Version>) {
+    let (lower, upper) = if version.major > 0 {
+        (
+            Version::new(version.major, 0, 0),
+            version
+                .major
+                .checked_add(1)
+                .map(|major| Version::new(major, 0, 0)),
+        )
+    } else if version.minor > 0 {
+        (
+            Version::new(0, version.minor, 0),
+            version
+                .minor
+                .checked_add(1)
+                .map(|minor| Version::new(0, minor, 0)),
+        )
+    } else {
+        (
+            Version::new(0, 0, version.patch),
+            version
+                .patch
+                .checked_add(1)
+                .map(|patch| Version::new(0, 0, patch)),
+        )
+    };
+
+    (lower, upper.map_or(Bound::Unbounded, Bound::Excluded))
+}
+
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[repr(transparent)]
+struct WrappedVersion {
+    inner: Version,
+}
+
+impl Borrow<Version> for WrappedVersion {
+    fn borrow(&self) -> &Version {
+        &self.inner
+    }
+}
+
+impl From<Version> for WrappedVersion {
+    fn from(version: Version) -> Self {
+        Self { inner: version }
+    }
+}
+
+impl From<WrappedVersion> for Version {
+    fn from(wrapped: WrappedVersion) -> Self {
+        wrapped.inner
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshSerialize for WrappedVersion {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let s = self.inner.to_string();
+        borsh::BorshSerialize::serialize(&s, writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshDeserialize for WrappedVersion {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let s: String = borsh::BorshDeserialize::deserialize_reader(reader)?;
+
+        let version = Version::parse(&s).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Failed to parse version from string",
+            )
+        })?;
+
+        Ok(Self { inner: version })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for WrappedVersion {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.inner.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for WrappedVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(Self {
+            inner: serde::Deserialize::deserialize(deserializer)?,
+        })
+    }
+}
+
+/// Strategies generating versions and `VersionMap`s for property tests.
+#[cfg(feature = "proptest")]
+pub mod strategies {
+    use super::VersionMap;
+    use proptest::arbitrary::Arbitrary;
+    use proptest::collection::{SizeRange, btree_map};
+    use proptest::prelude::*;
+    use semver::{Prerelease, Version};
+    use std::fmt::Debug;
+
+    /// Generates release versions with small components, so that generated versions often share
+    /// alternates.
+    pub fn release_version() -> impl Strategy<Value = Version> {
+        (0..4_u64, 0..4_u64, 0..4_u64)
+            .prop_map(|(major, minor, patch)| Version::new(major, minor, patch))
+    }
+
+    /// Generates release and pre-release versions, as `release_version` does.
+    pub fn version() -> impl Strategy<Value = Version> {
+        let pre = prop_oneof![
+            3 => Just(Prerelease::EMPTY),
+            1 => prop::sample::select(vec!["alpha", "alpha.1", "beta.2", "rc.1"])
+                .prop_map(|pre| Prerelease::new(pre).unwrap()),
+        ];
+
+        (release_version(), pre).prop_map(|(mut version, pre)| {
+            version.pre = pre;
+            version
+        })
+    }
+
+    /// Generates maps with the default policies, of `size` versions generated by `version`,
+    /// holding values generated by `values`.
+    pub fn version_map<T: Debug>(
+        values: impl Strategy<Value = T>,
+        size: impl Into<SizeRange>,
+    ) -> impl Strategy<Value = VersionMap<T>> {
+        btree_map(version(), values, size).prop_map(VersionMap::from_iter)
+    }
+
+    impl<T: Arbitrary + 'static> Arbitrary for VersionMap<T>
+    where
+        T::Strategy: 'static,
+    {
+        type Parameters = T::Parameters;
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(parameters: Self::Parameters) -> Self::Strategy {
+            version_map(any_with::<T>(parameters), 0..16).boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn test_version_map_basic_operations() {
+        let mut map = VersionMap::new();
+
+        let version0 = Version::new(0, 4, 2);
+        let version1 = Version::new(1, 0, 0);
+        let version2 = Version::new(1, 0, 1);
+        let version3 = Version::new(2, 0, 0);
+
+        // Test insertions
+        assert!(map.try_insert(version0.clone(), "value0").is_ok());
+        assert!(map.try_insert(version1.clone(), "value1").is_ok());
+        assert!(map.try_insert(version2.clone(), "value2").is_ok());
+        assert!(map.try_insert(version3.clone(), "value3").is_ok());
+
+        // Test duplicate insertion
+        assert!(map.try_insert(version1.clone(), "duplicate").is_err());
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn test_version_map_finds_stored_versions(map in proptest::prelude::any::<VersionMap<u8>>()) {
+            for (version, value) in &map {
+                proptest::prop_assert_eq!(map.get_exact(version), Some(value));
+                proptest::prop_assert!(map.get(version).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_version_map_alternate_zero() {
+        let mut map = VersionMap::new();
+
+        let version0 = Version::new(0, 0, 3);
+        let version1 = Version::new(0, 3, 3);
+
+        map.try_insert(version0.clone(), "value0").unwrap();
+        map.try_insert(version1.clone(), "value1").unwrap();
+
+        assert_eq!(map.get_version(&Version::new(0, 0, 1)), None);
+
+        assert_eq!(
+            map.get_version(&Version::new(0, 0, 3)),
+            Some((&version0, &"value0"))
+        );
+
+        assert_eq!(
+            map.get_version(&Version::new(0, 3, 0)),
+            Some((&version1, &"value1"))
+        );
+    }
+
+    #[test]
+    fn test_version_map_alternate_lookups() {
+        let mut map = VersionMap::new();
+
+        let version0 = Version::new(0, 4, 2);
+        let version1 = Version::new(1, 0, 0);
+        let version2 = Version::new(1, 0, 1);
+        let version3 = Version::new(2, 0, 0);
+
+        map.try_insert(version0.clone(), "value0").unwrap();
+        map.try_insert(version1.clone(), "value1").unwrap();
+        map.try_insert(version2.clone(), "value2").unwrap();
+        map.try_insert(version3.clone(), "value3").unwrap();
+
+        // Test exact matches
+        assert_eq!(map.get(&version0), Some(&"value0"));
+        assert_eq!(map.get(&version2), Some(&"value2"));
+        assert_eq!(map.get(&version3), Some(&"value3"));
+
+        // Test alternate matches (should get latest in group)
+        assert_eq!(map.get(&version1), Some(&"value2")); // 1.0.0 -> latest in 1.x.x group
+        assert_eq!(map.get(&Version::new(0, 4, 1)), Some(&"value0")); // 0.4.1 -> latest in 0.4.x group
+        assert_eq!(map.get(&Version::new(1, 1, 0)), Some(&"value2")); // 1.1.0 -> latest in 1.x.x group
+        assert_eq!(map.get(&Version::new(2, 0, 4)), Some(&"value3")); // 2.0.4 -> latest in 2.x.x group
+
+        // Test alternate matches with get_version
+        assert_eq!(map.get_version(&version1), Some((&version2, &"value2"))); // 1.0.0 -> latest in 1.x.x group
+        assert_eq!(
+            map.get_version(&Version::new(0, 4, 1)),
+            Some((&version0, &"value0"))
+        ); // 0.4.1 -> latest in 0.4.x group
+        assert_eq!(
+            map.get_version(&Version::new(1, 1, 0)),
+            Some((&version2, &"value2"))
+        ); // 1.1.0 -> latest in 1.x.x group
+        assert_eq!(
+            map.get_version(&Version::new(2, 0, 4)),
+            Some((&version3, &"value3"))
+        ); // 2.0.4 -> latest in 2.x.x group
+
+        // Test non-existent versions
+        assert_eq!(map.get(&Version::new(0, 1, 0)), None);
+        assert_eq!(map.get(&Version::new(3, 0, 0)), None);
+
+        // Test exact lookups
+        assert_eq!(map.get_exact(&version1), Some(&"value1"));
+        assert_eq!(map.get_exact(&Version::new(1, 1, 0)), None); // No exact match
+
+        *map.get_exact_mut(&version1).unwrap() = "updated";
+        assert_eq!(map.get_exact(&version1), Some(&"updated"));
+        assert_eq!(map.get_exact_mut(&Version::new(1, 1, 0)), None);
+    }
+
+    #[test]
+    fn test_version_map_get_or_insert_with() {
+        let version1 = Version::new(1, 0, 0);
+        let version2 = Version::new(1, 1, 0);
+        let mut map = VersionMap::from_iter([(version1.clone(), "value1")]);
+
+        assert_eq!(
+            *map.get_or_insert_with(version1.clone(), || unreachable!()),
+            "value1"
+        );
+
+        // Lazily inserted versions join their alternate group
+        *map.get_or_insert_with(version2.clone(), || "inserted") = "value2";
+        assert_eq!(map.get_exact(&version2), Some(&"value2"));
+        assert_eq!(map.get(&version1), Some(&"value2"));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_version_map_latest_operations() {
+        let mut map = VersionMap::new();
+
+        assert_eq!(map.get_latest(), None);
+        assert_eq!(map.get_or_latest(None), None);
+
+        map.insert(Version::new(1, 0, 0), "v1.0.0");
+        map.insert(Version::new(2, 0, 0), "v2.0.0");
+        map.insert(Version::new(0, 1, 0), "v0.1.0");
+
+        assert_eq!(map.get_latest(), Some((&Version::new(2, 0, 0), &"v2.0.0")));
+        assert_eq!(map.get_or_latest(None), Some(&"v2.0.0"));
+        assert_eq!(
+            map.get_or_latest(Some(&Version::new(1, 0, 0))),
+            Some(&"v1.0.0")
+        );
+    }
+
+    #[test]
+    fn test_version_map_insert_and_removal() {
+        let mut map = VersionMap::new();
+
+        let v1 = Version::new(1, 0, 0);
+        let v2 = Version::new(1, 0, 1);
+
+        map.insert(v1.clone(), "v1");
+        map.insert(v2.clone(), "v2");
+
+        assert_eq!(map.remove(&v1), Some("v1"));
+        assert_eq!(map.remove(&v1), None); // Already removed
+    }
+
+    #[test]
+    fn test_version_map_range() {
+        let mut map = VersionMap::new();
+
+        let versions = [
+            Version::new(0, 4, 2),
+            Version::new(1, 0, 0),
+            Version::new(1, 3, 0),
+            Version::parse("2.0.0-alpha").unwrap(),
+            Version::new(2, 0, 0),
+        ];
+
+        for version in &versions {
+            map.insert(version.clone(), version.to_string());
+        }
+
+        let keys = |range: Vec<(&Version, &String)>| {
+            range
+                .into_iter()
+                .map(|(k, _)| k.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys(map.range(..).collect()), versions);
+        assert_eq!(
+            keys(
+                map.range(Version::new(1, 0, 0)..Version::new(2, 0, 0))
+                    .collect()
+            ),
+            versions[1..4]
+        );
+        assert_eq!(
+            keys(
+                map.range(Version::new(1, 0, 0)..Version::parse("2.0.0-0").unwrap())
+                    .collect()
+            ),
+            versions[1..3]
+        );
+        assert_eq!(
+            keys(map.range(Version::new(1, 0, 0)..).rev().collect()),
+            versions[1..].iter().rev().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(map.range(Version::new(3, 0, 0)..).next(), None);
+
+        for (_, value) in map.range_mut(..Version::new(1, 0, 0)) {
+            value.push_str("-patched");
+        }
+        assert_eq!(
+            map.get_exact(&Version::new(0, 4, 2)),
+            Some(&"0.4.2-patched".to_string())
+        );
+    }
+
+    #[test]
+    fn test_version_map_pre_release_policy() {
+        let rc1 = Version::parse("1.0.0-rc.1").unwrap();
+        let rc2 = Version::parse("1.0.0-rc.2").unwrap();
+        let beta = Version::parse("1.1.0-beta").unwrap();
+
+        let mut map = VersionMap::new();
+        map.insert(rc1.clone(), "rc1");
+        map.insert(rc2.clone(), "rc2");
+        map.insert(beta.clone(), "beta");
+
+        // Exact policy: pre-releases are only matched exactly.
+        assert_eq!(map.pre_release_policy(), PreReleasePolicy::Exact);
+        assert_eq!(map.get(&rc1), Some(&"rc1"));
+        assert_eq!(map.get(&Version::parse("1.0.0-rc.0").unwrap()), None);
+        assert_eq!(map.get(&Version::new(1, 0, 0)), None);
+
+        map.set_pre_release_policy(PreReleasePolicy::OptIn);
+
+        // Opted-in requests select the latest pre-release of the same release.
+        assert_eq!(map.get_version(&rc1), Some((&rc2, &"rc2")));
+        assert_eq!(
+            map.get_version(&Version::parse("1.0.0-alpha").unwrap()),
+            Some((&rc2, &"rc2"))
+        );
+        assert_eq!(map.get(&Version::parse("1.0.0-rc.3").unwrap()), None);
+        assert_eq!(
+            map.get(&Version::parse("1.1.0-alpha").unwrap()),
+            Some(&"beta")
+        );
+
+        // Requests without a pre-release never select one.
+        assert_eq!(map.get(&Version::new(1, 0, 0)), None);
+
+        // Releases win over pre-releases once available.
+        map.insert(Version::new(1, 2, 0), "v1.2.0");
+        assert_eq!(map.get(&rc1), Some(&"v1.2.0"));
+        assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&"v1.2.0"));
+    }
+
+    #[test]
+    fn test_version_map_collection_traits() {
+        let versions = [
+            Version::new(2, 0, 0),
+            Version::new(0, 1, 0),
+            Version::new(1, 0, 0),
+        ];
+
+        let mut map: VersionMap<_> = versions
+            .iter()
+            .map(|version| (version.clone(), version.major))
+            .collect();
+
+        assert_eq!(map.len(), 3);
+        assert!(!map.is_empty());
+        assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&1));
+
+        map.extend([(Version::new(1, 1, 0), 11), (Version::new(2, 0, 0), 20)]);
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&Version::new(1, 0, 0)), Some(&11));
+        assert_eq!(map.get_exact(&Version::new(2, 0, 0)), Some(&20));
+
+        for (_, value) in &mut map {
+            *value += 100;
+        }
+
+        let borrowed: Vec<_> = (&map).into_iter().map(|(k, v)| (k.clone(), *v)).collect();
+        assert_eq!(map.iter().len(), 4);
+        assert_eq!(map.iter().next_back(), Some((&Version::new(2, 0, 0), &120)));
+
+        let owned: Vec<_> = map.into_iter().collect();
+        assert_eq!(owned, borrowed);
+        assert_eq!(
+            owned,
+            [
+                (Version::new(0, 1, 0), 100),
+                (Version::new(1, 0, 0), 101),
+                (Version::new(1, 1, 0), 111),
+                (Version::new(2, 0, 0), 120),
+            ]
+        );
+
+        assert!(VersionMap::<()>::new().is_empty());
+    }
+
+    #[test]
+    fn test_version_map_get_latest_compatible() {
+        let mut map = VersionMap::new();
+
+        for version in [
+            "0.0.3",
+            "0.0.4",
+            "0.2.1",
+            "0.2.7",
+            "1.0.0",
+            "1.9.0",
+            "2.0.0-alpha",
+        ] {
+            map.insert(Version::parse(version).unwrap(), version);
+        }
+        map.insert(Version::parse("1.9.1+build.5").unwrap(), "1.9.1+build.5");
+
+        let latest = |version: &str| {
+            map.get_latest_compatible(&Version::parse(version).unwrap())
+                .map(|(_, value)| *value)
+        };
+
+        assert_eq!(latest("0.0.3"), Some("0.0.3"));
+        assert_eq!(latest("0.0.5"), None);
+        assert_eq!(latest("0.2.0"), Some("0.2.7"));
+        assert_eq!(latest("0.2.9"), Some("0.2.7"));
+        assert_eq!(latest("1.0.0"), Some("1.9.1+build.5"));
+        assert_eq!(latest("1.5.0-rc.1"), Some("1.9.1+build.5"));
+        assert_eq!(latest("2.0.0"), None);
+        assert_eq!(latest("2.0.0-alpha"), None);
+        assert_eq!(latest("3.0.0"), None);
+
+        // Bounds saturate instead of overflowing.
+        let max = Version::new(u64::MAX, 1, 0);
+        let map = VersionMap::from_iter([(max.clone(), "max")]);
+        assert_eq!(
+            map.get_latest_compatible(&Version::new(u64::MAX, 0, 0)),
+            Some((&max, &"max"))
+        );
+    }
+
+    #[test]
+    fn test_version_map_strategies() {
+        let mut map: VersionMap<_> = ["0.1.0", "0.2.3", "1.0.0", "1.4.0", "1.4.1-rc.1"]
+            .into_iter()
+            .map(|version| (Version::parse(version).unwrap(), version))
+            .collect();
+
+        let get = |map: &VersionMap<&'static str>, version: &str| {
+            map.get(&Version::parse(version).unwrap()).copied()
+        };
+
+        assert!(matches!(map.strategy(), AlternateStrategy::CaretCompatible));
+        assert_eq!(get(&map, "0.1.0"), Some("0.1.0"));
+        assert_eq!(get(&map, "1.0.0"), Some("1.4.0"));
+
+        map.set_strategy(AlternateStrategy::Exact);
+        assert_eq!(get(&map, "1.0.0"), Some("1.0.0"));
+        assert_eq!(get(&map, "1.2.0"), None);
+
+        map.set_strategy(AlternateStrategy::LatestInMajor);
+        assert_eq!(get(&map, "0.1.0"), Some("0.2.3"));
+        assert_eq!(get(&map, "0.9.0"), Some("0.2.3"));
+        assert_eq!(get(&map, "1.2.0"), Some("1.4.0"));
+
+        // Group by major and minor, e.g. for ecosystems that break compatibility on minor bumps.
+        let map = map.with_strategy(AlternateStrategy::Custom(|version| {
+            Some(Version::new(version.major, version.minor, 0))
+        }));
+        assert_eq!(get(&map, "1.0.0"), Some("1.0.0"));
+        assert_eq!(get(&map, "1.4.0"), Some("1.4.0"));
+        assert_eq!(get(&map, "1.2.0"), None);
+
+        // Later insertions and removals use the configured strategy.
+        let mut map = map;
+        map.insert(Version::new(1, 4, 9), "1.4.9");
+        assert_eq!(get(&map, "1.4.0"), Some("1.4.9"));
+        map.remove(&Version::new(1, 4, 9));
+        assert_eq!(get(&map, "1.4.2"), Some("1.4.0"));
+    }
+
+    #[test]
+    fn test_version_map_merge() {
+        let mut base = VersionMap::new().with_strategy(AlternateStrategy::Exact);
+        base.insert(Version::new(1, 0, 0), vec!["base"]);
+        base.insert(Version::new(1, 2, 0), vec!["base"]);
+
+        let mut overlay = VersionMap::new();
+        overlay.insert(Version::new(1, 2, 0), vec!["overlay"]);
+        overlay.insert(Version::new(1, 3, 0), vec!["overlay"]);
+
+        let mut conflicts = Vec::new();
+        base.merge(overlay, |version, mut existing, incoming| {
+            conflicts.push(version.clone());
+            existing.extend(incoming);
+            existing
+        });
+
+        assert_eq!(conflicts, [Version::new(1, 2, 0)]);
+        assert_eq!(base.len(), 3);
+        assert_eq!(
+            base.get_exact(&Version::new(1, 2, 0)),
+            Some(&vec!["base", "overlay"])
+        );
+
+        // The merged map keeps its own strategy, and indexes merged versions with it.
+        assert!(matches!(base.strategy(), AlternateStrategy::Exact));
+        assert_eq!(base.get(&Version::new(1, 1, 0)), None);
+        base.set_strategy(AlternateStrategy::CaretCompatible);
+        assert_eq!(base.get(&Version::new(1, 1, 0)), Some(&vec!["overlay"]));
+    }
+
+    #[test]
+    fn test_version_map_diff() {
+        let old: VersionMap<_> = [(1, "a"), (2, "b"), (4, "d"), (5, "e")]
+            .into_iter()
+            .map(|(major, value)| (Version::new(major, 0, 0), value))
+            .collect();
+        let new: VersionMap<_> = [(0, "z"), (2, "b"), (4, "D"), (6, "f")]
+            .into_iter()
+            .map(|(major, value)| (Version::new(major, 0, 0), value))
+            .collect();
+
+        let changes = old.diff(&new);
+        let versions: Vec<_> = changes
+            .iter()
+            .map(|change| change.version().major)
+            .collect();
+        assert_eq!(versions, [0, 1, 4, 5, 6]);
+
+        assert!(matches!(changes[0], VersionChange::Added(_, &"z")));
+        assert!(matches!(changes[1], VersionChange::Removed(_, &"a")));
+        assert!(matches!(changes[2], VersionChange::Changed(_, &"d", &"D")));
+        assert!(matches!(changes[3], VersionChange::Removed(_, &"e")));
+        assert!(matches!(changes[4], VersionChange::Added(_, &"f")));
+
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(VersionMap::new().diff(&old).len(), old.len());
+    }
+
+    #[test]
+    fn test_version_map_candidates() {
+        let mut map: VersionMap<_> = [
+            "0.1.0",
+            "0.1.2",
+            "1.0.0",
+            "1.1.0-rc.1",
+            "1.1.0-rc.2",
+            "1.1.0",
+            "1.1.1+build",
+            "2.0.0",
+        ]
+        .into_iter()
+        .map(|version| (Version::parse(version).unwrap(), version))
+        .collect();
+
+        let candidates = |map: &VersionMap<&'static str>, version: &str| {
+            map.candidates(&Version::parse(version).unwrap())
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(candidates(&map, "0.1.1"), ["0.1.0", "0.1.2"]);
+        assert_eq!(candidates(&map, "1.5.0"), ["1.0.0", "1.1.0", "1.1.1+build"]);
+        assert_eq!(candidates(&map, "1.1.1+build"), ["1.1.1+build"]);
+        assert_eq!(candidates(&map, "1.1.0-rc.1"), ["1.1.0-rc.1"]);
+        assert!(candidates(&map, "3.0.0").is_empty());
+
+        map.set_pre_release_policy(PreReleasePolicy::OptIn);
+        assert_eq!(
+            candidates(&map, "1.1.0-rc.2"),
+            ["1.0.0", "1.1.0-rc.2", "1.1.0", "1.1.1+build"]
+        );
+
+        map.set_strategy(AlternateStrategy::Exact);
+        assert_eq!(candidates(&map, "1.1.0-rc.0"), ["1.1.0-rc.1", "1.1.0-rc.2"]);
+        assert_eq!(candidates(&map, "2.0.0"), ["2.0.0"]);
+
+        // Without a priority function, the selected version is always the last candidate.
+        for version in ["0.1.0", "1.1.0-rc.0", "2.0.0", "1.1.0"] {
+            let version = Version::parse(version).unwrap();
+            assert_eq!(
+                map.get_version(&version).map(|(k, _)| k),
+                map.candidates(&version).next_back()
+            );
+        }
+    }
+
+    #[test]
+    fn test_version_map_deprecation() {
+        let v1 = Version::new(1, 0, 0);
+        let v2 = Version::new(1, 1, 0);
+
+        let mut map = VersionMap::from_iter([(v1.clone(), "v1"), (v2.clone(), "v2")]);
+
+        assert!(map.deprecate(&v2, "broken release"));
+        assert!(!map.deprecate(&Version::new(3, 0, 0), "missing"));
+        assert_eq!(map.deprecation(&v1), None);
+
+        let (selected, _) = map.get_version(&v1).unwrap();
+        assert_eq!(selected, &v2);
+        assert_eq!(map.deprecation(selected), Some("broken release"));
+
+        assert_eq!(map.undeprecate(&v2), Some("broken release".to_string()));
+        assert_eq!(map.deprecation(&v2), None);
+
+        // Removing a version drops its marker, so it doesn't carry over on re-insertion.
+        map.deprecate(&v1, "old");
+        map.remove(&v1);
+        map.insert(v1.clone(), "v1");
+        assert_eq!(map.deprecation(&v1), None);
+
+        // Merging carries deprecation markers over from the other map.
+        let mut other = VersionMap::from_iter([(v2.clone(), "v2-other")]);
+        other.deprecate(&v2, "superseded");
+        map.merge(other, |_, _, other| other);
+        assert_eq!(map.deprecation(&v2), Some("superseded"));
+    }
+
+    #[test]
+    fn test_version_map_build_metadata_policy() {
+        let mut map: VersionMap<_> = ["1.0.0", "1.0.0+linux", "1.0.0+macos", "1.2.0+linux"]
+            .into_iter()
+            .map(|version| (Version::parse(version).unwrap(), version))
+            .collect();
+
+        let get = |map: &VersionMap<&'static str>, version: &str| {
+            map.get(&Version::parse(version).unwrap()).copied()
+        };
+
+        // Exact: build metadata on the request disables alternate lookup.
+        assert_eq!(map.build_metadata_policy(), BuildMetadataPolicy::Exact);
+        assert_eq!(get(&map, "1.0.0+macos"), Some("1.0.0+macos"));
+        assert_eq!(get(&map, "1.1.0+linux"), None);
+        assert_eq!(get(&map, "1.0.0"), Some("1.2.0+linux"));
+
+        map.set_build_metadata_policy(BuildMetadataPolicy::Ignore);
+        assert_eq!(get(&map, "1.0.0+macos"), Some("1.2.0+linux"));
+        assert_eq!(get(&map, "1.1.0+windows"), Some("1.2.0+linux"));
+
+        map.set_build_metadata_policy(BuildMetadataPolicy::RequireEqual);
+        assert_eq!(get(&map, "1.0.0+macos"), Some("1.0.0+macos"));
+        assert_eq!(get(&map, "1.1.0+linux"), Some("1.2.0+linux"));
+        assert_eq!(get(&map, "1.0.0"), Some("1.0.0"));
+        assert_eq!(get(&map, "1.0.0+windows"), None);
+
+        map.set_build_metadata_policy(BuildMetadataPolicy::PreferMatching);
+        assert_eq!(get(&map, "1.0.0+macos"), Some("1.0.0+macos"));
+        assert_eq!(get(&map, "1.0.0+windows"), Some("1.2.0+linux"));
+
+        // Without alternates, build metadata is still matched per policy.
+        map.set_strategy(AlternateStrategy::Exact);
+        assert_eq!(get(&map, "1.0.0+windows"), Some("1.0.0+macos"));
+        assert_eq!(get(&map, "1.0.0+linux"), Some("1.0.0+linux"));
+        map.set_build_metadata_policy(BuildMetadataPolicy::Ignore);
+        assert_eq!(get(&map, "1.0.0+linux"), Some("1.0.0+macos"));
+    }
+
+    #[test]
+    fn test_version_map_minimal_resolution() {
+        let map: VersionMap<_> = ["0.3.0", "0.3.4", "1.0.0", "1.2.0", "1.2.5", "1.4.0"]
+            .into_iter()
+            .map(|version| (Version::parse(version).unwrap(), version))
+            .collect();
+        let map = map.with_resolution_mode(ResolutionMode::Minimal);
+
+        let get = |version: &str| map.get(&Version::parse(version).unwrap()).copied();
+
+        assert_eq!(map.resolution_mode(), ResolutionMode::Minimal);
+        assert_eq!(get("1.0.0"), Some("1.0.0"));
+        assert_eq!(get("1.1.0"), Some("1.2.0"));
+        assert_eq!(get("1.2.1"), Some("1.2.5"));
+        assert_eq!(get("0.3.1"), Some("0.3.4"));
+
+        // Older compatible versions are never selected.
+        assert_eq!(get("1.5.0"), None);
+        assert_eq!(get("0.3.5"), None);
+
+        assert_eq!(map.get_or_latest(None), Some(&"0.3.0"));
+        assert_eq!(map.get_latest(), Some((&Version::new(1, 4, 0), &"1.4.0")));
+
+        // Candidates are unaffected by the resolution mode.
+        assert_eq!(map.candidates(&Version::new(1, 5, 0)).count(), 4);
+    }
+
+    #[test]
+    fn test_version_map_priority() {
+        let map: VersionMap<_> = ["1.0.0", "1.1.0-beta.1", "1.1.0", "1.2.0-nightly.3", "1.3.0"]
+            .into_iter()
+            .map(|version| (Version::parse(version).unwrap(), version))
+            .collect();
+
+        // Prefer the beta channel, then releases, then everything else.
+        let channel = |version: &Version| match version.pre.as_str() {
+            pre if pre.starts_with("beta") => 2,
+            "" => 1,
+            _ => 0,
+        };
+        let mut map = map
+            .with_pre_release_policy(PreReleasePolicy::OptIn)
+            .with_priority(channel);
+
+        let get = |map: &VersionMap<_>, version: &str| {
+            map.get(&Version::parse(version).unwrap()).copied()
+        };
+
+        assert!(map.priority().is_some());
+        assert_eq!(get(&map, "1.1.0-alpha"), Some("1.1.0-beta.1"));
+        assert_eq!(get(&map, "1.1.0-nightly.1"), Some("1.3.0"));
+        assert_eq!(get(&map, "1.0.0"), Some("1.3.0"));
+        assert_eq!(map.get_or_latest(None), Some(&"1.1.0-beta.1"));
+
+        // Ties are broken by the resolution mode.
+        map.set_resolution_mode(ResolutionMode::Minimal);
+        assert_eq!(get(&map, "1.0.0"), Some("1.0.0"));
+        assert_eq!(get(&map, "1.1.0-alpha"), Some("1.1.0-beta.1"));
+
+        map.set_priority(None);
+        assert_eq!(get(&map, "1.1.0-alpha"), Some("1.1.0-beta.1"));
+        assert_eq!(map.get_or_latest(None), Some(&"1.0.0"));
+    }
+
+    #[test]
+    fn test_version_alternate_function() {
+        // Pre-release versions have no alternates
+        let pre = Version::parse("1.0.0-alpha").unwrap();
+        assert_eq!(version_alternate(&pre), None);
+
+        // Major versions > 0
+        assert_eq!(
+            version_alternate(&Version::new(1, 2, 3)),
+            Some(Version::new(1, 0, 0))
+        );
+        assert_eq!(
+            version_alternate(&Version::new(2, 5, 1)),
+            Some(Version::new(2, 0, 0))
+        );
+
+        // Minor versions > 0 (when major is 0)
+        assert_eq!(
+            version_alternate(&Version::new(0, 1, 5)),
+            Some(Version::new(0, 1, 0))
+        );
+        assert_eq!(
+            version_alternate(&Version::new(0, 3, 2)),
+            Some(Version::new(0, 3, 0))
+        );
+
+        // Patch versions (when major and minor are 0)
+        assert_eq!(
+            version_alternate(&Version::new(0, 0, 1)),
+            Some(Version::new(0, 0, 1))
+        );
+        assert_eq!(
+            version_alternate(&Version::new(0, 0, 5)),
+            Some(Version::new(0, 0, 5))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "borsh")]
+    fn test_borsh_serialize_deserialize() {
+        use borsh::{BorshDeserialize, BorshSerialize};
+
+        let mut map = VersionMap::new();
+        map.insert(Version::new(1, 0, 0), "v1.0.0");
+        map.insert(Version::new(2, 0, 0), "v2.0.0");
+
+        // Serialize
+        let mut buffer = Vec::new();
+        map.serialize(&mut buffer).unwrap();
+
+        // Deserialize
+        let deserialized_map: VersionMap<String> =
+            BorshDeserialize::deserialize_reader(&mut &buffer[..]).unwrap();
+
+        assert_eq!(
+            deserialized_map.get(&Version::new(1, 0, 0)),
+            Some(&"v1.0.0".to_string())
+        );
+
+        assert_eq!(
+            deserialized_map.get(&Version::new(2, 0, 0)),
+            Some(&"v2.0.0".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_serialize_deserialize() {
+        let mut map = VersionMap::new();
+        map.insert(Version::new(1, 0, 0), "v1.0.0");
+        map.insert(Version::new(2, 0, 0), "v2.0.0");
+
+        // Serialize
+        let serialized = serde_json::to_string(&map).unwrap();
+
+        // Deserialize
+        let deserialized_map: VersionMap<String> = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(
+            deserialized_map.get(&Version::new(1, 0, 0)),
+            Some(&"v1.0.0".to_string())
+        );
+
+        assert_eq!(
+            deserialized_map.get(&Version::new(2, 0, 0)),
+            Some(&"v2.0.0".to_string())
+        );
+    }
+
+    #[cfg(any(feature = "borsh", feature = "serde"))]
+    fn configured_map() -> VersionMap<String> {
+        let mut map = VersionMap::new()
+            .with_strategy(AlternateStrategy::LatestInMajor)
+            .with_pre_release_policy(PreReleasePolicy::OptIn)
+            .with_build_metadata_policy(BuildMetadataPolicy::Ignore)
+            .with_resolution_mode(ResolutionMode::Minimal);
+        map.insert(Version::new(0, 1, 0), "v0.1.0".to_string());
+        map.insert(Version::new(0, 2, 0), "v0.2.0".to_string());
+        map.insert(
+            Version::parse("1.0.0-rc.1").unwrap(),
+            "v1.0.0-rc.1".to_string(),
+        );
+        map.deprecate(&Version::new(0, 1, 0), "use 0.2");
+        map
+    }
+
+    /// Only versions and values survive a round-trip; everything else resets to the defaults.
+    #[cfg(any(feature = "borsh", feature = "serde"))]
+    fn assert_round_trip_defaults(map: &VersionMap<String>) {
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            map.get_exact(&Version::new(0, 1, 0)),
+            Some(&"v0.1.0".to_string())
+        );
+        assert_eq!(map.deprecation(&Version::new(0, 1, 0)), None);
+        assert!(matches!(map.strategy(), AlternateStrategy::CaretCompatible));
+        assert_eq!(map.pre_release_policy(), PreReleasePolicy::Exact);
+        assert_eq!(map.build_metadata_policy(), BuildMetadataPolicy::Exact);
+        assert_eq!(map.resolution_mode(), ResolutionMode::Latest);
+        assert!(map.priority().is_none());
+
+        // Caret compatibility keeps 0.1 and 0.2 apart, where `LatestInMajor` would not.
+        assert_eq!(map.get(&Version::new(0, 1, 0)), Some(&"v0.1.0".to_string()));
+        assert_eq!(map.get(&Version::parse("1.0.0-rc.0").unwrap()), None);
+    }
+
+    #[test]
+    #[cfg(feature = "borsh")]
+    fn test_borsh_round_trip_drops_settings() {
+        use borsh::{BorshDeserialize, BorshSerialize};
+
+        let mut buffer = Vec::new();
+        configured_map().serialize(&mut buffer).unwrap();
+
+        let map: VersionMap<String> =
+            BorshDeserialize::deserialize_reader(&mut &buffer[..]).unwrap();
+        assert_round_trip_defaults(&map);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_round_trip_drops_settings() {
+        let serialized = serde_json::to_string(&configured_map()).unwrap();
+
+        let map: VersionMap<String> = serde_json::from_str(&serialized).unwrap();
+        assert_round_trip_defaults(&map);
+    }
+}